@@ -0,0 +1,43 @@
+/// Prefix of a message the client wants acknowledged, followed by
+/// `<id> <body>`
+pub const ACK_REQUEST_PREFIX: &str = "MSG ";
+/// Prefix of the acknowledgement, followed by the message's id
+pub const ACK_PREFIX: &str = "ACK ";
+
+/// Splits a `MSG <id> <body>` message into its id & body
+pub fn split(payload: &[u8]) -> Option<(u64, &[u8])> {
+    let rest = payload.strip_prefix(ACK_REQUEST_PREFIX.as_bytes())?;
+    let id_length = rest.iter().position(|byte| *byte == b' ')?;
+    let id = std::str::from_utf8(&rest[..id_length]).ok()?.parse().ok()?;
+    Some((id, &rest[id_length + 1..]))
+}
+
+/// Builds the acknowledgement of message `id`
+pub fn acknowledge(id: u64) -> Vec<u8> {
+    format!("{}{}", ACK_PREFIX, id).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_split_into_id_and_body() {
+        assert_eq!(split(b"MSG 7 hello"), Some((7, &b"hello"[..])));
+        assert_eq!(split(b"MSG 7 "), Some((7, &b""[..])));
+        assert_eq!(split(b"MSG 7 a b"), Some((7, &b"a b"[..])));
+    }
+
+    #[test]
+    fn other_messages_are_not_split() {
+        assert_eq!(split(b"PING"), None);
+        assert_eq!(split(b"MSG 7"), None);
+        assert_eq!(split(b"MSG x hello"), None);
+        assert_eq!(split(b"MSG -1 hello"), None);
+    }
+
+    #[test]
+    fn acknowledgements_carry_the_id() {
+        assert_eq!(acknowledge(7), b"ACK 7");
+    }
+}
@@ -1,53 +1,1468 @@
-use naia_server_socket::{PacketReceiver, PacketSender, ServerAddrs, Socket};
+use std::{
+    fmt, io,
+    net::{IpAddr, SocketAddr, TcpListener, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use naia_server_socket::{NaiaServerSocketError, ServerAddrs, Socket};
 use naia_socket_shared::SocketConfig;
+use signaling_shared::{
+    checksum::strip_checksum,
+    compression::{self, COMPRESSION_ACCEPTED, COMPRESSION_OFFER},
+    framing::encode_frame,
+    packet::Packet,
+    prometheus::PrometheusText,
+    SESSION_PATH, TRICKLE_PATH,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::{
+    ack,
+    app_config::AppConfig,
+    challenge,
+    client_registry::{ClientInfo, ClientRegistry, HandshakeState},
+    handler::MessageHandler,
+    metrics_endpoint::MetricsEndpoint,
+    packet_io::{NaiaIo, PacketIo},
+    server_stats::{Metrics, ServerStats},
+    stats_file::StatsFile,
+    tls,
+    trickle_endpoint::{server_candidate, TrickleCandidates, TrickleEndpoint},
+};
+
+// Port to listen on for the signaling portion of WebRTC
+const SESSION_PORT: u16 = 14191;
+// Port to listen on for UDP WebRTC data channels
+const WEBRTC_PORT: u16 = 14192;
+
+// at most one warning about each kind of dropped packet is logged per interval
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(1);
+// how often packet & byte rates are sampled and logged
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+// how long `run` sleeps while there's nothing to handle, doubling from the
+// shortest for as long as it stays idle
+const MIN_IDLE_SLEEP: Duration = Duration::from_millis(1);
+const MAX_IDLE_SLEEP: Duration = Duration::from_millis(10);
+// how long queued sends get to reach the socket once the App shuts down
+const SHUTDOWN_FLUSH: Duration = Duration::from_millis(100);
+// largest payload a compressed message is inflated to
+const MAX_DECOMPRESSED_SIZE: usize = u16::MAX as usize;
 
 pub struct App {
-    packet_sender: PacketSender,
-    packet_receiver: PacketReceiver,
+    config: AppConfig,
+    handler: Box<dyn MessageHandler>,
+    io: Box<dyn PacketIo>,
+    clients: ClientRegistry,
+    stats: ServerStats,
+    stats_file: Option<StatsFile>,
+    metrics_endpoint: Option<MetricsEndpoint>,
+    trickle_candidates: Option<TrickleCandidates>,
+    corrupt_warning: DropWarning,
+    rate_limit_warning: DropWarning,
+    full_batches: u32,
+    overloaded: bool,
+    shut_down: bool,
+    // when rates were last sampled, and the totals at that point
+    rate_sample: (Instant, ServerStats),
+    rates: Metrics,
+}
+
+/// Why the App couldn't start
+#[derive(Debug)]
+pub enum AppError {
+    /// The TLS certificate or key couldn't be loaded
+    LoadTls(io::Error),
+    /// The TLS terminator couldn't start in front of the signaling endpoint
+    StartTls(io::Error),
+    /// The metrics endpoint couldn't be bound
+    ServeMetrics {
+        address: SocketAddr,
+        error: io::Error,
+    },
+    /// The trickle ICE endpoint couldn't be bound
+    ServeTrickle {
+        address: SocketAddr,
+        error: io::Error,
+    },
+    /// `AppConfig::strict_public_url` is set, and the advertised URL's port
+    /// isn't the one the data channels listen on
+    PublicPortMismatch { public_url: String, port: u16 },
+    /// Neither the configured addresses nor any of the bind hosts could be
+    /// bound
+    Bind {
+        hosts: Vec<IpAddr>,
+        error: io::Error,
+    },
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AppError::LoadTls(error) => write!(f, "failed to load the TLS certificate: {}", error),
+            AppError::StartTls(error) => write!(f, "failed to start TLS: {}", error),
+            AppError::ServeMetrics { address, error } => {
+                write!(f, "failed to serve metrics on {}: {}", address, error)
+            }
+            AppError::ServeTrickle { address, error } => {
+                write!(f, "failed to serve trickle ICE on {}: {}", address, error)
+            }
+            AppError::PublicPortMismatch { public_url, port } => write!(
+                f,
+                "advertised URL {} doesn't use the data channel port {}",
+                public_url, port
+            ),
+            AppError::Bind { hosts, error } => write!(
+                f,
+                "could not bind to any of the listen addresses {:?}: {}",
+                hosts, error
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AppError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppError::LoadTls(error)
+            | AppError::StartTls(error)
+            | AppError::ServeMetrics { error, .. }
+            | AppError::ServeTrickle { error, .. }
+            | AppError::Bind { error, .. } => Some(error),
+            AppError::PublicPortMismatch { .. } => None,
+        }
+    }
 }
 
 impl App {
-    pub fn new() -> Self {
+    /// Starts listening, answering every client message through `handler`.
+    /// Fails if anything it's configured to serve can't be, rather than
+    /// starting without it.
+    pub fn new(
+        config: AppConfig,
+        handler: impl MessageHandler + 'static,
+    ) -> Result<Self, AppError> {
         info!("Naia Server Socket Demo started");
 
-        let server_address = ServerAddrs::new(
-            "127.0.0.1:14191"
-                .parse()
-                .expect("could not parse Session address/port"),
-            // IP Address to listen on for UDP WebRTC data channels
-            "127.0.0.1:14192"
-                .parse()
-                .expect("could not parse WebRTC data address/port"),
-            // The public WebRTC IP address to advertise
-            "http://127.0.0.1:14192",
-        );
+        // a certificate that can't be served is as fatal as a port in use
+        let tls_acceptor = match (&config.tls_cert, &config.tls_key) {
+            (Some(cert), Some(key)) => {
+                Some(tls::load_acceptor(cert, key).map_err(AppError::LoadTls)?)
+            }
+            _ => None,
+        };
+        // bound before the sockets, so a port in use fails without leaving
+        // naia listening
+        let metrics_endpoint = match config.metrics_addr {
+            Some(address) => {
+                let endpoint = MetricsEndpoint::serve(address)
+                    .map_err(|error| AppError::ServeMetrics { address, error })?;
+                info!("Serving metrics on http://{}/metrics", address);
+                Some(endpoint)
+            }
+            None => None,
+        };
+        let trickle_endpoint = match config.trickle_addr {
+            Some(address) => Some(
+                TrickleEndpoint::bind(address)
+                    .map_err(|error| AppError::ServeTrickle { address, error })?,
+            ),
+            None => None,
+        };
+        let server_address = bind_server_addrs(&config, tls_acceptor)?;
+        let trickle_candidates = trickle_endpoint.map(|endpoint| {
+            if let Ok(address) = endpoint.local_addr() {
+                info!("Serving trickle ICE on http://{}/{}", address, TRICKLE_PATH);
+            }
+            endpoint.serve(vec![server_candidate(&server_address)])
+        });
 
-        let mut socket = Socket::new(&SocketConfig::new(None, None));
+        let socket_config = SocketConfig::new(None, Some(SESSION_PATH.to_string()));
+        let mut socket = Socket::new(&socket_config);
         socket.listen(&server_address);
+        let io = NaiaIo {
+            sender: socket.packet_sender(),
+            receiver: socket.packet_receiver(),
+        };
+        let mut app = App::with_io(config, handler, Box::new(io), metrics_endpoint);
+        app.trickle_candidates = trickle_candidates;
+        Ok(app)
+    }
+
+    // everything past opening the socket, so tests can swap it for memory
+    fn with_io(
+        config: AppConfig,
+        handler: impl MessageHandler + 'static,
+        io: Box<dyn PacketIo>,
+        metrics_endpoint: Option<MetricsEndpoint>,
+    ) -> Self {
+        if config.validate_checksums {
+            info!("Validating payload checksums");
+        }
+        if config.shared_secret.is_some() {
+            info!("Answering shared-secret challenges");
+        }
+        if config.ack {
+            info!("Acknowledging messages sent with an id");
+        }
+        if config.framed {
+            info!("Expecting & sending length-prefixed frames");
+        }
+        if config.fragmented {
+            info!("Reassembling & splitting messages in fragments");
+        }
+        if config.binary {
+            info!("Speaking the binary packet protocol");
+        }
+        if config.broadcast {
+            info!(
+                "Broadcasting to every client heard from in the last {:?}",
+                config.client_timeout
+            );
+        }
+        if let Some(rate_limit) = config.rate_limit {
+            info!("Limiting each client to {} packets per second", rate_limit);
+        }
+        if config.sequence_outbound {
+            info!("Stamping outbound messages with per-client sequence numbers");
+        }
+
+        let stats_file = config.stats_file.as_ref().and_then(|path| {
+            match StatsFile::open(path, config.stats_interval) {
+                Ok(stats_file) => {
+                    info!("Writing stats snapshots to {}", path);
+                    Some(stats_file)
+                }
+                Err(error) => {
+                    warn!("Failed to open stats file {}: {}", path, error);
+                    None
+                }
+            }
+        });
 
-        App {
-            packet_sender: socket.packet_sender(),
-            packet_receiver: socket.packet_receiver(),
+        let app = App {
+            clients: ClientRegistry::new(config.client_timeout, config.max_reassembly_bytes),
+            config,
+            handler: Box::new(handler),
+            io,
+            stats: ServerStats::default(),
+            stats_file,
+            metrics_endpoint,
+            trickle_candidates: None,
+            corrupt_warning: DropWarning::default(),
+            rate_limit_warning: DropWarning::default(),
+            full_batches: 0,
+            overloaded: false,
+            shut_down: false,
+            rate_sample: (Instant::now(), ServerStats::default()),
+            rates: Metrics::default(),
+        };
+        app.publish_metrics();
+        app
+    }
+
+    pub fn stats(&self) -> &ServerStats {
+        &self.stats
+    }
+
+    /// The current totals, with the rates from the last sampled second
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            totals: self.stats.clone(),
+            ..self.rates.clone()
         }
     }
 
+    /// When a packet, keepalives included, last arrived from `address`
+    pub fn last_seen(&self, address: &SocketAddr) -> Option<Instant> {
+        self.clients.get(address).map(|session| session.last_seen())
+    }
+
+    /// What's known about `address`, if it's connected
+    pub fn client(&self, address: &SocketAddr) -> Option<ClientInfo> {
+        self.clients.info(address)
+    }
+
+    /// Every client heard from within `AppConfig::client_timeout`, as of the
+    /// last sweep
+    pub fn clients(&self) -> Vec<ClientInfo> {
+        self.clients.infos()
+    }
+
+    /// Number of clients heard from within `AppConfig::client_timeout`, as
+    /// of the last sweep
+    pub fn client_count(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// The candidates clients have trickled, if `AppConfig::trickle_addr` is
+    /// set
+    pub fn trickle_candidates(&self) -> Option<&TrickleCandidates> {
+        self.trickle_candidates.as_ref()
+    }
+
+    /// Whether the last several batches were all full, meaning packets are
+    /// arriving faster than the App is handling them
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded
+    }
+
+    /// Stops handling packets, writes a last stats snapshot and gives queued
+    /// sends a moment to go out, blocking the thread meanwhile. naia's socket
+    /// can't be closed, so it stops listening when the process exits.
+    pub fn shutdown(&mut self) {
+        if self.stop() {
+            std::thread::sleep(SHUTDOWN_FLUSH);
+        }
+    }
+
+    /// Like `shutdown`, but waits for queued sends without blocking the
+    /// runtime it's awaited on
+    pub async fn shutdown_async(&mut self) {
+        if self.stop() {
+            tokio::time::sleep(SHUTDOWN_FLUSH).await;
+        }
+    }
+
+    // returns whether the App was still running, so sends need flushing
+    fn stop(&mut self) -> bool {
+        if self.shut_down {
+            return false;
+        }
+        self.shut_down = true;
+
+        self.clients.expire();
+        info!(
+            "Server shutting down with {} client(s) connected, {} packets received & {} sent",
+            self.clients.len(),
+            self.stats.packets_received,
+            self.stats.packets_sent
+        );
+        if let Some(stats_file) = &mut self.stats_file {
+            stats_file.write(&self.stats);
+        }
+        true
+    }
+
+    /// Handles packets on the current tokio runtime until `shutdown` is
+    /// cancelled, then shuts down. naia's receiver can only be polled, so
+    /// while nothing arrives the App sleeps, backing off up to 10ms, rather
+    /// than spinning.
+    pub async fn run(&mut self, shutdown: CancellationToken) {
+        let mut idle_sleep = MIN_IDLE_SLEEP;
+        while !shutdown.is_cancelled() {
+            if self.update_batch() > 0 || self.is_overloaded() {
+                idle_sleep = MIN_IDLE_SLEEP;
+                // lets the runtime's other tasks in between batches
+                tokio::task::yield_now().await;
+                continue;
+            }
+            tokio::select! {
+                _ = shutdown.cancelled() => {}
+                _ = tokio::time::sleep(idle_sleep) => {}
+            }
+            idle_sleep = (idle_sleep * 2).min(MAX_IDLE_SLEEP);
+        }
+        self.shutdown_async().await;
+    }
+
     pub fn update(&mut self) {
-        match self.packet_receiver.receive() {
-            Ok(Some((address, payload))) => {
-                let message_from_client = String::from_utf8_lossy(payload);
-                info!("Server recv <- {}: {}", address, message_from_client);
+        if self.shut_down {
+            return;
+        }
+        self.receive_one();
+        self.tick();
+    }
 
-                if message_from_client.eq("PING") {
-                    let message_to_client: String = "PONG".to_string();
-                    info!("Server send -> {}: {}", address, message_to_client);
-                    self.packet_sender
-                        .send(&address, message_to_client.as_bytes());
+    /// Handles up to `AppConfig::batch_size` packets, returning how many were
+    /// received
+    pub fn update_batch(&mut self) -> usize {
+        if self.shut_down {
+            return 0;
+        }
+        let mut received = 0;
+        while received < self.config.batch_size && self.receive_one() {
+            received += 1;
+        }
+
+        if received > 0 && received == self.config.batch_size {
+            self.full_batches = self.full_batches.saturating_add(1);
+        } else {
+            self.full_batches = 0;
+        }
+
+        let overloaded = self.full_batches >= self.config.overload_batches;
+        if overloaded != self.overloaded {
+            if overloaded {
+                warn!(
+                    "Server overloaded: {} consecutive full batches of {} packets",
+                    self.full_batches, self.config.batch_size
+                );
+            } else {
+                info!("Server no longer overloaded");
+            }
+            self.overloaded = overloaded;
+        }
+
+        self.tick();
+        received
+    }
+
+    // the periodic work both `update` and `update_batch` do after receiving
+    fn tick(&mut self) {
+        if let Some(stats_file) = &mut self.stats_file {
+            stats_file.write_if_due(&self.stats);
+        }
+        self.clients.expire_if_due();
+        self.sample_rates_if_due();
+    }
+
+    // returns whether a packet was received
+    fn receive_one(&mut self) -> bool {
+        let (address, packet) = loop {
+            match self.io.receive() {
+                Ok(Some(packet)) => break packet,
+                Ok(None) => return false,
+                // naia 0.9's receiver swallows these, so for now clients that
+                // can't be reached are only dropped once they time out
+                Err(NaiaServerSocketError::SendError(address)) => self.clients.evict(&address),
+                Err(error) => {
+                    info!("Server Error: {}", error);
+                    return false;
                 }
             }
+        };
+        // attributes everything logged while handling the packet to its sender
+        let _span = info_span!("client", addr = %address).entered();
+        self.stats.packets_received += 1;
+        self.stats.bytes_received += packet.len() as u64;
+        let session = self.clients.register(address);
+        session.touch();
+
+        // a client over its limit still counts as connected, but is ignored
+        if let Some(rate_limit) = self.config.rate_limit {
+            if !session.within_rate(rate_limit) {
+                self.record_rate_limited_packet(&address);
+                return true;
+            }
+        }
+
+        let messages = if self.config.framed {
+            session.receive_frames(&packet)
+        } else {
+            vec![packet]
+        };
+        for message in messages {
+            if self.config.fragmented {
+                self.receive_fragment(&address, &message);
+            } else {
+                self.handle_message(&address, &message);
+            }
+        }
+        true
+    }
+
+    // handles the fragment's message once it's the last one missing
+    fn receive_fragment(&mut self, address: &SocketAddr, fragment: &[u8]) {
+        let expired = self.clients.expired_fragments();
+        // an earlier message in the same packet may have disconnected it
+        let received = match self.clients.receive_fragment(address, fragment) {
+            Some(received) => received,
+            None => return,
+        };
+        let given_up = self.clients.expired_fragments() - expired;
+        if given_up > 0 {
+            self.stats.reassembly_failures += given_up;
+            warn!(
+                "Gave up reassembling {} message(s) on a fragment from {}, missing fragments or over the reassembly budget every client shares",
+                given_up, address
+            );
+        }
+        match received {
+            Ok(Some(message)) => self.handle_message(address, &message),
             Ok(None) => {}
             Err(error) => {
-                info!("Server Error: {}", error);
+                debug!("Bad fragment from {}: {}", address, error);
+                self.record_corrupt_packet(address);
+            }
+        }
+    }
+
+    fn handle_message(&mut self, address: &SocketAddr, payload: &[u8]) {
+        let session = match self.clients.get_mut(address) {
+            Some(session) => session,
+            None => {
+                debug!("Dropping a message from {}, which disconnected", address);
+                return;
+            }
+        };
+        session.record_received();
+        let compressed = session.compressed();
+        let decompressed;
+        let payload = if compressed {
+            match compression::decompress(payload, MAX_DECOMPRESSED_SIZE) {
+                Ok(body) => {
+                    decompressed = body;
+                    &decompressed[..]
+                }
+                Err(_) => {
+                    self.record_corrupt_packet(address);
+                    return;
+                }
+            }
+        } else {
+            payload
+        };
+
+        // keepalives only refresh the session, with a checksum or without
+        let keepalive = [self.config.keepalive_marker];
+        if payload == keepalive {
+            return;
+        }
+        let payload = if self.config.validate_checksums {
+            match strip_checksum(payload) {
+                Some(body) => body,
+                None => {
+                    self.record_corrupt_packet(address);
+                    return;
+                }
+            }
+        } else {
+            payload
+        };
+        if payload == keepalive {
+            return;
+        }
+
+        // accepted as-is, so the client knows everything after is compressed
+        if !compressed && payload == COMPRESSION_OFFER {
+            self.send_control(address, COMPRESSION_ACCEPTED);
+            if let Some(session) = self.clients.get_mut(address) {
+                session.set_compressed();
+            }
+            info!("Client {} compresses its payloads", address);
+            return;
+        }
+
+        if !self.config.binary {
+            debug!(
+                "Server recv <- {}: {}",
+                address,
+                String::from_utf8_lossy(payload)
+            );
+        }
+
+        // acknowledge before anything can drop the message, so a retransmit
+        // isn't needed for it; the body is then handled like any other
+        let payload = match ack::split(payload).filter(|_| self.config.ack) {
+            Some((id, body)) => {
+                self.send_control(address, &ack::acknowledge(id));
+                body
+            }
+            None => payload,
+        };
+
+        // stop echoing while overloaded, to throttle clients
+        if self.overloaded && self.config.shed_load {
+            return;
+        }
+
+        if self.config.binary {
+            // challenges stay text, so they're answered the same either way
+            match self.answer_challenge(address, payload) {
+                Some(answer) => self.send_control(address, &answer),
+                None => self.handle_packet(address, payload),
+            }
+            return;
+        }
+
+        if let Some(answer) = self.answer_challenge(address, payload) {
+            self.send_control(address, &answer);
+            return;
+        }
+        self.clients.advance(address, HandshakeState::Active);
+        if let Some(message_to_client) = self.handler.handle(*address, payload) {
+            debug!(
+                "Server send -> {}: {}",
+                address,
+                String::from_utf8_lossy(&message_to_client)
+            );
+            self.send_to_client(address, &message_to_client);
+        }
+        if self.config.broadcast {
+            self.broadcast(address, payload);
+        }
+    }
+
+    // the binary protocol: pings are answered here, and data goes to the
+    // handler, its response wrapped as data too
+    fn handle_packet(&mut self, address: &SocketAddr, payload: &[u8]) {
+        let packet = match Packet::decode(payload) {
+            Ok(packet) => packet,
+            Err(error) => {
+                debug!("Undecodable packet from {}: {}", address, error);
+                self.record_corrupt_packet(address);
+                return;
+            }
+        };
+        debug!("Server recv <- {}: {:?}", address, packet);
+        match packet {
+            Packet::Ping {
+                sequence,
+                sent_at_us,
+            } => {
+                let pong = Packet::Pong {
+                    sequence,
+                    sent_at_us,
+                };
+                self.send_to_client(address, &pong.encode());
+            }
+            Packet::Data(body) => {
+                self.clients.advance(address, HandshakeState::Active);
+                if let Some(response) = self.handler.handle(*address, &body) {
+                    let response = Packet::Data(response);
+                    debug!("Server send -> {}: {:?}", address, response);
+                    self.send_to_client(address, &response.encode());
+                }
+                if self.config.broadcast {
+                    self.broadcast(address, payload);
+                }
+            }
+            Packet::Disconnect => {
+                self.clients.disconnect(address);
+            }
+            // the server never pings
+            Packet::Pong { .. } => {}
+        }
+    }
+
+    // the answer to a shared-secret challenge, if that's what this is.
+    // Challenges are answered here, since they belong to the server's
+    // config; everything else is up to the handler
+    fn answer_challenge(&mut self, address: &SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+        let secret = self.config.shared_secret.as_ref()?;
+        let message_from_client = String::from_utf8_lossy(payload);
+        let nonce = message_from_client.strip_prefix(challenge::CHALLENGE_PREFIX)?;
+        let answer = challenge::respond(secret.as_bytes(), nonce).into_bytes();
+        self.clients.advance(address, HandshakeState::Authenticated);
+        Some(answer)
+    }
+
+    // relays a client's message to every connected client, itself included
+    fn broadcast(&mut self, from: &SocketAddr, payload: &[u8]) {
+        self.clients.expire();
+        let recipients: Vec<SocketAddr> = self.clients.addresses().copied().collect();
+        for recipient in &recipients {
+            self.send_to_client(recipient, payload);
+        }
+        debug!(
+            "Server broadcast {} -> {} client(s)",
+            from,
+            recipients.len()
+        );
+    }
+
+    // logs the rates since the last sample, unless nothing happened
+    fn sample_rates_if_due(&mut self) {
+        let (sampled_at, previous) = &self.rate_sample;
+        let elapsed = sampled_at.elapsed();
+        if elapsed < RATE_INTERVAL {
+            return;
+        }
+
+        let seconds = elapsed.as_secs_f64();
+        let rate = |now: u64, before: u64| (now - before) as f64 / seconds;
+        self.rates = Metrics {
+            totals: self.stats.clone(),
+            packets_received_per_sec: rate(self.stats.packets_received, previous.packets_received),
+            packets_sent_per_sec: rate(self.stats.packets_sent, previous.packets_sent),
+            bytes_received_per_sec: rate(self.stats.bytes_received, previous.bytes_received),
+            bytes_sent_per_sec: rate(self.stats.bytes_sent, previous.bytes_sent),
+        };
+        if self.stats.packets_received != previous.packets_received
+            || self.stats.packets_sent != previous.packets_sent
+        {
+            info!(
+                "In: {:.1} packets/s ({:.0} B/s), out: {:.1} packets/s ({:.0} B/s), {} client(s)",
+                self.rates.packets_received_per_sec,
+                self.rates.bytes_received_per_sec,
+                self.rates.packets_sent_per_sec,
+                self.rates.bytes_sent_per_sec,
+                self.clients.len()
+            );
+        }
+        self.rate_sample = (Instant::now(), self.stats.clone());
+        self.publish_metrics();
+    }
+
+    // hands the latest metrics to the endpoint to serve, if there is one
+    fn publish_metrics(&self) {
+        if let Some(endpoint) = &self.metrics_endpoint {
+            endpoint.update(self.metrics_page());
+        }
+    }
+
+    fn metrics_page(&self) -> String {
+        let totals = &self.stats;
+        let mut page = PrometheusText::default();
+        page.counter(
+            "naia_server_packets_received_total",
+            "Packets received from clients",
+            totals.packets_received,
+        )
+        .counter(
+            "naia_server_packets_sent_total",
+            "Packets sent to clients",
+            totals.packets_sent,
+        )
+        .counter(
+            "naia_server_bytes_received_total",
+            "Bytes received from clients",
+            totals.bytes_received,
+        )
+        .counter(
+            "naia_server_bytes_sent_total",
+            "Bytes sent to clients",
+            totals.bytes_sent,
+        )
+        .counter(
+            "naia_server_corrupt_packets_total",
+            "Packets dropped for a bad checksum, compression, fragment or packet",
+            totals.corrupt_packets,
+        )
+        .counter(
+            "naia_server_rate_limited_packets_total",
+            "Packets dropped for exceeding the rate limit",
+            totals.rate_limited_packets,
+        )
+        .counter(
+            "naia_server_reassembly_failures_total",
+            "Messages given up on for missing fragments or over the reassembly budget",
+            totals.reassembly_failures,
+        )
+        .gauge(
+            "naia_server_packets_received_per_second",
+            "Packets received over the last sampled second",
+            self.rates.packets_received_per_sec,
+        )
+        .gauge(
+            "naia_server_packets_sent_per_second",
+            "Packets sent over the last sampled second",
+            self.rates.packets_sent_per_sec,
+        )
+        .gauge(
+            "naia_server_bytes_received_per_second",
+            "Bytes received over the last sampled second",
+            self.rates.bytes_received_per_sec,
+        )
+        .gauge(
+            "naia_server_bytes_sent_per_second",
+            "Bytes sent over the last sampled second",
+            self.rates.bytes_sent_per_sec,
+        )
+        .gauge(
+            "naia_server_connected_clients",
+            "Clients heard from within the client timeout",
+            self.clients.len() as f64,
+        )
+        .gauge(
+            "naia_server_reassembly_bytes",
+            "Fragment bytes held for partly received messages",
+            self.clients.reassembly_bytes() as f64,
+        );
+        page.finish()
+    }
+
+    // sends a message of the App's protocol, stamped with the client's next
+    // sequence number when that's on
+    fn send_to_client(&mut self, address: &SocketAddr, payload: &[u8]) {
+        self.send(address, payload, self.config.sequence_outbound);
+    }
+
+    // sends the server's own replies, to ACKs, challenges and compression
+    // offers, which are never stamped
+    fn send_control(&mut self, address: &SocketAddr, payload: &[u8]) {
+        self.send(address, payload, false);
+    }
+
+    fn send(&mut self, address: &SocketAddr, payload: &[u8], sequenced: bool) {
+        // sending mustn't bring back a client that has disconnected
+        let session = match self.clients.get_mut(address) {
+            Some(session) => session,
+            None => {
+                debug!("Not sending to {}, which disconnected", address);
+                return;
+            }
+        };
+        session.record_sent();
+        let mut message = if sequenced {
+            let mut stamped = session.next_outbound_sequence().to_be_bytes().to_vec();
+            stamped.extend_from_slice(payload);
+            stamped
+        } else {
+            payload.to_vec()
+        };
+        if session.compressed() {
+            message = compression::compress(&message);
+        }
+        let packets = if self.config.fragmented {
+            match session.fragment(&message) {
+                Some(fragments) => fragments,
+                None => {
+                    warn!(
+                        "Dropping {} byte message to {}, too large to fragment",
+                        message.len(),
+                        address
+                    );
+                    return;
+                }
+            }
+        } else {
+            vec![message]
+        };
+        for mut packet in packets {
+            if self.config.framed {
+                packet = match encode_frame(&packet) {
+                    Some(frame) => frame,
+                    None => {
+                        warn!(
+                            "Dropping {} byte message to {}, too large to frame",
+                            packet.len(),
+                            address
+                        );
+                        return;
+                    }
+                };
+            }
+            self.io.send(address, &packet);
+            self.stats.packets_sent += 1;
+            self.stats.bytes_sent += packet.len() as u64;
+        }
+    }
+
+    fn record_corrupt_packet(&mut self, address: &SocketAddr) {
+        self.stats.corrupt_packets += 1;
+        if let Some(dropped) = self.corrupt_warning.record() {
+            warn!(
+                "Server dropped {} corrupt packet(s), latest from {} ({} total)",
+                dropped, address, self.stats.corrupt_packets
+            );
+        }
+    }
+
+    fn record_rate_limited_packet(&mut self, address: &SocketAddr) {
+        self.stats.rate_limited_packets += 1;
+        if let Some(dropped) = self.rate_limit_warning.record() {
+            warn!(
+                "Server dropped {} packet(s) over the rate limit, latest from {} ({} total)",
+                dropped, address, self.stats.rate_limited_packets
+            );
+        }
+    }
+}
+
+// counts dropped packets between warnings, so a flood of them doesn't flood
+// the log too
+#[derive(Default)]
+struct DropWarning {
+    last_warning: Option<Instant>,
+    since_warning: u64,
+}
+
+impl DropWarning {
+    // counts a drop, returning how many to warn about if a warning is due
+    fn record(&mut self) -> Option<u64> {
+        self.since_warning += 1;
+
+        let now = Instant::now();
+        let should_warn = self
+            .last_warning
+            .is_none_or(|last| now.duration_since(last) >= DROP_WARNING_INTERVAL);
+        if !should_warn {
+            return None;
+        }
+        self.last_warning = Some(now);
+        Some(std::mem::take(&mut self.since_warning))
+    }
+}
+
+// clients sent a different port finish signaling but never exchange data,
+// so a mismatch is at least warned about
+fn check_public_port(
+    config: &AppConfig,
+    public_webrtc_url: &str,
+    webrtc_listen_addr: SocketAddr,
+) -> Result<(), AppError> {
+    let public_port = Url::parse(public_webrtc_url)
+        .ok()
+        .and_then(|url| url.port_or_known_default());
+    if public_port == Some(webrtc_listen_addr.port()) {
+        return Ok(());
+    }
+    if config.strict_public_url {
+        return Err(AppError::PublicPortMismatch {
+            public_url: public_webrtc_url.to_string(),
+            port: webrtc_listen_addr.port(),
+        });
+    }
+    warn!(
+        "Advertised URL {} doesn't use the data channel port {}; clients will only reach the server if something forwards it",
+        public_webrtc_url,
+        webrtc_listen_addr.port()
+    );
+    Ok(())
+}
+
+// picks the first host both ports can be bound on, since some environments
+// only allow binding one of the IPv4 / IPv6 loopbacks. Explicitly configured
+// addresses are used as-is. With TLS, the session address is served by the
+// TLS terminator and naia's endpoint listens behind it on loopback.
+fn bind_server_addrs(
+    config: &AppConfig,
+    tls_acceptor: Option<TlsAcceptor>,
+) -> Result<ServerAddrs, AppError> {
+    let mut last_error = None;
+    for host in &config.bind_hosts {
+        let session_listen_addr = config
+            .session_addr
+            .unwrap_or_else(|| SocketAddr::new(*host, SESSION_PORT));
+        let webrtc_listen_addr = config
+            .webrtc_addr
+            .unwrap_or_else(|| SocketAddr::new(*host, WEBRTC_PORT));
+
+        // the socket binds asynchronously & panics on failure, so check first
+        let bind_result = TcpListener::bind(session_listen_addr)
+            .and_then(|listener| UdpSocket::bind(webrtc_listen_addr).map(|_| listener));
+        match bind_result {
+            Ok(session_listener) => {
+                // The public WebRTC IP address to advertise
+                let public_webrtc_url = config
+                    .public_url
+                    .clone()
+                    .unwrap_or_else(|| format!("http://{}", webrtc_listen_addr));
+                // checked before TLS starts, so failing doesn't leave it running
+                check_public_port(config, &public_webrtc_url, webrtc_listen_addr)?;
+                let naia_session_addr = match tls_acceptor {
+                    Some(acceptor) => {
+                        let upstream =
+                            tls::serve(session_listener, acceptor).map_err(AppError::StartTls)?;
+                        info!(
+                            "Signaling on https://{}/{}",
+                            session_listen_addr, SESSION_PATH
+                        );
+                        upstream
+                    }
+                    None => {
+                        info!(
+                            "Signaling on http://{}/{}",
+                            session_listen_addr, SESSION_PATH
+                        );
+                        session_listen_addr
+                    }
+                };
+                info!("Data channels on {}", webrtc_listen_addr);
+                info!("Advertising {}", public_webrtc_url);
+                return Ok(ServerAddrs::new(
+                    naia_session_addr,
+                    webrtc_listen_addr,
+                    &public_webrtc_url,
+                ));
+            }
+            Err(error) => {
+                warn!(
+                    "Could not bind to {} / {}: {}",
+                    session_listen_addr, webrtc_listen_addr, error
+                );
+                last_error = Some(error);
+                // with both addresses given, other hosts wouldn't change anything
+                if config.session_addr.is_some() && config.webrtc_addr.is_some() {
+                    break;
+                }
             }
         }
     }
+
+    Err(AppError::Bind {
+        hosts: config.bind_hosts.clone(),
+        error: last_error
+            .unwrap_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no bind hosts")),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handler::PingPong, packet_io::MemoryIo};
+    use signaling_shared::{
+        checksum::append_checksum,
+        fragment::{Fragmenter, FRAGMENT_SIZE},
+    };
+
+    // an App over in-memory packets, answering PINGs, and the packets' other
+    // end
+    fn memory_app(config: AppConfig) -> (App, MemoryIo) {
+        let io = MemoryIo::default();
+        let app = App::with_io(config, PingPong::default(), Box::new(io.clone()), None);
+        (app, io)
+    }
+
+    fn client_addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    // listen addresses the OS picks the ports of
+    fn ephemeral_config() -> AppConfig {
+        AppConfig {
+            session_addr: Some("127.0.0.1:0".parse().unwrap()),
+            webrtc_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..AppConfig::default()
+        }
+    }
+
+    #[test]
+    fn port_in_use_is_an_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AppConfig {
+            session_addr: Some(taken.local_addr().unwrap()),
+            ..ephemeral_config()
+        };
+        match App::new(config, PingPong::default()) {
+            Err(AppError::Bind { .. }) => {}
+            other => panic!("expected a bind error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn trickle_port_in_use_is_an_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AppConfig {
+            trickle_addr: Some(taken.local_addr().unwrap()),
+            ..ephemeral_config()
+        };
+        match App::new(config, PingPong::default()) {
+            Err(AppError::ServeTrickle { .. }) => {}
+            other => panic!("expected a trickle error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn metrics_port_in_use_is_an_error() {
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = AppConfig {
+            metrics_addr: Some(taken.local_addr().unwrap()),
+            ..ephemeral_config()
+        };
+        match App::new(config, PingPong::default()) {
+            Err(AppError::ServeMetrics { .. }) => {}
+            other => panic!("expected a metrics error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn missing_certificate_is_an_error() {
+        let config = AppConfig {
+            tls_cert: Some("/nonexistent/cert.pem".to_string()),
+            tls_key: Some("/nonexistent/key.pem".to_string()),
+            ..ephemeral_config()
+        };
+        match App::new(config, PingPong::default()) {
+            Err(AppError::LoadTls(_)) => {}
+            other => panic!("expected a TLS error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn strict_public_port_mismatch_is_an_error() {
+        let config = AppConfig {
+            public_url: Some("http://203.0.113.7:1".to_string()),
+            strict_public_url: true,
+            ..ephemeral_config()
+        };
+        match App::new(config, PingPong::default()) {
+            Err(AppError::PublicPortMismatch { public_url, .. }) => {
+                assert_eq!(public_url, "http://203.0.113.7:1")
+            }
+            other => panic!("expected a public port error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn pings_get_pongs() {
+        let (mut app, io) = memory_app(AppConfig::default());
+        io.push(client_addr(1), b"PING");
+        assert_eq!(app.update_batch(), 1);
+        assert_eq!(io.take_sent(), vec![(client_addr(1), b"PONG".to_vec())]);
+    }
+
+    #[tokio::test]
+    async fn run_answers_until_cancelled() {
+        let (mut app, io) = memory_app(AppConfig::default());
+        io.push(client_addr(1), b"PING");
+        let shutdown = CancellationToken::new();
+        let mut sent = Vec::new();
+        let answered = async {
+            while sent.is_empty() {
+                sent.extend(io.take_sent());
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            shutdown.cancel();
+        };
+        let running = async { tokio::join!(app.run(shutdown.clone()), answered) };
+        tokio::time::timeout(Duration::from_secs(5), running)
+            .await
+            .expect("run kept going once cancelled");
+
+        assert_eq!(sent[0], (client_addr(1), b"PONG".to_vec()));
+        // nothing's handled once it's shut down
+        io.push(client_addr(1), b"PING");
+        assert_eq!(app.update_batch(), 0);
+    }
+
+    #[test]
+    fn checksums_are_validated_when_asked_to() {
+        let (mut app, io) = memory_app(AppConfig {
+            validate_checksums: true,
+            ..AppConfig::default()
+        });
+        let mut corrupt = append_checksum(b"PING");
+        corrupt[0] = b'X';
+        let keepalive = [app.config.keepalive_marker];
+        io.push(client_addr(1), &append_checksum(b"PING"));
+        io.push(client_addr(1), &corrupt);
+        io.push(client_addr(1), b"PIN");
+        io.push(client_addr(1), b"PING");
+        io.push(client_addr(1), &keepalive);
+        io.push(client_addr(1), &append_checksum(&keepalive));
+        app.update_batch();
+
+        // the PING with its checksum is answered, the PONG going out as-is
+        assert_eq!(io.take_sent(), vec![(client_addr(1), b"PONG".to_vec())]);
+        // keepalives needn't carry one
+        assert_eq!(app.stats().corrupt_packets, 3);
+    }
+
+    #[test]
+    fn keepalives_refresh_the_session_without_a_response() {
+        let (mut app, io) = memory_app(AppConfig::default());
+        let client = client_addr(1);
+        let keepalive = [app.config.keepalive_marker];
+
+        io.push(client, &keepalive);
+        assert_eq!(app.update_batch(), 1);
+        let first_seen = app.last_seen(&client).expect("keepalive connects");
+        std::thread::sleep(Duration::from_millis(5));
+        io.push(client, &keepalive);
+        app.update_batch();
+
+        assert!(app.last_seen(&client).unwrap() > first_seen);
+        assert!(io.take_sent().is_empty());
+        assert_eq!(app.stats().packets_sent, 0);
+        let info = app.client(&client).unwrap();
+        assert_eq!(info.handshake, HandshakeState::Connected);
+        assert_eq!(info.messages_sent, 0);
+    }
+
+    #[test]
+    fn acked_messages_are_acknowledged_before_being_handled() {
+        let (mut app, io) = memory_app(AppConfig {
+            ack: true,
+            ..AppConfig::default()
+        });
+        io.push(client_addr(1), b"MSG 3 PING");
+        io.push(client_addr(1), b"PING");
+        app.update_batch();
+        let sent: Vec<_> = io.take_sent().into_iter().map(|(_, sent)| sent).collect();
+        assert_eq!(
+            sent,
+            vec![b"ACK 3".to_vec(), b"PONG".to_vec(), b"PONG".to_vec()]
+        );
+    }
+
+    #[test]
+    fn acks_are_off_by_default() {
+        let (mut app, io) = memory_app(AppConfig::default());
+        io.push(client_addr(1), b"MSG 3 PING");
+        app.update_batch();
+        assert!(io.take_sent().is_empty());
+    }
+
+    // connects clients 1 to `count` with a keepalive each
+    fn connect_clients(app: &mut App, io: &MemoryIo, count: u16) {
+        for port in 1..=count {
+            io.push(client_addr(port), &[app.config.keepalive_marker]);
+        }
+        app.update_batch();
+        assert_eq!(app.client_count(), count as usize);
+    }
+
+    #[test]
+    fn broadcasts_reach_every_client_the_sender_included() {
+        let (mut app, io) = memory_app(AppConfig {
+            broadcast: true,
+            ..AppConfig::default()
+        });
+        connect_clients(&mut app, &io, 3);
+
+        io.push(client_addr(1), b"hello");
+        app.update_batch();
+        let mut sent = io.take_sent();
+        sent.sort();
+        let hello: Vec<_> = (1..=3)
+            .map(|port| (client_addr(port), b"hello".to_vec()))
+            .collect();
+        assert_eq!(sent, hello);
+
+        // answered and relayed both
+        io.push(client_addr(2), b"PING");
+        app.update_batch();
+        let sent = io.take_sent();
+        assert_eq!(sent[0], (client_addr(2), b"PONG".to_vec()));
+        let mut relayed = sent[1..].to_vec();
+        relayed.sort();
+        let ping: Vec<_> = (1..=3)
+            .map(|port| (client_addr(port), b"PING".to_vec()))
+            .collect();
+        assert_eq!(relayed, ping);
+    }
+
+    #[test]
+    fn keepalives_are_not_broadcast() {
+        let (mut app, io) = memory_app(AppConfig {
+            broadcast: true,
+            ..AppConfig::default()
+        });
+        connect_clients(&mut app, &io, 2);
+        assert!(io.take_sent().is_empty());
+    }
+
+    #[test]
+    fn clients_a_send_fails_to_are_dropped() {
+        let (mut app, io) = memory_app(AppConfig {
+            broadcast: true,
+            ..AppConfig::default()
+        });
+        connect_clients(&mut app, &io, 3);
+        io.fail_sends_to(client_addr(2));
+
+        io.push(client_addr(1), b"hello");
+        app.update_batch();
+        assert_eq!(app.client_count(), 2);
+        assert!(app.client(&client_addr(2)).is_none());
+
+        io.take_sent();
+        io.push(client_addr(1), b"again");
+        app.update_batch();
+        let mut recipients: Vec<_> = io.take_sent().into_iter().map(|(to, _)| to).collect();
+        recipients.sort();
+        assert_eq!(recipients, vec![client_addr(1), client_addr(3)]);
+    }
+
+    #[test]
+    fn compression_is_negotiated_per_client() {
+        let (mut app, io) = memory_app(AppConfig::default());
+        io.push(client_addr(1), COMPRESSION_OFFER);
+        app.update_batch();
+        assert_eq!(
+            io.take_sent(),
+            vec![(client_addr(1), COMPRESSION_ACCEPTED.to_vec())]
+        );
+
+        // one client compressing doesn't change how the others are answered
+        io.push(client_addr(1), &compression::compress(b"PING"));
+        io.push(client_addr(2), b"PING");
+        app.update_batch();
+        let sent = io.take_sent();
+        assert_eq!(sent[0].0, client_addr(1));
+        assert_eq!(
+            compression::decompress(&sent[0].1, MAX_DECOMPRESSED_SIZE).as_deref(),
+            Ok(&b"PONG"[..])
+        );
+        assert_eq!(sent[1], (client_addr(2), b"PONG".to_vec()));
+
+        // an uncompressed message from a compressing client is corrupt
+        io.push(client_addr(1), b"PING");
+        app.update_batch();
+        assert!(io.take_sent().is_empty());
+        assert_eq!(app.stats().corrupt_packets, 1);
+    }
+
+    #[test]
+    fn only_protocol_messages_are_sequenced() {
+        let (mut app, io) = memory_app(AppConfig {
+            sequence_outbound: true,
+            ack: true,
+            shared_secret: Some("secret".to_string()),
+            ..AppConfig::default()
+        });
+        let client = client_addr(1);
+        io.push(client, b"PING");
+        io.push(client, b"MSG 5 PING");
+        io.push(client, b"CHALLENGE abc");
+        io.push(client, COMPRESSION_OFFER);
+        app.update_batch();
+
+        let sent: Vec<_> = io.take_sent().into_iter().map(|(_, sent)| sent).collect();
+        assert_eq!(sent.len(), 5);
+        assert_eq!(sent[0], b"\0\0\0\0PONG");
+        assert_eq!(sent[1], b"ACK 5");
+        assert_eq!(sent[2], b"\0\0\0\x01PONG");
+        assert!(sent[3].starts_with(b"CHALLENGE_RESPONSE "));
+        assert_eq!(sent[4], COMPRESSION_ACCEPTED);
+    }
+
+    #[test]
+    fn messages_after_a_disconnect_dont_bring_the_client_back() {
+        let (mut app, io) = memory_app(AppConfig {
+            binary: true,
+            framed: true,
+            ..AppConfig::default()
+        });
+        let ping = Packet::Ping {
+            sequence: 1,
+            sent_at_us: 0,
+        };
+        io.push(client_addr(1), &encode_frame(&ping.encode()).unwrap());
+        app.update_batch();
+        assert_eq!(io.take_sent().len(), 1);
+
+        // both arrive in one packet, so the PING is read after the client left
+        let mut packet = encode_frame(&Packet::Disconnect.encode()).unwrap();
+        packet.extend(encode_frame(&ping.encode()).unwrap());
+        io.push(client_addr(1), &packet);
+        app.update_batch();
+        assert!(io.take_sent().is_empty());
+        assert_eq!(app.client_count(), 0);
+    }
+
+    // a stats file of its own for each test, removed once it's done with
+    struct TempStatsFile(std::path::PathBuf);
+
+    impl TempStatsFile {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "naia-stats-{}-{}.jsonl",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_file(&path);
+            TempStatsFile(path)
+        }
+
+        fn lines(&self) -> Vec<serde_json::Value> {
+            std::fs::read_to_string(&self.0)
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    impl Drop for TempStatsFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn going_over_the_reassembly_budget_gives_up_on_the_oldest_message() {
+        let (mut app, io) = memory_app(AppConfig {
+            fragmented: true,
+            max_reassembly_bytes: 2 * FRAGMENT_SIZE,
+            ..AppConfig::default()
+        });
+        let client = client_addr(1);
+        let mut fragmenter = Fragmenter::default();
+        let messages: Vec<_> = (0..3)
+            .map(|_| fragmenter.split(&[7; 2 * FRAGMENT_SIZE]).unwrap())
+            .collect();
+
+        // the first half of each, the third going over the budget
+        for fragments in &messages {
+            io.push(client, &fragments[0]);
+        }
+        app.update_batch();
+        assert_eq!(app.stats().reassembly_failures, 1);
+        assert!(app.metrics_page().contains(&format!(
+            "naia_server_reassembly_bytes {}\n",
+            2 * FRAGMENT_SIZE
+        )));
+
+        // the newest can still be finished, which frees what it held
+        io.push(client, &messages[2][1]);
+        app.update_batch();
+        assert_eq!(app.stats().reassembly_failures, 1);
+        assert!(app
+            .metrics_page()
+            .contains(&format!("naia_server_reassembly_bytes {}\n", FRAGMENT_SIZE)));
+    }
+
+    #[test]
+    fn every_client_shares_the_reassembly_budget() {
+        let (mut app, io) = memory_app(AppConfig {
+            fragmented: true,
+            max_reassembly_bytes: 3 * FRAGMENT_SIZE,
+            ..AppConfig::default()
+        });
+        // PINGs stamped long enough for two fragments, to see which get PONGs
+        let ping = format!("PING {}", "0".repeat(2 * FRAGMENT_SIZE - 5));
+        let mut fragmenter = Fragmenter::default();
+        let messages: Vec<_> = (0..4)
+            .map(|_| fragmenter.split(ping.as_bytes()).unwrap())
+            .collect();
+
+        // four clients each well within the budget alone, but not together
+        for (port, fragments) in (1..).zip(&messages) {
+            io.push(client_addr(port), &fragments[0]);
+        }
+        app.update_batch();
+        assert_eq!(app.stats().reassembly_failures, 1);
+        assert!(app.metrics_page().contains(&format!(
+            "naia_server_reassembly_bytes {}\n",
+            3 * FRAGMENT_SIZE
+        )));
+
+        // the second client's can still be finished, freeing what it held
+        io.push(client_addr(2), &messages[1][1]);
+        app.update_batch();
+        let answered: Vec<_> = io.take_sent().into_iter().map(|(to, _)| to).collect();
+        assert_eq!(answered, vec![client_addr(2); 2]);
+        assert_eq!(app.stats().reassembly_failures, 1);
+
+        // while the first client's, the oldest, was given up on
+        io.push(client_addr(1), &messages[0][1]);
+        app.update_batch();
+        assert!(io.take_sent().is_empty());
+        assert_eq!(app.stats().reassembly_failures, 1);
+    }
+
+    #[test]
+    fn update_writes_the_stats_file() {
+        let stats_file = TempStatsFile::new("update");
+        let (mut app, io) = memory_app(AppConfig {
+            stats_file: Some(stats_file.0.to_str().unwrap().to_string()),
+            stats_interval: Duration::from_millis(10),
+            ..AppConfig::default()
+        });
+
+        io.push(client_addr(1), b"PING");
+        app.update();
+        assert_eq!(stats_file.lines().len(), 1);
+        assert_eq!(stats_file.lines()[0]["packets_received"], 1);
+        // not due again yet
+        app.update();
+        assert_eq!(stats_file.lines().len(), 1);
+
+        std::thread::sleep(Duration::from_millis(20));
+        io.push(client_addr(1), b"PING");
+        app.update();
+        let lines = stats_file.lines();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["packets_received"], 2);
+        assert_eq!(lines[1]["packets_sent"], 2);
+
+        // and a last one on shutdown
+        app.shutdown();
+        assert_eq!(stats_file.lines().len(), 3);
+    }
 }
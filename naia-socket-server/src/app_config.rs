@@ -0,0 +1,517 @@
+use std::{
+    env, fmt,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
+    time::Duration,
+};
+
+use signaling_shared::fragment::DEFAULT_MAX_REASSEMBLY_BYTES;
+use url::{Position, Url};
+
+/// Options that change how the App handles incoming packets
+#[derive(Clone)]
+pub struct AppConfig {
+    /// Addresses to try listening on, in order, until one can be bound
+    pub bind_hosts: Vec<IpAddr>,
+    /// Address the signaling endpoint listens on, overriding `bind_hosts`
+    pub session_addr: Option<SocketAddr>,
+    /// Address the UDP data channels listen on, overriding `bind_hosts`
+    pub webrtc_addr: Option<SocketAddr>,
+    /// URL advertised to clients for the data channels, for when the server
+    /// is reached through another address (e.g. behind NAT or in Docker).
+    /// Defaults to the UDP listen address.
+    pub public_url: Option<String>,
+    /// Whether incoming payloads carry a trailing CRC32 that must be
+    /// validated before the payload is handled
+    pub validate_checksums: bool,
+    /// Maximum number of packets handled by a single `App::update_batch`
+    pub batch_size: usize,
+    /// Number of consecutive full batches after which the App is considered
+    /// overloaded
+    pub overload_batches: u32,
+    /// Whether to stop responding to clients while overloaded
+    pub shed_load: bool,
+    /// Secret used to answer clients' challenges, proving the server's
+    /// identity. Challenges are ignored when unset.
+    pub shared_secret: Option<String>,
+    /// Whether outbound messages are prefixed with a big-endian u32 sequence
+    /// number, counted per client; the server's replies to ACKs, challenges
+    /// and compression offers aren't
+    pub sequence_outbound: bool,
+    /// File to periodically append JSON snapshots of the App's stats to
+    pub stats_file: Option<String>,
+    /// Time between stats file snapshots
+    pub stats_interval: Duration,
+    /// Address to serve Prometheus metrics on, at `/metrics`
+    pub metrics_addr: Option<SocketAddr>,
+    /// Address to serve trickle ICE on, at `/rtc_candidate`
+    pub trickle_addr: Option<SocketAddr>,
+    /// Single-byte payload clients send as a keepalive. It only refreshes
+    /// the client's session and is never echoed.
+    pub keepalive_marker: u8,
+    /// Whether every message, in & out, is a frame with a big-endian u16
+    /// length prefix
+    pub framed: bool,
+    /// Whether every message, in & out, is split into fragments that each
+    /// fit a single data channel message, inside the frames if `framed`
+    pub fragmented: bool,
+    /// Most fragment bytes held for partly received messages, across every
+    /// client; past it, the oldest of any client are given up on
+    pub max_reassembly_bytes: usize,
+    /// Whether client messages are binary `Packet`s rather than the demo's
+    /// text; pings are then answered by the server itself, and only data
+    /// reaches the handler
+    pub binary: bool,
+    /// Whether every message a client sends is relayed to every connected
+    /// client, the sender included, besides being handled
+    pub broadcast: bool,
+    /// How long a client can go without sending anything, keepalives
+    /// included, before it's no longer considered connected
+    pub client_timeout: Duration,
+    /// What the demo server answers a `PING` with
+    pub reply: String,
+    /// Whether the demo server echoes every message back instead of only
+    /// answering PINGs
+    pub echo: bool,
+    /// Whether `MSG <id> <body>` messages are acknowledged with `ACK <id>`
+    /// before their body is handled
+    pub ack: bool,
+    /// Packets per second each client may send, in bursts of up to as many;
+    /// the rest are dropped. `None` doesn't limit clients.
+    pub rate_limit: Option<u32>,
+    /// Whether a `public_url` port that differs from the data channel
+    /// port stops the server from starting, rather than only being warned
+    /// about (it can be right behind a port-forwarding NAT)
+    pub strict_public_url: bool,
+    /// PEM certificate chain to serve the signaling endpoint over https
+    /// with, along with `tls_key`. Plain http when unset.
+    pub tls_cert: Option<String>,
+    /// PEM private key for `tls_cert`
+    pub tls_key: Option<String>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        AppConfig {
+            bind_hosts: vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+            session_addr: None,
+            webrtc_addr: None,
+            public_url: None,
+            validate_checksums: false,
+            batch_size: 64,
+            overload_batches: 100,
+            shed_load: false,
+            shared_secret: None,
+            sequence_outbound: false,
+            stats_file: None,
+            stats_interval: Duration::from_secs(1),
+            metrics_addr: None,
+            trickle_addr: None,
+            keepalive_marker: 0,
+            framed: false,
+            fragmented: false,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            binary: false,
+            broadcast: false,
+            client_timeout: Duration::from_secs(30),
+            reply: "PONG".to_string(),
+            echo: false,
+            ack: false,
+            rate_limit: None,
+            strict_public_url: false,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl AppConfig {
+    /// Builds an AppConfig from environment variables, falling back to the
+    /// default for anything unset. Fails if a listen or advertise address
+    /// can't be parsed or they don't fit together, since guessing one would
+    /// serve the wrong endpoint.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let default = AppConfig::default();
+        let config = AppConfig {
+            bind_hosts: env_list("NAIA_BIND_HOSTS").unwrap_or(default.bind_hosts),
+            session_addr: env_addr("NAIA_SESSION_ADDR")?,
+            webrtc_addr: env_addr("NAIA_WEBRTC_ADDR")?,
+            public_url: env_url("NAIA_PUBLIC_URL")?,
+            validate_checksums: env_flag("NAIA_VALIDATE_CHECKSUMS"),
+            batch_size: env_number("NAIA_BATCH_SIZE", "packet count")?
+                .unwrap_or(default.batch_size),
+            overload_batches: env_number("NAIA_OVERLOAD_BATCHES", "batch count")?
+                .unwrap_or(default.overload_batches),
+            shed_load: env_flag("NAIA_SHED_LOAD"),
+            shared_secret: env::var("NAIA_SHARED_SECRET").ok(),
+            sequence_outbound: env_flag("NAIA_SEQUENCE_OUTBOUND"),
+            stats_file: env::var("NAIA_STATS_FILE").ok(),
+            stats_interval: env_number("NAIA_STATS_INTERVAL", "whole number of seconds")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.stats_interval),
+            metrics_addr: env_addr("NAIA_METRICS_ADDR")?,
+            trickle_addr: env_addr("NAIA_TRICKLE_ADDR")?,
+            keepalive_marker: env_number("NAIA_KEEPALIVE_MARKER", "byte value, 0 to 255")?
+                .unwrap_or(default.keepalive_marker),
+            framed: env_flag("NAIA_FRAMED"),
+            fragmented: env_flag("NAIA_FRAGMENTED"),
+            max_reassembly_bytes: env_number("NAIA_MAX_REASSEMBLY_BYTES", "byte count")?
+                .unwrap_or(default.max_reassembly_bytes),
+            binary: env_flag("NAIA_BINARY"),
+            broadcast: env_flag("NAIA_BROADCAST"),
+            client_timeout: env_number("NAIA_CLIENT_TIMEOUT", "whole number of seconds")?
+                .map(Duration::from_secs)
+                .unwrap_or(default.client_timeout),
+            reply: env::var("NAIA_REPLY").unwrap_or(default.reply),
+            echo: env_flag("NAIA_ECHO"),
+            ack: env_flag("NAIA_ACK"),
+            rate_limit: env_number("NAIA_RATE_LIMIT", "packets per second")?,
+            strict_public_url: env_flag("NAIA_STRICT_PUBLIC_URL"),
+            tls_cert: env::var("NAIA_TLS_CERT").ok(),
+            tls_key: env::var("NAIA_TLS_KEY").ok(),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the listen & advertised addresses use the same IP version,
+    /// IPv4 or IPv6. An unspecified listen address (`0.0.0.0` or `::`) can
+    /// be advertised as anything, since it may be reached through NAT or,
+    /// for `::`, over either version. A TLS certificate needs its key, and
+    /// the other way round. Batches must hold at least one packet, it must
+    /// take at least one batch to be overloaded, and reassembly needs room
+    /// for at least a byte.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.batch_size == 0 {
+            return Err(ConfigError {
+                name: "NAIA_BATCH_SIZE",
+                value: self.batch_size.to_string(),
+                expected: "packet count of at least 1",
+            });
+        }
+        if self.overload_batches == 0 {
+            return Err(ConfigError {
+                name: "NAIA_OVERLOAD_BATCHES",
+                value: self.overload_batches.to_string(),
+                expected: "batch count of at least 1",
+            });
+        }
+        if self.max_reassembly_bytes == 0 {
+            return Err(ConfigError {
+                name: "NAIA_MAX_REASSEMBLY_BYTES",
+                value: self.max_reassembly_bytes.to_string(),
+                expected: "byte count of at least 1",
+            });
+        }
+        // a zero interval would write a snapshot every update
+        if self.stats_interval.is_zero() {
+            return Err(ConfigError {
+                name: "NAIA_STATS_INTERVAL",
+                value: self.stats_interval.as_secs().to_string(),
+                expected: "whole number of seconds, at least 1",
+            });
+        }
+        match (&self.tls_cert, &self.tls_key) {
+            (Some(_), None) => {
+                return Err(ConfigError {
+                    name: "NAIA_TLS_KEY",
+                    value: String::new(),
+                    expected: "private key path, needed along with NAIA_TLS_CERT",
+                })
+            }
+            (None, Some(_)) => {
+                return Err(ConfigError {
+                    name: "NAIA_TLS_CERT",
+                    value: String::new(),
+                    expected: "certificate path, needed along with NAIA_TLS_KEY",
+                })
+            }
+            _ => {}
+        }
+
+        if let (Some(session_addr), Some(webrtc_addr)) = (self.session_addr, self.webrtc_addr) {
+            if session_addr.is_ipv6() != webrtc_addr.is_ipv6() {
+                return Err(ConfigError {
+                    name: "NAIA_WEBRTC_ADDR",
+                    value: webrtc_addr.to_string(),
+                    expected: version_expected(session_addr, "NAIA_SESSION_ADDR"),
+                });
+            }
+        }
+
+        let (listen_name, listen_addr) = match (self.webrtc_addr, self.session_addr) {
+            (Some(webrtc_addr), _) => ("NAIA_WEBRTC_ADDR", webrtc_addr),
+            (None, Some(session_addr)) => ("NAIA_SESSION_ADDR", session_addr),
+            (None, None) => return Ok(()),
+        };
+        let public_url = match &self.public_url {
+            Some(public_url) => public_url,
+            None => return Ok(()),
+        };
+        match url_ip(public_url) {
+            Some(public_ip)
+                if !listen_addr.ip().is_unspecified()
+                    && public_ip.is_ipv6() != listen_addr.is_ipv6() =>
+            {
+                Err(ConfigError {
+                    name: "NAIA_PUBLIC_URL",
+                    value: public_url.clone(),
+                    expected: version_expected(listen_addr, listen_name),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+// describes what an address must be to match `addr`, configured by `name`
+fn version_expected(addr: SocketAddr, name: &'static str) -> &'static str {
+    match (addr.is_ipv6(), name) {
+        (true, "NAIA_SESSION_ADDR") => "IPv6 address, matching NAIA_SESSION_ADDR",
+        (true, "NAIA_WEBRTC_ADDR") => "IPv6 address, matching NAIA_WEBRTC_ADDR",
+        (false, "NAIA_SESSION_ADDR") => "IPv4 address, matching NAIA_SESSION_ADDR",
+        (false, "NAIA_WEBRTC_ADDR") => "IPv4 address, matching NAIA_WEBRTC_ADDR",
+        (true, _) => "IPv6 address",
+        (false, _) => "IPv4 address",
+    }
+}
+
+// the IP literal an http(s) URL points at, e.g. `http://[::1]:14192`;
+// `None` for a hostname
+fn url_ip(url: &str) -> Option<IpAddr> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split('/').next()?;
+    if let Some(bracketed) = authority.strip_prefix('[') {
+        return bracketed.split(']').next()?.parse().ok();
+    }
+    authority.split(':').next()?.parse().ok()
+}
+
+/// An environment variable that was set to something unusable
+#[derive(Debug)]
+pub struct ConfigError {
+    pub name: &'static str,
+    pub value: String,
+    pub expected: &'static str,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: '{}' is not a valid {}",
+            self.name, self.value, self.expected
+        )
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn env_flag(name: &str) -> bool {
+    matches!(
+        env::var(name).as_deref(),
+        Ok("1") | Ok("true") | Ok("yes") | Ok("on")
+    )
+}
+
+fn env_number<T: FromStr>(
+    name: &'static str,
+    expected: &'static str,
+) -> Result<Option<T>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(number) => Ok(Some(number)),
+            Err(_) => Err(ConfigError {
+                name,
+                value,
+                expected,
+            }),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_list<T: FromStr>(name: &str) -> Option<Vec<T>> {
+    let value = env::var(name).ok()?;
+    let parsed: Result<Vec<T>, _> = value.split(',').map(|item| item.trim().parse()).collect();
+    match parsed {
+        Ok(list) if !list.is_empty() => Some(list),
+        _ => {
+            warn!("Ignoring {}: '{}' is not a valid list", name, value);
+            None
+        }
+    }
+}
+
+fn env_addr(name: &'static str) -> Result<Option<SocketAddr>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => match value.trim().parse() {
+            Ok(addr) => Ok(Some(addr)),
+            Err(_) => Err(ConfigError {
+                name,
+                value,
+                expected: "socket address, e.g. 0.0.0.0:14191",
+            }),
+        },
+        Err(_) => Ok(None),
+    }
+}
+
+fn env_url(name: &'static str) -> Result<Option<String>, ConfigError> {
+    match env::var(name) {
+        Ok(value) => parse_public_url(name, &value).map(Some),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Checks `value` can be advertised as the public URL, normalizing it the
+/// way `NAIA_PUBLIC_URL` is; `name` is where it came from, for the error
+pub fn parse_public_url(name: &'static str, value: &str) -> Result<String, ConfigError> {
+    normalize_url(value.trim()).ok_or_else(|| ConfigError {
+        name,
+        value: value.to_string(),
+        expected: "http(s) URL with a host & port and no path, e.g. http://203.0.113.7:14192",
+    })
+}
+
+// naia panics on anything but `http(s)://<host>:<port>`, so that's all that's
+// accepted, minus any trailing slash
+fn normalize_url(value: &str) -> Option<String> {
+    let url = Url::parse(value).ok()?;
+    // Url hides a port that's the scheme's default, so look for it in the text
+    let has_port = url.port().is_some()
+        || url
+            .port_or_known_default()
+            .is_some_and(|port| value.contains(&format!(":{}", port)));
+    let valid = matches!(url.scheme(), "http" | "https")
+        && url.host_str().is_some()
+        && has_port
+        && url.path() == "/"
+        && url.query().is_none()
+        && url.fragment().is_none();
+    valid.then(|| url[..Position::AfterPort].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        assert!(AppConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn empty_batches_are_rejected() {
+        let config = AppConfig {
+            batch_size: 0,
+            ..AppConfig::default()
+        };
+        assert_eq!(config.validate().unwrap_err().name, "NAIA_BATCH_SIZE");
+
+        let config = AppConfig {
+            overload_batches: 0,
+            ..AppConfig::default()
+        };
+        assert_eq!(config.validate().unwrap_err().name, "NAIA_OVERLOAD_BATCHES");
+    }
+
+    #[test]
+    fn ipv6_addresses_are_accepted() {
+        let config = AppConfig {
+            session_addr: Some("[::1]:14191".parse().unwrap()),
+            webrtc_addr: Some("[::1]:14192".parse().unwrap()),
+            public_url: Some("http://[2001:db8::7]:14192".to_string()),
+            ..AppConfig::default()
+        };
+        config.validate().unwrap();
+
+        // an unspecified listen address can be reached over either
+        let config = AppConfig {
+            webrtc_addr: Some("[::]:14192".parse().unwrap()),
+            session_addr: Some("[::]:14191".parse().unwrap()),
+            public_url: Some("http://203.0.113.7:14192".to_string()),
+            ..AppConfig::default()
+        };
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn mixed_ip_versions_are_rejected() {
+        let config = AppConfig {
+            session_addr: Some("[::1]:14191".parse().unwrap()),
+            webrtc_addr: Some("127.0.0.1:14192".parse().unwrap()),
+            ..AppConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name, "NAIA_WEBRTC_ADDR");
+        assert_eq!(error.expected, "IPv6 address, matching NAIA_SESSION_ADDR");
+
+        let config = AppConfig {
+            session_addr: Some("[::1]:14191".parse().unwrap()),
+            webrtc_addr: Some("[::1]:14192".parse().unwrap()),
+            public_url: Some("http://203.0.113.7:14192".to_string()),
+            ..AppConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name, "NAIA_PUBLIC_URL");
+        assert_eq!(error.expected, "IPv6 address, matching NAIA_WEBRTC_ADDR");
+
+        let config = AppConfig {
+            session_addr: Some("127.0.0.1:14191".parse().unwrap()),
+            public_url: Some("http://[2001:db8::7]:14192".to_string()),
+            ..AppConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name, "NAIA_PUBLIC_URL");
+        assert_eq!(error.expected, "IPv4 address, matching NAIA_SESSION_ADDR");
+    }
+
+    #[test]
+    fn reassembly_needs_a_byte_budget() {
+        let config = AppConfig {
+            max_reassembly_bytes: 0,
+            ..AppConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name, "NAIA_MAX_REASSEMBLY_BYTES");
+        assert_eq!(error.expected, "byte count of at least 1");
+    }
+
+    #[test]
+    fn stats_need_an_interval() {
+        let config = AppConfig {
+            stats_interval: Duration::ZERO,
+            ..AppConfig::default()
+        };
+        let error = config.validate().unwrap_err();
+        assert_eq!(error.name, "NAIA_STATS_INTERVAL");
+        assert_eq!(error.value, "0");
+    }
+
+    #[test]
+    fn numbers_from_the_environment() {
+        // names of their own, since tests share the environment
+        assert_eq!(
+            env_number::<u32>("NAIA_TEST_UNSET", "number").unwrap(),
+            None
+        );
+
+        env::set_var("NAIA_TEST_NUMBER", " 42 ");
+        assert_eq!(
+            env_number::<u32>("NAIA_TEST_NUMBER", "number").unwrap(),
+            Some(42)
+        );
+
+        env::set_var("NAIA_TEST_NOT_A_NUMBER", "forty-two");
+        let error = env_number::<u32>("NAIA_TEST_NOT_A_NUMBER", "number").unwrap_err();
+        assert_eq!(error.name, "NAIA_TEST_NOT_A_NUMBER");
+        assert_eq!(error.value, "forty-two");
+
+        env::set_var("NAIA_TEST_TOO_BIG", "256");
+        assert!(env_number::<u8>("NAIA_TEST_TOO_BIG", "byte").is_err());
+    }
+}
@@ -0,0 +1,16 @@
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+/// Prefix of a client's challenge message, followed by its nonce
+pub const CHALLENGE_PREFIX: &str = "CHALLENGE ";
+/// Prefix of the reply, followed by hex-encoded HMAC-SHA256(secret, nonce)
+pub const CHALLENGE_RESPONSE_PREFIX: &str = "CHALLENGE_RESPONSE ";
+
+/// Builds the response to a client's challenge nonce
+pub fn respond(secret: &[u8], nonce: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC can take a key of any size");
+    mac.update(nonce.as_bytes());
+    let signature = hex::encode(mac.finalize().into_bytes());
+
+    format!("{}{}", CHALLENGE_RESPONSE_PREFIX, signature)
+}
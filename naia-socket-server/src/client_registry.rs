@@ -0,0 +1,364 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+use signaling_shared::fragment::FragmentError;
+
+use crate::client_session::ClientSession;
+
+// how often clients are checked for having timed out
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How far a client has got through the handshake, in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HandshakeState {
+    /// Packets have arrived, but nothing past keepalives & negotiation
+    Connected,
+    /// Its shared-secret challenge was answered
+    Authenticated,
+    /// A message has been handed to the App's handler
+    Active,
+}
+
+/// A snapshot of what the App knows about one client
+#[derive(Clone, Debug)]
+pub struct ClientInfo {
+    pub address: SocketAddr,
+    /// When the client's first packet arrived
+    pub connected_at: Instant,
+    /// When a packet, keepalives included, last arrived from the client
+    pub last_seen: Instant,
+    pub handshake: HandshakeState,
+    /// Messages received after framing & fragmenting are undone
+    pub messages_received: u64,
+    /// Messages sent, before framing & fragmenting
+    pub messages_sent: u64,
+}
+
+/// Every client heard from within the idle timeout, keyed by address.
+/// Clients are logged as they connect and disconnect, whether by timing out
+/// or saying so.
+pub struct ClientRegistry {
+    sessions: HashMap<SocketAddr, ClientSession>,
+    timeout: Duration,
+    max_reassembly_bytes: usize,
+    // fragment bytes held across every session
+    reassembly_bytes: usize,
+    expired_fragments: u64,
+    last_sweep: Instant,
+}
+
+impl ClientRegistry {
+    /// Evicts clients once `timeout` passes without a packet from them.
+    /// Their partly received messages hold at most `max_reassembly_bytes` of
+    /// fragments between them.
+    pub fn new(timeout: Duration, max_reassembly_bytes: usize) -> Self {
+        ClientRegistry {
+            sessions: HashMap::new(),
+            timeout,
+            max_reassembly_bytes,
+            reassembly_bytes: 0,
+            expired_fragments: 0,
+            last_sweep: Instant::now(),
+        }
+    }
+
+    /// The session of `address`, registering it as connected if it's new.
+    /// Only a packet arriving should register a client.
+    pub fn register(&mut self, address: SocketAddr) -> &mut ClientSession {
+        self.sessions.entry(address).or_insert_with(|| {
+            info!("Client {} connected", address);
+            ClientSession::new()
+        })
+    }
+
+    /// Adds a fragment from `address`, returning its message once it's
+    /// complete; `None` if the client has disconnected. Over the reassembly
+    /// budget, the oldest messages of any client are given up on, which can
+    /// be this one.
+    pub fn receive_fragment(
+        &mut self,
+        address: &SocketAddr,
+        fragment: &[u8],
+    ) -> Option<Result<Option<Vec<u8>>, FragmentError>> {
+        let session = self.sessions.get_mut(address)?;
+        let held = session.reassembly_bytes();
+        let expired = session.expired_fragments();
+        let received = session.receive_fragment(fragment);
+        self.reassembly_bytes = self.reassembly_bytes - held + session.reassembly_bytes();
+        self.expired_fragments += session.expired_fragments() - expired;
+
+        while self.reassembly_bytes > self.max_reassembly_bytes {
+            if !self.expire_oldest_fragments() {
+                break;
+            }
+        }
+        Some(received)
+    }
+
+    // gives up on the oldest partly received message of any client
+    fn expire_oldest_fragments(&mut self) -> bool {
+        let oldest = self
+            .sessions
+            .iter()
+            .filter_map(|(address, session)| Some((session.oldest_fragment()?, *address)))
+            .min();
+        let address = match oldest {
+            Some((_, address)) => address,
+            None => return false,
+        };
+        let session = self
+            .sessions
+            .get_mut(&address)
+            .expect("oldest session vanished");
+        let held = session.reassembly_bytes();
+        session.expire_oldest_fragments();
+        self.reassembly_bytes -= held - session.reassembly_bytes();
+        self.expired_fragments += 1;
+        debug!(
+            "Gave up on a message from {} to stay within the reassembly budget",
+            address
+        );
+        true
+    }
+
+    /// Messages from every client given up on for missing fragments, or to
+    /// stay within the reassembly budget
+    pub fn expired_fragments(&self) -> u64 {
+        self.expired_fragments
+    }
+
+    /// The session of `address`, `None` once it has disconnected
+    pub fn get_mut(&mut self, address: &SocketAddr) -> Option<&mut ClientSession> {
+        self.sessions.get_mut(address)
+    }
+
+    pub fn get(&self, address: &SocketAddr) -> Option<&ClientSession> {
+        self.sessions.get(address)
+    }
+
+    pub fn info(&self, address: &SocketAddr) -> Option<ClientInfo> {
+        self.sessions
+            .get(address)
+            .map(|session| session.info(*address))
+    }
+
+    pub fn infos(&self) -> Vec<ClientInfo> {
+        self.sessions
+            .iter()
+            .map(|(address, session)| session.info(*address))
+            .collect()
+    }
+
+    /// Moves `address` on to `state`, unless it's already further along
+    pub fn advance(&mut self, address: &SocketAddr, state: HandshakeState) {
+        if let Some(session) = self.sessions.get_mut(address) {
+            if state > session.handshake() {
+                debug!("Client {} handshake: {:?}", address, state);
+                session.advance(state);
+            }
+        }
+    }
+
+    /// Drops a client that said it's going away
+    pub fn disconnect(&mut self, address: &SocketAddr) {
+        if let Some(session) = self.remove(address) {
+            info!(
+                "Client {} disconnected after {} message(s) in & {} out",
+                address,
+                session.messages_received(),
+                session.messages_sent()
+            );
+        }
+    }
+
+    /// Drops a client that a send failed to
+    pub fn evict(&mut self, address: &SocketAddr) {
+        if let Some(session) = self.remove(address) {
+            info!(
+                "Client {} disconnected, a send to it failed ({} message(s) in & {} out)",
+                address,
+                session.messages_received(),
+                session.messages_sent()
+            );
+        }
+    }
+
+    // forgets a client, along with the fragments it held
+    fn remove(&mut self, address: &SocketAddr) -> Option<ClientSession> {
+        let session = self.sessions.remove(address)?;
+        self.reassembly_bytes -= session.reassembly_bytes();
+        Some(session)
+    }
+
+    pub fn expire_if_due(&mut self) {
+        if self.last_sweep.elapsed() >= SWEEP_INTERVAL {
+            self.expire();
+        }
+    }
+
+    // naia 0.9 doesn't report failed sends, so a client that has gone quiet
+    // for longer than the timeout is treated as disconnected
+    pub fn expire(&mut self) {
+        let timeout = self.timeout;
+        let reassembly_bytes = &mut self.reassembly_bytes;
+        self.sessions.retain(|address, session| {
+            let connected = session.last_seen().elapsed() < timeout;
+            if !connected {
+                *reassembly_bytes -= session.reassembly_bytes();
+                info!(
+                    "Client {} disconnected, nothing received for {:?} ({} message(s) in & {} out)",
+                    address,
+                    timeout,
+                    session.messages_received(),
+                    session.messages_sent()
+                );
+            }
+            connected
+        });
+        self.last_sweep = Instant::now();
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = &SocketAddr> {
+        self.sessions.keys()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// Fragment bytes held for every client's partly received messages
+    pub fn reassembly_bytes(&self) -> usize {
+        self.reassembly_bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use signaling_shared::fragment::{Fragmenter, DEFAULT_MAX_REASSEMBLY_BYTES, FRAGMENT_SIZE};
+
+    fn client_addr(port: u16) -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    #[test]
+    fn only_registering_connects_a_client() {
+        let mut clients =
+            ClientRegistry::new(Duration::from_secs(30), DEFAULT_MAX_REASSEMBLY_BYTES);
+        assert!(clients.get_mut(&client_addr(1)).is_none());
+        assert_eq!(clients.len(), 0);
+
+        clients.register(client_addr(1)).record_received();
+        clients.register(client_addr(1)).record_received();
+        assert_eq!(clients.len(), 1);
+        assert_eq!(
+            clients
+                .get_mut(&client_addr(1))
+                .unwrap()
+                .messages_received(),
+            2
+        );
+    }
+
+    #[test]
+    fn disconnected_clients_stay_gone() {
+        let mut clients =
+            ClientRegistry::new(Duration::from_secs(30), DEFAULT_MAX_REASSEMBLY_BYTES);
+        clients.register(client_addr(1));
+        clients.register(client_addr(2));
+        clients.register(client_addr(3));
+
+        clients.disconnect(&client_addr(1));
+        clients.evict(&client_addr(2));
+        assert!(clients.get_mut(&client_addr(1)).is_none());
+        assert!(clients.get_mut(&client_addr(2)).is_none());
+        assert!(clients.info(&client_addr(2)).is_none());
+        clients.advance(&client_addr(2), HandshakeState::Active);
+        assert_eq!(clients.len(), 1);
+        assert_eq!(
+            clients.addresses().collect::<Vec<_>>(),
+            vec![&client_addr(3)]
+        );
+    }
+
+    #[test]
+    fn quiet_clients_expire() {
+        let mut clients =
+            ClientRegistry::new(Duration::from_millis(10), DEFAULT_MAX_REASSEMBLY_BYTES);
+        clients.register(client_addr(1));
+        std::thread::sleep(Duration::from_millis(20));
+        clients.register(client_addr(2)).touch();
+        clients.expire();
+        assert!(clients.get_mut(&client_addr(1)).is_none());
+        assert!(clients.get_mut(&client_addr(2)).is_some());
+    }
+
+    #[test]
+    fn clients_share_one_reassembly_budget() {
+        let mut clients = ClientRegistry::new(Duration::from_secs(30), 3 * FRAGMENT_SIZE);
+        let mut fragmenter = Fragmenter::default();
+        // two halves from each of two clients, oldest first
+        let messages: Vec<_> = (0..4)
+            .map(|_| fragmenter.split(&[7; 2 * FRAGMENT_SIZE]).unwrap())
+            .collect();
+        let senders = [
+            client_addr(1),
+            client_addr(2),
+            client_addr(1),
+            client_addr(2),
+        ];
+        for address in &senders[..2] {
+            clients.register(*address);
+        }
+        for (address, fragments) in senders.iter().zip(&messages).take(3) {
+            assert_eq!(
+                clients.receive_fragment(address, &fragments[0]),
+                Some(Ok(None))
+            );
+        }
+        assert_eq!(clients.reassembly_bytes(), 3 * FRAGMENT_SIZE);
+        assert_eq!(clients.expired_fragments(), 0);
+
+        // the fourth goes over, so the first client's oldest message goes
+        assert_eq!(
+            clients.receive_fragment(&senders[3], &messages[3][0]),
+            Some(Ok(None))
+        );
+        assert_eq!(clients.expired_fragments(), 1);
+        assert_eq!(clients.reassembly_bytes(), 3 * FRAGMENT_SIZE);
+        assert_eq!(
+            clients.get(&client_addr(1)).unwrap().reassembly_bytes(),
+            FRAGMENT_SIZE
+        );
+        // the second client's first message is still there to finish
+        assert_eq!(
+            clients.receive_fragment(&senders[1], &messages[1][1]),
+            Some(Ok(Some(vec![7; 2 * FRAGMENT_SIZE])))
+        );
+        assert_eq!(clients.reassembly_bytes(), 2 * FRAGMENT_SIZE);
+    }
+
+    #[test]
+    fn disconnecting_frees_a_clients_fragments() {
+        let mut clients = ClientRegistry::new(Duration::from_secs(30), 4 * FRAGMENT_SIZE);
+        let fragments = Fragmenter::default()
+            .split(&[7; 2 * FRAGMENT_SIZE])
+            .unwrap();
+        for port in 1..=3 {
+            clients.register(client_addr(port));
+            clients.receive_fragment(&client_addr(port), &fragments[0]);
+        }
+        assert_eq!(clients.reassembly_bytes(), 3 * FRAGMENT_SIZE);
+
+        clients.disconnect(&client_addr(1));
+        clients.evict(&client_addr(2));
+        assert_eq!(clients.reassembly_bytes(), FRAGMENT_SIZE);
+        assert_eq!(
+            clients.receive_fragment(&client_addr(1), &fragments[1]),
+            None
+        );
+    }
+}
@@ -0,0 +1,169 @@
+use std::{net::SocketAddr, time::Instant};
+
+use signaling_shared::{
+    fragment::{FragmentError, Fragmenter, Reassembler, DEFAULT_REASSEMBLY_TIMEOUT},
+    framing::FrameDecoder,
+};
+
+use crate::{
+    client_registry::{ClientInfo, HandshakeState},
+    rate_limit::TokenBucket,
+};
+
+/// State the App keeps for each client it has heard from
+pub struct ClientSession {
+    outbound_sequence: u32,
+    connected_at: Instant,
+    last_seen: Instant,
+    handshake: HandshakeState,
+    messages_received: u64,
+    messages_sent: u64,
+    frames: FrameDecoder,
+    fragmenter: Fragmenter,
+    reassembler: Reassembler,
+    // only kept when the App rate limits clients
+    bucket: Option<TokenBucket>,
+    compressed: bool,
+}
+
+impl Default for ClientSession {
+    fn default() -> Self {
+        ClientSession::new()
+    }
+}
+
+impl ClientSession {
+    /// A new client's session. Its partly received messages count towards
+    /// the reassembly budget its `ClientRegistry` shares between clients.
+    pub fn new() -> Self {
+        ClientSession {
+            outbound_sequence: 0,
+            connected_at: Instant::now(),
+            last_seen: Instant::now(),
+            handshake: HandshakeState::Connected,
+            messages_received: 0,
+            messages_sent: 0,
+            frames: FrameDecoder::default(),
+            fragmenter: Fragmenter::default(),
+            // the registry keeps every session within one budget
+            reassembler: Reassembler::with_max_bytes(DEFAULT_REASSEMBLY_TIMEOUT, usize::MAX),
+            bucket: None,
+            compressed: false,
+        }
+    }
+
+    /// Returns the sequence number for the next message sent to this client,
+    /// wrapping after `u32::MAX`
+    pub fn next_outbound_sequence(&mut self) -> u32 {
+        let sequence = self.outbound_sequence;
+        self.outbound_sequence = self.outbound_sequence.wrapping_add(1);
+        sequence
+    }
+
+    /// Records that a packet, keepalives included, arrived from this client
+    pub fn touch(&mut self) {
+        self.last_seen = Instant::now();
+    }
+
+    /// Returns whether this client is still within `rate` packets per
+    /// second, counting this one
+    pub fn within_rate(&mut self, rate: u32) -> bool {
+        self.bucket
+            .get_or_insert_with(|| TokenBucket::new(rate))
+            .try_take(rate)
+    }
+
+    /// Buffers a framed packet from this client, returning every frame it
+    /// completed
+    pub fn receive_frames(&mut self, packet: &[u8]) -> Vec<Vec<u8>> {
+        self.frames.push(packet);
+        std::iter::from_fn(|| self.frames.next_frame())
+            .map(|frame| frame.to_vec())
+            .collect()
+    }
+
+    /// Adds a fragment from this client, returning its message once it's
+    /// complete
+    pub fn receive_fragment(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, FragmentError> {
+        self.reassembler.push(fragment)
+    }
+
+    /// Messages from this client given up on for missing fragments, or to
+    /// stay within the reassembly budget
+    pub fn expired_fragments(&self) -> u64 {
+        self.reassembler.expired()
+    }
+
+    /// Fragment bytes held for this client's partly received messages
+    pub fn reassembly_bytes(&self) -> usize {
+        self.reassembler.held_bytes()
+    }
+
+    /// When the first fragment of this client's oldest partly received
+    /// message arrived
+    pub fn oldest_fragment(&self) -> Option<Instant> {
+        self.reassembler.oldest_started()
+    }
+
+    /// Gives up on this client's oldest partly received message, `false` if
+    /// there's none
+    pub fn expire_oldest_fragments(&mut self) -> bool {
+        self.reassembler.expire_oldest()
+    }
+
+    /// Splits a message to this client into fragments, `None` if it's too
+    /// large to
+    pub fn fragment(&mut self, message: &[u8]) -> Option<Vec<Vec<u8>>> {
+        self.fragmenter.split(message)
+    }
+
+    /// Whether this client asked for its payloads to be compressed, both ways
+    pub fn compressed(&self) -> bool {
+        self.compressed
+    }
+
+    pub fn set_compressed(&mut self) {
+        self.compressed = true;
+    }
+
+    /// When a packet last arrived from this client
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+
+    pub fn handshake(&self) -> HandshakeState {
+        self.handshake
+    }
+
+    /// Moves the handshake on to `state`, unless it's already further along
+    pub fn advance(&mut self, state: HandshakeState) {
+        self.handshake = self.handshake.max(state);
+    }
+
+    pub fn record_received(&mut self) {
+        self.messages_received += 1;
+    }
+
+    pub fn record_sent(&mut self) {
+        self.messages_sent += 1;
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent
+    }
+
+    pub fn info(&self, address: SocketAddr) -> ClientInfo {
+        ClientInfo {
+            address,
+            connected_at: self.connected_at,
+            last_seen: self.last_seen,
+            handshake: self.handshake,
+            messages_received: self.messages_received,
+            messages_sent: self.messages_sent,
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::net::SocketAddr;
+
+/// Decides how the server answers each message a client sends. Checksums,
+/// framing, keepalives and challenges are dealt with before it's called.
+pub trait MessageHandler {
+    /// Returns the response to send back to `address`, or `None` to send
+    /// nothing. With broadcasting on, the message is relayed to every client
+    /// either way.
+    fn handle(&mut self, address: SocketAddr, payload: &[u8]) -> Option<Vec<u8>>;
+}
+
+impl<F> MessageHandler for F
+where
+    F: FnMut(SocketAddr, &[u8]) -> Option<Vec<u8>>,
+{
+    fn handle(&mut self, address: SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+        self(address, payload)
+    }
+}
+
+/// The demo's protocol: answers `PING` with its reply (`PONG` by default),
+/// and `PING <stamp>` with `<reply> <stamp>`
+pub struct PingPong {
+    reply: Vec<u8>,
+}
+
+impl PingPong {
+    pub fn new(reply: impl Into<Vec<u8>>) -> Self {
+        PingPong {
+            reply: reply.into(),
+        }
+    }
+}
+
+impl Default for PingPong {
+    fn default() -> Self {
+        PingPong::new("PONG")
+    }
+}
+
+impl MessageHandler for PingPong {
+    fn handle(&mut self, _address: SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+        if payload == b"PING" {
+            return Some(self.reply.clone());
+        }
+
+        // echo a PING's stamp back so the client can time the round trip
+        let stamp = payload.strip_prefix(b"PING ")?;
+        let mut response = self.reply.clone();
+        response.push(b' ');
+        response.extend_from_slice(stamp);
+        Some(response)
+    }
+}
+
+/// Sends every message straight back to the client it came from
+pub struct Echo;
+
+impl MessageHandler for Echo {
+    fn handle(&mut self, _address: SocketAddr, payload: &[u8]) -> Option<Vec<u8>> {
+        Some(payload.to_vec())
+    }
+}
@@ -0,0 +1,29 @@
+//! # Naia Socket Server
+//! A demo server that answers webrtc-rs clients over a naia server socket.
+//! The protocol is pluggable: [`App`] hands every client message to a
+//! [`MessageHandler`], [`PingPong`] being the demo's own
+
+#[macro_use]
+extern crate tracing;
+
+mod ack;
+mod app;
+mod app_config;
+mod challenge;
+mod client_registry;
+mod client_session;
+mod handler;
+mod metrics_endpoint;
+mod packet_io;
+mod rate_limit;
+mod server_stats;
+mod stats_file;
+mod tls;
+mod trickle_endpoint;
+
+pub use app::{App, AppError};
+pub use app_config::{parse_public_url, AppConfig, ConfigError};
+pub use client_registry::{ClientInfo, HandshakeState};
+pub use handler::{Echo, MessageHandler, PingPong};
+pub use server_stats::{Metrics, ServerStats};
+pub use trickle_endpoint::TrickleCandidates;
@@ -1,20 +1,191 @@
-#[macro_use]
-extern crate log;
+use std::{env, net::SocketAddr, process};
 
-use log::LevelFilter;
-use simple_logger::SimpleLogger;
+use clap::Parser;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 
-mod app;
-use app::App;
+use naia_socket_server::{parse_public_url, App, AppConfig, ConfigError, Echo, PingPong};
 
-fn main() {
-    SimpleLogger::new()
-        .with_level(LevelFilter::Info)
-        .init()
-        .expect("A logger was already initialized");
+/// Command-line options for the server. Each one overrides the environment
+/// variable it mentions; every other setting is only read from the
+/// environment.
+#[derive(Parser)]
+struct Args {
+    /// Signaling (TCP) listen address, e.g. `0.0.0.0:14191`; overrides
+    /// NAIA_SESSION_ADDR
+    #[clap(long)]
+    session_addr: Option<SocketAddr>,
 
-    let mut app = App::new();
-    loop {
-        app.update();
+    /// Data channel (UDP) listen address, e.g. `0.0.0.0:14192`; overrides
+    /// NAIA_WEBRTC_ADDR
+    #[clap(long)]
+    data_addr: Option<SocketAddr>,
+
+    /// Address to serve Prometheus metrics on at `/metrics`, e.g.
+    /// `127.0.0.1:9090`; overrides NAIA_METRICS_ADDR
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Address to serve trickle ICE on at `/rtc_candidate`, e.g.
+    /// `0.0.0.0:14193`; overrides NAIA_TRICKLE_ADDR
+    #[clap(long)]
+    trickle_addr: Option<SocketAddr>,
+
+    /// Data channel URL advertised to clients, e.g.
+    /// `http://203.0.113.7:14192`; overrides NAIA_PUBLIC_URL
+    #[clap(long)]
+    public_url: Option<String>,
+
+    /// PEM certificate chain to serve signaling over https with; overrides
+    /// NAIA_TLS_CERT
+    #[clap(long)]
+    tls_cert: Option<String>,
+
+    /// PEM private key for the TLS certificate; overrides NAIA_TLS_KEY
+    #[clap(long)]
+    tls_key: Option<String>,
+
+    /// Log filter, e.g. `debug` or `info,webrtc_unreliable=warn`; overrides
+    /// NAIA_LOG_LEVEL and RUST_LOG
+    #[clap(long, parse(try_from_str = parse_log_filter))]
+    log_level: Option<String>,
+}
+
+impl Args {
+    // the environment's config, with whatever was given on the command line
+    // in place of it
+    fn config(&self) -> Result<AppConfig, ConfigError> {
+        let mut config = AppConfig::from_env()?;
+        if let Some(session_addr) = self.session_addr {
+            config.session_addr = Some(session_addr);
+        }
+        if let Some(data_addr) = self.data_addr {
+            config.webrtc_addr = Some(data_addr);
+        }
+        if let Some(metrics_addr) = self.metrics_addr {
+            config.metrics_addr = Some(metrics_addr);
+        }
+        if let Some(trickle_addr) = self.trickle_addr {
+            config.trickle_addr = Some(trickle_addr);
+        }
+        if let Some(public_url) = &self.public_url {
+            config.public_url = Some(parse_public_url("--public-url", public_url)?);
+        }
+        if let Some(tls_cert) = &self.tls_cert {
+            config.tls_cert = Some(tls_cert.clone());
+        }
+        if let Some(tls_key) = &self.tls_key {
+            config.tls_key = Some(tls_key.clone());
+        }
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_log_filter(input: &str) -> Result<String, String> {
+    EnvFilter::try_new(input)
+        .map(|_| input.to_string())
+        .map_err(|error| format!("invalid log filter '{}': {}", input, error))
+}
+
+// per-packet logs are at debug level, so NAIA_LOG_LEVEL=debug shows them.
+// The filter comes from --log-level, then NAIA_LOG_LEVEL, then RUST_LOG as
+// for any Rust binary. NAIA_LOG_FORMAT=json logs JSON lines for log
+// ingestion. There's no logging yet to report a bad NAIA_LOG_LEVEL with, so
+// it's printed to stderr instead.
+fn init_logging(log_level: Option<&str>) {
+    let filter = log_filter(log_level, env::var("NAIA_LOG_LEVEL").ok()).unwrap_or_else(|error| {
+        eprintln!("Invalid configuration: {}", error);
+        process::exit(1);
+    });
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if env::var("NAIA_LOG_FORMAT").as_deref() == Ok("json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
+
+// --log-level has already been checked by clap, NAIA_LOG_LEVEL hasn't
+fn log_filter(log_level: Option<&str>, env_level: Option<String>) -> Result<EnvFilter, String> {
+    if let Some(log_level) = log_level {
+        return Ok(EnvFilter::new(log_level));
+    }
+    if let Some(level) = env_level {
+        return EnvFilter::try_new(&level).map_err(|error| {
+            format!(
+                "NAIA_LOG_LEVEL: '{}' is not a valid log filter: {}",
+                level, error
+            )
+        });
+    }
+    Ok(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+}
+
+// cancels `shutdown` once Ctrl-C or SIGTERM arrives
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    let signal = shutdown_signal().await;
+    info!("{} received, shutting down", signal);
+    shutdown.cancel();
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "Ctrl-C",
+        _ = terminate.recv() => "SIGTERM",
+    }
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let args = Args::parse();
+    init_logging(args.log_level.as_deref());
+
+    let config = args.config().unwrap_or_else(|error| {
+        error!("Invalid configuration: {}", error);
+        process::exit(1);
+    });
+    let echo = config.echo;
+    let app = if echo {
+        App::new(config, Echo)
+    } else {
+        let handler = PingPong::new(config.reply.clone());
+        App::new(config, handler)
+    };
+    let mut app = app.unwrap_or_else(|error| {
+        error!("Failed to start: {}", error);
+        process::exit(1);
+    });
+    if echo {
+        info!("Echoing every message back");
+    }
+    let shutdown = CancellationToken::new();
+    tokio::spawn(cancel_on_signal(shutdown.clone()));
+    app.run(shutdown).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_invalid_env_log_level_is_reported() {
+        let error = log_filter(None, Some("info,[".to_string())).unwrap_err();
+        assert!(error.starts_with("NAIA_LOG_LEVEL: 'info,[' is not a valid log filter"));
+
+        assert!(log_filter(None, Some("info,webrtc_unreliable=warn".to_string())).is_ok());
+        // --log-level takes precedence, so a bad NAIA_LOG_LEVEL is ignored
+        assert!(log_filter(Some("debug"), Some("info,[".to_string())).is_ok());
     }
 }
@@ -0,0 +1,107 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use signaling_shared::prometheus::CONTENT_TYPE;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+// the most of a scrape request that's read, headers included
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+// how long a scrape gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the latest page of metrics over HTTP, at `/metrics`, from a thread
+/// of its own. The App replaces the page as it samples its rates.
+pub struct MetricsEndpoint {
+    page: Arc<Mutex<String>>,
+}
+
+impl MetricsEndpoint {
+    /// Binds `address` straight away, so a port in use fails here
+    pub fn serve(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let page = Arc::new(Mutex::new(String::new()));
+        let served = Arc::clone(&page);
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .enable_time()
+                .build()
+                .expect("failed to build the metrics runtime");
+            runtime.block_on(accept_loop(listener, served));
+        });
+        Ok(MetricsEndpoint { page })
+    }
+
+    pub fn update(&self, page: String) {
+        *self.page.lock().expect("metrics page lock poisoned") = page;
+    }
+}
+
+async fn accept_loop(listener: TcpListener, page: Arc<Mutex<String>>) {
+    let listener =
+        tokio::net::TcpListener::from_std(listener).expect("failed to adopt the metrics listener");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!("Failed to accept a metrics connection: {}", error);
+                continue;
+            }
+        };
+        let page = Arc::clone(&page);
+        tokio::spawn(async move {
+            if let Err(error) = respond(stream, page).await {
+                debug!("Metrics request from {} failed: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, page: Arc<Mutex<String>>) -> io::Result<()> {
+    let request = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await {
+        Ok(request) => request?,
+        Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+    };
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = page.lock().expect("metrics page lock poisoned").clone();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            CONTENT_TYPE,
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// reads up to the end of the headers; a scrape has no body
+async fn read_request(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() >= MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request headers too large",
+            ));
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&chunk[..read]);
+    }
+    Ok(request)
+}
@@ -0,0 +1,95 @@
+use std::net::SocketAddr;
+
+use naia_server_socket::{NaiaServerSocketError, PacketReceiver, PacketSender};
+
+/// Where the App receives packets from & sends them to. A send that fails
+/// comes back from `receive` as a `SendError` for its address, the way
+/// naia reports it.
+pub trait PacketIo {
+    fn receive(&mut self) -> Result<Option<(SocketAddr, Vec<u8>)>, NaiaServerSocketError>;
+    fn send(&mut self, address: &SocketAddr, payload: &[u8]);
+}
+
+/// The naia socket's ends
+pub struct NaiaIo {
+    pub sender: PacketSender,
+    pub receiver: PacketReceiver,
+}
+
+impl PacketIo for NaiaIo {
+    fn receive(&mut self) -> Result<Option<(SocketAddr, Vec<u8>)>, NaiaServerSocketError> {
+        self.receiver
+            .receive()
+            .map(|packet| packet.map(|(address, payload)| (address, payload.to_vec())))
+    }
+
+    fn send(&mut self, address: &SocketAddr, payload: &[u8]) {
+        self.sender.send(address, payload);
+    }
+}
+
+#[cfg(test)]
+pub use memory::MemoryIo;
+
+#[cfg(test)]
+mod memory {
+    use std::{
+        cell::RefCell,
+        collections::{HashSet, VecDeque},
+        rc::Rc,
+    };
+
+    use super::*;
+
+    #[derive(Default)]
+    struct State {
+        inbound: VecDeque<Result<(SocketAddr, Vec<u8>), NaiaServerSocketError>>,
+        sent: Vec<(SocketAddr, Vec<u8>)>,
+        unreachable: HashSet<SocketAddr>,
+    }
+
+    /// In-memory packets, to drive the App without a socket. Clones share
+    /// the packets, so a test keeps one to push & inspect them with.
+    #[derive(Clone, Default)]
+    pub struct MemoryIo {
+        state: Rc<RefCell<State>>,
+    }
+
+    impl MemoryIo {
+        /// Queues a packet for the App to receive from `from`
+        pub fn push(&self, from: SocketAddr, payload: &[u8]) {
+            self.state
+                .borrow_mut()
+                .inbound
+                .push_back(Ok((from, payload.to_vec())));
+        }
+
+        /// Takes everything the App has sent since last called
+        pub fn take_sent(&self) -> Vec<(SocketAddr, Vec<u8>)> {
+            std::mem::take(&mut self.state.borrow_mut().sent)
+        }
+
+        /// Fails every send to `address` from now on, reporting it the way
+        /// naia would if its receiver didn't swallow the error
+        pub fn fail_sends_to(&self, address: SocketAddr) {
+            self.state.borrow_mut().unreachable.insert(address);
+        }
+    }
+
+    impl PacketIo for MemoryIo {
+        fn receive(&mut self) -> Result<Option<(SocketAddr, Vec<u8>)>, NaiaServerSocketError> {
+            self.state.borrow_mut().inbound.pop_front().transpose()
+        }
+
+        fn send(&mut self, address: &SocketAddr, payload: &[u8]) {
+            let mut state = self.state.borrow_mut();
+            if state.unreachable.contains(address) {
+                state
+                    .inbound
+                    .push_back(Err(NaiaServerSocketError::SendError(*address)));
+            } else {
+                state.sent.push((*address, payload.to_vec()));
+            }
+        }
+    }
+}
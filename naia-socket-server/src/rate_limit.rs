@@ -0,0 +1,32 @@
+use std::time::Instant;
+
+/// Token bucket allowing `rate` packets per second on average, in bursts of
+/// up to `rate`
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// A full bucket, so a new client can send a burst straight away
+    pub fn new(rate: u32) -> Self {
+        TokenBucket {
+            tokens: f64::from(rate),
+            last_refill: Instant::now(),
+        }
+    }
+
+    /// Takes a token for one packet, returning false if there was none left
+    pub fn try_take(&mut self, rate: u32) -> bool {
+        let now = Instant::now();
+        let refill = now.duration_since(self.last_refill).as_secs_f64() * f64::from(rate);
+        self.tokens = (self.tokens + refill).min(f64::from(rate));
+        self.last_refill = now;
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
@@ -0,0 +1,31 @@
+/// Counters describing what the App has seen so far
+#[derive(Clone, Default)]
+pub struct ServerStats {
+    /// Number of packets received from clients
+    pub packets_received: u64,
+    /// Number of packets sent to clients
+    pub packets_sent: u64,
+    /// Number of packets dropped because they were malformed or failed
+    /// validation: a bad checksum, or a payload that didn't decompress or
+    /// decode as a fragment or binary packet
+    pub corrupt_packets: u64,
+    /// Number of packets dropped because their client exceeded the rate limit
+    pub rate_limited_packets: u64,
+    /// Number of messages given up on, because fragments of them never
+    /// arrived or their fragments went over the reassembly budget
+    pub reassembly_failures: u64,
+    /// Bytes received from clients, framing & checksums included
+    pub bytes_received: u64,
+    /// Bytes sent to clients, framing & sequence numbers included
+    pub bytes_sent: u64,
+}
+
+/// The App's counters, along with its rates over the last sampled second
+#[derive(Clone, Default)]
+pub struct Metrics {
+    pub totals: ServerStats,
+    pub packets_received_per_sec: f64,
+    pub packets_sent_per_sec: f64,
+    pub bytes_received_per_sec: f64,
+    pub bytes_sent_per_sec: f64,
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::json;
+
+use crate::server_stats::ServerStats;
+
+/// Appends JSON snapshots of the App's stats to a file, at most once per
+/// interval
+pub struct StatsFile {
+    path: String,
+    file: File,
+    interval: Duration,
+    last_write: Option<Instant>,
+}
+
+impl StatsFile {
+    pub fn open(path: &str, interval: Duration) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(StatsFile {
+            path: path.to_string(),
+            file,
+            interval,
+            last_write: None,
+        })
+    }
+
+    /// Writes a snapshot if at least one interval has passed since the last
+    pub fn write_if_due(&mut self, stats: &ServerStats) {
+        let now = Instant::now();
+        if self
+            .last_write
+            .is_some_and(|last| now.duration_since(last) < self.interval)
+        {
+            return;
+        }
+        self.write(stats);
+    }
+
+    /// Writes a snapshot now, e.g. a final one at shutdown
+    pub fn write(&mut self, stats: &ServerStats) {
+        self.last_write = Some(Instant::now());
+
+        let mut line = match snapshot_json(stats) {
+            Some(line) => line,
+            None => return,
+        };
+        line.push('\n');
+        if let Err(error) = self.file.write_all(line.as_bytes()) {
+            warn!("Failed to write stats file {}: {}", self.path, error);
+        }
+    }
+}
+
+fn snapshot_json(stats: &ServerStats) -> Option<String> {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default();
+
+    let snapshot = json!({
+        "timestamp_ms": timestamp_ms as u64,
+        "packets_received": stats.packets_received,
+        "packets_sent": stats.packets_sent,
+        "corrupt_packets": stats.corrupt_packets,
+        "rate_limited_packets": stats.rate_limited_packets,
+        "reassembly_failures": stats.reassembly_failures,
+        "bytes_received": stats.bytes_received,
+        "bytes_sent": stats.bytes_sent,
+    });
+
+    serde_json::to_string(&snapshot).ok()
+}
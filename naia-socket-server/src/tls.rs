@@ -0,0 +1,179 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+/// Reads a PEM certificate chain & private key into an acceptor for the
+/// signaling endpoint
+pub fn load_acceptor(cert_path: &str, key_path: &str) -> io::Result<TlsAcceptor> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert_path)?))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("no certificates in {}", cert_path)));
+    }
+
+    let mut key = None;
+    for item in rustls_pemfile::read_all(&mut BufReader::new(File::open(key_path)?))? {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(der)
+            | rustls_pemfile::Item::RSAKey(der)
+            | rustls_pemfile::Item::ECKey(der) => {
+                key = Some(der);
+                break;
+            }
+            _ => {}
+        }
+    }
+    let key = key.ok_or_else(|| invalid(format!("no private key in {}", key_path)))?;
+
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            certs.into_iter().map(Certificate).collect(),
+            PrivateKey(key),
+        )
+        .map_err(|error| invalid(format!("{}: {}", cert_path, error)))?;
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+// how long a client gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+// pause after a failed accept, e.g. out of file descriptors, doubled while
+// accepting keeps failing
+const MIN_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Terminates TLS on `listener`, from a thread of its own, handing each
+/// decrypted connection to the plain signaling endpoint. Returns the
+/// loopback address that endpoint should listen on, a free port picked for
+/// it.
+pub fn serve(listener: TcpListener, acceptor: TlsAcceptor) -> io::Result<SocketAddr> {
+    let loopback = match listener.local_addr()?.ip() {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+    };
+    let reservation = reserve_port(loopback)?;
+    let upstream = reservation.local_addr()?;
+    listener.set_nonblocking(true)?;
+    thread::spawn(move || {
+        // held for as long as connections are forwarded to the port
+        let _reservation = reservation;
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_io()
+            .enable_time()
+            .build()
+            .expect("failed to build the TLS runtime");
+        runtime.block_on(accept_loop(listener, acceptor, upstream));
+    });
+    Ok(upstream)
+}
+
+// naia binds the signaling endpoint itself, from an address, so the port is
+// reserved with a socket that's bound but never listens. Sockets that set
+// SO_REUSEADDR, as every std listener on Unix does (naia's included), can
+// still bind & listen on it, but nothing else can take the port meanwhile.
+#[cfg(unix)]
+fn reserve_port(ip: IpAddr) -> io::Result<tokio::net::TcpSocket> {
+    let socket = match ip {
+        IpAddr::V4(_) => tokio::net::TcpSocket::new_v4()?,
+        IpAddr::V6(_) => tokio::net::TcpSocket::new_v6()?,
+    };
+    socket.set_reuseaddr(true)?;
+    socket.bind(SocketAddr::new(ip, 0))?;
+    Ok(socket)
+}
+
+// elsewhere SO_REUSEADDR lets a socket take over a port in use, and std
+// doesn't set it, so the port is only picked, and freed again for naia
+#[cfg(not(unix))]
+fn reserve_port(ip: IpAddr) -> io::Result<ReservedAddr> {
+    Ok(ReservedAddr(TcpListener::bind((ip, 0))?.local_addr()?))
+}
+
+#[cfg(not(unix))]
+struct ReservedAddr(SocketAddr);
+
+#[cfg(not(unix))]
+impl ReservedAddr {
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.0)
+    }
+}
+
+async fn accept_loop(listener: TcpListener, acceptor: TlsAcceptor, upstream: SocketAddr) {
+    let listener =
+        tokio::net::TcpListener::from_std(listener).expect("failed to adopt the TLS listener");
+    let mut backoff = MIN_ACCEPT_BACKOFF;
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => {
+                backoff = MIN_ACCEPT_BACKOFF;
+                accepted
+            }
+            Err(error) => {
+                warn!(
+                    "Failed to accept a signaling connection: {}; retrying in {:?}",
+                    error, backoff
+                );
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            if let Err(error) = forward(stream, acceptor, upstream).await {
+                debug!("Signaling connection from {} failed: {}", peer, error);
+            }
+        });
+    }
+}
+
+// a failed handshake is the client's problem, so it's only logged at debug
+async fn forward(stream: TcpStream, acceptor: TlsAcceptor, upstream: SocketAddr) -> io::Result<()> {
+    let mut tls_stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream))
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out"))??;
+    let mut upstream = TcpStream::connect(upstream).await?;
+    tokio::io::copy_bidirectional(&mut tls_stream, &mut upstream).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn reserved_port_can_only_be_listened_on_by_reusing_sockets() {
+        let reservation = reserve_port(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+        let upstream = reservation.local_addr().unwrap();
+
+        // what naia does with the address
+        let listener = TcpListener::bind(upstream).unwrap();
+        assert_eq!(listener.local_addr().unwrap(), upstream);
+        std::net::TcpStream::connect(upstream).unwrap();
+
+        // once something listens, nothing else gets the port either
+        assert!(TcpListener::bind(upstream).is_err());
+    }
+
+    #[test]
+    fn missing_files_fail_to_load() {
+        assert!(load_acceptor("/nonexistent/cert.pem", "/nonexistent/key.pem").is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    net::{IpAddr, SocketAddr, TcpListener},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use naia_server_socket::ServerAddrs;
+use signaling_shared::{SessionCandidate, TrickleResponse, CONNECTION_ID_HEADER, TRICKLE_PATH};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use url::Url;
+
+// the most of a request that's read, headers & body included
+const MAX_REQUEST_SIZE: usize = 16 * 1024;
+// how long a client gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// connections whose candidates are kept; past it, the oldest are forgotten
+const MAX_CONNECTIONS: usize = 1024;
+// candidates kept for each connection, which is more than ICE gathers
+const MAX_CANDIDATES: usize = 64;
+
+/// Serves trickle ICE over HTTP, at `/rtc_candidate`, from a thread of its
+/// own. A client posts each local candidate as it's gathered, under its
+/// connection id, and gets the server's candidates back with the first one;
+/// a GET polls for all of them. The data channels are served ICE-lite from
+/// the one advertised address, so that's the only server candidate, and the
+/// client candidates are only kept to be looked up.
+pub struct TrickleEndpoint {
+    listener: TcpListener,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    server_candidates: Vec<SessionCandidate>,
+    // each connection's candidates, and the order connections were first seen
+    clients: HashMap<String, Vec<SessionCandidate>>,
+    order: VecDeque<String>,
+}
+
+impl TrickleEndpoint {
+    /// Binds `address` straight away, so a port in use fails here, before
+    /// the server's candidates are known
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(TrickleEndpoint {
+            listener,
+            state: Arc::default(),
+        })
+    }
+
+    /// The address bound, with the port picked if `bind` was given port 0
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Starts answering requests with `server_candidates`
+    pub fn serve(self, server_candidates: Vec<SessionCandidate>) -> TrickleCandidates {
+        self.state
+            .lock()
+            .expect("trickle state lock poisoned")
+            .server_candidates = server_candidates;
+        let state = Arc::clone(&self.state);
+        let listener = self.listener;
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_io()
+                .enable_time()
+                .build()
+                .expect("failed to build the trickle runtime");
+            runtime.block_on(accept_loop(listener, state));
+        });
+        TrickleCandidates { state: self.state }
+    }
+}
+
+/// The candidates clients have trickled to a served `TrickleEndpoint`
+#[derive(Clone)]
+pub struct TrickleCandidates {
+    state: Arc<Mutex<State>>,
+}
+
+impl TrickleCandidates {
+    /// Candidates the client with `connection_id` has posted so far, oldest
+    /// first
+    pub fn client_candidates(&self, connection_id: &str) -> Vec<SessionCandidate> {
+        self.state
+            .lock()
+            .expect("trickle state lock poisoned")
+            .clients
+            .get(connection_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// The host candidate clients reach the data channels on: the advertised
+/// URL's address, or the listen address when the URL names a host rather
+/// than an IP, matching the candidate naia puts in its session response
+pub fn server_candidate(addrs: &ServerAddrs) -> SessionCandidate {
+    let advertised = Url::parse(&addrs.public_webrtc_url).ok().and_then(|url| {
+        let ip: IpAddr = url
+            .host_str()?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse()
+            .ok()?;
+        Some(SocketAddr::new(ip, url.port_or_known_default()?))
+    });
+    let address = advertised.unwrap_or(addrs.webrtc_listen_addr);
+    SessionCandidate {
+        candidate: format!(
+            "candidate:1 1 UDP 2122317823 {} {} typ host",
+            address.ip(),
+            address.port()
+        ),
+        sdp_m_line_index: 0,
+        sdp_mid: "0".to_string(),
+    }
+}
+
+async fn accept_loop(listener: TcpListener, state: Arc<Mutex<State>>) {
+    let listener =
+        tokio::net::TcpListener::from_std(listener).expect("failed to adopt the trickle listener");
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!("Failed to accept a trickle connection: {}", error);
+                continue;
+            }
+        };
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            if let Err(error) = respond(stream, state).await {
+                debug!("Trickle request from {} failed: {}", peer, error);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream, state: Arc<Mutex<State>>) -> io::Result<()> {
+    let (head, body) = match tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream)).await
+    {
+        Ok(request) => request?,
+        Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+    };
+    let (status, body) = answer(
+        &head,
+        &body,
+        &mut state.lock().expect("trickle state lock poisoned"),
+    );
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+// the status & body a request gets, recording a posted candidate
+fn answer(head: &str, body: &[u8], state: &mut State) -> (&'static str, String) {
+    let mut request_line = head.lines().next().unwrap_or_default().split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    if path.split('?').next() != Some(&format!("/{}", TRICKLE_PATH)) {
+        return ("404 Not Found", String::new());
+    }
+    let connection_id = match header(head, CONNECTION_ID_HEADER) {
+        Some(connection_id) if !connection_id.is_empty() => connection_id.to_string(),
+        _ => {
+            return (
+                "400 Bad Request",
+                format!("missing {} header", CONNECTION_ID_HEADER),
+            )
+        }
+    };
+
+    let candidates = match method {
+        "GET" => state.server_candidates.clone(),
+        "POST" => {
+            let candidate: SessionCandidate = match serde_json::from_slice(body) {
+                Ok(candidate) => candidate,
+                Err(error) => return ("400 Bad Request", format!("invalid candidate: {}", error)),
+            };
+            debug!(
+                "Connection {} trickled ICE candidate '{}'",
+                connection_id, candidate.candidate
+            );
+            // the server's candidates go out with a connection's first post
+            if state.record(connection_id, candidate) {
+                state.server_candidates.clone()
+            } else {
+                Vec::new()
+            }
+        }
+        _ => return ("405 Method Not Allowed", String::new()),
+    };
+    let response = TrickleResponse {
+        candidate: candidates,
+    };
+    (
+        "200 OK",
+        serde_json::to_string(&response).expect("a trickle response always serializes"),
+    )
+}
+
+impl State {
+    // keeps `candidate` for `connection_id`, returning whether it's the
+    // connection's first
+    fn record(&mut self, connection_id: String, candidate: SessionCandidate) -> bool {
+        if let Some(candidates) = self.clients.get_mut(&connection_id) {
+            if candidates.len() < MAX_CANDIDATES {
+                candidates.push(candidate);
+            }
+            return false;
+        }
+        if self.order.len() >= MAX_CONNECTIONS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.clients.remove(&oldest);
+            }
+        }
+        self.order.push_back(connection_id.clone());
+        self.clients.insert(connection_id, vec![candidate]);
+        true
+    }
+}
+
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().skip(1).find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim().eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+// reads the headers, then as much body as Content-Length declares
+async fn read_request(stream: &mut TcpStream) -> io::Result<(String, Vec<u8>)> {
+    let too_large = || io::Error::new(io::ErrorKind::InvalidData, "request too large");
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 1024];
+    let head_end = loop {
+        if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if request.len() >= MAX_REQUEST_SIZE {
+            return Err(too_large());
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        request.extend_from_slice(&chunk[..read]);
+    };
+    let head = String::from_utf8_lossy(&request[..head_end]).into_owned();
+    let length: usize = match header(&head, "content-length") {
+        Some(length) => length
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid Content-Length"))?,
+        None => 0,
+    };
+    if head_end + 4 + length > MAX_REQUEST_SIZE {
+        return Err(too_large());
+    }
+    let mut body = request[head_end + 4..].to_vec();
+    while body.len() < length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(length);
+    Ok((head, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(candidate: &str) -> SessionCandidate {
+        SessionCandidate {
+            candidate: candidate.to_string(),
+            sdp_m_line_index: 0,
+            sdp_mid: "0".to_string(),
+        }
+    }
+
+    async fn request(address: SocketAddr, request: String) -> String {
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn post(connection_id: &str, body: &str) -> String {
+        format!(
+            "POST /rtc_candidate HTTP/1.1\r\n{}: {}\r\nContent-Length: {}\r\n\r\n{}",
+            CONNECTION_ID_HEADER,
+            connection_id,
+            body.len(),
+            body
+        )
+    }
+
+    fn response_candidates(response: &str) -> Vec<String> {
+        let (_, body) = response.split_once("\r\n\r\n").unwrap();
+        let response: TrickleResponse = serde_json::from_str(body).unwrap();
+        response
+            .candidate
+            .into_iter()
+            .map(|candidate| candidate.candidate)
+            .collect()
+    }
+
+    #[test]
+    fn the_advertised_ip_is_the_candidate() {
+        let listen: SocketAddr = "0.0.0.0:14192".parse().unwrap();
+        let advertised = ServerAddrs::new(listen, listen, "http://203.0.113.7:14192");
+        assert_eq!(
+            server_candidate(&advertised).candidate,
+            "candidate:1 1 UDP 2122317823 203.0.113.7 14192 typ host"
+        );
+        let ipv6 = ServerAddrs::new(listen, listen, "http://[2001:db8::1]:4000");
+        assert_eq!(
+            server_candidate(&ipv6).candidate,
+            "candidate:1 1 UDP 2122317823 2001:db8::1 4000 typ host"
+        );
+        // a host name can't go in a candidate
+        let named = ServerAddrs::new(listen, listen, "http://example.com:14192");
+        assert_eq!(
+            server_candidate(&named).candidate,
+            "candidate:1 1 UDP 2122317823 0.0.0.0 14192 typ host"
+        );
+    }
+
+    #[tokio::test]
+    async fn posted_candidates_are_answered_with_the_servers_once() {
+        let endpoint = TrickleEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        let server = "candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host";
+        let trickled = endpoint.serve(vec![candidate(server)]);
+
+        let body = serde_json::to_string(&candidate("candidate:2 1 udp 1 192.0.2.1 5000 typ host"))
+            .unwrap();
+        let first = request(address, post("abc", &body)).await;
+        assert!(first.starts_with("HTTP/1.1 200 OK\r\n"), "{}", first);
+        assert_eq!(response_candidates(&first), [server]);
+
+        let body = serde_json::to_string(&candidate("candidate:3 1 udp 1 192.0.2.2 5000 typ host"))
+            .unwrap();
+        let second = request(address, post("abc", &body)).await;
+        assert!(response_candidates(&second).is_empty());
+
+        let polled = request(
+            address,
+            format!(
+                "GET /rtc_candidate HTTP/1.1\r\n{}: abc\r\n\r\n",
+                CONNECTION_ID_HEADER
+            ),
+        )
+        .await;
+        assert_eq!(response_candidates(&polled), [server]);
+
+        let kept: Vec<_> = trickled
+            .client_candidates("abc")
+            .into_iter()
+            .map(|candidate| candidate.candidate)
+            .collect();
+        assert_eq!(
+            kept,
+            [
+                "candidate:2 1 udp 1 192.0.2.1 5000 typ host",
+                "candidate:3 1 udp 1 192.0.2.2 5000 typ host"
+            ]
+        );
+        assert!(trickled.client_candidates("other").is_empty());
+    }
+
+    #[tokio::test]
+    async fn bad_requests_are_refused() {
+        let endpoint = TrickleEndpoint::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = endpoint.local_addr().unwrap();
+        endpoint.serve(Vec::new());
+
+        let unidentified = request(
+            address,
+            "POST /rtc_candidate HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}".to_string(),
+        )
+        .await;
+        assert!(
+            unidentified.starts_with("HTTP/1.1 400 "),
+            "{}",
+            unidentified
+        );
+
+        let malformed = request(address, post("abc", "{\"candidate\":1}")).await;
+        assert!(malformed.starts_with("HTTP/1.1 400 "), "{}", malformed);
+
+        let elsewhere = request(address, "GET /metrics HTTP/1.1\r\n\r\n".to_string()).await;
+        assert!(elsewhere.starts_with("HTTP/1.1 404 "), "{}", elsewhere);
+    }
+
+    #[test]
+    fn the_oldest_connections_are_forgotten() {
+        let mut state = State::default();
+        for index in 0..=MAX_CONNECTIONS {
+            assert!(state.record(index.to_string(), candidate("c")));
+        }
+        assert_eq!(state.clients.len(), MAX_CONNECTIONS);
+        assert!(!state.clients.contains_key("0"));
+        assert!(!state.record(MAX_CONNECTIONS.to_string(), candidate("c")));
+    }
+}
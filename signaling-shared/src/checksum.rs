@@ -0,0 +1,70 @@
+//! The CRC32 a client can append to every payload, and a server validating
+//! checksums strips before handling it. It's big-endian, of every byte in
+//! front of it, and inside compression: it's appended before a payload is
+//! deflated, and checked once it's inflated again.
+
+/// Number of bytes a CRC32 checksum occupies at the end of a payload
+pub const CHECKSUM_SIZE: usize = 4;
+
+/// `payload` followed by its checksum
+pub fn append_checksum(payload: &[u8]) -> Vec<u8> {
+    let mut checksummed = Vec::with_capacity(payload.len() + CHECKSUM_SIZE);
+    checksummed.extend_from_slice(payload);
+    checksummed.extend_from_slice(&crc32fast::hash(payload).to_be_bytes());
+    checksummed
+}
+
+/// Validates the big-endian CRC32 appended to the end of `payload`, returning
+/// the payload without its checksum if it matches
+pub fn strip_checksum(payload: &[u8]) -> Option<&[u8]> {
+    if payload.len() < CHECKSUM_SIZE {
+        return None;
+    }
+
+    let (body, checksum) = payload.split_at(payload.len() - CHECKSUM_SIZE);
+    let expected = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+
+    if crc32fast::hash(body) == expected {
+        Some(body)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checksummed_payloads_round_trip() {
+        for payload in [&b""[..], b"PING", &[0xff; 2000]] {
+            let checksummed = append_checksum(payload);
+            assert_eq!(checksummed.len(), payload.len() + CHECKSUM_SIZE);
+            assert_eq!(strip_checksum(&checksummed), Some(payload));
+        }
+        // the CRC32 check value, big-endian
+        assert_eq!(
+            &append_checksum(b"123456789")[9..],
+            &[0xcb, 0xf4, 0x39, 0x26]
+        );
+    }
+
+    #[test]
+    fn a_changed_byte_fails_the_check() {
+        let checksummed = append_checksum(b"PING");
+        for index in 0..checksummed.len() {
+            let mut corrupt = checksummed.clone();
+            corrupt[index] ^= 0x01;
+            assert_eq!(strip_checksum(&corrupt), None, "byte {} flipped", index);
+        }
+    }
+
+    #[test]
+    fn payloads_shorter_than_a_checksum_fail_the_check() {
+        for length in 0..CHECKSUM_SIZE {
+            assert_eq!(strip_checksum(&vec![0; length]), None);
+        }
+        // the checksum of nothing is zero
+        assert_eq!(strip_checksum(&[0; CHECKSUM_SIZE]), Some(&b""[..]));
+    }
+}
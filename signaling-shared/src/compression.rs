@@ -0,0 +1,211 @@
+//! The deflate compression a client can ask for with `COMPRESSION_OFFER`.
+//! Once the server answers `COMPRESSION_ACCEPTED`, every message either way
+//! starts with a flag byte saying whether the rest of it is deflated.
+
+use std::fmt;
+
+use miniz_oxide::{
+    deflate::compress_to_vec,
+    inflate::{decompress_slice_iter_to_slice, decompress_to_vec_with_limit, TINFLStatus},
+};
+
+/// A client's request to compress its connection's payloads
+pub const COMPRESSION_OFFER: &[u8] = b"COMPRESSION deflate";
+/// The reply, after which every message either way carries a flag byte
+pub const COMPRESSION_ACCEPTED: &[u8] = b"COMPRESSION_ACCEPTED deflate";
+/// Flag byte before a payload sent as-is
+pub const UNCOMPRESSED: u8 = 0;
+/// Flag byte before a deflated payload
+pub const DEFLATED: u8 = 1;
+
+// fastest deflate level, since messages are small & latency matters more
+const COMPRESSION_LEVEL: u8 = 1;
+
+/// Why a message couldn't be decompressed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressionError {
+    /// An empty message, with no flag byte
+    Empty,
+    /// A flag byte that's neither `UNCOMPRESSED` nor `DEFLATED`
+    UnknownFlag(u8),
+    /// A deflated body that doesn't inflate
+    Corrupt(TINFLStatus),
+    /// A payload larger than the `max_size` it's decompressed to
+    TooLarge { max_size: usize },
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::Empty => write!(f, "empty message has no compression flag"),
+            CompressionError::UnknownFlag(flag) => write!(f, "unknown compression flag {}", flag),
+            CompressionError::Corrupt(status) => {
+                write!(f, "failed to inflate message: {:?}", status)
+            }
+            CompressionError::TooLarge { max_size } => {
+                write!(f, "message decompresses to more than {} bytes", max_size)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {}
+
+/// Prefixes `payload` with its flag byte, deflating it only if that makes
+/// it smaller
+pub fn compress(payload: &[u8]) -> Vec<u8> {
+    let deflated = compress_to_vec(payload, COMPRESSION_LEVEL);
+    let (flag, body) = if deflated.len() < payload.len() {
+        (DEFLATED, &deflated[..])
+    } else {
+        (UNCOMPRESSED, payload)
+    };
+    let mut message = Vec::with_capacity(1 + body.len());
+    message.push(flag);
+    message.extend_from_slice(body);
+    message
+}
+
+/// Reads a message written by `compress`, failing if it decompresses to
+/// more than `max_size` bytes, so a tiny one can't claim gigabytes
+pub fn decompress(message: &[u8], max_size: usize) -> Result<Vec<u8>, CompressionError> {
+    let (flag, body) = message.split_first().ok_or(CompressionError::Empty)?;
+    let payload = match *flag {
+        UNCOMPRESSED => body.to_vec(),
+        DEFLATED => {
+            decompress_to_vec_with_limit(body, max_size).map_err(|error| match error.status {
+                TINFLStatus::HasMoreOutput => CompressionError::TooLarge { max_size },
+                status => CompressionError::Corrupt(status),
+            })?
+        }
+        flag => return Err(CompressionError::UnknownFlag(flag)),
+    };
+    if payload.len() > max_size {
+        return Err(CompressionError::TooLarge { max_size });
+    }
+    Ok(payload)
+}
+
+/// Like `decompress`, but inflates straight into `buffer` and returns the
+/// payload's length, failing if it doesn't fit
+pub fn decompress_into(message: &[u8], buffer: &mut [u8]) -> Result<usize, CompressionError> {
+    let (flag, body) = message.split_first().ok_or(CompressionError::Empty)?;
+    let too_large = CompressionError::TooLarge {
+        max_size: buffer.len(),
+    };
+    match *flag {
+        UNCOMPRESSED => {
+            let payload = buffer.get_mut(..body.len()).ok_or(too_large)?;
+            payload.copy_from_slice(body);
+            Ok(body.len())
+        }
+        DEFLATED => {
+            match decompress_slice_iter_to_slice(buffer, std::iter::once(body), false, true) {
+                Ok(length) => Ok(length),
+                Err(TINFLStatus::HasMoreOutput) => Err(too_large),
+                Err(status) => Err(CompressionError::Corrupt(status)),
+            }
+        }
+        flag => Err(CompressionError::UnknownFlag(flag)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compressible() -> Vec<u8> {
+        b"PONG ".repeat(200)
+    }
+
+    // bytes from a xorshift generator, which deflate can't shrink
+    fn incompressible() -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compressible_payloads_are_deflated() {
+        let payload = compressible();
+        let message = compress(&payload);
+        assert_eq!(message[0], DEFLATED);
+        assert!(message.len() < payload.len());
+        assert_eq!(decompress(&message, payload.len()), Ok(payload.clone()));
+
+        let mut buffer = vec![0u8; payload.len()];
+        assert_eq!(decompress_into(&message, &mut buffer), Ok(payload.len()));
+        assert_eq!(buffer, payload);
+    }
+
+    #[test]
+    fn incompressible_payloads_are_sent_as_is() {
+        let payload = incompressible();
+        let message = compress(&payload);
+        assert_eq!(message[0], UNCOMPRESSED);
+        assert_eq!(&message[1..], &payload[..]);
+        assert_eq!(decompress(&message, payload.len()), Ok(payload.clone()));
+
+        let mut buffer = vec![0u8; payload.len()];
+        assert_eq!(decompress_into(&message, &mut buffer), Ok(payload.len()));
+        assert_eq!(buffer, payload);
+
+        assert_eq!(compress(b""), vec![UNCOMPRESSED]);
+        assert_eq!(decompress(&compress(b""), 0), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_messages_are_rejected() {
+        let mut buffer = [0u8; 16];
+        assert_eq!(decompress(b"", 16), Err(CompressionError::Empty));
+        assert_eq!(
+            decompress_into(b"", &mut buffer),
+            Err(CompressionError::Empty)
+        );
+        assert_eq!(
+            decompress(&[7, 1, 2], 16),
+            Err(CompressionError::UnknownFlag(7))
+        );
+        assert_eq!(
+            decompress_into(&[7, 1, 2], &mut buffer),
+            Err(CompressionError::UnknownFlag(7))
+        );
+        assert!(matches!(
+            decompress(&[DEFLATED, 0xff, 0xff], 16),
+            Err(CompressionError::Corrupt(_))
+        ));
+        assert!(matches!(
+            decompress_into(&[DEFLATED, 0xff, 0xff], &mut buffer),
+            Err(CompressionError::Corrupt(_))
+        ));
+    }
+
+    #[test]
+    fn payloads_past_the_limit_are_rejected() {
+        let too_large = CompressionError::TooLarge { max_size: 100 };
+        let deflated = compress(&compressible());
+        let sent_as_is = compress(&incompressible());
+        let mut buffer = [0u8; 100];
+        assert_eq!(decompress(&deflated, 100), Err(too_large));
+        assert_eq!(decompress_into(&deflated, &mut buffer), Err(too_large));
+        assert_eq!(decompress(&sent_as_is, 100), Err(too_large));
+        assert_eq!(decompress_into(&sent_as_is, &mut buffer), Err(too_large));
+
+        // a small message can't claim a huge payload
+        let bomb = compress(&vec![0u8; u16::MAX as usize + 1]);
+        assert_eq!(bomb[0], DEFLATED);
+        assert!(bomb.len() < 1000);
+        assert_eq!(
+            decompress(&bomb, u16::MAX as usize),
+            Err(CompressionError::TooLarge {
+                max_size: u16::MAX as usize
+            })
+        );
+    }
+}
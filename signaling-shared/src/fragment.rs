@@ -0,0 +1,446 @@
+//! Splits messages too big for a single data channel message into
+//! fragments, and puts them back together on the other side. Every fragment
+//! starts with a header of the message's id (big-endian u32), its own index
+//! and the message's fragment count (big-endian u16s). Fragments can arrive
+//! in any order, or not at all, so a message that isn't complete within the
+//! reassembly timeout is given up on, as are the oldest messages once those
+//! waiting hold more bytes than the reassembler's budget.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// Size of the header in front of every fragment
+pub const FRAGMENT_HEADER_SIZE: usize = 8;
+/// Most message bytes a fragment carries, small enough that header &
+/// fragment fit a 1500 byte MTU with room for the DTLS & SCTP overhead
+pub const FRAGMENT_SIZE: usize = 1200;
+/// Largest message that can be fragmented
+pub const MAX_FRAGMENTED_SIZE: usize = FRAGMENT_SIZE * u16::MAX as usize;
+/// How long a partly received message waits for its missing fragments by
+/// default
+pub const DEFAULT_REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Most fragment bytes held for partly received messages at once, by
+/// default
+pub const DEFAULT_MAX_REASSEMBLY_BYTES: usize = 4 * 1024 * 1024;
+// partly received messages kept at once; the oldest is given up on to make
+// room for another
+const MAX_PENDING_MESSAGES: usize = 64;
+
+/// Why a fragment was rejected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FragmentError {
+    /// Shorter than the header
+    Truncated,
+    /// A fragment count of zero, or an index past it
+    BadIndex,
+    /// The count differs from earlier fragments of the same message
+    CountMismatch,
+}
+
+impl fmt::Display for FragmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FragmentError::Truncated => write!(f, "fragment shorter than its header"),
+            FragmentError::BadIndex => write!(f, "fragment index outside its count"),
+            FragmentError::CountMismatch => {
+                write!(
+                    f,
+                    "fragment count differs from the message's other fragments"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FragmentError {}
+
+/// Numbers outgoing messages and splits them into fragments
+#[derive(Default)]
+pub struct Fragmenter {
+    next_id: u32,
+}
+
+impl Fragmenter {
+    /// Splits `payload` into fragments of at most `FRAGMENT_SIZE` bytes
+    /// each, behind their header; `None` if it's larger than
+    /// `MAX_FRAGMENTED_SIZE`. An empty payload is still sent, as one empty
+    /// fragment.
+    pub fn split(&mut self, payload: &[u8]) -> Option<Vec<Vec<u8>>> {
+        if payload.len() > MAX_FRAGMENTED_SIZE {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let count = payload.len().div_ceil(FRAGMENT_SIZE).max(1);
+        let fragments = (0..count)
+            .map(|index| {
+                let chunk = &payload[(index * FRAGMENT_SIZE).min(payload.len())
+                    ..((index + 1) * FRAGMENT_SIZE).min(payload.len())];
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+                fragment.extend_from_slice(&id.to_be_bytes());
+                fragment.extend_from_slice(&(index as u16).to_be_bytes());
+                fragment.extend_from_slice(&(count as u16).to_be_bytes());
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect();
+        Some(fragments)
+    }
+}
+
+/// Collects fragments until each message they belong to is complete
+pub struct Reassembler {
+    timeout: Duration,
+    max_bytes: usize,
+    pending: HashMap<u32, PendingMessage>,
+    // fragment bytes held across every pending message
+    held_bytes: usize,
+    expired: u64,
+}
+
+// a message some of whose fragments have arrived
+struct PendingMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    bytes: usize,
+    started: Instant,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Reassembler::new(DEFAULT_REASSEMBLY_TIMEOUT)
+    }
+}
+
+impl Reassembler {
+    /// Gives up on a message once `timeout` has passed since its first
+    /// fragment arrived
+    pub fn new(timeout: Duration) -> Self {
+        Reassembler::with_max_bytes(timeout, DEFAULT_MAX_REASSEMBLY_BYTES)
+    }
+
+    /// Like `new`, but gives up on the oldest messages whenever those
+    /// waiting hold more than `max_bytes` of fragments between them, so a
+    /// message much larger than that can't be reassembled at all
+    pub fn with_max_bytes(timeout: Duration, max_bytes: usize) -> Self {
+        Reassembler {
+            timeout,
+            max_bytes,
+            pending: HashMap::new(),
+            held_bytes: 0,
+            expired: 0,
+        }
+    }
+
+    /// Adds a received fragment, returning its message once that's the last
+    /// fragment missing
+    pub fn push(&mut self, fragment: &[u8]) -> Result<Option<Vec<u8>>, FragmentError> {
+        if fragment.len() < FRAGMENT_HEADER_SIZE {
+            return Err(FragmentError::Truncated);
+        }
+        let id = u32::from_be_bytes([fragment[0], fragment[1], fragment[2], fragment[3]]);
+        let index = u16::from_be_bytes([fragment[4], fragment[5]]) as usize;
+        let count = u16::from_be_bytes([fragment[6], fragment[7]]) as usize;
+        let chunk = &fragment[FRAGMENT_HEADER_SIZE..];
+        if index >= count {
+            return Err(FragmentError::BadIndex);
+        }
+        if count == 1 {
+            return Ok(Some(chunk.to_vec()));
+        }
+
+        let now = Instant::now();
+        self.expire(now);
+        if !self.pending.contains_key(&id) && self.pending.len() >= MAX_PENDING_MESSAGES {
+            self.expire_oldest();
+        }
+        let pending = self.pending.entry(id).or_insert_with(|| PendingMessage {
+            fragments: vec![None; count],
+            received: 0,
+            bytes: 0,
+            started: now,
+        });
+        if pending.fragments.len() != count {
+            return Err(FragmentError::CountMismatch);
+        }
+        // a duplicate replaces the earlier copy without counting twice
+        match pending.fragments[index].replace(chunk.to_vec()) {
+            Some(earlier) => {
+                pending.bytes -= earlier.len();
+                self.held_bytes -= earlier.len();
+            }
+            None => pending.received += 1,
+        }
+        pending.bytes += chunk.len();
+        self.held_bytes += chunk.len();
+
+        if pending.received == count {
+            let pending = self.pending.remove(&id).expect("pending message vanished");
+            self.held_bytes -= pending.bytes;
+            return Ok(Some(
+                pending.fragments.into_iter().flatten().flatten().collect(),
+            ));
+        }
+        // over budget, the oldest go first, which can be this one
+        while self.held_bytes > self.max_bytes {
+            self.expire_oldest();
+        }
+        Ok(None)
+    }
+
+    /// Messages given up on so far, for missing fragments or to make room
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Messages still waiting on fragments
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Fragment bytes held for the messages still waiting on others
+    pub fn held_bytes(&self) -> usize {
+        self.held_bytes
+    }
+
+    /// When the first fragment of the oldest message still waiting arrived
+    pub fn oldest_started(&self) -> Option<Instant> {
+        self.pending.values().map(|pending| pending.started).min()
+    }
+
+    /// Gives up on the oldest message still waiting, e.g. for a budget kept
+    /// across several reassemblers; `false` if none is
+    pub fn expire_oldest(&mut self) -> bool {
+        let oldest = self
+            .pending
+            .iter()
+            .min_by_key(|(_, pending)| pending.started)
+            .map(|(id, _)| *id);
+        match oldest.and_then(|id| self.pending.remove(&id)) {
+            Some(pending) => {
+                self.held_bytes -= pending.bytes;
+                self.expired += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        let timeout = self.timeout;
+        let before = self.pending.len();
+        let held_bytes = &mut self.held_bytes;
+        self.pending.retain(|_, pending| {
+            let waiting = now.duration_since(pending.started) < timeout;
+            if !waiting {
+                *held_bytes -= pending.bytes;
+            }
+            waiting
+        });
+        self.expired += (before - self.pending.len()) as u64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a payload long enough to need `count` fragments
+    fn payload(count: usize) -> Vec<u8> {
+        (0..FRAGMENT_SIZE * count - 1).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn single_fragment_round_trip() {
+        let fragments = Fragmenter::default().split(b"PING").unwrap();
+        assert_eq!(fragments.len(), 1);
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&fragments[0]), Ok(Some(b"PING".to_vec())));
+
+        let empty = Fragmenter::default().split(b"").unwrap();
+        assert_eq!(reassembler.push(&empty[0]), Ok(Some(Vec::new())));
+    }
+
+    #[test]
+    fn out_of_order_fragments() {
+        let message = payload(3);
+        let fragments = Fragmenter::default().split(&message).unwrap();
+        assert_eq!(fragments.len(), 3);
+
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&fragments[2]), Ok(None));
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.pending(), 1);
+        assert_eq!(reassembler.push(&fragments[1]), Ok(Some(message)));
+        assert_eq!(reassembler.pending(), 0);
+    }
+
+    #[test]
+    fn duplicate_fragment_counts_once() {
+        let message = payload(2);
+        let fragments = Fragmenter::default().split(&message).unwrap();
+
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.push(&fragments[1]), Ok(Some(message)));
+    }
+
+    #[test]
+    fn malformed_fragments() {
+        let mut reassembler = Reassembler::default();
+        assert_eq!(
+            reassembler.push(&[0; FRAGMENT_HEADER_SIZE - 1]),
+            Err(FragmentError::Truncated)
+        );
+        // index 2 of 2
+        assert_eq!(
+            reassembler.push(&[0, 0, 0, 1, 0, 2, 0, 2]),
+            Err(FragmentError::BadIndex)
+        );
+        assert_eq!(
+            reassembler.push(&[0, 0, 0, 1, 0, 0, 0, 0]),
+            Err(FragmentError::BadIndex)
+        );
+        assert_eq!(reassembler.push(&[0, 0, 0, 1, 0, 0, 0, 2, 9]), Ok(None));
+        assert_eq!(
+            reassembler.push(&[0, 0, 0, 1, 0, 1, 0, 3, 9]),
+            Err(FragmentError::CountMismatch)
+        );
+    }
+
+    #[test]
+    fn too_many_pending_gives_up_on_the_oldest() {
+        let mut fragmenter = Fragmenter::default();
+        let mut reassembler = Reassembler::default();
+        let messages: Vec<_> = (0..=MAX_PENDING_MESSAGES)
+            .map(|_| fragmenter.split(&payload(2)).unwrap())
+            .collect();
+        for fragments in &messages {
+            assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        }
+        assert_eq!(reassembler.pending(), MAX_PENDING_MESSAGES);
+        assert_eq!(reassembler.expired(), 1);
+
+        // the first message was dropped, so its last fragment starts over
+        assert_eq!(reassembler.push(&messages[0][1]), Ok(None));
+        assert_eq!(
+            reassembler.push(&messages[MAX_PENDING_MESSAGES][1]),
+            Ok(Some(payload(2)))
+        );
+    }
+
+    #[test]
+    fn incomplete_message_times_out() {
+        let fragments = Fragmenter::default().split(&payload(2)).unwrap();
+        let mut reassembler = Reassembler::new(Duration::from_millis(10));
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        std::thread::sleep(Duration::from_millis(20));
+
+        // the late fragment starts a message of its own
+        assert_eq!(reassembler.push(&fragments[1]), Ok(None));
+        assert_eq!(reassembler.expired(), 1);
+        assert_eq!(reassembler.pending(), 1);
+    }
+
+    #[test]
+    fn oversized_payload_is_not_split() {
+        assert!(Fragmenter::default()
+            .split(&vec![0; MAX_FRAGMENTED_SIZE + 1])
+            .is_none());
+        let fragments = Fragmenter::default()
+            .split(&vec![0; MAX_FRAGMENTED_SIZE])
+            .unwrap();
+        assert_eq!(fragments.len(), u16::MAX as usize);
+    }
+
+    #[test]
+    fn held_bytes_are_released_when_messages_complete_or_expire() {
+        let mut fragmenter = Fragmenter::default();
+        let mut reassembler = Reassembler::new(Duration::from_millis(10));
+        let complete = fragmenter.split(&payload(2)).unwrap();
+        assert_eq!(reassembler.push(&complete[0]), Ok(None));
+        assert_eq!(reassembler.held_bytes(), FRAGMENT_SIZE);
+        // a duplicate doesn't count twice
+        assert_eq!(reassembler.push(&complete[0]), Ok(None));
+        assert_eq!(reassembler.held_bytes(), FRAGMENT_SIZE);
+        assert!(reassembler.push(&complete[1]).unwrap().is_some());
+        assert_eq!(reassembler.held_bytes(), 0);
+
+        let abandoned = fragmenter.split(&payload(2)).unwrap();
+        assert_eq!(reassembler.push(&abandoned[0]), Ok(None));
+        std::thread::sleep(Duration::from_millis(20));
+        let late = fragmenter.split(&payload(2)).unwrap();
+        assert_eq!(reassembler.push(&late[1]), Ok(None));
+        assert_eq!(reassembler.expired(), 1);
+        assert_eq!(reassembler.held_bytes(), FRAGMENT_SIZE - 1);
+    }
+
+    #[test]
+    fn going_over_the_byte_budget_gives_up_on_the_oldest() {
+        let mut fragmenter = Fragmenter::default();
+        // room for four fragments
+        let mut reassembler =
+            Reassembler::with_max_bytes(DEFAULT_REASSEMBLY_TIMEOUT, 4 * FRAGMENT_SIZE);
+        let messages: Vec<_> = (0..5)
+            .map(|_| fragmenter.split(&payload(2)).unwrap())
+            .collect();
+        for fragments in &messages[..4] {
+            assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        }
+        assert_eq!(reassembler.held_bytes(), 4 * FRAGMENT_SIZE);
+        assert_eq!(reassembler.expired(), 0);
+
+        assert_eq!(reassembler.push(&messages[4][0]), Ok(None));
+        assert_eq!(reassembler.expired(), 1);
+        assert_eq!(reassembler.pending(), 4);
+        assert_eq!(reassembler.held_bytes(), 4 * FRAGMENT_SIZE);
+
+        // the first message was given up on, so its last fragment starts
+        // over, and the second goes to make room for it
+        assert_eq!(reassembler.push(&messages[0][1]), Ok(None));
+        assert_eq!(reassembler.expired(), 2);
+        assert_eq!(reassembler.held_bytes(), 4 * FRAGMENT_SIZE - 1);
+        assert_eq!(reassembler.push(&messages[4][1]), Ok(Some(payload(2))));
+        assert_eq!(reassembler.push(&messages[1][1]), Ok(None));
+        assert_eq!(reassembler.held_bytes(), 4 * FRAGMENT_SIZE - 2);
+    }
+
+    #[test]
+    fn a_message_over_the_byte_budget_is_never_reassembled() {
+        let fragments = Fragmenter::default().split(&payload(3)).unwrap();
+        let mut reassembler =
+            Reassembler::with_max_bytes(DEFAULT_REASSEMBLY_TIMEOUT, FRAGMENT_SIZE);
+        assert_eq!(reassembler.push(&fragments[0]), Ok(None));
+        assert_eq!(reassembler.push(&fragments[1]), Ok(None));
+        assert_eq!(reassembler.expired(), 1);
+        assert_eq!(reassembler.held_bytes(), 0);
+        assert_eq!(reassembler.push(&fragments[2]), Ok(None));
+        assert_eq!(reassembler.held_bytes(), FRAGMENT_SIZE - 1);
+    }
+
+    #[test]
+    fn the_oldest_message_can_be_given_up_on() {
+        let mut fragmenter = Fragmenter::default();
+        let first = fragmenter.split(&payload(2)).unwrap();
+        let second = fragmenter.split(&payload(2)).unwrap();
+        let mut reassembler = Reassembler::default();
+        assert_eq!(reassembler.oldest_started(), None);
+        assert!(!reassembler.expire_oldest());
+
+        assert_eq!(reassembler.push(&first[0]), Ok(None));
+        let started = reassembler.oldest_started().unwrap();
+        assert_eq!(reassembler.push(&second[0]), Ok(None));
+        assert_eq!(reassembler.oldest_started(), Some(started));
+
+        assert!(reassembler.expire_oldest());
+        assert_eq!(reassembler.expired(), 1);
+        assert_eq!(reassembler.held_bytes(), FRAGMENT_SIZE);
+        assert_eq!(reassembler.push(&second[1]), Ok(Some(payload(2))));
+        assert_eq!(reassembler.oldest_started(), None);
+    }
+}
@@ -0,0 +1,114 @@
+//! Length-prefixes messages sent over a stream-like link, and splits the
+//! received bytes back into them. Every frame starts with its payload's
+//! length as a big-endian u16, so a frame can span several reads, and a
+//! read can hold several frames.
+
+use bytes::{Buf, Bytes, BytesMut};
+
+/// Size of the big-endian u16 length written before every frame
+pub const LENGTH_PREFIX_SIZE: usize = 2;
+/// Largest payload a single frame can carry
+pub const MAX_FRAME_SIZE: usize = u16::MAX as usize;
+
+/// Prefixes `payload` with its length as a big-endian u16, or returns `None`
+/// if it is too large to frame
+pub fn encode_frame(payload: &[u8]) -> Option<Vec<u8>> {
+    if payload.len() > MAX_FRAME_SIZE {
+        return None;
+    }
+    let mut frame = Vec::with_capacity(LENGTH_PREFIX_SIZE + payload.len());
+    frame.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    frame.extend_from_slice(payload);
+    Some(frame)
+}
+
+/// Accumulates received bytes and splits them back into the frames written
+/// by `encode_frame`, however the bytes were split across reads
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: BytesMut,
+}
+
+impl FrameDecoder {
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Takes the next complete frame's payload, if one has arrived, as a
+    /// slice of the received bytes rather than a copy
+    pub fn next_frame(&mut self) -> Option<Bytes> {
+        if self.buffer.len() < LENGTH_PREFIX_SIZE {
+            return None;
+        }
+        let length = u16::from_be_bytes([self.buffer[0], self.buffer[1]]) as usize;
+        if self.buffer.len() < LENGTH_PREFIX_SIZE + length {
+            return None;
+        }
+
+        self.buffer.advance(LENGTH_PREFIX_SIZE);
+        Some(self.buffer.split_to(length).freeze())
+    }
+
+    /// Bytes received that aren't part of a complete frame yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_split_across_reads() {
+        let frame = encode_frame(b"PING").unwrap();
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(&frame[..1]);
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(&frame[1..3]);
+        assert_eq!(decoder.next_frame(), None);
+        decoder.push(&frame[3..]);
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b"PING"[..]));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.buffered(), 0);
+    }
+
+    #[test]
+    fn back_to_back_frames_in_one_read() {
+        let mut bytes = encode_frame(b"one").unwrap();
+        bytes.extend(encode_frame(b"").unwrap());
+        bytes.extend(encode_frame(b"three").unwrap());
+        // and the start of a fourth
+        bytes.extend(&encode_frame(b"four").unwrap()[..3]);
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&bytes);
+
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b"one"[..]));
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b""[..]));
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b"three"[..]));
+        assert_eq!(decoder.next_frame(), None);
+        assert_eq!(decoder.buffered(), 3);
+
+        decoder.push(b"our");
+        assert_eq!(decoder.next_frame().as_deref(), Some(&b"four"[..]));
+    }
+
+    #[test]
+    fn largest_frame() {
+        let payload = vec![7u8; MAX_FRAME_SIZE];
+        let frame = encode_frame(&payload).unwrap();
+        assert_eq!(frame.len(), LENGTH_PREFIX_SIZE + MAX_FRAME_SIZE);
+
+        let mut decoder = FrameDecoder::default();
+        for chunk in frame.chunks(1500) {
+            decoder.push(chunk);
+        }
+        assert_eq!(decoder.next_frame().as_deref(), Some(&payload[..]));
+    }
+
+    #[test]
+    fn oversized_payload_is_not_framed() {
+        assert_eq!(encode_frame(&vec![0u8; MAX_FRAME_SIZE + 1]), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! # Signaling Shared
+//! The signaling protocol spoken between the naia server and its clients:
+//! where the offer is posted, and the session response the server answers
+//! it with, along with the `fragment` format large messages are split into
+//! on the data channel, the length-prefixed `framing` messages are sent in,
+//! the `compression` codec a connection can agree on, the `checksum`
+//! payloads can carry, the binary `packet` protocol and the `prometheus`
+//! text the metrics endpoints serve. Shared by the server and the native &
+//! browser clients, so a change to the schema changes them all. Nothing
+//! here depends on the platform.
+
+pub mod checksum;
+pub mod compression;
+pub mod fragment;
+pub mod framing;
+pub mod packet;
+pub mod prometheus;
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// Path of the signaling endpoint clients post their offer to, without the
+/// leading slash, as naia's `SocketConfig` takes it
+pub const SESSION_PATH: &str = "rtc_session";
+/// Path of the server's trickle ICE endpoint, without the leading slash.
+/// Clients post each `SessionCandidate` to it, under `CONNECTION_ID_HEADER`,
+/// or poll it with a GET, and get a `TrickleResponse` back.
+pub const TRICKLE_PATH: &str = "rtc_candidate";
+/// Header the connection id is sent under on the signaling & trickle
+/// requests
+pub const CONNECTION_ID_HEADER: &str = "X-Connection-Id";
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionAnswer {
+    pub sdp: String,
+    #[serde(rename = "type")]
+    pub type_str: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionCandidate {
+    pub candidate: String,
+    #[serde(rename = "sdpMLineIndex")]
+    pub sdp_m_line_index: u16,
+    #[serde(rename = "sdpMid")]
+    pub sdp_mid: String,
+}
+
+/// The session response in the shape the naia server sends it:
+///
+/// ```json
+/// {
+///     "answer": { "sdp": "v=0\r\n...", "type": "answer" },
+///     "candidate": {
+///         "candidate": "candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host",
+///         "sdpMLineIndex": 0,
+///         "sdpMid": "0"
+///     }
+/// }
+/// ```
+///
+/// A missing field fails to deserialize, as does an `sdpMLineIndex` that
+/// isn't a number from 0 to 65535; the webrtc-rs client reports these as
+/// `SignalingError::MissingField`, `WrongType` or `OutOfRange` for the
+/// field at fault, and a body that isn't JSON at all as
+/// `SignalingError::InvalidJson`. Candidates are always serialized as an
+/// array.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct SessionResponse {
+    pub answer: SessionAnswer,
+    // servers may send one candidate object, or an array of them
+    #[serde(deserialize_with = "one_or_many")]
+    pub candidate: Vec<SessionCandidate>,
+}
+
+/// What a trickle ICE endpoint answers a posted `SessionCandidate` with:
+/// the server's own candidates gathered since, as one object or an array
+/// under `candidate`. A body with no `candidate` field carries none.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TrickleResponse {
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub candidate: Vec<SessionCandidate>,
+}
+
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<SessionCandidate>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(SessionCandidate),
+        Many(Vec<SessionCandidate>),
+    }
+
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(candidate) => vec![candidate],
+        OneOrMany::Many(candidates) => candidates,
+    })
+}
@@ -0,0 +1,179 @@
+//! The binary message protocol, an alternative to the demo's `PING` & `PONG`
+//! text for game-style traffic. Every packet starts with a one byte tag;
+//! pings & pongs follow it with their sequence number and send time as
+//! big-endian u64s, and data with its payload, as-is.
+
+use std::fmt;
+
+const PING_TAG: u8 = 1;
+const PONG_TAG: u8 = 2;
+const DATA_TAG: u8 = 3;
+const DISCONNECT_TAG: u8 = 4;
+// a ping or pong's sequence number & send time
+const STAMP_SIZE: usize = 16;
+
+/// A message in the binary protocol
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Packet {
+    /// Asks for a `Pong` carrying the same sequence number & send time. A
+    /// sequence number of 0 marks a ping that isn't timed.
+    Ping {
+        sequence: u64,
+        sent_at_us: u64,
+    },
+    Pong {
+        sequence: u64,
+        sent_at_us: u64,
+    },
+    /// An application payload
+    Data(Vec<u8>),
+    /// The sender is going away, so its session can be dropped straight away
+    Disconnect,
+}
+
+/// Why bytes couldn't be read as a `Packet`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    Empty,
+    UnknownTag(u8),
+    /// A ping or pong without its whole stamp, or a disconnect with a body
+    BadLength {
+        tag: u8,
+        length: usize,
+    },
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Empty => write!(f, "empty packet"),
+            PacketError::UnknownTag(tag) => write!(f, "unknown packet tag {}", tag),
+            PacketError::BadLength { tag, length } => {
+                write!(f, "packet with tag {} can't be {} bytes long", tag, length)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PacketError {}
+
+impl Packet {
+    /// Serializes the packet, tag first
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Packet::Ping {
+                sequence,
+                sent_at_us,
+            } => encode_stamp(PING_TAG, *sequence, *sent_at_us),
+            Packet::Pong {
+                sequence,
+                sent_at_us,
+            } => encode_stamp(PONG_TAG, *sequence, *sent_at_us),
+            Packet::Data(payload) => {
+                let mut packet = Vec::with_capacity(1 + payload.len());
+                packet.push(DATA_TAG);
+                packet.extend_from_slice(payload);
+                packet
+            }
+            Packet::Disconnect => vec![DISCONNECT_TAG],
+        }
+    }
+
+    /// Reads a packet written by `encode`
+    pub fn decode(bytes: &[u8]) -> Result<Packet, PacketError> {
+        let (&tag, body) = bytes.split_first().ok_or(PacketError::Empty)?;
+        let bad_length = PacketError::BadLength {
+            tag,
+            length: bytes.len(),
+        };
+        match tag {
+            PING_TAG | PONG_TAG => {
+                if body.len() != STAMP_SIZE {
+                    return Err(bad_length);
+                }
+                let sequence = u64::from_be_bytes(body[..8].try_into().expect("8 byte slice"));
+                let sent_at_us = u64::from_be_bytes(body[8..].try_into().expect("8 byte slice"));
+                Ok(if tag == PING_TAG {
+                    Packet::Ping {
+                        sequence,
+                        sent_at_us,
+                    }
+                } else {
+                    Packet::Pong {
+                        sequence,
+                        sent_at_us,
+                    }
+                })
+            }
+            DATA_TAG => Ok(Packet::Data(body.to_vec())),
+            DISCONNECT_TAG if body.is_empty() => Ok(Packet::Disconnect),
+            DISCONNECT_TAG => Err(bad_length),
+            tag => Err(PacketError::UnknownTag(tag)),
+        }
+    }
+}
+
+fn encode_stamp(tag: u8, sequence: u64, sent_at_us: u64) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(1 + STAMP_SIZE);
+    packet.push(tag);
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(&sent_at_us.to_be_bytes());
+    packet
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let packets = [
+            Packet::Ping {
+                sequence: 1,
+                sent_at_us: u64::MAX,
+            },
+            Packet::Pong {
+                sequence: 0,
+                sent_at_us: 42,
+            },
+            Packet::Data(b"PING".to_vec()),
+            Packet::Data(Vec::new()),
+            Packet::Disconnect,
+        ];
+        for packet in packets {
+            assert_eq!(Packet::decode(&packet.encode()), Ok(packet));
+        }
+    }
+
+    #[test]
+    fn stamp_is_big_endian() {
+        let packet = Packet::Ping {
+            sequence: 1,
+            sent_at_us: 2,
+        };
+        assert_eq!(
+            packet.encode(),
+            [&[PING_TAG][..], &1u64.to_be_bytes(), &2u64.to_be_bytes()].concat()
+        );
+    }
+
+    #[test]
+    fn malformed_packets() {
+        assert_eq!(Packet::decode(&[]), Err(PacketError::Empty));
+        assert_eq!(Packet::decode(&[9]), Err(PacketError::UnknownTag(9)));
+        assert_eq!(
+            Packet::decode(&[PONG_TAG, 0, 0]),
+            Err(PacketError::BadLength {
+                tag: PONG_TAG,
+                length: 3
+            })
+        );
+        assert_eq!(
+            Packet::decode(&[DISCONNECT_TAG, 0]),
+            Err(PacketError::BadLength {
+                tag: DISCONNECT_TAG,
+                length: 2
+            })
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Writes metrics in Prometheus' text exposition format, for the server's
+//! and native client's metrics endpoints to serve.
+
+use std::fmt::Write;
+
+/// Content type of the text format, as a scrape expects it
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// A page of metrics, each written with its help & type lines
+#[derive(Default)]
+pub struct PrometheusText {
+    text: String,
+}
+
+impl PrometheusText {
+    /// A total that only goes up, e.g. packets sent
+    pub fn counter(&mut self, name: &str, help: &str, value: u64) -> &mut Self {
+        self.metric(name, help, "counter", &value.to_string())
+    }
+
+    /// A value that goes up & down, e.g. bytes per second
+    pub fn gauge(&mut self, name: &str, help: &str, value: f64) -> &mut Self {
+        self.metric(name, help, "gauge", &value.to_string())
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+
+    fn metric(&mut self, name: &str, help: &str, kind: &str, value: &str) -> &mut Self {
+        // writing to a String can't fail
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+        let _ = writeln!(self.text, "{} {}", name, value);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metrics_with_help_and_type() {
+        let mut page = PrometheusText::default();
+        page.counter("packets_total", "Packets sent", 3).gauge(
+            "bytes_per_second",
+            "Bytes sent per second",
+            1.5,
+        );
+        assert_eq!(
+            page.finish(),
+            "# HELP packets_total Packets sent\n\
+             # TYPE packets_total counter\n\
+             packets_total 3\n\
+             # HELP bytes_per_second Bytes sent per second\n\
+             # TYPE bytes_per_second gauge\n\
+             bytes_per_second 1.5\n"
+        );
+    }
+
+    #[test]
+    fn empty_page() {
+        assert_eq!(PrometheusText::default().finish(), "");
+    }
+}
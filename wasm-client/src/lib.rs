@@ -0,0 +1,137 @@
+//! # WASM Client
+//! A browser client for the demo server: the same `/rtc_session` signaling
+//! as the webrtc-rs client, over the browser's own `RTCPeerConnection`.
+//! Once the data channel opens it sends a PING every few seconds and logs
+//! every message received to the console.
+
+use js_sys::{Array, Reflect, Uint8Array};
+use signaling_shared::SessionResponse;
+use wasm_bindgen::{prelude::*, JsCast};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    console, MessageEvent, RequestInit, Response, RtcConfiguration, RtcDataChannel,
+    RtcDataChannelInit, RtcDataChannelType, RtcIceCandidateInit, RtcIceServer, RtcPeerConnection,
+    RtcSdpType, RtcSessionDescriptionInit,
+};
+
+/// Signaling endpoint of a server running locally with the default ports
+pub const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:14191/rtc_session";
+
+const DATA_CHANNEL_LABEL: &str = "data";
+const STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+const PING_INTERVAL_MS: i32 = 5000;
+
+/// Connects to the server's signaling endpoint at `server_url`, e.g.
+/// [`DEFAULT_SERVER_URL`], resolving once signaling is done. The PINGs
+/// start when the data channel opens, and keep going for as long as the
+/// page is open.
+#[wasm_bindgen]
+pub async fn connect(server_url: String) -> Result<(), JsValue> {
+    let ice_server = RtcIceServer::new();
+    ice_server.set_urls(&JsValue::from_str(STUN_SERVER));
+    let configuration = RtcConfiguration::new();
+    configuration.set_ice_servers(&Array::of1(&ice_server));
+    let peer_connection = RtcPeerConnection::new_with_configuration(&configuration)?;
+
+    // unreliable & unordered, like the native client's default channel
+    let channel_init = RtcDataChannelInit::new();
+    channel_init.set_ordered(false);
+    channel_init.set_max_retransmits(0);
+    let data_channel = peer_connection
+        .create_data_channel_with_data_channel_dict(DATA_CHANNEL_LABEL, &channel_init);
+    handle_data_channel(&data_channel);
+
+    let offer = JsFuture::from(peer_connection.create_offer()).await?;
+    let offer_sdp = Reflect::get(&offer, &JsValue::from_str("sdp"))?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("offer has no SDP"))?;
+    let offer_init = RtcSessionDescriptionInit::new(RtcSdpType::Offer);
+    offer_init.set_sdp(&offer_sdp);
+    JsFuture::from(peer_connection.set_local_description(&offer_init)).await?;
+
+    let sdp = peer_connection
+        .local_description()
+        .ok_or_else(|| JsValue::from_str("no local description after setting it"))?
+        .sdp();
+    let session_response = send_offer(&server_url, sdp).await?;
+
+    let answer_init = RtcSessionDescriptionInit::new(RtcSdpType::Answer);
+    answer_init.set_sdp(&session_response.answer.sdp);
+    JsFuture::from(peer_connection.set_remote_description(&answer_init)).await?;
+
+    for candidate in &session_response.candidate {
+        let candidate_init = RtcIceCandidateInit::new(&candidate.candidate);
+        candidate_init.set_sdp_m_line_index(Some(candidate.sdp_m_line_index));
+        candidate_init.set_sdp_mid(Some(&candidate.sdp_mid));
+        JsFuture::from(
+            peer_connection
+                .add_ice_candidate_with_opt_rtc_ice_candidate_init(Some(&candidate_init)),
+        )
+        .await?;
+    }
+    console::log_1(&"Signaling complete, waiting for the data channel".into());
+
+    Ok(())
+}
+
+// POSTs the offer, reading the server's answer & candidate. No custom
+// headers are sent (not even the connection id), since they'd need a CORS
+// preflight the naia server doesn't answer.
+async fn send_offer(server_url: &str, sdp: String) -> Result<SessionResponse, JsValue> {
+    let window = web_sys::window().ok_or_else(|| JsValue::from_str("no window"))?;
+    let request = RequestInit::new();
+    request.set_method("POST");
+    request.set_body(&JsValue::from_str(&sdp));
+
+    let response: Response = JsFuture::from(window.fetch_with_str_and_init(server_url, &request))
+        .await?
+        .dyn_into()?;
+    if !response.ok() {
+        return Err(JsValue::from_str(&format!(
+            "signaling server answered {}",
+            response.status()
+        )));
+    }
+    let body = JsFuture::from(response.text()?)
+        .await?
+        .as_string()
+        .ok_or_else(|| JsValue::from_str("session response isn't text"))?;
+    serde_json::from_str(&body)
+        .map_err(|error| JsValue::from_str(&format!("invalid session response: {}", error)))
+}
+
+// PINGs once the channel opens and logs whatever arrives. The callbacks live
+// as long as the page, so they're leaked rather than kept.
+fn handle_data_channel(data_channel: &RtcDataChannel) {
+    data_channel.set_binary_type(RtcDataChannelType::Arraybuffer);
+
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new(|event: MessageEvent| {
+        let data = event.data();
+        let message = match data.as_string() {
+            Some(text) => text,
+            None => String::from_utf8_lossy(&Uint8Array::new(&data).to_vec()).into_owned(),
+        };
+        console::log_1(&format!("Client recv <- {}", message).into());
+    });
+    data_channel.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    let ping_channel = data_channel.clone();
+    let onopen = Closure::<dyn FnMut()>::new(move || {
+        console::log_1(&"Data channel open".into());
+        let channel = ping_channel.clone();
+        let ping = Closure::<dyn FnMut()>::new(move || match channel.send_with_u8_array(b"PING") {
+            Ok(()) => console::log_1(&"Client send -> PING".into()),
+            Err(error) => console::error_2(&"Failed to send PING:".into(), &error),
+        });
+        if let Some(window) = web_sys::window() {
+            let _ = window.set_interval_with_callback_and_timeout_and_arguments_0(
+                ping.as_ref().unchecked_ref(),
+                PING_INTERVAL_MS,
+            );
+        }
+        ping.forget();
+    });
+    data_channel.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+}
@@ -0,0 +1,238 @@
+use anyhow::Result;
+use bytes::Bytes;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::{client_stats::ClientStats, transport::Transport};
+
+/// Prefix of a message the server should acknowledge, followed by
+/// `<id> <body>`
+pub const ACK_REQUEST_PREFIX: &str = "MSG ";
+/// Prefix of the server's acknowledgement, followed by the message's id
+pub const ACK_PREFIX: &str = "ACK ";
+
+// shortest time between checks for messages due a retransmit
+const MIN_RETRANSMIT_CHECK: Duration = Duration::from_millis(1);
+
+/// When unacknowledged messages are sent again, and when they're given up on
+#[derive(Clone, Copy, Debug)]
+pub struct AckSettings {
+    /// How long to wait for an ACK before retransmitting
+    pub timeout: Duration,
+    /// Retransmits of a message before it's dropped as undeliverable
+    pub max_retransmits: u32,
+}
+
+struct Outstanding {
+    message: Bytes,
+    sent_at: Instant,
+    retransmits: u32,
+}
+
+/// Messages sent with an id that the server hasn't acknowledged yet, shared
+/// between the loop sending them and the one reading the ACKs
+#[derive(Default)]
+pub struct AckTracker {
+    next_id: AtomicU64,
+    outstanding: Mutex<HashMap<u64, Outstanding>>,
+}
+
+impl AckTracker {
+    /// Wraps `payload` as `MSG <id> <payload>`, tracking it until the server
+    /// acknowledges the id
+    pub fn track(&self, payload: &[u8]) -> Bytes {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut message = format!("{}{} ", ACK_REQUEST_PREFIX, id).into_bytes();
+        message.extend_from_slice(payload);
+        let message = Bytes::from(message);
+
+        self.lock().insert(
+            id,
+            Outstanding {
+                message: message.clone(),
+                sent_at: Instant::now(),
+                retransmits: 0,
+            },
+        );
+        message
+    }
+
+    /// Stops tracking `id`, returning whether it was still outstanding; a
+    /// retransmitted message can be acknowledged more than once
+    pub fn acknowledge(&self, id: u64) -> bool {
+        self.lock().remove(&id).is_some()
+    }
+
+    /// Number of messages waiting on an ACK
+    pub fn outstanding(&self) -> usize {
+        self.lock().len()
+    }
+
+    // takes the messages that waited too long & marks them resent, dropping
+    // those out of retransmits; returns what to resend and the ids dropped
+    fn take_due(&self, settings: AckSettings) -> (Vec<Bytes>, Vec<u64>) {
+        let now = Instant::now();
+        let mut resend = Vec::new();
+        let mut dropped = Vec::new();
+        self.lock().retain(|id, outstanding| {
+            if now.duration_since(outstanding.sent_at) < settings.timeout {
+                return true;
+            }
+            if outstanding.retransmits >= settings.max_retransmits {
+                dropped.push(*id);
+                return false;
+            }
+            outstanding.retransmits += 1;
+            outstanding.sent_at = now;
+            resend.push(outstanding.message.clone());
+            true
+        });
+        (resend, dropped)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Outstanding>> {
+        self.outstanding.lock().expect("ack tracker lock poisoned")
+    }
+}
+
+/// Reads the id out of an `ACK <id>` message
+pub fn parse_ack(message: &[u8]) -> Option<u64> {
+    std::str::from_utf8(message.strip_prefix(ACK_PREFIX.as_bytes())?)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Resends every tracked message that goes unacknowledged for
+/// `settings.timeout`, until the transport closes or the client shuts down
+pub async fn retransmit_loop<T: Transport + ?Sized>(
+    transport: Arc<T>,
+    acks: Arc<AckTracker>,
+    stats: Arc<ClientStats>,
+    settings: AckSettings,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let check_interval = (settings.timeout / 4).max(MIN_RETRANSMIT_CHECK);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(check_interval) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+
+        let (resend, dropped) = acks.take_due(settings);
+        for id in dropped {
+            stats.record_ack_dropped();
+            warn!(
+                "Message {} unacknowledged after {} retransmits, giving up",
+                id, settings.max_retransmits
+            );
+        }
+        for message in resend {
+            if let Err(err) = transport.send(&message).await {
+                info!("Datachannel closed; Exit the retransmit_loop: {}", err);
+                return Ok(());
+            }
+            stats.record_retransmit();
+            debug!("Retransmitted '{}'", String::from_utf8_lossy(&message));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    #[test]
+    fn tracked_messages_are_wrapped_with_rising_ids() {
+        let acks = AckTracker::default();
+        assert_eq!(&acks.track(b"one")[..], b"MSG 1 one");
+        assert_eq!(&acks.track(b"")[..], b"MSG 2 ");
+        assert_eq!(acks.outstanding(), 2);
+
+        assert!(acks.acknowledge(1));
+        // a retransmit's second ACK finds nothing left to acknowledge
+        assert!(!acks.acknowledge(1));
+        assert!(!acks.acknowledge(7));
+        assert_eq!(acks.outstanding(), 1);
+    }
+
+    #[test]
+    fn acks_are_parsed() {
+        assert_eq!(parse_ack(b"ACK 42"), Some(42));
+        assert_eq!(parse_ack(b"ACK 42\n"), Some(42));
+        assert_eq!(parse_ack(b"ACK"), None);
+        assert_eq!(parse_ack(b"ACK x"), None);
+        assert_eq!(parse_ack(b"PONG 42"), None);
+    }
+
+    #[tokio::test]
+    async fn unacknowledged_messages_are_retransmitted_then_dropped() {
+        let (near, far) = MemoryTransport::pair();
+        let acks = Arc::new(AckTracker::default());
+        let stats = Arc::new(ClientStats::default());
+        let settings = AckSettings {
+            timeout: Duration::from_millis(20),
+            max_retransmits: 2,
+        };
+        let (_shutdown_sender, shutdown) = watch::channel(false);
+        let sent = acks.track(b"hello");
+        let acked = acks.track(b"acked");
+        let retransmits = tokio::spawn(retransmit_loop(
+            Arc::new(near),
+            Arc::clone(&acks),
+            Arc::clone(&stats),
+            settings,
+            shutdown,
+        ));
+        acks.acknowledge(2);
+
+        let mut buffer = [0u8; 64];
+        for _ in 0..settings.max_retransmits {
+            let length = tokio::time::timeout(Duration::from_secs(1), far.recv(&mut buffer))
+                .await
+                .expect("retransmitted")
+                .unwrap();
+            assert_eq!(&buffer[..length], &sent[..]);
+            assert_ne!(&buffer[..length], &acked[..]);
+        }
+        while acks.outstanding() > 0 {
+            tokio::time::sleep(settings.timeout).await;
+        }
+
+        assert_eq!(stats.retransmits(), 2);
+        assert_eq!(stats.acks_dropped(), 1);
+        retransmits.abort();
+    }
+
+    #[tokio::test]
+    async fn retransmit_loop_stops_on_shutdown() {
+        let (near, _far) = MemoryTransport::pair();
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let settings = AckSettings {
+            timeout: Duration::from_secs(60),
+            max_retransmits: 1,
+        };
+        let retransmits = tokio::spawn(retransmit_loop(
+            Arc::new(near),
+            Arc::new(AckTracker::default()),
+            Arc::new(ClientStats::default()),
+            settings,
+            shutdown,
+        ));
+        shutdown_sender.send(true).unwrap();
+        tokio::time::timeout(Duration::from_secs(1), retransmits)
+            .await
+            .expect("loop exits")
+            .unwrap()
+            .unwrap();
+    }
+}
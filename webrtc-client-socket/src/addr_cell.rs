@@ -0,0 +1,95 @@
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+use tokio::sync::watch;
+
+/// Address of the server's data channel endpoint, as learned from its ICE
+/// candidate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ServerAddr {
+    /// The server's candidate has been received
+    Found(SocketAddr),
+    /// Still waiting on the server's candidate
+    Finding,
+}
+
+/// Shared cell holding the server's address once it is known, which can be
+/// subscribed to for every change
+#[derive(Clone)]
+pub struct AddrCell {
+    sender: Arc<watch::Sender<ServerAddr>>,
+    // kept so the channel stays open, and sending never fails, whether
+    // anyone has subscribed or not
+    receiver: watch::Receiver<ServerAddr>,
+}
+
+impl Default for AddrCell {
+    fn default() -> Self {
+        AddrCell::holding(ServerAddr::Finding)
+    }
+}
+
+impl AddrCell {
+    /// Creates a cell that already holds `addr`, skipping ICE resolution
+    pub fn with_addr(addr: SocketAddr) -> Self {
+        AddrCell::holding(ServerAddr::Found(addr))
+    }
+
+    fn holding(addr: ServerAddr) -> Self {
+        let (sender, receiver) = watch::channel(addr);
+        AddrCell {
+            sender: Arc::new(sender),
+            receiver,
+        }
+    }
+
+    /// Forces the cell to `addr`, whatever state it was in
+    pub fn set(&self, addr: SocketAddr) {
+        let _ = self.sender.send(ServerAddr::Found(addr));
+    }
+
+    /// Stores the address from the server's ICE candidate, e.g.
+    /// `candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host`; returns
+    /// whether the candidate held an address
+    pub fn receive_candidate(&self, candidate: &str) -> bool {
+        match parse_candidate(candidate) {
+            Some(addr) => {
+                self.set(addr);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self) -> ServerAddr {
+        *self.receiver.borrow()
+    }
+
+    /// Receives the address as it is now, then every time it's stored,
+    /// e.g. to show the connection's status as it resolves
+    pub fn subscribe(&self) -> watch::Receiver<ServerAddr> {
+        self.receiver.clone()
+    }
+
+    /// Waits until the server's address is known, returning straight away if
+    /// it already is
+    pub async fn wait_resolved(&self) -> SocketAddr {
+        let mut receiver = self.subscribe();
+        loop {
+            if let ServerAddr::Found(addr) = *receiver.borrow() {
+                return addr;
+            }
+            // self holds the sender, so it can't be dropped while waiting
+            receiver.changed().await.expect("addr cell sender dropped");
+        }
+    }
+}
+
+// the connection address & port are the 5th & 6th fields of a candidate
+fn parse_candidate(candidate: &str) -> Option<SocketAddr> {
+    let mut fields = candidate.split_whitespace().skip(4);
+    let ip = fields.next()?.parse::<IpAddr>().ok()?;
+    let port = fields.next()?.parse::<u16>().ok()?;
+    Some(SocketAddr::new(ip, port))
+}
@@ -0,0 +1,38 @@
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use signaling_shared::packet::Packet;
+use tokio::time::{Duration, Instant};
+
+use crate::{client_stats::ClientStats, transport::Transport};
+
+/// Writes `count` PINGs back to back, ignoring the write loop's pacing, and
+/// returns how long writing them took. With `binary` they're untimed binary
+/// `Ping`s.
+pub async fn send_burst<T: Transport + ?Sized>(
+    transport: &T,
+    stats: &ClientStats,
+    count: u32,
+    binary: bool,
+) -> Result<Duration> {
+    let started = Instant::now();
+    let message = if binary {
+        let ping = Packet::Ping {
+            sequence: 0,
+            sent_at_us: 0,
+        };
+        Bytes::from(ping.encode())
+    } else {
+        Bytes::from_static(b"PING")
+    };
+    for sent in 0..count {
+        let length = transport
+            .send(&message)
+            .await
+            .with_context(|| format!("burst failed after {} of {} messages", sent, count))?;
+        stats.record_sent(length);
+    }
+
+    let elapsed = started.elapsed();
+    info!("Burst of {} messages written in {:?}", count, elapsed);
+    Ok(elapsed)
+}
@@ -0,0 +1,48 @@
+use std::sync::Arc;
+use tokio::time::Duration;
+use webrtc::ice_transport::ice_candidate_pair::RTCIceCandidatePair;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::event_log::{ClientEvent, EventLog};
+
+/// Logs the selected ICE candidate pair every `interval` until the peer
+/// connection closes, warning when it changes since that means the network
+/// path migrated, e.g. a phone roaming from wifi to cellular
+pub async fn monitor_candidate_pair(
+    peer_connection: Arc<RTCPeerConnection>,
+    interval: Duration,
+    events: EventLog,
+) {
+    let mut selected: Option<RTCIceCandidatePair> = None;
+    loop {
+        tokio::time::sleep(interval).await;
+        if peer_connection.connection_state() == RTCPeerConnectionState::Closed {
+            return;
+        }
+
+        let current = peer_connection
+            .sctp()
+            .transport()
+            .ice_transport()
+            .get_selected_candidate_pair()
+            .await;
+        match (&selected, &current) {
+            (_, None) => info!("No ICE candidate pair selected yet"),
+            (Some(previous), Some(pair)) if previous != pair => {
+                warn!(
+                    "Selected ICE candidate pair changed, network migrated: {} -> {}",
+                    previous, pair
+                );
+                events.record(ClientEvent::CandidatePairChanged {
+                    from: previous.to_string(),
+                    to: pair.to_string(),
+                });
+            }
+            (_, Some(pair)) => info!("Selected ICE candidate pair: {}", pair),
+        }
+        if current.is_some() {
+            selected = current;
+        }
+    }
+}
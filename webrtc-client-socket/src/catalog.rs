@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+
+use crate::{
+    client::{ChannelSettings, SIGNALING_PROTOCOLS},
+    compression::COMPRESSION_CODECS,
+    quality::QualityThresholds,
+    reconnect::ReconnectReason,
+    slow_start::RampShape,
+};
+
+/// Writes every supported option value with a one-line description, grouped
+/// under a heading per kind, for the client's `list` command
+pub fn write_catalog(out: &mut impl Write) -> io::Result<()> {
+    let reasons: Vec<_> = ReconnectReason::ALL
+        .iter()
+        .map(|reason| (reason.name(), reason.description()))
+        .collect();
+    let sections: [(&str, &[(&str, &str)]); 6] = [
+        (
+            "Data channel profiles (--channel <label>:<profile>)",
+            ChannelSettings::PROFILES,
+        ),
+        ("Compression codecs (--compress)", COMPRESSION_CODECS),
+        ("Signaling protocols", SIGNALING_PROTOCOLS),
+        ("Quality thresholds", QualityThresholds::FLAGS),
+        ("Slow start shapes (--slow-start-shape)", RampShape::SHAPES),
+        ("Reconnect reasons (--event-log)", &reasons),
+    ];
+
+    for (index, (heading, values)) in sections.iter().enumerate() {
+        if index > 0 {
+            writeln!(out)?;
+        }
+        writeln!(out, "{}:", heading)?;
+        let width = values.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, description) in values.iter() {
+            writeln!(out, "  {:width$}  {}", name, description, width = width)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalog() -> String {
+        let mut out = Vec::new();
+        write_catalog(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn every_listed_value_is_accepted() {
+        for (name, _) in ChannelSettings::PROFILES {
+            assert!(ChannelSettings::profile(name).is_some(), "{}", name);
+        }
+        for (name, _) in RampShape::SHAPES {
+            assert!(name.parse::<RampShape>().is_ok(), "{}", name);
+        }
+        for (name, _) in COMPRESSION_CODECS {
+            assert!(signaling_shared::compression::COMPRESSION_OFFER.ends_with(name.as_bytes()));
+        }
+        for reason in ReconnectReason::ALL {
+            assert_eq!(
+                serde_json::to_value(reason).unwrap(),
+                serde_json::json!(reason.name())
+            );
+        }
+    }
+
+    #[test]
+    fn values_are_listed_under_their_heading_and_aligned() {
+        let catalog = catalog();
+        assert!(catalog.starts_with("Data channel profiles (--channel <label>:<profile>):\n"));
+        assert!(catalog.contains(
+            "  reliable    ordered & retransmitted until delivered\n  unreliable  unordered"
+        ));
+        assert!(catalog.contains("\n\nCompression codecs (--compress):\n  deflate  "));
+        assert!(catalog.contains("  --max-rtt-ms  highest mean round trip time allowed\n"));
+        assert!(catalog.contains("  keepalive_timeout     "));
+        assert!(catalog.ends_with('\n'));
+    }
+}
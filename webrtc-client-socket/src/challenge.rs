@@ -0,0 +1,109 @@
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+use tokio::time::{self, Duration};
+
+use crate::transport::Transport;
+
+/// Prefix of the message carrying the client's nonce
+pub const CHALLENGE_PREFIX: &str = "CHALLENGE ";
+/// Prefix of the server's reply carrying HMAC-SHA256(secret, nonce)
+pub const CHALLENGE_RESPONSE_PREFIX: &str = "CHALLENGE_RESPONSE ";
+
+const NONCE_SIZE: usize = 16;
+
+/// Sends a random nonce over the transport and waits for the server to answer
+/// with HMAC-SHA256(secret, nonce), proving it knows the shared secret.
+/// Messages received before the answer, up to `max_message_size` bytes
+/// each, are discarded.
+pub async fn verify_server<T: Transport + ?Sized>(
+    transport: &T,
+    secret: &[u8],
+    timeout: Duration,
+    max_message_size: usize,
+) -> Result<()> {
+    let nonce = hex::encode(rand::random::<[u8; NONCE_SIZE]>());
+
+    let challenge = format!("{}{}", CHALLENGE_PREFIX, nonce);
+    transport
+        .send(&Bytes::from(challenge))
+        .await
+        .context("failed to send challenge")?;
+
+    let signature = time::timeout(timeout, receive_response(transport, max_message_size))
+        .await
+        .context("timed out waiting for the challenge response")??;
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).context("invalid shared secret")?;
+    mac.update(nonce.as_bytes());
+    if mac.verify(&signature).is_err() {
+        bail!("server failed the shared-secret challenge");
+    }
+
+    Ok(())
+}
+
+async fn receive_response<T: Transport + ?Sized>(
+    transport: &T,
+    max_message_size: usize,
+) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; max_message_size];
+    loop {
+        let length = transport
+            .recv(&mut buffer)
+            .await
+            .context("channel closed before the challenge response arrived")?;
+
+        let message = String::from_utf8_lossy(&buffer[..length]);
+        if let Some(signature) = message.strip_prefix(CHALLENGE_RESPONSE_PREFIX) {
+            return hex::decode(signature.trim()).context("malformed challenge response");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    const SECRET: &[u8] = b"secret";
+
+    // answers the challenge the way the server does, after a message larger
+    // than a packet
+    async fn answer(server: MemoryTransport, secret: &'static [u8]) {
+        let mut buffer = vec![0u8; 1500];
+        let length = server.recv(&mut buffer).await.unwrap();
+        let message = String::from_utf8(buffer[..length].to_vec()).unwrap();
+        let nonce = message.strip_prefix(CHALLENGE_PREFIX).unwrap();
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret).unwrap();
+        mac.update(nonce.as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        server.send(&Bytes::from(vec![b'x'; 4000])).await.unwrap();
+        let response = format!("{}{}", CHALLENGE_RESPONSE_PREFIX, signature);
+        server.send(&Bytes::from(response)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_right_secret_passes() {
+        let (client, server) = MemoryTransport::pair();
+        tokio::spawn(answer(server, SECRET));
+        verify_server(&client, SECRET, Duration::from_secs(1), 4096)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_wrong_secret_fails() {
+        let (client, server) = MemoryTransport::pair();
+        tokio::spawn(answer(server, b"other"));
+        let error = verify_server(&client, SECRET, Duration::from_secs(1), 4096)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "server failed the shared-secret challenge"
+        );
+    }
+}
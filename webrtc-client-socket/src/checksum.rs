@@ -0,0 +1,85 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use signaling_shared::checksum::append_checksum;
+use std::sync::Arc;
+
+use crate::transport::Transport;
+
+/// Wraps a Transport so every message sent carries the CRC32 of
+/// `signaling_shared::checksum`, for a server validating them. It has to sit
+/// above any `CompressedTransport`, since the server checks the checksum of
+/// what it inflates. Received messages come through as-is; the server
+/// doesn't checksum its own.
+pub struct ChecksumTransport<T: Transport + ?Sized> {
+    inner: Arc<T>,
+}
+
+impl<T: Transport + ?Sized> ChecksumTransport<T> {
+    pub fn new(inner: Arc<T>) -> Self {
+        ChecksumTransport { inner }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for ChecksumTransport<T> {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        self.inner
+            .send(&Bytes::from(append_checksum(payload)))
+            .await?;
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        self.inner.recv(buffer).await
+    }
+
+    fn buffered_amount(&self) -> usize {
+        self.inner.buffered_amount()
+    }
+
+    async fn buffered_amount_low(&self, threshold: usize) {
+        self.inner.buffered_amount_low(threshold).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compression::CompressedTransport, transport::MemoryTransport};
+    use signaling_shared::checksum::{strip_checksum, CHECKSUM_SIZE};
+
+    #[tokio::test]
+    async fn sent_messages_carry_their_checksum() {
+        let (near, far) = MemoryTransport::pair();
+        let near = ChecksumTransport::new(Arc::new(near));
+        assert_eq!(near.send(&Bytes::from_static(b"PING")).await.unwrap(), 4);
+
+        let mut buffer = [0u8; 64];
+        let length = far.recv(&mut buffer).await.unwrap();
+        assert_eq!(length, 4 + CHECKSUM_SIZE);
+        assert_eq!(strip_checksum(&buffer[..length]), Some(&b"PING"[..]));
+
+        // and replies arrive untouched
+        far.send(&Bytes::from_static(b"PONG")).await.unwrap();
+        let length = near.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"PONG");
+    }
+
+    #[tokio::test]
+    async fn the_checksum_is_inside_the_compression() {
+        let (near, far) = MemoryTransport::pair();
+        let near = ChecksumTransport::new(Arc::new(CompressedTransport::new(Arc::new(near))));
+        let far = CompressedTransport::new(Arc::new(far));
+        let payload = Bytes::from(b"PING ".repeat(100));
+        near.send(&payload).await.unwrap();
+
+        let mut buffer = [0u8; 1024];
+        let length = far.recv(&mut buffer).await.unwrap();
+        assert_eq!(strip_checksum(&buffer[..length]), Some(&payload[..]));
+    }
+}
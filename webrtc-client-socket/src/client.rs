@@ -0,0 +1,1650 @@
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{Bytes, BytesMut};
+use reqwest::{Certificate, Client as HttpClient, Url};
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Duration;
+use tracing::{field, Instrument, Span};
+use webrtc::api::setting_engine::SettingEngine;
+use webrtc::api::APIBuilder;
+use webrtc::data::data_channel::DataChannel;
+use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::data_channel::{OnOpenHdlrFn, RTCDataChannel};
+use webrtc::dtls_transport::dtls_role::DTLSRole;
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::math_rand_alpha;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::{
+    addr_cell::{AddrCell, ServerAddr},
+    client_stats::{ClientStats, ConnectionStats},
+    connection_events::{ConnectionEvent, ConnectionEvents},
+    connection_status::ConnectionStatus,
+    event_log::{ClientEvent, EventLog},
+    fragmenting::FragmentedTransport,
+    framing::FramedTransport,
+    ice_servers::{GatheredCandidates, DEFAULT_STUN_SERVER},
+    loops::{DEFAULT_MAX_BUFFERED_AMOUNT, DEFAULT_MAX_MESSAGE_SIZE},
+    reconnect::ReconnectReason,
+    send_queue::{SendQueue, DEFAULT_SEND_QUEUE_CAPACITY},
+    signaling::{
+        apply_session_response, get_session_response, read_capped_body, ResponseFieldPaths,
+        SignalingError,
+    },
+    transport::Transport,
+    trickle::{trickle_candidates, TrickleEndpoint},
+};
+
+pub use signaling_shared::{fragment::DEFAULT_MAX_REASSEMBLY_BYTES, CONNECTION_ID_HEADER};
+/// Label of the main data channel, the one `Client::send` & `Client::incoming`
+/// use
+pub const DATA_CHANNEL_LABEL: &str = "data";
+/// Length of a randomly generated connection id
+pub const CONNECTION_ID_LENGTH: usize = 16;
+/// Largest signaling response body read by default, in bytes
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 1024 * 1024;
+
+/// Signaling attempts made by default before giving up
+pub const DEFAULT_SIGNALING_ATTEMPTS: u32 = 5;
+/// Default time limit on each signaling attempt, response body included
+pub const DEFAULT_SIGNALING_TIMEOUT: Duration = Duration::from_secs(10);
+/// Default limit on waiting for ICE gathering in non-trickle mode
+pub const DEFAULT_GATHERING_TIMEOUT: Duration = Duration::from_secs(5);
+/// Name & description of every way of signaling the server
+pub const SIGNALING_PROTOCOLS: &[(&str, &str)] = &[
+    (
+        "trickle",
+        "posts the offer at once, then each later candidate to --trickle-url, the default",
+    ),
+    (
+        "non-trickle",
+        "posts the offer once ICE gathering completes, with --non-trickle",
+    ),
+];
+
+// pause after the first failed signaling attempt, doubled after each one
+const SIGNALING_RETRY_DELAY: Duration = Duration::from_millis(500);
+const MAX_SIGNALING_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// received messages `incoming` holds before it stops reading
+const INCOMING_CAPACITY: usize = 64;
+// `incoming` copies messages into chunks this big, handing each out as a
+// slice of one, so small messages don't need an allocation apiece
+const INCOMING_CHUNK_SIZE: usize = 64 * 1024;
+
+// the chunk `incoming` is copying messages into
+#[derive(Default)]
+struct IncomingChunks {
+    chunk: BytesMut,
+}
+
+impl IncomingChunks {
+    // copies `message` into the chunk, starting a new one if it's full;
+    // `reserve` reuses the old chunk once every message in it is dropped
+    fn copy(&mut self, message: &[u8]) -> Bytes {
+        if self.chunk.capacity() < message.len() {
+            self.chunk.reserve(INCOMING_CHUNK_SIZE.max(message.len()));
+        }
+        self.chunk.extend_from_slice(message);
+        self.chunk.split().freeze()
+    }
+}
+
+/// How the data channel delivers messages. With neither limit set, lost
+/// messages are retransmitted until they arrive.
+#[derive(Clone, Copy, Debug)]
+pub struct ChannelSettings {
+    /// Whether messages are delivered in the order they were sent
+    pub ordered: bool,
+    /// Retransmit a lost message at most this many times
+    pub max_retransmits: Option<u16>,
+    /// Stop retransmitting a message this many milliseconds after sending it
+    pub max_packet_lifetime: Option<u16>,
+}
+
+impl Default for ChannelSettings {
+    // unordered & never retransmitted, like the naia client
+    fn default() -> Self {
+        ChannelSettings {
+            ordered: false,
+            max_retransmits: Some(0),
+            max_packet_lifetime: None,
+        }
+    }
+}
+
+impl ChannelSettings {
+    /// Ordered & retransmitted until delivered, for control messages
+    pub fn reliable() -> Self {
+        ChannelSettings {
+            ordered: true,
+            max_retransmits: None,
+            max_packet_lifetime: None,
+        }
+    }
+
+    /// Unordered & never retransmitted, for state that's soon superseded
+    pub fn unreliable() -> Self {
+        ChannelSettings::default()
+    }
+
+    /// Retransmitted until delivered but unordered, so one lost message
+    /// doesn't hold up the rest of a transfer
+    pub fn bulk() -> Self {
+        ChannelSettings {
+            ordered: false,
+            max_retransmits: None,
+            max_packet_lifetime: None,
+        }
+    }
+
+    /// Name & description of every profile `profile` knows
+    pub const PROFILES: &'static [(&'static str, &'static str)] = &[
+        ("reliable", "ordered & retransmitted until delivered"),
+        ("unreliable", "unordered & never retransmitted, the default"),
+        ("bulk", "unordered but retransmitted until delivered"),
+    ];
+
+    /// The settings named `reliable`, `unreliable` or `bulk`
+    pub fn profile(name: &str) -> Option<Self> {
+        match name {
+            "reliable" => Some(ChannelSettings::reliable()),
+            "unreliable" => Some(ChannelSettings::unreliable()),
+            "bulk" => Some(ChannelSettings::bulk()),
+            _ => None,
+        }
+    }
+
+    /// Fails if both retransmit limits are set, which WebRTC doesn't allow
+    pub fn validate(&self) -> Result<()> {
+        if self.max_retransmits.is_some() && self.max_packet_lifetime.is_some() {
+            bail!("a data channel can't limit both its retransmits and its packet lifetime");
+        }
+        Ok(())
+    }
+}
+
+/// A data channel opened alongside the main one, e.g. a reliable channel for
+/// control messages next to unreliable state updates
+#[derive(Clone, Debug)]
+pub struct LabeledChannel {
+    /// Unique among the client's channels, and never `data`
+    pub label: String,
+    pub settings: ChannelSettings,
+}
+
+/// A message from `Client::route_incoming`, with the label of the channel it
+/// arrived on
+#[derive(Clone, Debug)]
+pub struct ChannelMessage {
+    pub label: String,
+    pub payload: Bytes,
+}
+
+/// Everything Client::connect needs to reach the server
+#[derive(Clone)]
+pub struct ClientConfig {
+    /// Signaling endpoint of the server, e.g. `http://127.0.0.1:14191/rtc_session`
+    pub server_url: Url,
+    /// Sent with the signaling request, to correlate client & server logs
+    pub connection_id: String,
+    pub ice_servers: Vec<RTCIceServer>,
+    /// DTLS role the client takes when answering: `Client` (the default),
+    /// `Server`, or `Auto` to leave it to webrtc
+    pub answering_dtls_role: DTLSRole,
+    pub response_field_paths: ResponseFieldPaths,
+    /// Largest signaling response body to read, in bytes
+    pub max_response_bytes: usize,
+    /// How many times to try the signaling request before giving up
+    pub signaling_attempts: u32,
+    /// Time limit on each signaling attempt
+    pub signaling_timeout: Duration,
+    /// Wait up to this long for ICE gathering to complete before sending
+    /// the offer, so it carries every local candidate (non-trickle mode).
+    /// `None` sends the offer straight away, relying on the server's
+    /// candidate.
+    pub gathering_timeout: Option<Duration>,
+    /// Trickle ICE endpoint each local candidate is posted to as it's
+    /// gathered, once the server has answered the offer. Whatever
+    /// candidates it answers with are added too.
+    pub trickle_url: Option<Url>,
+    /// Extra CA certificate trusted for an https signaling endpoint, e.g. for
+    /// a self-signed development server
+    pub ca_certificate: Option<Certificate>,
+    /// Accept any certificate from an https signaling endpoint. Only for
+    /// development, since it allows anyone to impersonate the server.
+    pub insecure_skip_verify: bool,
+    /// Send & receive every message as a length-prefixed frame
+    pub framed: bool,
+    /// Split every message into fragments that each fit a single data
+    /// channel message, and reassemble received ones; inside the frames
+    /// when `framed` is set too
+    pub fragmented: bool,
+    /// Most fragment bytes each channel holds for partly received messages
+    /// when `fragmented` is set; past it, the oldest are given up on
+    pub max_reassembly_bytes: usize,
+    pub channel: ChannelSettings,
+    /// Extra data channels to open. The connection only waits for the main
+    /// channel, so one of these failing to open doesn't end it.
+    pub extra_channels: Vec<LabeledChannel>,
+    /// Largest message `Client::incoming` can receive, in bytes
+    pub max_message_size: usize,
+    /// Messages each channel's send queue holds before `Client::send` starts
+    /// dropping them
+    pub send_queue_capacity: usize,
+    /// The send queue waits for the data channel to buffer no more than this
+    /// many bytes before writing to it
+    pub max_buffered_amount: usize,
+    /// Kept up to date with the peer connection's state
+    pub status: Arc<ConnectionStatus>,
+    /// Counts what `Client::send` & `Client::incoming` move, and whatever
+    /// else is given it, e.g. the read & write loops
+    pub stats: Arc<ClientStats>,
+    pub events: EventLog,
+    /// Where connecting, connected, disconnected, candidate & error events
+    /// are delivered, see `ConnectionEvents::channel`
+    pub connection_events: ConnectionEvents,
+}
+
+impl ClientConfig {
+    /// Defaults for connecting to `server_url`: a random connection id and
+    /// Google's public STUN server
+    pub fn new(server_url: Url) -> Self {
+        ClientConfig {
+            server_url,
+            connection_id: math_rand_alpha(CONNECTION_ID_LENGTH),
+            ice_servers: vec![RTCIceServer {
+                urls: vec![DEFAULT_STUN_SERVER.to_string()],
+                ..Default::default()
+            }],
+            answering_dtls_role: DTLSRole::Client,
+            response_field_paths: ResponseFieldPaths::default(),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            signaling_attempts: DEFAULT_SIGNALING_ATTEMPTS,
+            signaling_timeout: DEFAULT_SIGNALING_TIMEOUT,
+            gathering_timeout: None,
+            trickle_url: None,
+            ca_certificate: None,
+            insecure_skip_verify: false,
+            framed: false,
+            fragmented: false,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            channel: ChannelSettings::default(),
+            extra_channels: Vec::new(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            max_buffered_amount: DEFAULT_MAX_BUFFERED_AMOUNT,
+            status: Arc::new(ConnectionStatus::default()),
+            stats: Arc::new(ClientStats::default()),
+            events: EventLog::default(),
+            connection_events: ConnectionEvents::default(),
+        }
+    }
+}
+
+// how far a data channel has got with opening
+#[derive(Clone)]
+enum ChannelState {
+    Opening,
+    Open { transport: Arc<SendQueue> },
+    Failed(String),
+}
+
+// how an opened channel's transport is put together
+#[derive(Clone)]
+struct TransportSettings {
+    framed: bool,
+    fragmented: bool,
+    max_reassembly_bytes: usize,
+    send_queue_capacity: usize,
+    max_buffered_amount: usize,
+    stats: Arc<ClientStats>,
+}
+
+/// An open data channel to the server, along with the peer connection it
+/// runs over and any extra channels opened next to it
+pub struct Client {
+    peer_connection: Arc<RTCPeerConnection>,
+    // every channel by label, the main one included
+    channels: HashMap<String, watch::Receiver<ChannelState>>,
+    transport: Arc<SendQueue>,
+    addr_cell: AddrCell,
+    stats: Arc<ClientStats>,
+    status: Arc<ConnectionStatus>,
+    events: EventLog,
+    span: Span,
+    max_message_size: usize,
+    ended: watch::Receiver<Option<Ended>>,
+}
+
+impl Client {
+    /// Signals the server & waits for the data channel to open. The peer
+    /// connection is closed again if that fails.
+    pub async fn connect(config: ClientConfig) -> Result<Client> {
+        match Client::connect_until(config, future::pending()).await? {
+            Some(client) => Ok(client),
+            None => unreachable!("a pending future never cancels connecting"),
+        }
+    }
+
+    /// Like `connect`, but gives up once `cancel` resolves, e.g. on Ctrl-C,
+    /// returning `None`. The peer connection is closed first, however far
+    /// signaling had got.
+    pub async fn connect_until(
+        config: ClientConfig,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<Client>> {
+        validate_channels(&config)?;
+        // attributes everything logged about this connection to it, with the
+        // server's address once it's known
+        let span = info_span!("peer_connection", server_addr = field::Empty);
+        let connection_events = config.connection_events.clone();
+        connection_events.send(ConnectionEvent::Connecting);
+        let peer_connection =
+            match new_peer_connection(&config.ice_servers, config.answering_dtls_role)
+                .instrument(span.clone())
+                .await
+            {
+                Ok(peer_connection) => peer_connection,
+                Err(error) => {
+                    connection_events.send(ConnectionEvent::Error {
+                        reason: format!("{:#}", error),
+                    });
+                    return Err(error);
+                }
+            };
+        let connected = tokio::select! {
+            connected = connect_over(config, Arc::clone(&peer_connection))
+                .instrument(span.clone()) => connected,
+            _ = cancel => {
+                close_peer_connection(&peer_connection).instrument(span).await;
+                return Ok(None);
+            }
+        };
+        match connected {
+            Ok(client) => Ok(Some(client)),
+            Err(error) => {
+                connection_events.send(ConnectionEvent::Error {
+                    reason: format!("{:#}", error),
+                });
+                close_peer_connection(&peer_connection)
+                    .instrument(span)
+                    .await;
+                Err(error)
+            }
+        }
+    }
+
+    /// Sets up the peer connection & data channels as `connect` would and
+    /// returns the SDP offer it would send, without contacting the server.
+    /// Waits for ICE gathering, for at most `gathering_timeout` or
+    /// `DEFAULT_GATHERING_TIMEOUT`, so the offer lists the local candidates.
+    /// The peer connection is closed again before returning.
+    pub async fn offer(mut config: ClientConfig) -> Result<String> {
+        validate_channels(&config)?;
+        config.gathering_timeout = config.gathering_timeout.or(Some(DEFAULT_GATHERING_TIMEOUT));
+        let span = info_span!("peer_connection");
+        async {
+            let peer_connection =
+                new_peer_connection(&config.ice_servers, config.answering_dtls_role).await?;
+            let offered = offer_over(&config, &peer_connection).await;
+            close_peer_connection(&peer_connection).await;
+            Ok(offered?.sdp)
+        }
+        .instrument(span)
+        .await
+    }
+
+    /// Queues a single message for the server, without waiting for it to be
+    /// written. Fails with `SendQueueFull` if the queue has no room, counting
+    /// the dropped message in the stats.
+    pub async fn send(&self, payload: &[u8]) -> Result<usize> {
+        self.queue(payload)
+    }
+
+    // `send` never waits, which lets the socket API's sender be synchronous
+    pub(crate) fn queue(&self, payload: &[u8]) -> Result<usize> {
+        let queued = self.transport.try_send(&Bytes::copy_from_slice(payload))?;
+        self.stats.record_sent(queued);
+        Ok(queued)
+    }
+
+    /// Starts reading messages from the server into the returned receiver,
+    /// until the data channel closes or the receiver is dropped. Nothing
+    /// else should read from the transport once this is called. Messages
+    /// share pooled buffers, so holding onto one keeps its chunk alive;
+    /// copy it out to keep it around.
+    pub fn incoming(&self) -> mpsc::Receiver<Bytes> {
+        self.incoming_on(DATA_CHANNEL_LABEL)
+    }
+
+    /// Merges what arrives on each of `labels` into one receiver, tagging
+    /// every message with its channel's label. Each channel is read with
+    /// `incoming_on`, so one that's also read elsewhere splits its messages
+    /// between the two.
+    pub fn route_incoming(&self, labels: &[&str]) -> mpsc::Receiver<ChannelMessage> {
+        let (sender, receiver) = mpsc::channel(INCOMING_CAPACITY);
+        for label in labels {
+            let mut incoming = self.incoming_on(label);
+            let sender = sender.clone();
+            let label = label.to_string();
+            tokio::spawn(
+                async move {
+                    while let Some(payload) = incoming.recv().await {
+                        let message = ChannelMessage {
+                            label: label.clone(),
+                            payload,
+                        };
+                        if sender.send(message).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                .instrument(self.span.clone()),
+            );
+        }
+        receiver
+    }
+
+    /// Like `send`, on the channel labeled `label`, failing if it isn't open
+    pub async fn send_on(&self, label: &str, payload: &[u8]) -> Result<usize> {
+        let transport = match self.channel_state(label)? {
+            ChannelState::Open { transport, .. } => transport,
+            ChannelState::Opening => bail!("data channel '{}' is not open yet", label),
+            ChannelState::Failed(reason) => {
+                bail!("data channel '{}' failed to open: {}", label, reason)
+            }
+        };
+        let queued = transport.try_send(&Bytes::copy_from_slice(payload))?;
+        self.stats.record_sent(queued);
+        Ok(queued)
+    }
+
+    /// Like `incoming`, for the channel labeled `label`. Reading starts once
+    /// the channel opens; if it never does the receiver is closed.
+    pub fn incoming_on(&self, label: &str) -> mpsc::Receiver<Bytes> {
+        let (sender, receiver) = mpsc::channel(INCOMING_CAPACITY);
+        let state = match self.channels.get(label) {
+            Some(state) => state.clone(),
+            None => {
+                warn!("No data channel labeled '{}' to read from", label);
+                return receiver;
+            }
+        };
+        let label = label.to_string();
+        let stats = Arc::clone(&self.stats);
+        let max_message_size = self.max_message_size;
+        let ended = self.ended.clone();
+        tokio::spawn(
+            async move {
+                let transport = tokio::select! {
+                    opened = wait_open(state, &label) => match opened {
+                        Ok(transport) => transport,
+                        Err(error) => {
+                            warn!("Not reading from data channel '{}': {:#}", label, error);
+                            return;
+                        }
+                    },
+                    _ = wait_for_end(ended) => return,
+                };
+                let mut buffer = vec![0u8; max_message_size];
+                let mut chunks = IncomingChunks::default();
+                loop {
+                    let length = match transport.recv(&mut buffer).await {
+                        Ok(length) => length,
+                        Err(error) => {
+                            stats.record_read_error();
+                            info!(
+                                "Datachannel '{}' closed; Stop reading incoming messages: {}",
+                                label, error
+                            );
+                            return;
+                        }
+                    };
+                    stats.record_received(length);
+                    if sender.send(chunks.copy(&buffer[..length])).await.is_err() {
+                        return;
+                    }
+                }
+            }
+            .instrument(self.span.clone()),
+        );
+        receiver
+    }
+
+    /// The transport of the channel labeled `label`, if it's open
+    pub fn channel(&self, label: &str) -> Option<Arc<dyn Transport>> {
+        match self.channels.get(label)?.borrow().clone() {
+            ChannelState::Open { transport, .. } => Some(transport as Arc<dyn Transport>),
+            _ => None,
+        }
+    }
+
+    /// Waits for the channel labeled `label` to open, failing if it can't or
+    /// the connection ends first
+    pub async fn wait_channel(&self, label: &str) -> Result<Arc<dyn Transport>> {
+        let state = match self.channels.get(label) {
+            Some(state) => state.clone(),
+            None => bail!("no data channel labeled '{}'", label),
+        };
+        tokio::select! {
+            opened = wait_open(state, label) => Ok(opened?),
+            error = self.closed() => Err(error),
+        }
+    }
+
+    fn channel_state(&self, label: &str) -> Result<ChannelState> {
+        match self.channels.get(label) {
+            Some(state) => Ok(state.borrow().clone()),
+            None => bail!("no data channel labeled '{}'", label),
+        }
+    }
+
+    /// The transport messages are sent & received over, for the read &
+    /// write loops. Sending on it goes through the same queue as
+    /// `Client::send`, but waits for room instead of dropping.
+    pub fn transport(&self) -> Arc<dyn Transport> {
+        self.transport.clone()
+    }
+
+    /// The span this connection's logs belong to, for instrumenting tasks
+    /// that work on it
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Messages & bytes sent and received so far, and read errors
+    pub fn stats(&self) -> &Arc<ClientStats> {
+        &self.stats
+    }
+
+    /// Round trip, jitter & loss estimates from the PONGs read so far
+    pub fn connection_stats(&self) -> ConnectionStats {
+        self.stats.connection_stats()
+    }
+
+    /// The connection's state, kept up to date as it changes
+    pub fn status(&self) -> &Arc<ConnectionStatus> {
+        &self.status
+    }
+
+    /// Whether the peer connection is connected and the data channel is
+    /// open & carrying traffic, see `ConnectionStatus::is_connected`
+    pub fn is_connected(&self) -> bool {
+        self.status.is_connected()
+    }
+
+    /// The last state reported by the peer connection
+    pub fn connection_state(&self) -> RTCPeerConnectionState {
+        self.status.connection_state()
+    }
+
+    /// Where the connection's lifecycle events are recorded
+    pub fn events(&self) -> &EventLog {
+        &self.events
+    }
+
+    pub fn addr_cell(&self) -> &AddrCell {
+        &self.addr_cell
+    }
+
+    pub fn server_addr(&self) -> ServerAddr {
+        self.addr_cell.get()
+    }
+
+    pub fn peer_connection(&self) -> &Arc<RTCPeerConnection> {
+        &self.peer_connection
+    }
+
+    /// Resolves once the peer connection fails or is closed, with the reason
+    pub async fn closed(&self) -> anyhow::Error {
+        wait_for_end(self.ended.clone()).await
+    }
+
+    /// Closes every open data channel, then the peer connection, so the
+    /// server doesn't hold a half-open session until it times out
+    pub async fn close(&self) {
+        async {
+            for (label, state) in &self.channels {
+                let opened = state.borrow().clone();
+                if let ChannelState::Open { transport } = opened {
+                    match transport.close().await {
+                        Ok(()) => info!("Data channel '{}' closed", label),
+                        Err(error) => {
+                            warn!("Failed to close data channel '{}': {}", label, error)
+                        }
+                    }
+                }
+            }
+            close_peer_connection(&self.peer_connection).await;
+        }
+        .instrument(self.span.clone())
+        .await
+    }
+
+    // a client whose main channel is already open over `transport`, on a
+    // peer connection that's never signaled, for testing what's built on
+    // top of one
+    #[cfg(test)]
+    pub(crate) async fn over(transport: Arc<dyn Transport>) -> Client {
+        let peer_connection = new_peer_connection(&[], DTLSRole::Client)
+            .await
+            .expect("failed to create an offline peer connection");
+        let stats = Arc::new(ClientStats::default());
+        let transport = Arc::new(SendQueue::new(
+            transport,
+            Arc::clone(&stats),
+            DEFAULT_SEND_QUEUE_CAPACITY,
+            DEFAULT_MAX_BUFFERED_AMOUNT,
+        ));
+        let (_, state) = watch::channel(ChannelState::Open {
+            transport: Arc::clone(&transport),
+        });
+        // the callback keeps the sender alive for as long as the connection
+        let (ended_sender, ended) = watch::channel(None);
+        peer_connection
+            .on_peer_connection_state_change(Box::new(move |state| {
+                if state == RTCPeerConnectionState::Closed {
+                    let _ = ended_sender.send(Some(Ended {
+                        state,
+                        ice_state: RTCIceConnectionState::Closed,
+                    }));
+                }
+                Box::pin(async {})
+            }))
+            .await;
+
+        Client {
+            peer_connection,
+            channels: HashMap::from([(DATA_CHANNEL_LABEL.to_string(), state)]),
+            transport,
+            addr_cell: AddrCell::default(),
+            stats,
+            status: Arc::new(ConnectionStatus::default()),
+            events: EventLog::default(),
+            span: Span::current(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            ended,
+        }
+    }
+}
+
+// channels that never opened are closed along with the peer connection
+async fn close_peer_connection(peer_connection: &RTCPeerConnection) {
+    match peer_connection.close().await {
+        Ok(()) => info!("Peer connection closed"),
+        Err(error) => warn!("Failed to close the peer connection: {}", error),
+    }
+}
+
+async fn new_peer_connection(
+    ice_servers: &[RTCIceServer],
+    answering_dtls_role: DTLSRole,
+) -> Result<Arc<RTCPeerConnection>> {
+    // create a SettingEngine and enable Detach
+    let mut setting_engine = SettingEngine::default();
+    setting_engine.detach_data_channels();
+    info!("Answering DTLS role: {}", answering_dtls_role);
+    // the setting engine only takes an explicit role, auto keeps its default
+    if answering_dtls_role != DTLSRole::Auto {
+        setting_engine
+            .set_answering_dtls_role(answering_dtls_role)
+            .context("failed to set the answering DTLS role")?;
+    }
+
+    // create the API object
+    let api = APIBuilder::new()
+        .with_setting_engine(setting_engine)
+        .build();
+
+    // prepare the connection's configuration
+    let peer_connection_config = RTCConfiguration {
+        ice_servers: ice_servers.to_vec(),
+        ..Default::default()
+    };
+
+    // create a new RTCPeerConnection
+    Ok(Arc::new(
+        api.new_peer_connection(peer_connection_config)
+            .await
+            .context("failed to create peer connection")?,
+    ))
+}
+
+// signals the server over `peer_connection`, returning once the data
+// channel is open & detached
+async fn connect_over(
+    config: ClientConfig,
+    peer_connection: Arc<RTCPeerConnection>,
+) -> Result<Client> {
+    let span = Span::current();
+    let Offered {
+        channels,
+        main_state,
+        ended,
+        sdp,
+        local_candidates,
+    } = offer_over(&config, &peer_connection).await?;
+
+    // send a request to server to initiate connection (signaling, essentially)
+    let http_client = build_http_client(&config)?;
+
+    // wait to receive a response from server
+    let response = send_offer(&http_client, &config, sdp).await?;
+    let status = response.status();
+    let response_string = read_capped_body(response, config.max_response_bytes).await?;
+
+    // an error page isn't a malformed session, so don't try parsing it as one
+    if !status.is_success() {
+        error!(
+            "Signaling server answered {}: {}",
+            status,
+            response_string.trim()
+        );
+        return Err(SignalingError::Rejected(status, response_string).into());
+    }
+
+    // parse session from server response
+    let session_response =
+        get_session_response(response_string.as_str(), &config.response_field_paths)
+            .context("failed to parse session response")?;
+
+    // remember where the server is, for logging
+    let addr_cell = AddrCell::default();
+    let addr_found = session_response
+        .candidate
+        .iter()
+        .any(|candidate| addr_cell.receive_candidate(&candidate.candidate));
+    match addr_cell.get() {
+        ServerAddr::Found(addr) if addr_found => {
+            span.record("server_addr", field::display(addr));
+        }
+        _ => warn!("Could not read an address from any of the server's candidates"),
+    }
+
+    // apply the server's answer & candidates to the connection
+    let server_candidates: Vec<String> = session_response
+        .candidate
+        .iter()
+        .map(|candidate| candidate.candidate.clone())
+        .collect();
+    apply_session_response(&peer_connection, session_response).await?;
+    for candidate in server_candidates {
+        config
+            .connection_events
+            .send(ConnectionEvent::CandidateAdded {
+                candidate,
+                local: false,
+            });
+    }
+
+    // the server knows the connection now, so it can take candidates
+    if let (Some(url), Some(local_candidates)) = (&config.trickle_url, local_candidates) {
+        let endpoint = TrickleEndpoint {
+            http_client,
+            url: url.clone(),
+            connection_id: config.connection_id.clone(),
+            timeout: config.signaling_timeout,
+            max_response_bytes: config.max_response_bytes,
+            events: config.connection_events.clone(),
+        };
+        tokio::spawn(
+            trickle_candidates(endpoint, Arc::clone(&peer_connection), local_candidates)
+                .instrument(span.clone()),
+        );
+    }
+
+    // wait for the main data channel, unless the connection gives out first
+    let transport = tokio::select! {
+        opened = wait_open(main_state, DATA_CHANNEL_LABEL) => opened?,
+        error = wait_for_end(ended.clone()) => return Err(error),
+    };
+
+    Ok(Client {
+        peer_connection,
+        channels,
+        transport,
+        addr_cell,
+        stats: config.stats,
+        status: config.status,
+        events: config.events,
+        span,
+        max_message_size: config.max_message_size,
+        ended,
+    })
+}
+
+// the channels & callbacks set up ahead of signaling, and the offer
+struct Offered {
+    channels: HashMap<String, watch::Receiver<ChannelState>>,
+    main_state: watch::Receiver<ChannelState>,
+    ended: watch::Receiver<Option<Ended>>,
+    sdp: String,
+    // local candidates to trickle, if there's an endpoint for them
+    local_candidates: Option<mpsc::UnboundedReceiver<String>>,
+}
+
+// opens the data channels & sets the local description on `peer_connection`,
+// returning the offer to send the server
+async fn offer_over(config: &ClientConfig, peer_connection: &RTCPeerConnection) -> Result<Offered> {
+    // webrtc runs the callbacks on its own tasks, outside this span
+    let span = Span::current();
+
+    // the main channel has to open, the extra ones only get a warning
+    let main_channel = LabeledChannel {
+        label: DATA_CHANNEL_LABEL.to_string(),
+        settings: config.channel,
+    };
+    let transport_settings = TransportSettings {
+        framed: config.framed,
+        fragmented: config.fragmented,
+        max_reassembly_bytes: config.max_reassembly_bytes,
+        send_queue_capacity: config.send_queue_capacity,
+        max_buffered_amount: config.max_buffered_amount,
+        stats: Arc::clone(&config.stats),
+    };
+    let mut channels = HashMap::new();
+    let main_state = open_channel(peer_connection, &main_channel, &transport_settings).await?;
+    channels.insert(main_channel.label, main_state.clone());
+    for channel in &config.extra_channels {
+        match open_channel(peer_connection, channel, &transport_settings).await {
+            Ok(state) => {
+                channels.insert(channel.label.clone(), state);
+            }
+            Err(error) => warn!("{:#}", error),
+        }
+    }
+
+    // set once the peer connection fails or closes
+    let (ended_sender, ended) = watch::channel(None);
+
+    // peer_connection's on_peer_connection_state_change callback
+    let state_status = Arc::clone(&config.status);
+    let state_events = config.events.clone();
+    let state_connection_events = config.connection_events.clone();
+    let state_span = span.clone();
+    peer_connection
+        .on_peer_connection_state_change(Box::new(move |state| {
+            let _entered = state_span.enter();
+            let previous = state_status.connection_state();
+            state_status.set_connection_state(state);
+            state_events.record(ClientEvent::PeerConnectionState {
+                state: state.to_string(),
+            });
+            match state {
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Disconnected => {
+                    warn!("Peer connection state: {} -> {}", previous, state)
+                }
+                _ => info!("Peer connection state: {} -> {}", previous, state),
+            }
+            match state {
+                RTCPeerConnectionState::Connected => {
+                    state_connection_events.send(ConnectionEvent::Connected)
+                }
+                RTCPeerConnectionState::Disconnected => {
+                    state_connection_events.send(ConnectionEvent::Disconnected {
+                        reason: "peer connection disconnected".to_string(),
+                    })
+                }
+                RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed => {
+                    let ended = Ended {
+                        state,
+                        ice_state: state_status.ice_connection_state(),
+                    };
+                    state_connection_events.send(ConnectionEvent::Disconnected {
+                        reason: ended.error().to_string(),
+                    });
+                    let _ = ended_sender.send(Some(ended));
+                }
+                _ => {}
+            }
+            Box::pin(async {})
+        }))
+        .await;
+
+    // peer_connection's on_ice_connection_state_change callback. ICE failing
+    // means no network path was found, which calls for a different fix than
+    // DTLS or the data channel failing over a path that works
+    let ice_status = Arc::clone(&config.status);
+    let ice_events = config.events.clone();
+    let ice_span = span.clone();
+    peer_connection
+        .on_ice_connection_state_change(Box::new(move |state| {
+            let _entered = ice_span.enter();
+            let previous = ice_status.ice_connection_state();
+            ice_status.set_ice_connection_state(state);
+            ice_events.record(ClientEvent::IceConnectionState {
+                state: state.to_string(),
+            });
+            match state {
+                RTCIceConnectionState::Failed => error!(
+                    "ICE connection state: {} -> {}; no network path to the server was found, a STUN or TURN server may be needed to get through NAT or a firewall",
+                    previous, state
+                ),
+                RTCIceConnectionState::Disconnected => {
+                    warn!("ICE connection state: {} -> {}", previous, state)
+                }
+                _ => info!("ICE connection state: {} -> {}", previous, state),
+            }
+            Box::pin(async {})
+        }))
+        .await;
+
+    // queues local candidates for trickling; the sender is dropped once
+    // gathering finishes, which ends the trickle
+    let (candidate_sender, local_candidates) = match config.trickle_url {
+        Some(_) => {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            (Some(sender), Some(receiver))
+        }
+        None => (None, None),
+    };
+    let candidate_sender = Arc::new(std::sync::Mutex::new(candidate_sender));
+    let gathered = Arc::new(std::sync::Mutex::new(GatheredCandidates::default()));
+    let ice_servers = config.ice_servers.clone();
+    let candidate_gathered = Arc::clone(&gathered);
+
+    // peer_connection's on_ice_candidate callback
+    let candidate_connection_events = config.connection_events.clone();
+    let candidate_span = span.clone();
+    peer_connection
+        .on_ice_candidate(Box::new(move |candidate_opt| {
+            let _entered = candidate_span.enter();
+            let candidate_sender = Arc::clone(&candidate_sender);
+            let connection_events = candidate_connection_events.clone();
+            let mut gathered = candidate_gathered
+                .lock()
+                .expect("gathered candidates lock poisoned");
+            let candidate = match candidate_opt {
+                Some(candidate) => candidate,
+                None => {
+                    info!("all local candidates received");
+                    gathered.warn_unreachable(&ice_servers);
+                    candidate_sender
+                        .lock()
+                        .expect("candidate sender lock poisoned")
+                        .take();
+                    return Box::pin(async {});
+                }
+            };
+            info!("received ice candidate from: {}", candidate.address);
+            gathered.record(candidate.typ);
+
+            Box::pin(
+                async move {
+                    let sender = candidate_sender
+                        .lock()
+                        .expect("candidate sender lock poisoned")
+                        .clone();
+                    let init = match candidate.to_json().await {
+                        Ok(init) => init,
+                        Err(error) => {
+                            warn!("Failed to serialize ICE candidate: {}", error);
+                            return;
+                        }
+                    };
+                    connection_events.send(ConnectionEvent::CandidateAdded {
+                        candidate: init.candidate.clone(),
+                        local: true,
+                    });
+                    // the trickle finds the candidate's m-line in the local
+                    // description, which is set by the time it's sent
+                    if let Some(sender) = sender {
+                        let _ = sender.send(init.candidate);
+                    }
+                }
+                .instrument(candidate_span.clone()),
+            )
+        }))
+        .await;
+
+    // create an offer to send to the server
+    let offer = peer_connection
+        .create_offer(None)
+        .await
+        .context("failed to create offer")?;
+
+    // must be asked for before gathering starts, or its end could be missed
+    let mut gathering_complete = peer_connection.gathering_complete_promise().await;
+
+    // sets the LocalDescription, and starts our UDP listeners
+    peer_connection
+        .set_local_description(offer)
+        .await
+        .context("failed to set local description")?;
+
+    if let Some(gathering_timeout) = config.gathering_timeout {
+        match tokio::time::timeout(gathering_timeout, gathering_complete.recv()).await {
+            Ok(_) => debug!("ICE gathering complete"),
+            Err(_) => {
+                warn!(
+                    "ICE gathering still incomplete after {:?}, sending the candidates gathered so far",
+                    gathering_timeout
+                );
+                gathered
+                    .lock()
+                    .expect("gathered candidates lock poisoned")
+                    .warn_unreachable(&config.ice_servers);
+            }
+        }
+    }
+
+    let sdp = peer_connection
+        .local_description()
+        .await
+        .context("no local description after setting it")?
+        .sdp;
+
+    Ok(Offered {
+        channels,
+        main_state,
+        ended,
+        sdp,
+        local_candidates,
+    })
+}
+
+// labels must be unique, since channels are looked up by them, and each
+// channel's settings must be ones WebRTC allows
+fn validate_channels(config: &ClientConfig) -> Result<()> {
+    config.channel.validate()?;
+    let mut labels = vec![DATA_CHANNEL_LABEL];
+    for channel in &config.extra_channels {
+        if labels.contains(&channel.label.as_str()) {
+            bail!("more than one data channel labeled '{}'", channel.label);
+        }
+        channel
+            .settings
+            .validate()
+            .with_context(|| format!("invalid settings for data channel '{}'", channel.label))?;
+        labels.push(&channel.label);
+    }
+    Ok(())
+}
+
+// creates a data channel, returning its state; once it opens the state holds
+// the detached channel & the transport over it
+async fn open_channel(
+    peer_connection: &RTCPeerConnection,
+    channel: &LabeledChannel,
+    transport_settings: &TransportSettings,
+) -> Result<watch::Receiver<ChannelState>> {
+    let label = channel.label.clone();
+    let ChannelSettings {
+        ordered,
+        max_retransmits,
+        max_packet_lifetime,
+    } = channel.settings;
+    info!(
+        "Data channel '{}': ordered {}, max retransmits {:?}, max packet lifetime {:?}",
+        label,
+        ordered,
+        max_retransmits,
+        max_packet_lifetime.map(|lifetime| Duration::from_millis(lifetime.into()))
+    );
+    let data_channel_config = RTCDataChannelInit {
+        ordered: Some(ordered),
+        max_retransmits,
+        max_packet_life_time: max_packet_lifetime,
+        ..Default::default()
+    };
+
+    let data_channel = peer_connection
+        .create_data_channel(&label, Some(data_channel_config))
+        .await
+        .with_context(|| format!("failed to create data channel '{}'", label))?;
+
+    // webrtc runs the callbacks on its own tasks, outside this span
+    let span = Span::current();
+
+    // datachannel on_error callback
+    let error_span = span.clone();
+    let error_label = label.clone();
+    data_channel
+        .on_error(Box::new(move |error| {
+            let _entered = error_span.enter();
+            warn!("data channel '{}' error: {:?}", error_label, error);
+            Box::pin(async {})
+        }))
+        .await;
+
+    let (state_sender, state) = watch::channel(ChannelState::Opening);
+    let state_sender = Arc::new(state_sender);
+
+    // datachannel on_open callback, which hands over the detached channel
+    data_channel
+        .on_open(open_handler(
+            Arc::clone(&data_channel),
+            Arc::new(AtomicBool::new(false)),
+            Arc::clone(&state_sender),
+            transport_settings.clone(),
+            span.clone(),
+        ))
+        .await;
+
+    // a channel closed before it opened never will
+    let close_sender = state_sender;
+    let close_span = span;
+    data_channel
+        .on_close(Box::new(move || {
+            let _entered = close_span.enter();
+            if matches!(*close_sender.borrow(), ChannelState::Opening) {
+                warn!("Data channel '{}' closed before opening", label);
+                let _ = close_sender.send(ChannelState::Failed("closed before opening".into()));
+            }
+            Box::pin(async {})
+        }))
+        .await;
+
+    Ok(state)
+}
+
+// hands over the detached channel when it opens. webrtc runs each handler
+// at most once, so `opened` is shared by every handler the channel gets,
+// and only the first to run detaches it.
+fn open_handler(
+    data_channel: Arc<RTCDataChannel>,
+    opened: Arc<AtomicBool>,
+    open_sender: Arc<watch::Sender<ChannelState>>,
+    transport_settings: TransportSettings,
+    open_span: Span,
+) -> OnOpenHdlrFn {
+    Box::new(move || {
+        let _entered = open_span.enter();
+        // only detach once per channel
+        if opened.swap(true, Ordering::SeqCst) {
+            info!(
+                "Data channel '{}'-'{}' already open, ignoring repeated on_open.",
+                data_channel.label(),
+                data_channel.id()
+            );
+            return Box::pin(async {});
+        }
+
+        info!(
+            "Data channel '{}'-'{}' open.",
+            data_channel.label(),
+            data_channel.id()
+        );
+
+        let data_channel = Arc::clone(&data_channel);
+        let open_sender = Arc::clone(&open_sender);
+        let transport_settings = transport_settings.clone();
+        Box::pin(
+            async move {
+                // the connection can be torn down before on_open's future runs
+                let opened = match data_channel.detach().await {
+                    Ok(detached) => detached_state(detached, &transport_settings),
+                    Err(error) => {
+                        warn!(
+                            "Failed to detach data channel '{}': {}",
+                            data_channel.label(),
+                            error
+                        );
+                        ChannelState::Failed(format!("failed to detach: {}", error))
+                    }
+                };
+                let _ = open_sender.send(opened);
+            }
+            .instrument(open_span.clone()),
+        )
+    })
+}
+
+// everything goes through the transport, closing the channel included,
+// whose sends are queued for a single writer
+fn detached_state(data_channel: Arc<DataChannel>, settings: &TransportSettings) -> ChannelState {
+    let transport: Arc<dyn Transport> = if settings.framed {
+        Arc::new(FramedTransport::new(data_channel))
+    } else {
+        data_channel
+    };
+    let transport: Arc<dyn Transport> = if settings.fragmented {
+        Arc::new(FragmentedTransport::new(
+            transport,
+            Arc::clone(&settings.stats),
+            settings.max_reassembly_bytes,
+        ))
+    } else {
+        transport
+    };
+    let transport = Arc::new(SendQueue::new(
+        transport,
+        Arc::clone(&settings.stats),
+        settings.send_queue_capacity,
+        settings.max_buffered_amount,
+    ));
+    ChannelState::Open { transport }
+}
+
+// resolves once the channel has opened or failed to
+async fn wait_open(
+    mut state: watch::Receiver<ChannelState>,
+    label: &str,
+) -> Result<Arc<SendQueue>> {
+    loop {
+        let current = state.borrow().clone();
+        match current {
+            ChannelState::Open { transport, .. } => return Ok(transport),
+            ChannelState::Failed(reason) => {
+                bail!("data channel '{}' failed to open: {}", label, reason)
+            }
+            ChannelState::Opening => {}
+        }
+        if state.changed().await.is_err() {
+            bail!("data channel '{}' never opened", label);
+        }
+    }
+}
+
+// certificates are verified against the system roots (and the configured CA)
+// unless verification is explicitly turned off
+fn build_http_client(config: &ClientConfig) -> Result<HttpClient> {
+    let mut builder = HttpClient::builder();
+    if let Some(certificate) = &config.ca_certificate {
+        builder = builder.add_root_certificate(certificate.clone());
+    }
+    if config.insecure_skip_verify {
+        warn!("Not verifying the signaling server's TLS certificate");
+        builder = builder.danger_accept_invalid_certs(true);
+    }
+    builder
+        .build()
+        .context("failed to build the signaling HTTP client")
+}
+
+// posts the offer, retrying failed attempts with a doubling delay since the
+// server may still be starting up
+async fn send_offer(
+    http_client: &HttpClient,
+    config: &ClientConfig,
+    sdp: String,
+) -> Result<reqwest::Response> {
+    let attempts = config.signaling_attempts.max(1);
+    let mut delay = SIGNALING_RETRY_DELAY;
+    for attempt in 1..=attempts {
+        // a non-streaming String body lets reqwest set Content-Length itself
+        let request = http_client
+            .post(config.server_url.clone())
+            .header(CONNECTION_ID_HEADER, &config.connection_id)
+            .timeout(config.signaling_timeout)
+            .body(sdp.clone());
+
+        match request.send().await {
+            Ok(response) => return Ok(response),
+            Err(error) if attempt < attempts => {
+                warn!(
+                    "Signaling attempt {}/{} failed: {}; retrying in {:?}",
+                    attempt, attempts, error, delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_SIGNALING_RETRY_DELAY);
+            }
+            Err(error) => {
+                return Err(error).with_context(|| {
+                    format!(
+                        "could not send signaling request after {} attempt(s)",
+                        attempts
+                    )
+                })
+            }
+        }
+    }
+    unreachable!("at least one signaling attempt is made")
+}
+
+// the state the peer connection ended in, and its ICE transport's then
+#[derive(Clone, Copy)]
+struct Ended {
+    state: RTCPeerConnectionState,
+    ice_state: RTCIceConnectionState,
+}
+
+impl Ended {
+    // what the connection ending means, telling ICE failures apart
+    fn error(&self) -> anyhow::Error {
+        match (self.state, self.ice_state) {
+            (RTCPeerConnectionState::Failed, RTCIceConnectionState::Failed) => {
+                ReconnectReason::IceFailed.error(anyhow!(
+                    "peer connection failed: ICE found no network path to the server (check the STUN/TURN configuration)"
+                ))
+            }
+            (RTCPeerConnectionState::Failed, _) => ReconnectReason::ChannelClosed.error(anyhow!(
+                "peer connection failed with ICE {}, so the DTLS handshake or the connection over it failed",
+                self.ice_state
+            )),
+            _ => ReconnectReason::ChannelClosed.error(anyhow!("peer connection {}", self.state)),
+        }
+    }
+}
+
+async fn wait_for_end(mut ended: watch::Receiver<Option<Ended>>) -> anyhow::Error {
+    loop {
+        if let Some(ended) = *ended.borrow() {
+            return ended.error();
+        }
+        if ended.changed().await.is_err() {
+            return ReconnectReason::ChannelClosed.error(anyhow!("peer connection dropped"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        signaling::{SessionAnswer, SessionCandidate, SessionResponse},
+        test_alloc::allocations,
+        test_http::{serve_once, serve_once_with, Request},
+    };
+    use webrtc::peer_connection::sdp::{
+        sdp_type::RTCSdpType, session_description::RTCSessionDescription,
+    };
+
+    const SDP: &str = "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\n";
+
+    #[tokio::test]
+    async fn the_offer_carries_its_length_and_connection_id() {
+        let (url, served) =
+            serve_once(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n{}".to_vec()).await;
+        let config = ClientConfig {
+            connection_id: "abcdefghijklmnop".to_string(),
+            ..ClientConfig::new(url.parse().unwrap())
+        };
+
+        let response = send_offer(&HttpClient::new(), &config, SDP.to_string())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+
+        let request = served.await.unwrap();
+        assert!(request.head.starts_with("POST /rtc_session HTTP/1.1"));
+        assert_eq!(
+            request.header("content-length"),
+            Some(SDP.len().to_string().as_str())
+        );
+        assert_eq!(
+            request.header(CONNECTION_ID_HEADER),
+            Some("abcdefghijklmnop")
+        );
+        assert_eq!(request.header("transfer-encoding"), None);
+        assert_eq!(request.body, SDP.as_bytes());
+    }
+
+    // a peer connection holding an offer of its own, waiting on the answer
+    async fn offering() -> Arc<RTCPeerConnection> {
+        let peer_connection = new_peer_connection(&[], DTLSRole::Client).await.unwrap();
+        peer_connection
+            .create_data_channel(DATA_CHANNEL_LABEL, None)
+            .await
+            .unwrap();
+        let offer = peer_connection.create_offer(None).await.unwrap();
+        peer_connection.set_local_description(offer).await.unwrap();
+        peer_connection
+    }
+
+    #[tokio::test]
+    async fn local_candidates_are_trickled_for_the_data_channel_m_line() {
+        let peer_connection = offering().await;
+        let sdp = peer_connection.local_description().await.unwrap().sdp;
+        let (mid, index) = crate::trickle::data_media(&sdp).unwrap();
+
+        let server_candidate = "candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host";
+        let body = format!(
+            r#"{{"candidate":{{"candidate":"{}","sdpMLineIndex":0,"sdpMid":"{}"}}}}"#,
+            server_candidate, mid
+        );
+        let (url, served) = serve_once(
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .into_bytes(),
+        )
+        .await;
+        let (events, mut received_events) = ConnectionEvents::channel();
+        let endpoint = TrickleEndpoint {
+            http_client: HttpClient::new(),
+            url: url.parse().unwrap(),
+            connection_id: "abcdefghijklmnop".to_string(),
+            timeout: Duration::from_secs(5),
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            events,
+        };
+        let (sender, candidates) = mpsc::unbounded_channel();
+        sender
+            .send("candidate:2 1 udp 2130706431 192.0.2.1 50000 typ host".to_string())
+            .unwrap();
+        drop(sender);
+        trickle_candidates(endpoint, Arc::clone(&peer_connection), candidates).await;
+
+        let request = served.await.unwrap();
+        assert_eq!(
+            request.header(CONNECTION_ID_HEADER),
+            Some("abcdefghijklmnop")
+        );
+        let posted: SessionCandidate = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(
+            posted.candidate,
+            "candidate:2 1 udp 2130706431 192.0.2.1 50000 typ host"
+        );
+        assert_eq!(posted.sdp_mid, mid);
+        assert_eq!(posted.sdp_m_line_index, index);
+        match received_events.try_recv().unwrap() {
+            ConnectionEvent::CandidateAdded { candidate, local } => {
+                assert_eq!(candidate, server_candidate);
+                assert!(!local);
+            }
+            other => panic!("expected the server's candidate, got {:?}", other),
+        }
+        peer_connection.close().await.unwrap();
+    }
+
+    fn response(sdp: &str) -> SessionResponse {
+        SessionResponse {
+            answer: SessionAnswer {
+                sdp: sdp.to_string(),
+                type_str: "answer".to_string(),
+            },
+            candidate: vec![SessionCandidate {
+                candidate: "candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host".to_string(),
+                sdp_m_line_index: 0,
+                sdp_mid: "0".to_string(),
+            }],
+        }
+    }
+
+    // answers no server should send, each of which has to fail cleanly
+    const MALFORMED_ANSWERS: &[&str] = &[
+        "",
+        "\r\n",
+        "v=0",
+        "not an sdp at all",
+        "v=1\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n",
+        "v=0\r\no=-\r\n",
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n",
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=application\r\n",
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=application notaport UDP/DTLS/SCTP webrtc-datachannel\r\n",
+        "v=0\r\no=- 0 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\nm=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\nc=IN IP4 0.0.0.0\r\na=mid:0\r\n",
+        "v=0\r\no=- 99999999999999999999999 0 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n",
+        "v=0\r\na=\u{0}\u{1}\u{2}\r\n",
+    ];
+
+    #[tokio::test]
+    async fn malformed_answers_are_errors() {
+        for sdp in MALFORMED_ANSWERS {
+            let peer_connection = offering().await;
+            let result = apply_session_response(&peer_connection, response(sdp)).await;
+            assert!(result.is_err(), "accepted {:?}", sdp);
+            peer_connection.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_answers_dont_panic() {
+        // a real answer to cut short
+        let offerer = offering().await;
+        let answering = new_peer_connection(&[], DTLSRole::Auto).await.unwrap();
+        answering
+            .set_remote_description(offerer.local_description().await.unwrap())
+            .await
+            .unwrap();
+        let answer = answering.create_answer(None).await.unwrap().sdp;
+        answering.close().await.unwrap();
+        offerer.close().await.unwrap();
+
+        for length in (0..answer.len()).step_by(7) {
+            let peer_connection = offering().await;
+            // whether a cut happens to leave a usable answer doesn't matter
+            let _ = apply_session_response(&peer_connection, response(&answer[..length])).await;
+            peer_connection.close().await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn a_repeated_open_is_ignored() {
+        let peer_connection = new_peer_connection(&[], DTLSRole::Client).await.unwrap();
+        let data_channel = peer_connection
+            .create_data_channel(DATA_CHANNEL_LABEL, None)
+            .await
+            .unwrap();
+        let (state_sender, state) = watch::channel(ChannelState::Opening);
+        let state_sender = Arc::new(state_sender);
+        let settings = TransportSettings {
+            framed: false,
+            fragmented: false,
+            max_reassembly_bytes: DEFAULT_MAX_REASSEMBLY_BYTES,
+            send_queue_capacity: DEFAULT_SEND_QUEUE_CAPACITY,
+            max_buffered_amount: DEFAULT_MAX_BUFFERED_AMOUNT,
+            stats: Arc::new(ClientStats::default()),
+        };
+        let opened = Arc::new(AtomicBool::new(false));
+        let on_open = |settings: &TransportSettings| {
+            open_handler(
+                Arc::clone(&data_channel),
+                Arc::clone(&opened),
+                Arc::clone(&state_sender),
+                settings.clone(),
+                Span::current(),
+            )
+        };
+        let (first, second) = (on_open(&settings), on_open(&settings));
+
+        // never actually opened, so detaching fails, but it's still handled
+        first().await;
+        assert!(
+            matches!(&*state.borrow(), ChannelState::Failed(error) if error.contains("detach"))
+        );
+
+        // the second time around, nothing is detached or handed over
+        let _ = state_sender.send(ChannelState::Opening);
+        second().await;
+        assert!(matches!(*state.borrow(), ChannelState::Opening));
+        peer_connection.close().await.unwrap();
+    }
+
+    // answers `offer` the way the naia server would, with a candidate
+    // nothing listens on so the channel never opens
+    async fn answer(offer: String) -> Vec<u8> {
+        let answering = new_peer_connection(&[], DTLSRole::Auto).await.unwrap();
+        let mut description = RTCSessionDescription::default();
+        description.sdp_type = RTCSdpType::Offer;
+        description.sdp = offer;
+        answering.set_remote_description(description).await.unwrap();
+        let sdp = answering.create_answer(None).await.unwrap().sdp;
+        answering.close().await.unwrap();
+
+        let mut session = response(&sdp);
+        session.candidate[0].candidate = "candidate:1 1 UDP 2122317823 127.0.0.1 9 typ host".into();
+        let body = serde_json::to_string(&session).unwrap();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn connecting_can_be_cancelled_at_any_point() {
+        // from before the offer is sent to well into ICE
+        for delay_ms in [0, 0, 1, 2, 5, 10, 20, 50, 100, 200] {
+            let (url, _served) = serve_once_with(|request: &Request| {
+                let offer = String::from_utf8(request.body.clone()).unwrap();
+                answer(offer)
+            })
+            .await;
+            let config = ClientConfig {
+                ice_servers: Vec::new(),
+                signaling_attempts: 1,
+                ..ClientConfig::new(url.parse().unwrap())
+            };
+
+            let cancel = tokio::time::sleep(Duration::from_millis(delay_ms));
+            match Client::connect_until(config, cancel).await {
+                Ok(None) => {}
+                Err(error) => panic!("cancelled after {}ms: {:#}", delay_ms, error),
+                Ok(Some(_)) => panic!("connected to a candidate nothing listens on"),
+            }
+        }
+    }
+
+    // messages the size of a game's updates, many of which fit in a chunk
+    const SMALL_MESSAGES: usize = 10_000;
+    const SMALL_MESSAGE: &[u8] = &[7u8; 64];
+
+    #[test]
+    fn incoming_messages_share_chunks() {
+        let mut chunks = IncomingChunks::default();
+        let mut messages = Vec::with_capacity(SMALL_MESSAGES);
+        let (chunked, ()) = allocations(|| {
+            for _ in 0..SMALL_MESSAGES {
+                messages.push(chunks.copy(SMALL_MESSAGE));
+            }
+        });
+        let mut copies = Vec::with_capacity(SMALL_MESSAGES);
+        let (copied, ()) = allocations(|| {
+            for _ in 0..SMALL_MESSAGES {
+                copies.push(Bytes::copy_from_slice(SMALL_MESSAGE));
+            }
+        });
+
+        assert!(messages.iter().all(|message| message == SMALL_MESSAGE));
+        assert!(copies.iter().all(|copy| copy == SMALL_MESSAGE));
+        // a 64KiB chunk & its shared header per chunk held, against an
+        // allocation per message
+        let held = (SMALL_MESSAGES * SMALL_MESSAGE.len()).div_ceil(INCOMING_CHUNK_SIZE);
+        assert_eq!(chunked, 2 * held);
+        assert_eq!(copied, SMALL_MESSAGES);
+    }
+
+    #[test]
+    fn dropped_messages_free_their_chunk_for_reuse() {
+        let mut chunks = IncomingChunks::default();
+        drop(chunks.copy(SMALL_MESSAGE));
+        let (allocated, ()) = allocations(|| {
+            for _ in 0..SMALL_MESSAGES {
+                drop(chunks.copy(SMALL_MESSAGE));
+            }
+        });
+        // the first chunk is reclaimed each time it fills up
+        assert_eq!(allocated, 0);
+    }
+
+    #[test]
+    fn messages_bigger_than_a_chunk_get_their_own() {
+        let mut chunks = IncomingChunks::default();
+        let big = vec![1u8; INCOMING_CHUNK_SIZE * 2];
+        let small = chunks.copy(SMALL_MESSAGE);
+        let message = chunks.copy(&big);
+        assert_eq!(message, big);
+        assert_eq!(small, SMALL_MESSAGE);
+    }
+}
@@ -0,0 +1,627 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::watch, time::Duration};
+
+// round trips are counted into power-of-two microsecond buckets, the last
+// one holding everything from about 9 minutes up
+const RTT_BUCKETS: usize = 31;
+
+// the jitter estimate moves a sixteenth of the way to each new sample, as in
+// RFC 3550's interarrival jitter
+const JITTER_GAIN: u64 = 16;
+
+/// The link's health as the read loop has measured it from echoed PINGs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ConnectionStats {
+    /// Round trip of the latest PONG
+    pub latest_rtt: Option<Duration>,
+    pub average_rtt: Option<Duration>,
+    /// Smoothed variation between consecutive round trips
+    pub jitter: Option<Duration>,
+    /// Share of sent messages that never got a PONG
+    pub loss: Option<f64>,
+    /// Share of PING sequence numbers that were never echoed
+    pub sequence_loss: Option<f64>,
+}
+
+/// Counters shared between the client's read & write loops
+pub struct ClientStats {
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    messages_received: AtomicU64,
+    bytes_received: AtomicU64,
+    unexpected_responses: AtomicU64,
+    pongs_received: AtomicU64,
+    read_errors: AtomicU64,
+    // highest PING sequence number echoed back so far
+    highest_sequence: AtomicU64,
+    missing_sequences: AtomicU64,
+    out_of_order: AtomicU64,
+    // highest sequence number the server stamped a message with, plus one
+    // so 0 means none has arrived; only the read loop records these
+    server_highest_sequence: AtomicU64,
+    // sequence numbers from the first the server stamped to the highest
+    server_sequences_spanned: AtomicU64,
+    server_missing_sequences: AtomicU64,
+    server_out_of_order: AtomicU64,
+    acks_received: AtomicU64,
+    retransmits: AtomicU64,
+    acks_dropped: AtomicU64,
+    queue_drops: AtomicU64,
+    reassembly_failures: AtomicU64,
+    // fragment bytes the channels are holding for partly received messages
+    reassembly_bytes: AtomicU64,
+    rtt_total_us: AtomicU64,
+    rtt_samples: AtomicU64,
+    rtt_buckets: [AtomicU64; RTT_BUCKETS],
+    rtt_max_us: AtomicU64,
+    rtt_latest_us: AtomicU64,
+    jitter_us: AtomicU64,
+    created: Instant,
+    // micros after `created` the latest message was sent
+    last_sent_us: AtomicU64,
+    // micros after `created` the latest message was received
+    last_received_us: AtomicU64,
+    awaiting_pong: AtomicBool,
+}
+
+impl Default for ClientStats {
+    fn default() -> Self {
+        ClientStats {
+            messages_sent: AtomicU64::default(),
+            bytes_sent: AtomicU64::default(),
+            messages_received: AtomicU64::default(),
+            bytes_received: AtomicU64::default(),
+            unexpected_responses: AtomicU64::default(),
+            pongs_received: AtomicU64::default(),
+            read_errors: AtomicU64::default(),
+            highest_sequence: AtomicU64::default(),
+            missing_sequences: AtomicU64::default(),
+            out_of_order: AtomicU64::default(),
+            server_highest_sequence: AtomicU64::default(),
+            server_sequences_spanned: AtomicU64::default(),
+            server_missing_sequences: AtomicU64::default(),
+            server_out_of_order: AtomicU64::default(),
+            acks_received: AtomicU64::default(),
+            retransmits: AtomicU64::default(),
+            acks_dropped: AtomicU64::default(),
+            queue_drops: AtomicU64::default(),
+            reassembly_failures: AtomicU64::default(),
+            reassembly_bytes: AtomicU64::default(),
+            rtt_total_us: AtomicU64::default(),
+            rtt_samples: AtomicU64::default(),
+            rtt_buckets: Default::default(),
+            rtt_max_us: AtomicU64::default(),
+            rtt_latest_us: AtomicU64::default(),
+            jitter_us: AtomicU64::default(),
+            created: Instant::now(),
+            last_sent_us: AtomicU64::default(),
+            last_received_us: AtomicU64::default(),
+            awaiting_pong: AtomicBool::default(),
+        }
+    }
+}
+
+impl ClientStats {
+    /// Records a message of `bytes` length written to the transport
+    pub fn record_sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+        self.last_sent_us.store(self.now_us(), Ordering::Relaxed);
+        self.awaiting_pong.store(true, Ordering::Relaxed);
+    }
+
+    /// Records a PONG with its measured round trip time. Without one, the
+    /// round trip is timed from the latest message sent if it hasn't been
+    /// answered yet.
+    pub fn record_pong(&self, rtt: Option<Duration>) {
+        self.pongs_received.fetch_add(1, Ordering::Relaxed);
+        let latest_answered = self.awaiting_pong.swap(false, Ordering::Relaxed);
+        let rtt_us = match rtt {
+            Some(rtt) => rtt.as_micros() as u64,
+            None if latest_answered => self
+                .now_us()
+                .saturating_sub(self.last_sent_us.load(Ordering::Relaxed)),
+            None => return,
+        };
+        // PONGs are only read by the read loop, so nothing races these
+        let previous_us = self.rtt_latest_us.swap(rtt_us, Ordering::Relaxed);
+        if self.rtt_samples.load(Ordering::Relaxed) > 0 {
+            let jitter_us = self.jitter_us.load(Ordering::Relaxed);
+            let difference_us = rtt_us.abs_diff(previous_us);
+            let updated_us = if difference_us > jitter_us {
+                jitter_us + (difference_us - jitter_us) / JITTER_GAIN
+            } else {
+                jitter_us - (jitter_us - difference_us) / JITTER_GAIN
+            };
+            self.jitter_us.store(updated_us, Ordering::Relaxed);
+        }
+        self.rtt_total_us.fetch_add(rtt_us, Ordering::Relaxed);
+        self.rtt_samples.fetch_add(1, Ordering::Relaxed);
+        // bucket i holds round trips below 2^i micros
+        let bucket = (64 - rtt_us.leading_zeros() as usize).min(RTT_BUCKETS - 1);
+        self.rtt_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.rtt_max_us.fetch_max(rtt_us, Ordering::Relaxed);
+    }
+
+    /// Records a message of `bytes` length read from the transport
+    pub fn record_received(&self, bytes: usize) {
+        // stored before counting, so a counted message always has its time
+        self.last_received_us
+            .store(self.now_us(), Ordering::Relaxed);
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+        self.bytes_received
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a received message that didn't match the expected protocol
+    pub fn record_unexpected_response(&self) {
+        self.unexpected_responses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the sequence number a PONG echoed. Skipping ahead counts the
+    /// numbers in between as missing; one arriving behind the highest seen
+    /// is out of order, and no longer missing.
+    pub fn record_sequence(&self, sequence: u64) {
+        let highest = self.highest_sequence.fetch_max(sequence, Ordering::Relaxed);
+        if sequence > highest {
+            self.missing_sequences
+                .fetch_add(sequence - highest - 1, Ordering::Relaxed);
+        } else if sequence < highest {
+            self.out_of_order.fetch_add(1, Ordering::Relaxed);
+            let _ = self.missing_sequences.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |missing| missing.checked_sub(1),
+            );
+        }
+    }
+
+    /// Records the sequence number the server stamped a message with, like
+    /// `record_sequence` does an echoed one. Gaps are counted from the
+    /// first number that arrives, since the server may have sent some
+    /// before the read loop started, and the server wrapping past
+    /// `u32::MAX` isn't allowed for.
+    pub fn record_server_sequence(&self, sequence: u32) {
+        let sequence = sequence as u64 + 1;
+        let highest = self.server_highest_sequence.load(Ordering::Relaxed);
+        if highest == 0 {
+            self.server_highest_sequence
+                .store(sequence, Ordering::Relaxed);
+            self.server_sequences_spanned.store(1, Ordering::Relaxed);
+        } else if sequence > highest {
+            self.server_highest_sequence
+                .store(sequence, Ordering::Relaxed);
+            self.server_sequences_spanned
+                .fetch_add(sequence - highest, Ordering::Relaxed);
+            self.server_missing_sequences
+                .fetch_add(sequence - highest - 1, Ordering::Relaxed);
+        } else if sequence < highest {
+            self.server_out_of_order.fetch_add(1, Ordering::Relaxed);
+            let _ = self.server_missing_sequences.fetch_update(
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+                |missing| missing.checked_sub(1),
+            );
+        }
+    }
+
+    /// Records an ACK for a message that was still waiting on one
+    pub fn record_ack(&self) {
+        self.acks_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records an unacknowledged message being sent again
+    pub fn record_retransmit(&self) {
+        self.retransmits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a message given up on after its last retransmit went
+    /// unacknowledged
+    pub fn record_ack_dropped(&self) {
+        self.acks_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a message `Client::send` turned away because the send queue
+    /// was full
+    pub fn record_queue_drop(&self) {
+        self.queue_drops.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records messages given up on for missing fragments, or to stay
+    /// within the reassembly budget
+    pub fn record_reassembly_failures(&self, messages: u64) {
+        self.reassembly_failures
+            .fetch_add(messages, Ordering::Relaxed);
+    }
+
+    /// Records a channel going from holding `before` fragment bytes for
+    /// partly received messages to holding `after`
+    pub fn record_reassembly_bytes(&self, before: usize, after: usize) {
+        if after > before {
+            self.reassembly_bytes
+                .fetch_add((after - before) as u64, Ordering::Relaxed);
+        } else {
+            self.reassembly_bytes
+                .fetch_sub((before - after) as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// Records a failed read from the transport
+    pub fn record_read_error(&self) {
+        self.read_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages_sent(&self) -> u64 {
+        self.messages_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_sent(&self) -> u64 {
+        self.bytes_sent.load(Ordering::Relaxed)
+    }
+
+    pub fn messages_received(&self) -> u64 {
+        self.messages_received.load(Ordering::Relaxed)
+    }
+
+    pub fn bytes_received(&self) -> u64 {
+        self.bytes_received.load(Ordering::Relaxed)
+    }
+
+    pub fn unexpected_responses(&self) -> u64 {
+        self.unexpected_responses.load(Ordering::Relaxed)
+    }
+
+    pub fn pongs_received(&self) -> u64 {
+        self.pongs_received.load(Ordering::Relaxed)
+    }
+
+    pub fn read_errors(&self) -> u64 {
+        self.read_errors.load(Ordering::Relaxed)
+    }
+
+    pub fn acks_received(&self) -> u64 {
+        self.acks_received.load(Ordering::Relaxed)
+    }
+
+    pub fn retransmits(&self) -> u64 {
+        self.retransmits.load(Ordering::Relaxed)
+    }
+
+    pub fn acks_dropped(&self) -> u64 {
+        self.acks_dropped.load(Ordering::Relaxed)
+    }
+
+    pub fn queue_drops(&self) -> u64 {
+        self.queue_drops.load(Ordering::Relaxed)
+    }
+
+    pub fn reassembly_failures(&self) -> u64 {
+        self.reassembly_failures.load(Ordering::Relaxed)
+    }
+
+    /// Fragment bytes held for partly received messages, across channels
+    pub fn reassembly_bytes(&self) -> u64 {
+        self.reassembly_bytes.load(Ordering::Relaxed)
+    }
+
+    /// PING sequence numbers below the highest one echoed that haven't been
+    /// echoed themselves
+    pub fn missing_sequences(&self) -> u64 {
+        self.missing_sequences.load(Ordering::Relaxed)
+    }
+
+    /// PONGs that arrived after one for a later PING
+    pub fn out_of_order(&self) -> u64 {
+        self.out_of_order.load(Ordering::Relaxed)
+    }
+
+    /// Share of sequence numbers up to the highest echoed that are still
+    /// missing; `None` until a sequenced PONG arrives
+    pub fn sequence_loss(&self) -> Option<f64> {
+        let highest = self.highest_sequence.load(Ordering::Relaxed);
+        if highest == 0 {
+            return None;
+        }
+        Some(self.missing_sequences() as f64 / highest as f64)
+    }
+
+    /// Sequence numbers the server skipped past that haven't arrived since
+    pub fn server_missing_sequences(&self) -> u64 {
+        self.server_missing_sequences.load(Ordering::Relaxed)
+    }
+
+    /// Messages from the server that arrived after one it sent later
+    pub fn server_out_of_order(&self) -> u64 {
+        self.server_out_of_order.load(Ordering::Relaxed)
+    }
+
+    /// Share of the server's sequence numbers, from the first to arrive to
+    /// the highest, that are still missing; `None` until one arrives
+    pub fn server_sequence_loss(&self) -> Option<f64> {
+        let spanned = self.server_sequences_spanned.load(Ordering::Relaxed);
+        if spanned == 0 {
+            return None;
+        }
+        Some(self.server_missing_sequences() as f64 / spanned as f64)
+    }
+
+    /// Share of sent messages that never got a PONG, not counting a latest
+    /// message still waiting on its answer; `None` until something is sent
+    pub fn loss(&self) -> Option<f64> {
+        let in_flight = self.awaiting_pong.load(Ordering::Relaxed) as u64;
+        let sent = self.messages_sent().saturating_sub(in_flight);
+        if sent == 0 {
+            return None;
+        }
+        let lost = sent.saturating_sub(self.pongs_received());
+        Some(lost as f64 / sent as f64)
+    }
+
+    /// Mean time from sending a message to its PONG arriving, `None` until a
+    /// round trip has been measured
+    pub fn average_rtt(&self) -> Option<Duration> {
+        let samples = self.rtt_samples.load(Ordering::Relaxed);
+        if samples == 0 {
+            return None;
+        }
+        let total_us = self.rtt_total_us.load(Ordering::Relaxed);
+        Some(Duration::from_micros(total_us / samples))
+    }
+
+    /// Round trip of the latest PONG, `None` until one has been measured
+    pub fn latest_rtt(&self) -> Option<Duration> {
+        if self.rtt_samples.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(Duration::from_micros(
+            self.rtt_latest_us.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// Smoothed difference between consecutive round trips, `None` until two
+    /// have been measured
+    pub fn jitter(&self) -> Option<Duration> {
+        if self.rtt_samples.load(Ordering::Relaxed) < 2 {
+            return None;
+        }
+        Some(Duration::from_micros(
+            self.jitter_us.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// Round trip, jitter & loss estimates as of now
+    pub fn connection_stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            latest_rtt: self.latest_rtt(),
+            average_rtt: self.average_rtt(),
+            jitter: self.jitter(),
+            loss: self.loss(),
+            sequence_loss: self.sequence_loss(),
+        }
+    }
+
+    /// Time since the latest message was received, or since the stats were
+    /// created if nothing has been
+    pub fn since_last_received(&self) -> Duration {
+        if self.messages_received() == 0 {
+            return self.created.elapsed();
+        }
+        let last_received_us = self.last_received_us.load(Ordering::Relaxed);
+        Duration::from_micros(self.now_us().saturating_sub(last_received_us))
+    }
+
+    /// Round trip time that `percentile` (0 to 100) of measured round trips
+    /// stayed under, to within a factor of two; `None` until one is measured
+    pub fn rtt_percentile(&self, percentile: f64) -> Option<Duration> {
+        let samples = self.rtt_samples.load(Ordering::Relaxed);
+        if samples == 0 {
+            return None;
+        }
+        let wanted = ((samples as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut counted = 0;
+        for (bucket, count) in self.rtt_buckets.iter().enumerate() {
+            counted += count.load(Ordering::Relaxed);
+            if counted >= wanted {
+                let upper_us = (1u64 << bucket).min(self.rtt_max_us.load(Ordering::Relaxed));
+                return Some(Duration::from_micros(upper_us));
+            }
+        }
+        self.max_rtt()
+    }
+
+    /// Longest round trip measured, `None` until one is
+    pub fn max_rtt(&self) -> Option<Duration> {
+        if self.rtt_samples.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        Some(Duration::from_micros(
+            self.rtt_max_us.load(Ordering::Relaxed),
+        ))
+    }
+
+    /// Micros since the stats were created, the clock PINGs are stamped with
+    pub fn now_us(&self) -> u64 {
+        self.created.elapsed().as_micros() as u64
+    }
+
+    /// Serializes the current counters, along with a unix timestamp in
+    /// milliseconds, into a single line of JSON
+    pub fn snapshot_json(&self) -> Result<String> {
+        let counters = [
+            ("messages_sent", &self.messages_sent),
+            ("bytes_sent", &self.bytes_sent),
+            ("messages_received", &self.messages_received),
+            ("bytes_received", &self.bytes_received),
+            ("unexpected_responses", &self.unexpected_responses),
+            ("pongs_received", &self.pongs_received),
+            ("read_errors", &self.read_errors),
+            ("missing_sequences", &self.missing_sequences),
+            ("out_of_order", &self.out_of_order),
+            ("server_missing_sequences", &self.server_missing_sequences),
+            ("server_out_of_order", &self.server_out_of_order),
+            ("acks_received", &self.acks_received),
+            ("retransmits", &self.retransmits),
+            ("acks_dropped", &self.acks_dropped),
+            ("queue_drops", &self.queue_drops),
+            ("reassembly_failures", &self.reassembly_failures),
+        ];
+
+        let mut object: Map<String, Value> = counters
+            .iter()
+            .map(|(name, counter)| {
+                let value = counter.load(Ordering::Relaxed);
+                (name.to_string(), Value::from(value))
+            })
+            .collect();
+        object.insert(
+            "timestamp_ms".to_string(),
+            Value::from(unix_millis() as u64),
+        );
+
+        serde_json::to_string(&object).context("failed to serialize client stats")
+    }
+}
+
+/// Logs a one-line summary of `stats` every `interval` until `shutdown`
+/// flips
+pub async fn log_stats(
+    stats: &ClientStats,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // the first tick is immediate, when there's nothing to report yet
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.changed() => return,
+        }
+        let loss = stats
+            .loss()
+            .map(|loss| format!(", loss {:.2}%", loss * 100.0))
+            .unwrap_or_default();
+        let sequence_loss = stats
+            .sequence_loss()
+            .map(|loss| {
+                format!(
+                    ", {} missing ({:.2}%), {} out of order",
+                    stats.missing_sequences(),
+                    loss * 100.0,
+                    stats.out_of_order()
+                )
+            })
+            .unwrap_or_default();
+        let server_sequence_loss = stats
+            .server_sequence_loss()
+            .map(|loss| {
+                format!(
+                    ", {} missing from the server ({:.2}%), {} out of order",
+                    stats.server_missing_sequences(),
+                    loss * 100.0,
+                    stats.server_out_of_order()
+                )
+            })
+            .unwrap_or_default();
+        let rtt = stats
+            .average_rtt()
+            .map(|rtt| format!(", mean round trip {:?}", rtt))
+            .unwrap_or_default();
+        let jitter = stats
+            .jitter()
+            .map(|jitter| format!(", jitter {:?}", jitter))
+            .unwrap_or_default();
+        info!(
+            "Stats: sent {} messages ({} bytes), received {} messages ({} bytes), {} read errors{}{}{}{}{}",
+            stats.messages_sent(),
+            stats.bytes_sent(),
+            stats.messages_received(),
+            stats.bytes_received(),
+            stats.read_errors(),
+            loss,
+            sequence_loss,
+            server_sequence_loss,
+            rtt,
+            jitter
+        );
+    }
+}
+
+/// Appends a snapshot of `stats` to the file at `path` every `interval`, as
+/// JSON lines
+pub async fn write_stats_file(path: &str, interval: Duration, stats: &ClientStats) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("failed to open stats file {}", path))?;
+
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let mut line = stats.snapshot_json()?;
+        line.push('\n');
+        file.write_all(line.as_bytes())
+            .await
+            .with_context(|| format!("failed to write stats file {}", path))?;
+    }
+}
+
+pub(crate) fn unix_millis() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_sequence_gaps_count_from_the_first_to_arrive() {
+        let stats = ClientStats::default();
+        assert_eq!(stats.server_sequence_loss(), None);
+
+        // 0 to 2 went to the readiness check before the read loop started
+        for sequence in [3, 4, 6, 8] {
+            stats.record_server_sequence(sequence);
+        }
+        assert_eq!(stats.server_missing_sequences(), 2);
+        assert_eq!(stats.server_out_of_order(), 0);
+        assert_eq!(stats.server_sequence_loss(), Some(2.0 / 6.0));
+
+        stats.record_server_sequence(5);
+        assert_eq!(stats.server_missing_sequences(), 1);
+        assert_eq!(stats.server_out_of_order(), 1);
+        // a duplicate changes nothing
+        stats.record_server_sequence(8);
+        assert_eq!(stats.server_missing_sequences(), 1);
+        assert_eq!(stats.server_out_of_order(), 1);
+
+        let snapshot: Value = serde_json::from_str(&stats.snapshot_json().unwrap()).unwrap();
+        assert_eq!(snapshot["server_missing_sequences"], 1);
+        assert_eq!(snapshot["server_out_of_order"], 1);
+        // echoed PING sequences are counted apart
+        assert_eq!(snapshot["missing_sequences"], 0);
+    }
+
+    #[test]
+    fn echoed_sequence_gaps_count_from_one() {
+        let stats = ClientStats::default();
+        assert_eq!(stats.sequence_loss(), None);
+        stats.record_sequence(3);
+        assert_eq!(stats.missing_sequences(), 2);
+        stats.record_sequence(1);
+        assert_eq!(stats.missing_sequences(), 1);
+        assert_eq!(stats.out_of_order(), 1);
+        assert_eq!(stats.sequence_loss(), Some(1.0 / 3.0));
+    }
+}
@@ -0,0 +1,175 @@
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use signaling_shared::compression::{
+    compress, decompress_into, COMPRESSION_ACCEPTED, COMPRESSION_OFFER,
+};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tokio::time::{self, Duration};
+
+use crate::transport::Transport;
+
+/// Name & description of every codec `--compress` can agree on
+pub const COMPRESSION_CODECS: &[(&str, &str)] = &[(
+    "deflate",
+    "deflates each payload that gets smaller for it, at the fastest level",
+)];
+
+/// Asks the server to compress the connection, returning whether it agreed
+/// within `timeout`. Messages received before the answer, up to
+/// `max_message_size` bytes each, are discarded.
+pub async fn negotiate_compression<T: Transport + ?Sized>(
+    transport: &T,
+    timeout: Duration,
+    max_message_size: usize,
+) -> Result<bool> {
+    transport
+        .send(&Bytes::from_static(COMPRESSION_OFFER))
+        .await
+        .context("failed to send the compression offer")?;
+
+    match time::timeout(timeout, receive_acceptance(transport, max_message_size)).await {
+        Ok(result) => result.map(|_| true),
+        Err(_) => Ok(false),
+    }
+}
+
+async fn receive_acceptance<T: Transport + ?Sized>(
+    transport: &T,
+    max_message_size: usize,
+) -> Result<()> {
+    let mut buffer = vec![0u8; max_message_size];
+    loop {
+        let length = transport
+            .recv(&mut buffer)
+            .await
+            .context("channel closed before the compression answer arrived")?;
+        if buffer[..length] == *COMPRESSION_ACCEPTED {
+            return Ok(());
+        }
+    }
+}
+
+/// Wraps a Transport so every message is sent through `compress` and every
+/// received one through `decompress`
+pub struct CompressedTransport<T: Transport + ?Sized> {
+    inner: Arc<T>,
+    // what's read from the inner transport, kept between reads so each one
+    // doesn't allocate
+    message: Mutex<Vec<u8>>,
+}
+
+impl<T: Transport + ?Sized> CompressedTransport<T> {
+    pub fn new(inner: Arc<T>) -> Self {
+        CompressedTransport {
+            inner,
+            message: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for CompressedTransport<T> {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        self.inner.send(&Bytes::from(compress(payload))).await?;
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut message = self.message.lock().await;
+        // a deflated message can be as large as a whole uncompressed one
+        let size = buffer.len() + 1;
+        if message.len() < size {
+            message.resize(size, 0);
+        }
+        let length = self.inner.recv(&mut message[..size]).await?;
+        Ok(decompress_into(&message[..length], buffer)?)
+    }
+
+    fn buffered_amount(&self) -> usize {
+        self.inner.buffered_amount()
+    }
+
+    async fn buffered_amount_low(&self, threshold: usize) {
+        self.inner.buffered_amount_low(threshold).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{framing::FramedTransport, transport::MemoryTransport};
+
+    fn compressible() -> Vec<u8> {
+        b"PING ".repeat(200)
+    }
+
+    // bytes from a xorshift generator, which deflate can't shrink
+    fn incompressible() -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..1000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn negotiation_skips_messages_larger_than_a_packet() {
+        let (client, server) = MemoryTransport::pair();
+        server.send(&Bytes::from(vec![b'x'; 4000])).await.unwrap();
+        server
+            .send(&Bytes::from_static(COMPRESSION_ACCEPTED))
+            .await
+            .unwrap();
+
+        assert!(negotiate_compression(&client, Duration::from_secs(1), 4096)
+            .await
+            .unwrap());
+        let mut buffer = vec![0u8; 1500];
+        let length = server.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], COMPRESSION_OFFER);
+    }
+
+    #[tokio::test]
+    async fn no_answer_leaves_compression_off() {
+        let (client, _server) = MemoryTransport::pair();
+        assert!(
+            !negotiate_compression(&client, Duration::from_millis(10), 4096)
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn transport_round_trips_through_framing() {
+        let (near, far) = MemoryTransport::pair();
+        let near = CompressedTransport::new(Arc::new(FramedTransport::new(Arc::new(near))));
+        let far = CompressedTransport::new(Arc::new(FramedTransport::new(Arc::new(far))));
+
+        let payloads = [
+            compressible(),
+            incompressible(),
+            b"PING".to_vec(),
+            Vec::new(),
+        ];
+        for payload in &payloads {
+            let sent = near.send(&Bytes::from(payload.clone())).await.unwrap();
+            assert_eq!(sent, payload.len());
+        }
+        // the same read buffer serves every size of message
+        let mut buffer = vec![0u8; 1500];
+        for payload in &payloads {
+            let length = far.recv(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..length], &payload[..]);
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use tokio::sync::mpsc;
+
+/// What's happening to a connection, at the level an application shows its
+/// user, as opposed to the raw state changes of a `ClientEvent`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// A connection attempt started signaling the server
+    Connecting,
+    /// The peer connection connected
+    Connected,
+    /// The peer connection was interrupted, failed or closed
+    Disconnected { reason: String },
+    /// A local candidate was gathered, or one of the server's was added
+    CandidateAdded { candidate: String, local: bool },
+    /// A connection attempt failed
+    Error { reason: String },
+}
+
+/// Where a client delivers its `ConnectionEvent`s. The default delivers them
+/// nowhere. Clones, e.g. of a `ClientConfig` a `Reconnector` reuses, deliver
+/// to the same receiver.
+#[derive(Clone, Default)]
+pub struct ConnectionEvents {
+    sender: Option<mpsc::UnboundedSender<ConnectionEvent>>,
+}
+
+impl ConnectionEvents {
+    /// Events to set as `ClientConfig::connection_events`, and the receiver
+    /// they arrive on
+    pub fn channel() -> (ConnectionEvents, mpsc::UnboundedReceiver<ConnectionEvent>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (
+            ConnectionEvents {
+                sender: Some(sender),
+            },
+            receiver,
+        )
+    }
+
+    // a dropped receiver only means nobody's listening any more
+    pub(crate) fn send(&self, event: ConnectionEvent) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Client, ClientConfig};
+
+    #[test]
+    fn clones_deliver_to_the_same_receiver() {
+        let (events, mut receiver) = ConnectionEvents::channel();
+        events.send(ConnectionEvent::Connecting);
+        events.clone().send(ConnectionEvent::Connected);
+
+        assert_eq!(receiver.try_recv(), Ok(ConnectionEvent::Connecting));
+        assert_eq!(receiver.try_recv(), Ok(ConnectionEvent::Connected));
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn sending_without_a_receiver_is_fine() {
+        ConnectionEvents::default().send(ConnectionEvent::Connecting);
+
+        let (events, receiver) = ConnectionEvents::channel();
+        drop(receiver);
+        events.send(ConnectionEvent::Connected);
+    }
+
+    #[tokio::test]
+    async fn failed_connect_reports_connecting_then_error() {
+        // nothing listens on a port that was just freed
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/rtc_session", port);
+        let (events, mut receiver) = ConnectionEvents::channel();
+        let config = ClientConfig {
+            ice_servers: Vec::new(),
+            signaling_attempts: 1,
+            connection_events: events,
+            ..ClientConfig::new(url.parse().unwrap())
+        };
+
+        assert!(Client::connect(config).await.is_err());
+        assert_eq!(receiver.recv().await, Some(ConnectionEvent::Connecting));
+        loop {
+            match receiver.recv().await {
+                Some(ConnectionEvent::Error { .. }) => break,
+                Some(ConnectionEvent::CandidateAdded { .. }) => {}
+                other => panic!("expected an error event, got {:?}", other),
+            }
+        }
+    }
+}
@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
+
+use webrtc::ice_transport::ice_connection_state::RTCIceConnectionState;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+/// Tracks whether the client is connected, using only atomics so it can be
+/// polled frequently without contending with the read & write loops
+pub struct ConnectionStatus {
+    peer_connection_state: AtomicU8,
+    ice_connection_state: AtomicU8,
+    channel_open: AtomicBool,
+    created: Instant,
+    // millis after `created` the channel first opened, plus one; zero if never
+    opened_at_ms: AtomicU64,
+}
+
+impl Default for ConnectionStatus {
+    fn default() -> Self {
+        ConnectionStatus {
+            peer_connection_state: AtomicU8::default(),
+            ice_connection_state: AtomicU8::default(),
+            channel_open: AtomicBool::default(),
+            created: Instant::now(),
+            opened_at_ms: AtomicU64::default(),
+        }
+    }
+}
+
+impl ConnectionStatus {
+    pub fn set_connection_state(&self, state: RTCPeerConnectionState) {
+        self.peer_connection_state
+            .store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_ice_connection_state(&self, state: RTCIceConnectionState) {
+        self.ice_connection_state
+            .store(state as u8, Ordering::Relaxed);
+    }
+
+    pub fn set_channel_open(&self, open: bool) {
+        if open {
+            let opened_at_ms = self.created.elapsed().as_millis() as u64 + 1;
+            let _ = self.opened_at_ms.compare_exchange(
+                0,
+                opened_at_ms,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            );
+        }
+        self.channel_open.store(open, Ordering::Relaxed);
+    }
+
+    /// How long it has been since the data channel first opened, zero if it
+    /// never did
+    pub fn connected_for(&self) -> Duration {
+        match self.opened_at_ms.load(Ordering::Relaxed) {
+            0 => Duration::ZERO,
+            opened_at_ms => self
+                .created
+                .elapsed()
+                .saturating_sub(Duration::from_millis(opened_at_ms - 1)),
+        }
+    }
+
+    /// The last state reported by the peer connection
+    pub fn connection_state(&self) -> RTCPeerConnectionState {
+        RTCPeerConnectionState::from(self.peer_connection_state.load(Ordering::Relaxed))
+    }
+
+    /// The last state reported by the ICE transport, which finds the network
+    /// path the peer connection runs over
+    pub fn ice_connection_state(&self) -> RTCIceConnectionState {
+        RTCIceConnectionState::from(self.ice_connection_state.load(Ordering::Relaxed))
+    }
+
+    /// Whether the peer connection is connected and the data channel is
+    /// open & carrying traffic
+    pub fn is_connected(&self) -> bool {
+        self.connection_state() == RTCPeerConnectionState::Connected
+            && self.channel_open.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,112 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc;
+
+use crate::{client_stats::unix_millis, reconnect::ReconnectReason};
+
+/// A lifecycle event worth keeping for post-mortem debugging, serialized
+/// with its name under `event` alongside its fields
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum ClientEvent {
+    PeerConnectionState {
+        state: String,
+    },
+    IceConnectionState {
+        state: String,
+    },
+    DataChannelOpen,
+    DataChannelClosed,
+    CandidatePairChanged {
+        from: String,
+        to: String,
+    },
+    Reconnecting {
+        attempt: u32,
+        reason: ReconnectReason,
+        error: String,
+    },
+    Error {
+        reason: String,
+    },
+    Finished,
+}
+
+/// Appends every recorded event, with a unix timestamp in milliseconds, to a
+/// file as JSON lines, and hands it to every subscriber. The default log has
+/// no file, so only subscribers see its events.
+#[derive(Clone, Default)]
+pub struct EventLog {
+    file: Option<Arc<Mutex<File>>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<ClientEvent>>>>,
+}
+
+impl EventLog {
+    pub fn open(path: &str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open event log {}", path))?;
+        Ok(EventLog {
+            file: Some(Arc::new(Mutex::new(file))),
+            subscribers: Arc::default(),
+        })
+    }
+
+    /// A receiver of every event recorded from now on, e.g. to follow the
+    /// connection's state changes & reconnects. Dropping it unsubscribes.
+    pub fn subscribe(&self) -> mpsc::UnboundedReceiver<ClientEvent> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.lock_subscribers().push(sender);
+        receiver
+    }
+
+    fn lock_subscribers(
+        &self,
+    ) -> std::sync::MutexGuard<'_, Vec<mpsc::UnboundedSender<ClientEvent>>> {
+        self.subscribers
+            .lock()
+            .expect("event log subscribers lock poisoned")
+    }
+
+    /// Writes `event` straight away, so the log is complete even if the
+    /// process exits right after
+    pub fn record(&self, event: ClientEvent) {
+        self.lock_subscribers()
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+
+        let file = match &self.file {
+            Some(file) => file,
+            None => return,
+        };
+
+        let result = serde_json::to_value(&event)
+            .and_then(|mut value| {
+                if let Value::Object(object) = &mut value {
+                    object.insert(
+                        "timestamp_ms".to_string(),
+                        Value::from(unix_millis() as u64),
+                    );
+                }
+                serde_json::to_string(&value)
+            })
+            .context("failed to serialize event")
+            .and_then(|mut line| {
+                line.push('\n');
+                let mut file = file.lock().expect("event log lock poisoned");
+                file.write_all(line.as_bytes())
+                    .context("failed to write event log")
+            });
+        if let Err(error) = result {
+            warn!("{:#}", error);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use signaling_shared::fragment::{
+    Fragmenter, Reassembler, DEFAULT_REASSEMBLY_TIMEOUT, MAX_FRAGMENTED_SIZE,
+};
+
+use crate::{client_stats::ClientStats, loops::MESSAGE_SIZE, transport::Transport};
+
+/// Wraps a Transport so every message is split into fragments small enough
+/// for a single data channel message, and received fragments are put back
+/// together, so messages of any size up to `MAX_FRAGMENTED_SIZE` get
+/// through an unreliable channel whole or not at all. At most
+/// `max_reassembly_bytes` of fragments are held for partly received
+/// messages, the oldest given up on past it. Messages given up on, and the
+/// bytes held, are counted in `stats`.
+pub struct FragmentedTransport<T: Transport + ?Sized> {
+    inner: Arc<T>,
+    stats: Arc<ClientStats>,
+    fragmenter: std::sync::Mutex<Fragmenter>,
+    reader: Mutex<FragmentReader>,
+}
+
+// the reassembler, and the buffer reads from the inner transport go into
+struct FragmentReader {
+    reassembler: Reassembler,
+    chunk: Vec<u8>,
+}
+
+impl<T: Transport + ?Sized> FragmentedTransport<T> {
+    pub fn new(inner: Arc<T>, stats: Arc<ClientStats>, max_reassembly_bytes: usize) -> Self {
+        FragmentedTransport {
+            inner,
+            stats,
+            fragmenter: std::sync::Mutex::new(Fragmenter::default()),
+            reader: Mutex::new(FragmentReader {
+                reassembler: Reassembler::with_max_bytes(
+                    DEFAULT_REASSEMBLY_TIMEOUT,
+                    max_reassembly_bytes,
+                ),
+                chunk: vec![0u8; MESSAGE_SIZE],
+            }),
+        }
+    }
+}
+
+// a closed channel's partial messages are gone, so they stop counting
+impl<T: Transport + ?Sized> Drop for FragmentedTransport<T> {
+    fn drop(&mut self) {
+        let held = self.reader.get_mut().reassembler.held_bytes();
+        self.stats.record_reassembly_bytes(held, 0);
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for FragmentedTransport<T> {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        let fragments = match self
+            .fragmenter
+            .lock()
+            .expect("fragmenter lock poisoned")
+            .split(payload)
+        {
+            Some(fragments) => fragments,
+            None => bail!(
+                "payload of {} bytes is larger than the {} byte fragmenting limit",
+                payload.len(),
+                MAX_FRAGMENTED_SIZE
+            ),
+        };
+        for fragment in fragments {
+            self.inner.send(&Bytes::from(fragment)).await?;
+        }
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut reader = self.reader.lock().await;
+        let FragmentReader { reassembler, chunk } = &mut *reader;
+        loop {
+            let length = self.inner.recv(chunk).await?;
+            let expired = reassembler.expired();
+            let held = reassembler.held_bytes();
+            let pushed = reassembler.push(&chunk[..length]);
+            self.stats
+                .record_reassembly_bytes(held, reassembler.held_bytes());
+            let message = match pushed {
+                Ok(message) => message,
+                // the rest of the message can still arrive intact
+                Err(error) => {
+                    warn!("Dropping {} byte fragment: {}", length, error);
+                    continue;
+                }
+            };
+            if reassembler.expired() > expired {
+                self.stats
+                    .record_reassembly_failures(reassembler.expired() - expired);
+                warn!(
+                    "Gave up reassembling {} message(s) missing fragments or over the reassembly budget ({} so far)",
+                    reassembler.expired() - expired,
+                    reassembler.expired()
+                );
+            }
+            if let Some(message) = message {
+                if message.len() > buffer.len() {
+                    bail!(
+                        "reassembled message of {} bytes does not fit in a {} byte buffer",
+                        message.len(),
+                        buffer.len()
+                    );
+                }
+                buffer[..message.len()].copy_from_slice(&message);
+                return Ok(message.len());
+            }
+        }
+    }
+
+    fn buffered_amount(&self) -> usize {
+        self.inner.buffered_amount()
+    }
+
+    async fn buffered_amount_low(&self, threshold: usize) {
+        self.inner.buffered_amount_low(threshold).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+    use signaling_shared::fragment::{DEFAULT_MAX_REASSEMBLY_BYTES, FRAGMENT_SIZE};
+
+    #[tokio::test]
+    async fn messages_of_any_size_round_trip() {
+        let (near, far) = MemoryTransport::pair();
+        let stats = Arc::new(ClientStats::default());
+        let near = FragmentedTransport::new(
+            Arc::new(near),
+            Arc::clone(&stats),
+            DEFAULT_MAX_REASSEMBLY_BYTES,
+        );
+        let far = FragmentedTransport::new(
+            Arc::new(far),
+            Arc::clone(&stats),
+            DEFAULT_MAX_REASSEMBLY_BYTES,
+        );
+
+        let payloads: Vec<Vec<u8>> = [
+            0,
+            1,
+            FRAGMENT_SIZE,
+            FRAGMENT_SIZE + 1,
+            7 * FRAGMENT_SIZE / 2,
+        ]
+        .iter()
+        .map(|&size| (0..size).map(|byte| byte as u8).collect())
+        .collect();
+        for payload in &payloads {
+            let sent = near.send(&Bytes::from(payload.clone())).await.unwrap();
+            assert_eq!(sent, payload.len());
+        }
+        let mut buffer = vec![0u8; 4 * FRAGMENT_SIZE];
+        for payload in &payloads {
+            let length = far.recv(&mut buffer).await.unwrap();
+            assert_eq!(&buffer[..length], &payload[..]);
+        }
+        assert_eq!(stats.reassembly_failures(), 0);
+        assert_eq!(stats.reassembly_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn fragments_are_reassembled_in_any_order() {
+        let (near, far) = MemoryTransport::pair();
+        let stats = Arc::new(ClientStats::default());
+        let far = FragmentedTransport::new(
+            Arc::new(far),
+            Arc::clone(&stats),
+            DEFAULT_MAX_REASSEMBLY_BYTES,
+        );
+
+        let payload: Vec<u8> = (0..3 * FRAGMENT_SIZE)
+            .map(|byte| (byte % 251) as u8)
+            .collect();
+        let mut fragments = Fragmenter::default().split(&payload).unwrap();
+        fragments.reverse();
+        // a malformed fragment in between is skipped without losing the rest
+        near.send(&Bytes::from(fragments.remove(0))).await.unwrap();
+        near.send(&Bytes::from_static(b"bad")).await.unwrap();
+        for fragment in fragments {
+            near.send(&Bytes::from(fragment)).await.unwrap();
+        }
+
+        let mut buffer = vec![0u8; 4 * FRAGMENT_SIZE];
+        let length = far.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], &payload[..]);
+        assert_eq!(stats.reassembly_bytes(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_message_too_big_for_the_buffer_is_an_error() {
+        let (near, far) = MemoryTransport::pair();
+        let stats = Arc::new(ClientStats::default());
+        let near = FragmentedTransport::new(
+            Arc::new(near),
+            Arc::clone(&stats),
+            DEFAULT_MAX_REASSEMBLY_BYTES,
+        );
+        let far = FragmentedTransport::new(Arc::new(far), stats, DEFAULT_MAX_REASSEMBLY_BYTES);
+
+        near.send(&Bytes::from(vec![0u8; 2 * FRAGMENT_SIZE]))
+            .await
+            .unwrap();
+        let mut buffer = vec![0u8; FRAGMENT_SIZE];
+        assert!(far.recv(&mut buffer).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn going_over_the_byte_budget_gives_up_on_the_oldest_message() {
+        let (near, far) = MemoryTransport::pair();
+        let stats = Arc::new(ClientStats::default());
+        let far = FragmentedTransport::new(Arc::new(far), Arc::clone(&stats), 2 * FRAGMENT_SIZE);
+
+        // the first halves of three messages, then the rest of the newest
+        let mut fragmenter = Fragmenter::default();
+        let messages: Vec<_> = (0..3u8)
+            .map(|byte| fragmenter.split(&[byte; 2 * FRAGMENT_SIZE]).unwrap())
+            .collect();
+        for fragments in &messages {
+            near.send(&Bytes::from(fragments[0].clone())).await.unwrap();
+        }
+        near.send(&Bytes::from(messages[2][1].clone()))
+            .await
+            .unwrap();
+
+        let mut buffer = vec![0u8; 4 * FRAGMENT_SIZE];
+        let length = far.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], &[2; 2 * FRAGMENT_SIZE][..]);
+        assert_eq!(stats.reassembly_failures(), 1);
+        // the second message's first half is all that's left
+        assert_eq!(stats.reassembly_bytes(), FRAGMENT_SIZE as u64);
+
+        drop(far);
+        assert_eq!(stats.reassembly_bytes(), 0);
+    }
+}
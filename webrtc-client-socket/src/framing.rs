@@ -0,0 +1,142 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use signaling_shared::framing::{encode_frame, FrameDecoder, LENGTH_PREFIX_SIZE, MAX_FRAME_SIZE};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::transport::Transport;
+
+/// Wraps a Transport so every message is sent as a length-prefixed frame,
+/// and received messages are reassembled from frames, so binary payloads
+/// and frames spanning several reads come through intact
+pub struct FramedTransport<T: Transport + ?Sized> {
+    inner: Arc<T>,
+    reader: Mutex<FrameReader>,
+}
+
+// the decoder, and the buffer reads from the inner transport go into, big
+// enough for the largest frame the other side can send in one message
+struct FrameReader {
+    decoder: FrameDecoder,
+    chunk: Vec<u8>,
+}
+
+impl<T: Transport + ?Sized> FramedTransport<T> {
+    pub fn new(inner: Arc<T>) -> Self {
+        FramedTransport {
+            inner,
+            reader: Mutex::new(FrameReader {
+                decoder: FrameDecoder::default(),
+                chunk: vec![0u8; LENGTH_PREFIX_SIZE + MAX_FRAME_SIZE],
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: Transport + ?Sized> Transport for FramedTransport<T> {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        let frame = encode_frame(payload).ok_or_else(|| {
+            anyhow!(
+                "payload of {} bytes is larger than the {} byte frame limit",
+                payload.len(),
+                MAX_FRAME_SIZE
+            )
+        })?;
+        let frame = Bytes::from(frame);
+        self.inner.send(&frame).await?;
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut reader = self.reader.lock().await;
+        let FrameReader { decoder, chunk } = &mut *reader;
+        loop {
+            if let Some(frame) = decoder.next_frame() {
+                if frame.len() > buffer.len() {
+                    bail!(
+                        "frame of {} bytes does not fit in a {} byte buffer",
+                        frame.len(),
+                        buffer.len()
+                    );
+                }
+                buffer[..frame.len()].copy_from_slice(&frame);
+                return Ok(frame.len());
+            }
+
+            let length = self.inner.recv(chunk).await?;
+            decoder.push(&chunk[..length]);
+        }
+    }
+
+    fn buffered_amount(&self) -> usize {
+        self.inner.buffered_amount()
+    }
+
+    async fn buffered_amount_low(&self, threshold: usize) {
+        self.inner.buffered_amount_low(threshold).await
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    #[tokio::test]
+    async fn frames_split_across_messages() {
+        let (near, far) = MemoryTransport::pair();
+        let framed = FramedTransport::new(Arc::new(far));
+        let mut bytes = encode_frame(b"PING").unwrap();
+        bytes.extend(encode_frame(b"PONG").unwrap());
+        for chunk in bytes.chunks(3) {
+            near.send(&Bytes::copy_from_slice(chunk)).await.unwrap();
+        }
+
+        let mut buffer = [0u8; 16];
+        let length = framed.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"PING");
+        let length = framed.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"PONG");
+    }
+
+    #[tokio::test]
+    async fn largest_frame_in_one_message() {
+        let (near, far) = MemoryTransport::pair();
+        let framed = FramedTransport::new(Arc::new(far));
+        let payload = Bytes::from(vec![1u8; MAX_FRAME_SIZE]);
+        FramedTransport::new(Arc::new(near))
+            .send(&payload)
+            .await
+            .unwrap();
+
+        let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+        let length = framed.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], &payload[..]);
+    }
+
+    #[tokio::test]
+    async fn oversized_payload_is_rejected() {
+        let (near, _far) = MemoryTransport::pair();
+        let framed = FramedTransport::new(Arc::new(near));
+        let payload = Bytes::from(vec![0u8; MAX_FRAME_SIZE + 1]);
+        assert!(framed.send(&payload).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn frame_larger_than_the_buffer_is_an_error() {
+        let (near, far) = MemoryTransport::pair();
+        let framed = FramedTransport::new(Arc::new(far));
+        near.send(&Bytes::from(encode_frame(b"too long").unwrap()))
+            .await
+            .unwrap();
+
+        let mut buffer = [0u8; 4];
+        assert!(framed.recv(&mut buffer).await.is_err());
+    }
+}
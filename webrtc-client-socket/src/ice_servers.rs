@@ -0,0 +1,111 @@
+use anyhow::{bail, Result};
+use webrtc::ice_transport::{ice_candidate_type::RTCIceCandidateType, ice_server::RTCIceServer};
+
+/// STUN server used when none are configured
+pub const DEFAULT_STUN_SERVER: &str = "stun:stun.l.google.com:19302";
+
+/// Builds the ICE server list from `stun:`, `turn:` & `turns:` URLs. TURN
+/// servers need both `username` and `credential`, which are shared by every
+/// TURN URL given.
+pub fn build_ice_servers(
+    urls: &[String],
+    username: Option<&str>,
+    credential: Option<&str>,
+) -> Result<Vec<RTCIceServer>> {
+    let mut ice_servers = Vec::with_capacity(urls.len());
+    for url in urls {
+        let scheme = url.split(':').next().unwrap_or_default();
+        let ice_server = match scheme {
+            "stun" => RTCIceServer {
+                urls: vec![url.clone()],
+                ..Default::default()
+            },
+            "turn" | "turns" => match (username, credential) {
+                (Some(username), Some(credential)) => RTCIceServer {
+                    urls: vec![url.clone()],
+                    username: username.to_string(),
+                    credential: credential.to_string(),
+                    ..Default::default()
+                },
+                _ => bail!(
+                    "TURN server '{}' needs both --turn-username and --turn-credential",
+                    url
+                ),
+            },
+            _ => bail!(
+                "ICE server '{}' must start with stun:, turn: or turns:",
+                url
+            ),
+        };
+        ice_servers.push(ice_server);
+    }
+    Ok(ice_servers)
+}
+
+/// The kinds of local candidate gathering produced, to tell which of the
+/// ICE servers couldn't be reached once it finishes or times out
+#[derive(Default)]
+pub(crate) struct GatheredCandidates {
+    server_reflexive: bool,
+    relayed: bool,
+    warned: bool,
+}
+
+impl GatheredCandidates {
+    pub(crate) fn record(&mut self, candidate_type: RTCIceCandidateType) {
+        match candidate_type {
+            RTCIceCandidateType::Srflx => self.server_reflexive = true,
+            RTCIceCandidateType::Relay => self.relayed = true,
+            _ => {}
+        }
+    }
+
+    /// Warns about the kinds of ICE server that yielded no candidate. The
+    /// connection goes ahead regardless, with the candidates that were
+    /// gathered: without relayed ones the client has to reach the server
+    /// directly or through its NAT. Only the first call warns.
+    pub(crate) fn warn_unreachable(&mut self, ice_servers: &[RTCIceServer]) {
+        if std::mem::replace(&mut self.warned, true) {
+            return;
+        }
+        let configured = |schemes: &[&str]| -> Vec<String> {
+            ice_servers
+                .iter()
+                .filter(|ice_server| {
+                    ice_server
+                        .urls
+                        .iter()
+                        .any(|url| schemes.contains(&url.split(':').next().unwrap_or_default()))
+                })
+                .map(describe_ice_server)
+                .collect()
+        };
+        let stun_servers = configured(&["stun"]);
+        if !self.server_reflexive && !stun_servers.is_empty() {
+            warn!(
+                "No server reflexive candidates gathered; STUN server(s) unreachable: {}",
+                stun_servers.join("; ")
+            );
+        }
+        let turn_servers = configured(&["turn", "turns"]);
+        if !self.relayed && !turn_servers.is_empty() {
+            warn!(
+                "No relayed candidates gathered; TURN server(s) unreachable or rejected the credentials, continuing without a relay: {}",
+                turn_servers.join("; ")
+            );
+        }
+    }
+}
+
+/// Describes an ICE server for logging, without its credential
+pub fn describe_ice_server(ice_server: &RTCIceServer) -> String {
+    if ice_server.username.is_empty() {
+        ice_server.urls.join(", ")
+    } else {
+        format!(
+            "{} (username '{}', credential redacted)",
+            ice_server.urls.join(", "),
+            ice_server.username
+        )
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::{client_stats::ClientStats, reconnect::ReconnectReason, transport::Transport};
+
+/// Byte value keepalives are sent as unless configured otherwise. The server
+/// drops single-byte payloads of this value instead of handling them.
+pub const DEFAULT_KEEPALIVE_MARKER: u8 = 0;
+
+/// Sends a single `marker` byte every `interval` until the transport closes
+/// or the client shuts down,
+/// so the server keeps seeing the client while it has nothing else to send
+pub async fn keepalive_loop<T: Transport + ?Sized>(
+    transport: Arc<T>,
+    marker: u8,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let keepalive = Bytes::from(vec![marker]);
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+        if let Err(err) = transport.send(&keepalive).await {
+            info!("Datachannel closed; Exit the keepalive_loop: {}", err);
+            return Ok(());
+        }
+    }
+}
+
+/// Fails once nothing, PONGs or otherwise, has been received for `timeout`,
+/// counting from when it's called, which catches a half-open connection that
+/// keeps accepting sends; resolves once the client shuts down otherwise. The
+/// error is tagged `ReconnectReason::KeepaliveTimeout`.
+pub async fn receive_timeout(
+    stats: &ClientStats,
+    timeout: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let started = Instant::now();
+    loop {
+        let quiet_for = stats.since_last_received().min(started.elapsed());
+        if quiet_for >= timeout {
+            return Err(ReconnectReason::KeepaliveTimeout.error(anyhow!(
+                "nothing received from the server for {:?}",
+                quiet_for
+            )));
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(timeout - quiet_for) => {}
+            _ = shutdown.changed() => return Ok(()),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! # WebRTC Client Socket
+//! A webrtc-rs client for naia's signaling & data channel, behind naia's
+//! socket API in [`ClientSocket`], and the building blocks of the example
+//! client it's demoed by. Everything that talks over the data channel is
+//! generic over a [`Transport`], so it can also be driven without a live
+//! peer connection
+
+#[macro_use]
+extern crate tracing;
+
+mod ack;
+mod addr_cell;
+mod burst;
+mod candidate_pair;
+mod catalog;
+mod challenge;
+mod checksum;
+mod client;
+mod client_stats;
+mod compression;
+mod connection_events;
+mod connection_status;
+mod event_log;
+mod fragmenting;
+mod framing;
+mod ice_servers;
+mod keepalive;
+mod load_test;
+mod log_sampler;
+mod loops;
+mod metrics;
+mod quality;
+mod readiness;
+mod reconnect;
+mod repl;
+mod send_queue;
+mod session;
+mod signaling;
+mod slow_start;
+mod socket;
+// an allocator counting allocations, for the tests of the receive path
+#[cfg(test)]
+mod test_alloc;
+// a one-shot HTTP server, for the signaling requests' tests
+#[cfg(test)]
+mod test_http;
+mod transport;
+mod trickle;
+
+pub use ack::{
+    parse_ack, retransmit_loop, AckSettings, AckTracker, ACK_PREFIX, ACK_REQUEST_PREFIX,
+};
+pub use addr_cell::{AddrCell, ServerAddr};
+pub use burst::send_burst;
+pub use candidate_pair::monitor_candidate_pair;
+pub use catalog::write_catalog;
+pub use challenge::verify_server;
+pub use checksum::ChecksumTransport;
+pub use client::{
+    ChannelMessage, ChannelSettings, Client, ClientConfig, LabeledChannel, CONNECTION_ID_HEADER,
+    CONNECTION_ID_LENGTH, DATA_CHANNEL_LABEL, DEFAULT_GATHERING_TIMEOUT,
+    DEFAULT_MAX_REASSEMBLY_BYTES, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_SIGNALING_ATTEMPTS,
+    DEFAULT_SIGNALING_TIMEOUT, SIGNALING_PROTOCOLS,
+};
+pub use client_stats::{log_stats, write_stats_file, ClientStats, ConnectionStats};
+pub use compression::{negotiate_compression, CompressedTransport, COMPRESSION_CODECS};
+pub use connection_events::{ConnectionEvent, ConnectionEvents};
+pub use connection_status::ConnectionStatus;
+pub use event_log::{ClientEvent, EventLog};
+pub use fragmenting::FragmentedTransport;
+pub use framing::FramedTransport;
+pub use ice_servers::{build_ice_servers, describe_ice_server, DEFAULT_STUN_SERVER};
+pub use keepalive::{keepalive_loop, receive_timeout, DEFAULT_KEEPALIVE_MARKER};
+pub use load_test::{run_load_test, LoadTestSettings, LoadTestSummary};
+pub use log_sampler::LogSampler;
+pub use loops::{
+    read_loop, write_loop, ReadSettings, SendSettings, DEFAULT_MAX_BUFFERED_AMOUNT,
+    DEFAULT_MAX_MESSAGE_SIZE, MESSAGE_SIZE,
+};
+pub use metrics::MetricsEndpoint;
+pub use quality::QualityThresholds;
+pub use readiness::confirm_ready;
+pub use reconnect::{Backoff, ReconnectBudget, ReconnectReason, Reconnector};
+pub use repl::run_repl;
+pub use send_queue::{SendQueue, SendQueueFull, DEFAULT_SEND_QUEUE_CAPACITY};
+pub use session::SessionSettings;
+pub use signaling::{
+    add_server_candidates, apply_session_response, get_session_response, read_capped_body,
+    ResponseFieldPaths, SessionAnswer, SessionCandidate, SessionResponse, SignalingError,
+    TrickleResponse,
+};
+pub use signaling_shared::compression::{
+    compress, decompress, decompress_into, CompressionError, COMPRESSION_ACCEPTED,
+    COMPRESSION_OFFER, DEFLATED, UNCOMPRESSED,
+};
+pub use signaling_shared::framing::{
+    encode_frame, FrameDecoder, LENGTH_PREFIX_SIZE, MAX_FRAME_SIZE,
+};
+pub use slow_start::{RampShape, SlowStart};
+pub use socket::{ClientSocket, PacketReceiver, PacketSender};
+pub use transport::{MemoryTransport, Transport};
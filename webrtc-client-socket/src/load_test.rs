@@ -0,0 +1,296 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::Instrument;
+use webrtc::peer_connection::math_rand_alpha;
+
+use crate::{
+    client::{Client, ClientConfig, CONNECTION_ID_LENGTH},
+    client_stats::ClientStats,
+    connection_status::ConnectionStatus,
+    log_sampler::LogSampler,
+    loops::{read_loop, write_loop, ReadSettings, SendSettings},
+};
+
+// how long connections get to read the last echoes once sending stops
+const DRAIN: Duration = Duration::from_secs(1);
+
+/// How a load test runs. Every connection gets a copy of the send & read
+/// settings, and a `ClientConfig` of its own.
+#[derive(Clone)]
+pub struct LoadTestSettings {
+    /// Peer connections to open at once
+    pub connections: usize,
+    pub send: SendSettings,
+    pub read: ReadSettings,
+    /// How long to send for once connected; without it, until `stop`
+    /// resolves or every connection has sent `send.count`
+    pub duration: Option<Duration>,
+    /// Log 1 in every this many received messages, per connection
+    pub log_every: u64,
+    /// Log at most this many received messages per second, per connection
+    pub log_max_per_sec: Option<u32>,
+}
+
+/// What a load test's connections did between them
+#[derive(Clone, Debug, Default)]
+pub struct LoadTestSummary {
+    pub connections: usize,
+    /// Connections whose data channel opened
+    pub connected: usize,
+    /// Why the connections that failed did, with how many failed that way
+    pub errors: BTreeMap<String, usize>,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    /// Sent messages that never got a PONG
+    pub lost: u64,
+    pub read_errors: u64,
+    pub queue_drops: u64,
+    pub average_rtt: Option<Duration>,
+    pub max_rtt: Option<Duration>,
+    /// From the first connection starting to the last one closing
+    pub elapsed: Duration,
+}
+
+impl LoadTestSummary {
+    /// Share of sent messages that never got a PONG, `None` if nothing was
+    /// sent
+    pub fn loss(&self) -> Option<f64> {
+        (self.messages_sent > 0).then(|| self.lost as f64 / self.messages_sent as f64)
+    }
+
+    fn add(&mut self, stats: &ClientStats) {
+        self.messages_sent += stats.messages_sent();
+        self.bytes_sent += stats.bytes_sent();
+        self.messages_received += stats.messages_received();
+        self.bytes_received += stats.bytes_received();
+        self.lost += stats.messages_sent().saturating_sub(stats.pongs_received());
+        self.read_errors += stats.read_errors();
+        self.queue_drops += stats.queue_drops();
+        self.max_rtt = self.max_rtt.max(stats.max_rtt());
+    }
+}
+
+/// Opens `settings.connections` peer connections from `config`, each under
+/// a connection id of its own, and runs the read & write loops over all of
+/// them until the test ends, returning their combined stats
+pub async fn run_load_test(
+    config: ClientConfig,
+    settings: LoadTestSettings,
+    stop: impl Future<Output = ()>,
+) -> LoadTestSummary {
+    let started = Instant::now();
+    let (stop_sender, stop_receiver) = watch::channel(false);
+    let (connection_stats, tasks): (Vec<_>, Vec<_>) = (0..settings.connections)
+        .map(|index| {
+            let connection_id = math_rand_alpha(CONNECTION_ID_LENGTH);
+            let span = info_span!("load", index, conn = %connection_id);
+            let config = ClientConfig {
+                connection_id,
+                status: Arc::new(ConnectionStatus::default()),
+                stats: Arc::new(ClientStats::default()),
+                ..config.clone()
+            };
+            let stats = Arc::clone(&config.stats);
+            let task = tokio::spawn(
+                run_connection(config, settings.clone(), stop_receiver.clone()).instrument(span),
+            );
+            (stats, task)
+        })
+        .unzip();
+
+    // the connections run concurrently, so waiting on them in turn only
+    // decides the order their outcomes are read in
+    tokio::pin!(stop);
+    let mut stopped = false;
+    let mut summary = LoadTestSummary {
+        connections: settings.connections,
+        ..LoadTestSummary::default()
+    };
+    for mut task in tasks {
+        let joined = loop {
+            tokio::select! {
+                joined = &mut task => break joined,
+                _ = &mut stop, if !stopped => {
+                    stopped = true;
+                    let _ = stop_sender.send(true);
+                }
+            }
+        };
+        let (connected, error) = match joined {
+            Ok(outcome) => outcome,
+            Err(error) => (false, Some(format!("connection task failed: {}", error))),
+        };
+        summary.connected += connected as usize;
+        if let Some(error) = error {
+            *summary.errors.entry(error).or_default() += 1;
+        }
+    }
+
+    let mut rtt_total = Duration::ZERO;
+    let mut rtt_connections = 0;
+    for stats in &connection_stats {
+        summary.add(stats);
+        if let Some(rtt) = stats.average_rtt() {
+            rtt_total += rtt;
+            rtt_connections += 1;
+        }
+    }
+    summary.average_rtt = (rtt_connections > 0).then(|| rtt_total / rtt_connections);
+    summary.elapsed = started.elapsed();
+    summary
+}
+
+// connects & runs the loops until the test ends, returning whether the data
+// channel opened and the error that ended the connection, if one did
+async fn run_connection(
+    config: ClientConfig,
+    settings: LoadTestSettings,
+    stop: watch::Receiver<bool>,
+) -> (bool, Option<String>) {
+    let LoadTestSettings {
+        send,
+        read,
+        duration,
+        log_every,
+        log_max_per_sec,
+        ..
+    } = settings;
+    let stats = Arc::clone(&config.stats);
+    let client = match Client::connect_until(config, stopped(stop.clone())).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return (false, Some("stopped before connecting".to_string())),
+        Err(error) => return (false, Some(format!("{:#}", error))),
+    };
+
+    let transport = client.transport();
+    let (loop_stop_sender, loop_stop) = watch::channel(false);
+    let read_task = tokio::spawn(
+        read_loop(
+            Arc::clone(&transport),
+            Arc::clone(&stats),
+            LogSampler::new(log_every, log_max_per_sec),
+            client.addr_cell().clone(),
+            read,
+            loop_stop.clone(),
+        )
+        .in_current_span(),
+    );
+    let counted = send.count.is_some();
+    let write_addr_cell = client.addr_cell().clone();
+    let mut write_task = tokio::spawn(
+        async move {
+            write_loop(transport, stats, send, None, write_addr_cell, loop_stop).await?;
+            // once the count is sent, give the last echoes a moment to arrive
+            if counted {
+                tokio::time::sleep(DRAIN).await;
+            }
+            Ok::<(), anyhow::Error>(())
+        }
+        .in_current_span(),
+    );
+
+    let duration = async {
+        match duration {
+            Some(duration) => tokio::time::sleep(duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    let mut written = false;
+    let error = tokio::select! {
+        _ = stopped(stop) => None,
+        _ = duration => None,
+        joined = &mut write_task => {
+            written = true;
+            match joined {
+                Ok(Ok(())) => None,
+                Ok(Err(error)) => Some(format!("{:#}", error)),
+                Err(error) => Some(format!("write loop failed: {}", error)),
+            }
+        }
+        error = client.closed() => Some(format!("{:#}", error)),
+    };
+
+    // the loops finish what they're doing before the channel is closed
+    let _ = loop_stop_sender.send(true);
+    let joined = tokio::time::timeout(DRAIN, async {
+        let _ = read_task.await;
+        if !written {
+            let _ = write_task.await;
+        }
+    })
+    .await;
+    if joined.is_err() {
+        warn!("The read & write loops didn't exit within {:?}", DRAIN);
+    }
+    client.close().await;
+    (true, error)
+}
+
+// resolves once the test is stopped, or can no longer be
+async fn stopped(mut stop: watch::Receiver<bool>) {
+    while !*stop.borrow() {
+        if stop.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_is_the_share_never_ponged() {
+        let mut summary = LoadTestSummary::default();
+        assert_eq!(summary.loss(), None);
+
+        let stats = ClientStats::default();
+        for _ in 0..4 {
+            stats.record_sent(4);
+        }
+        summary.add(&stats);
+        summary.add(&stats);
+        assert_eq!(summary.messages_sent, 8);
+        assert_eq!(summary.bytes_sent, 32);
+        assert_eq!(summary.lost, 8);
+        assert_eq!(summary.loss(), Some(1.0));
+    }
+
+    #[tokio::test]
+    async fn failed_connections_are_counted_by_error() {
+        // nothing listens on a port that was just freed
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("http://127.0.0.1:{}/rtc_session", port);
+        let config = ClientConfig {
+            ice_servers: Vec::new(),
+            signaling_attempts: 1,
+            ..ClientConfig::new(url.parse().unwrap())
+        };
+        let settings = LoadTestSettings {
+            connections: 3,
+            send: SendSettings::default(),
+            read: ReadSettings::default(),
+            duration: None,
+            log_every: 1,
+            log_max_per_sec: None,
+        };
+
+        let summary = run_load_test(config, settings, std::future::pending()).await;
+        assert_eq!(summary.connections, 3);
+        assert_eq!(summary.connected, 0);
+        assert_eq!(summary.errors.values().sum::<usize>(), 3);
+        assert_eq!(summary.messages_sent, 0);
+        assert_eq!(summary.loss(), None);
+    }
+}
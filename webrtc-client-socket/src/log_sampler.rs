@@ -0,0 +1,72 @@
+use std::time::Instant;
+
+/// Decides which of a high-rate stream of events get logged, keeping either
+/// 1 in every N, at most K per second (via a token bucket), or both
+pub struct LogSampler {
+    every: u64,
+    seen: u64,
+    bucket: Option<TokenBucket>,
+}
+
+impl LogSampler {
+    /// Logs one event in every `every`, further limited to `max_per_second`
+    /// lines if given
+    pub fn new(every: u64, max_per_second: Option<u32>) -> Self {
+        LogSampler {
+            every: every.max(1),
+            seen: 0,
+            bucket: max_per_second.map(TokenBucket::new),
+        }
+    }
+
+    /// Records an event, returning whether it should be logged
+    pub fn sample(&mut self) -> bool {
+        let index = self.seen;
+        self.seen += 1;
+        if !index.is_multiple_of(self.every) {
+            return false;
+        }
+
+        match &mut self.bucket {
+            Some(bucket) => bucket.try_take(),
+            None => true,
+        }
+    }
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        LogSampler::new(1, None)
+    }
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32) -> Self {
+        let capacity = f64::from(per_second);
+        TokenBucket {
+            capacity,
+            tokens: capacity,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
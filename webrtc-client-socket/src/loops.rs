@@ -0,0 +1,664 @@
+use anyhow::Result;
+use bytes::Bytes;
+use signaling_shared::{framing::MAX_FRAME_SIZE, packet::Packet};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    ack::{parse_ack, AckTracker},
+    addr_cell::{AddrCell, ServerAddr},
+    client_stats::ClientStats,
+    log_sampler::LogSampler,
+    readiness::READY_STAMP,
+    slow_start::SlowStart,
+    transport::Transport,
+};
+
+/// Size of a single read from the underlying data channel
+pub const MESSAGE_SIZE: usize = 1500;
+
+/// Largest message read_loop accepts by default, big enough for a whole
+/// frame when the transport reassembles them
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = MAX_FRAME_SIZE;
+
+// the default payload, which gets a sequence number & timestamp appended
+const PING: &[u8] = b"PING";
+// the only message the server sends back in plain ping/pong mode
+const PONG: &[u8] = b"PONG";
+/// Size of the big-endian sequence number the server stamps its messages
+/// with under `NAIA_SEQUENCE_OUTBOUND=1`
+pub const SEQUENCE_SIZE: usize = 4;
+
+// time between PINGs at full rate, unless configured otherwise
+const SEND_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Bytes the transport can buffer before write_loop pauses, by default
+pub const DEFAULT_MAX_BUFFERED_AMOUNT: usize = 256 * 1024;
+
+// at most this many throttling log lines per second
+const THROTTLE_LOGS_PER_SEC: u32 = 1;
+
+/// What write_loop sends and how often. A zero interval sends as fast as
+/// the transport's backpressure allows.
+#[derive(Clone)]
+pub struct SendSettings {
+    pub interval: Duration,
+    pub payload: Bytes,
+    /// Sending pauses once more than this many bytes are buffered, and
+    /// resumes when the buffer has drained to half of it
+    pub max_buffered_amount: usize,
+    /// Pad stamped PINGs with spaces up to this many bytes, so fixed-size
+    /// messages can still be timed
+    pub pad_to: Option<usize>,
+    /// Stop after sending this many messages
+    pub count: Option<u64>,
+    /// Send every message with an id for the server to acknowledge
+    pub acks: Option<Arc<AckTracker>>,
+    /// Send binary `Packet`s instead of text: the default payload as a
+    /// stamped `Ping`, anything else as `Data`, and a `Disconnect` on
+    /// shutdown. Stamped PINGs aren't padded.
+    pub binary: bool,
+}
+
+impl Default for SendSettings {
+    fn default() -> Self {
+        SendSettings {
+            interval: SEND_INTERVAL,
+            payload: Bytes::from_static(b"PING"),
+            max_buffered_amount: DEFAULT_MAX_BUFFERED_AMOUNT,
+            pad_to: None,
+            count: None,
+            acks: None,
+            binary: false,
+        }
+    }
+}
+
+/// How read_loop treats what it receives
+#[derive(Clone)]
+pub struct ReadSettings {
+    /// Count & warn about every message that isn't a PONG
+    pub strict_pong: bool,
+    /// Largest message that can be received whole, in bytes
+    pub max_message_size: usize,
+    /// Matches the server's ACKs to messages write_loop sent with an id
+    pub acks: Option<Arc<AckTracker>>,
+    /// Read binary `Packet`s instead of text
+    pub binary: bool,
+    /// The server stamps every message but its ACKs with a sequence number,
+    /// which is taken off and counted in the stats' server sequence gaps
+    pub server_sequences: bool,
+}
+
+impl Default for ReadSettings {
+    fn default() -> Self {
+        ReadSettings {
+            strict_pong: false,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            acks: None,
+            binary: false,
+            server_sequences: false,
+        }
+    }
+}
+
+// read_loop shows how to read from the transport (usually a detached datachannel)
+pub async fn read_loop<T: Transport + ?Sized>(
+    transport: Arc<T>,
+    stats: Arc<ClientStats>,
+    mut log_sampler: LogSampler,
+    addr_cell: AddrCell,
+    read_settings: ReadSettings,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let ReadSettings {
+        strict_pong,
+        max_message_size,
+        acks,
+        binary,
+        server_sequences,
+    } = read_settings;
+    // messages larger than the buffer can't be read whole, so it's sized to
+    // the largest message expected
+    let mut buffer = vec![0u8; max_message_size];
+    loop {
+        let message_length = tokio::select! {
+            result = transport.recv(&mut buffer) => match result {
+                Ok(length) => length,
+                Err(err) => {
+                    stats.record_read_error();
+                    info!("Datachannel closed; Exit the read_loop: {}", err);
+                    return Ok(());
+                }
+            },
+            _ = shutdown.changed() => {
+                info!("Shutting down; Exit the read_loop");
+                return Ok(());
+            }
+        };
+        stats.record_received(message_length);
+        if message_length == buffer.len() {
+            warn!(
+                "Message from {} filled the whole {} byte read buffer and may be truncated",
+                server_addr_string(&addr_cell),
+                buffer.len()
+            );
+        }
+
+        let mut message = &buffer[..message_length];
+        if let Some(acks) = &acks {
+            if let Some(id) = parse_ack(message) {
+                if acks.acknowledge(id) {
+                    stats.record_ack();
+                }
+                continue;
+            }
+        }
+        if server_sequences {
+            if message.len() < SEQUENCE_SIZE {
+                stats.record_unexpected_response();
+                warn!(
+                    "Message from {} is too short to carry a sequence number ({} so far)",
+                    server_addr_string(&addr_cell),
+                    stats.unexpected_responses()
+                );
+                continue;
+            }
+            let (sequence, body) = message.split_at(SEQUENCE_SIZE);
+            let mut sequence_bytes = [0u8; SEQUENCE_SIZE];
+            sequence_bytes.copy_from_slice(sequence);
+            stats.record_server_sequence(u32::from_be_bytes(sequence_bytes));
+            message = body;
+        }
+        if binary {
+            match Packet::decode(message) {
+                // answers an untimed ping
+                Ok(Packet::Pong { sequence: 0, .. }) => stats.record_pong(None),
+                Ok(Packet::Pong {
+                    sequence,
+                    sent_at_us,
+                }) => {
+                    record_stamped_pong(&stats, &mut log_sampler, &addr_cell, sequence, sent_at_us)
+                }
+                Ok(Packet::Disconnect) => {
+                    info!("Server disconnected; Exit the read_loop");
+                    return Ok(());
+                }
+                Ok(Packet::Data(payload)) if !strict_pong => {
+                    if log_sampler.sample() {
+                        info!(
+                            "Data from {}: {} bytes",
+                            server_addr_string(&addr_cell),
+                            payload.len()
+                        );
+                    }
+                }
+                // the server never pings, so that's as unexpected as data
+                // when only PONGs should arrive
+                Ok(packet) => {
+                    stats.record_unexpected_response();
+                    warn!(
+                        "Unexpected packet from {} ({} so far): {:?}",
+                        server_addr_string(&addr_cell),
+                        stats.unexpected_responses(),
+                        packet
+                    );
+                }
+                Err(error) => {
+                    stats.record_unexpected_response();
+                    warn!(
+                        "Undecodable packet from {} ({} so far): {}",
+                        server_addr_string(&addr_cell),
+                        stats.unexpected_responses(),
+                        error
+                    );
+                }
+            }
+            continue;
+        }
+        match parse_pong(message) {
+            Some(Some((sequence, sent_at_us))) => {
+                record_stamped_pong(&stats, &mut log_sampler, &addr_cell, sequence, sent_at_us)
+            }
+            Some(None) => {
+                stats.record_pong(None);
+                if log_sampler.sample() {
+                    info!(
+                        "Message from {}: {}",
+                        server_addr_string(&addr_cell),
+                        String::from_utf8_lossy(message)
+                    );
+                }
+            }
+            // catch a misbehaving server or protocol drift
+            None if strict_pong => {
+                stats.record_unexpected_response();
+                warn!(
+                    "Unexpected response from {} ({} so far): {:?}",
+                    server_addr_string(&addr_cell),
+                    stats.unexpected_responses(),
+                    String::from_utf8_lossy(message)
+                );
+            }
+            None => {
+                if log_sampler.sample() {
+                    info!(
+                        "Unexpected message from {}: {:?}",
+                        server_addr_string(&addr_cell),
+                        String::from_utf8_lossy(message)
+                    );
+                }
+            }
+        }
+    }
+}
+
+// times the round trip of a PONG that echoed a PING's sequence & send time
+fn record_stamped_pong(
+    stats: &ClientStats,
+    log_sampler: &mut LogSampler,
+    addr_cell: &AddrCell,
+    sequence: u64,
+    sent_at_us: u64,
+) {
+    let rtt = Duration::from_micros(stats.now_us().saturating_sub(sent_at_us));
+    stats.record_pong(Some(rtt));
+    stats.record_sequence(sequence);
+    if log_sampler.sample() {
+        info!(
+            "PONG {} from {}, round trip {:?}",
+            sequence,
+            server_addr_string(addr_cell),
+            rtt
+        );
+    }
+}
+
+// write_loop shows how to write to the transport (usually a detached datachannel)
+pub async fn write_loop<T: Transport + ?Sized>(
+    transport: Arc<T>,
+    stats: Arc<ClientStats>,
+    send_settings: SendSettings,
+    mut slow_start: Option<SlowStart>,
+    addr_cell: AddrCell,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let SendSettings {
+        interval: full_rate_interval,
+        payload,
+        max_buffered_amount,
+        pad_to,
+        count,
+        acks,
+        binary,
+    } = send_settings;
+    let resume_buffered_amount = max_buffered_amount / 2;
+    let mut throttle_log = LogSampler::new(1, Some(THROTTLE_LOGS_PER_SEC));
+    let mut throttles: u64 = 0;
+    if full_rate_interval.is_zero() {
+        info!(
+            "Sending {} byte payload {:?} as fast as backpressure allows",
+            payload.len(),
+            String::from_utf8_lossy(&payload)
+        );
+    } else {
+        info!(
+            "Sending {} byte payload {:?} every {:?}",
+            payload.len(),
+            String::from_utf8_lossy(&payload),
+            full_rate_interval
+        );
+    }
+
+    // the default PING is stamped so its PONG can be timed
+    let stamp_pings = payload == PING;
+    let mut sequence: u64 = 0;
+
+    let started = Instant::now();
+    let mut result = Result::<usize>::Ok(0);
+    let mut sent: u64 = 0;
+    while result.is_ok() {
+        if count.is_some_and(|count| sent >= count) {
+            info!("Sent all {} messages; Exit the write_loop", sent);
+            return Ok(());
+        }
+
+        let interval = match slow_start
+            .and_then(|ramp| ramp.interval_at(full_rate_interval, started.elapsed()))
+        {
+            Some(interval) => interval,
+            None => {
+                if slow_start.take().is_some() {
+                    info!("Slow start finished, sending at full rate");
+                }
+                full_rate_interval
+            }
+        };
+
+        // a backed up send buffer only adds latency & memory, so wait for it
+        // to drain instead of queueing more
+        let buffered = transport.buffered_amount();
+        if buffered > max_buffered_amount {
+            throttles += 1;
+            let log_throttle = throttle_log.sample();
+            if log_throttle {
+                info!(
+                    "Send buffer holds {} bytes, pausing until it drains to {} ({} pauses so far)",
+                    buffered, resume_buffered_amount, throttles
+                );
+            }
+            let paused = Instant::now();
+            tokio::select! {
+                _ = transport.buffered_amount_low(resume_buffered_amount) => {}
+                _ = shutdown.changed() => {
+                    info!("Shutting down; Exit the write_loop");
+                    send_disconnect(&*transport, binary).await;
+                    return Ok(());
+                }
+            }
+            if log_throttle {
+                info!("Send buffer drained, resuming after {:?}", paused.elapsed());
+            }
+        }
+
+        let timeout = tokio::time::sleep(interval);
+        tokio::pin!(timeout);
+
+        tokio::select! {
+            _ = timeout.as_mut() =>{
+                let message = if binary && stamp_pings {
+                    sequence += 1;
+                    let ping = Packet::Ping {
+                        sequence,
+                        sent_at_us: stats.now_us(),
+                    };
+                    Bytes::from(ping.encode())
+                } else if binary {
+                    Bytes::from(Packet::Data(payload.to_vec()).encode())
+                } else if stamp_pings {
+                    sequence += 1;
+                    let mut stamped = format!("PING {} {}", sequence, stats.now_us());
+                    if let Some(size) = pad_to {
+                        while stamped.len() < size {
+                            stamped.push(' ');
+                        }
+                    }
+                    Bytes::from(stamped)
+                } else {
+                    payload.clone()
+                };
+                let message = match &acks {
+                    Some(acks) => acks.track(&message),
+                    None => message,
+                };
+                if !binary {
+                    debug!(
+                        "Sending '{}' to {}",
+                        String::from_utf8_lossy(&message),
+                        server_addr_string(&addr_cell)
+                    );
+                }
+                result = transport.send(&message).await;
+                if let Ok(length) = result {
+                    stats.record_sent(length);
+                    sent += 1;
+                }
+            }
+            _ = shutdown.changed() => {
+                info!("Shutting down; Exit the write_loop");
+                send_disconnect(&*transport, binary).await;
+                return Ok(());
+            }
+        };
+    }
+
+    Ok(())
+}
+
+// tells a binary server the client is going, so it drops the session now
+// rather than once it times out; a failure only means it's gone already
+async fn send_disconnect<T: Transport + ?Sized>(transport: &T, binary: bool) {
+    if !binary {
+        return;
+    }
+    let disconnect = Bytes::from(Packet::Disconnect.encode());
+    if let Err(error) = transport.send(&disconnect).await {
+        debug!("Couldn't send Disconnect: {:#}", error);
+    }
+}
+
+// the server's address for log lines, empty until its candidate arrives
+fn server_addr_string(addr_cell: &AddrCell) -> String {
+    match addr_cell.get() {
+        ServerAddr::Found(addr) => addr.to_string(),
+        ServerAddr::Finding => "".to_string(),
+    }
+}
+
+// reads a PONG, returning the `<sequence> <sent at>` it echoes from a
+// stamped PING if there is one
+fn parse_pong(message: &[u8]) -> Option<Option<(u64, u64)>> {
+    let stamp = message.strip_prefix(PONG)?;
+    // unstamped, or the readiness check's, arriving after it gave up
+    if stamp.is_empty() || stamp.strip_prefix(b" ") == Some(READY_STAMP) {
+        return Some(None);
+    }
+    // anything after the stamp is padding
+    let mut fields = std::str::from_utf8(stamp.strip_prefix(b" ")?)
+        .ok()?
+        .split(' ');
+    let sequence = fields.next()?.parse().ok()?;
+    let sent_at_us = fields.next()?.parse().ok()?;
+    Some(Some((sequence, sent_at_us)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    fn stamped(sequence: u32, message: &[u8]) -> Bytes {
+        let mut stamped = sequence.to_be_bytes().to_vec();
+        stamped.extend_from_slice(message);
+        Bytes::from(stamped)
+    }
+
+    // runs read_loop over `messages` until the far end closes
+    async fn read(messages: Vec<Bytes>, read_settings: ReadSettings) -> Arc<ClientStats> {
+        let (near, far) = MemoryTransport::pair();
+        for message in &messages {
+            far.send(message).await.unwrap();
+        }
+        drop(far);
+        let stats = Arc::new(ClientStats::default());
+        let (_shutdown_sender, shutdown) = watch::channel(false);
+        read_loop(
+            Arc::new(near),
+            Arc::clone(&stats),
+            LogSampler::default(),
+            AddrCell::default(),
+            read_settings,
+            shutdown,
+        )
+        .await
+        .unwrap();
+        stats
+    }
+
+    #[tokio::test]
+    async fn server_sequences_are_taken_off_and_counted() {
+        let acks = Arc::new(AckTracker::default());
+        acks.track(b"PING");
+        let messages = vec![
+            stamped(0, b"PONG"),
+            Bytes::from_static(b"ACK 1"),
+            stamped(2, b"PONG"),
+            stamped(1, b"PONG"),
+            stamped(4, b"hello"),
+        ];
+        let stats = read(
+            messages,
+            ReadSettings {
+                acks: Some(Arc::clone(&acks)),
+                server_sequences: true,
+                ..ReadSettings::default()
+            },
+        )
+        .await;
+
+        assert_eq!(stats.pongs_received(), 3);
+        assert_eq!(stats.acks_received(), 1);
+        assert_eq!(acks.outstanding(), 0);
+        assert_eq!(stats.server_missing_sequences(), 1);
+        assert_eq!(stats.server_out_of_order(), 1);
+    }
+
+    #[tokio::test]
+    async fn stamped_messages_are_not_pongs_without_server_sequences() {
+        let messages = vec![stamped(0, b"PONG"), Bytes::from_static(b"PONG")];
+        let stats = read(
+            messages,
+            ReadSettings {
+                strict_pong: true,
+                ..ReadSettings::default()
+            },
+        )
+        .await;
+        assert_eq!(stats.pongs_received(), 1);
+        assert_eq!(stats.unexpected_responses(), 1);
+        assert_eq!(stats.server_sequence_loss(), None);
+    }
+
+    #[tokio::test]
+    async fn messages_too_short_for_a_sequence_are_unexpected() {
+        let messages = vec![Bytes::from_static(b"OK")];
+        let stats = read(
+            messages,
+            ReadSettings {
+                server_sequences: true,
+                ..ReadSettings::default()
+            },
+        )
+        .await;
+        assert_eq!(stats.unexpected_responses(), 1);
+        assert_eq!(stats.server_sequence_loss(), None);
+    }
+
+    #[test]
+    fn pongs_are_parsed() {
+        assert_eq!(parse_pong(b"PONG"), Some(None));
+        assert_eq!(parse_pong(b"PONG ready"), Some(None));
+        assert_eq!(parse_pong(b"PONG 3 1000"), Some(Some((3, 1000))));
+        assert_eq!(parse_pong(b"PONG 3 1000   "), Some(Some((3, 1000))));
+        assert_eq!(parse_pong(b"PONG 3"), None);
+        assert_eq!(parse_pong(b"\0\0\0\x01PONG"), None);
+        assert_eq!(parse_pong(b"PING"), None);
+    }
+
+    #[tokio::test]
+    async fn a_message_larger_than_a_read_arrives_whole() {
+        let (near, far) = MemoryTransport::pair();
+        let mut pong = b"PONG 1 0".to_vec();
+        pong.resize(4 * MESSAGE_SIZE, b' ');
+        // the frame split the way SCTP may deliver it, in packet-sized reads
+        let frame = signaling_shared::framing::encode_frame(&pong).unwrap();
+        for piece in frame.chunks(MESSAGE_SIZE) {
+            far.send(&Bytes::copy_from_slice(piece)).await.unwrap();
+        }
+        drop(far);
+
+        let stats = Arc::new(ClientStats::default());
+        let (_shutdown_sender, shutdown) = watch::channel(false);
+        read_loop(
+            Arc::new(crate::framing::FramedTransport::new(Arc::new(near))),
+            Arc::clone(&stats),
+            LogSampler::default(),
+            AddrCell::default(),
+            ReadSettings::default(),
+            shutdown,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stats.messages_received(), 1);
+        assert_eq!(stats.bytes_received(), pong.len() as u64);
+        assert_eq!(stats.pongs_received(), 1);
+    }
+
+    // answers each PING after `delay` the way the naia server does with
+    // NAIA_ACKS=1, acknowledging its id first
+    async fn echo_server(server: MemoryTransport, delay: Duration) {
+        let mut buffer = vec![0u8; 1500];
+        while let Ok(length) = server.recv(&mut buffer).await {
+            let message = std::str::from_utf8(&buffer[..length]).unwrap().to_string();
+            let (id, body) = message
+                .strip_prefix("MSG ")
+                .and_then(|rest| rest.split_once(' '))
+                .expect("every message asks for an ACK");
+            server
+                .send(&Bytes::from(format!("ACK {}", id)))
+                .await
+                .unwrap();
+            tokio::time::sleep(delay).await;
+            let pong = body.replacen("PING", "PONG", 1);
+            if server.send(&Bytes::from(pong)).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn an_echoing_server_acks_and_times_every_ping() {
+        let (near, far) = MemoryTransport::pair();
+        let near = Arc::new(near);
+        tokio::spawn(echo_server(far, Duration::from_millis(5)));
+        let stats = Arc::new(ClientStats::default());
+        let acks = Arc::new(AckTracker::default());
+        let (shutdown_sender, shutdown) = watch::channel(false);
+        let reader = tokio::spawn(read_loop(
+            Arc::clone(&near),
+            Arc::clone(&stats),
+            LogSampler::default(),
+            AddrCell::default(),
+            ReadSettings {
+                acks: Some(Arc::clone(&acks)),
+                ..ReadSettings::default()
+            },
+            shutdown.clone(),
+        ));
+
+        let send_settings = SendSettings {
+            interval: Duration::from_millis(1),
+            count: Some(3),
+            acks: Some(Arc::clone(&acks)),
+            ..SendSettings::default()
+        };
+        write_loop(
+            near,
+            Arc::clone(&stats),
+            send_settings,
+            None,
+            AddrCell::default(),
+            shutdown,
+        )
+        .await
+        .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while stats.pongs_received() < 3 {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("every PING gets its PONG");
+        shutdown_sender.send(true).unwrap();
+        reader.await.unwrap().unwrap();
+
+        assert_eq!(stats.messages_sent(), 3);
+        assert_eq!(stats.acks_received(), 3);
+        assert_eq!(acks.outstanding(), 0);
+        assert_eq!(stats.missing_sequences(), 0);
+        assert!(stats.average_rtt().unwrap() >= Duration::from_millis(5));
+        assert!(stats.max_rtt().unwrap() >= stats.average_rtt().unwrap());
+    }
+}
@@ -0,0 +1,268 @@
+use anyhow::{Context, Result};
+use signaling_shared::prometheus::{PrometheusText, CONTENT_TYPE};
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+use tracing::Instrument;
+
+use crate::{client_stats::ClientStats, connection_status::ConnectionStatus};
+
+// how often the byte & message rates are sampled
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+// the most of a scrape request that's read, headers included
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+// how long a scrape gets to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves the stats of whichever connection it was last pointed at over
+/// HTTP, at `/metrics`, in Prometheus' text format. Each reconnect starts
+/// the counters over, which Prometheus reads as a counter reset.
+#[derive(Clone)]
+pub struct MetricsEndpoint {
+    exported: Arc<Mutex<Exported>>,
+}
+
+// the connection being exported, and its rates over the last sampled second
+#[derive(Default)]
+struct Exported {
+    connection: Option<(Arc<ClientStats>, Arc<ConnectionStatus>)>,
+    connections: u64,
+    // messages sent & received, bytes sent & received, as of the last sample
+    sampled: [u64; 4],
+    sampled_at: Option<Instant>,
+    rates: [f64; 4],
+}
+
+impl MetricsEndpoint {
+    /// Binds `address` and serves it until the runtime shuts down
+    pub async fn serve(address: SocketAddr) -> Result<MetricsEndpoint> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("failed to serve metrics on {}", address))?;
+        let endpoint = MetricsEndpoint {
+            exported: Arc::new(Mutex::new(Exported::default())),
+        };
+        tokio::spawn(accept_loop(listener, endpoint.clone()).in_current_span());
+        tokio::spawn(sample_rates(endpoint.clone()).in_current_span());
+        Ok(endpoint)
+    }
+
+    /// Exports a new connection's stats & status from now on
+    pub fn watch(&self, stats: Arc<ClientStats>, status: Arc<ConnectionStatus>) {
+        let mut exported = self.lock();
+        exported.connection = Some((stats, status));
+        exported.connections += 1;
+        exported.sampled = [0; 4];
+        exported.sampled_at = Some(Instant::now());
+        exported.rates = [0.0; 4];
+    }
+
+    /// The metrics as a scrape gets them
+    pub fn render(&self) -> String {
+        let exported = self.lock();
+        let mut page = PrometheusText::default();
+        page.counter(
+            "naia_client_connections_total",
+            "Connection attempts made",
+            exported.connections,
+        );
+        let (stats, status) = match &exported.connection {
+            Some(connection) => connection,
+            None => return page.finish(),
+        };
+        let [messages_sent, messages_received, bytes_sent, bytes_received] = exported.rates;
+        page.gauge(
+            "naia_client_connected",
+            "Whether the data channel is open and the peer connection connected",
+            if status.is_connected() { 1.0 } else { 0.0 },
+        )
+        .counter(
+            "naia_client_messages_sent_total",
+            "Messages written to the transport",
+            stats.messages_sent(),
+        )
+        .counter(
+            "naia_client_messages_received_total",
+            "Messages read from the transport",
+            stats.messages_received(),
+        )
+        .counter(
+            "naia_client_bytes_sent_total",
+            "Bytes written to the transport",
+            stats.bytes_sent(),
+        )
+        .counter(
+            "naia_client_bytes_received_total",
+            "Bytes read from the transport",
+            stats.bytes_received(),
+        )
+        .counter(
+            "naia_client_reassembly_failures_total",
+            "Messages given up on for missing fragments or over the reassembly budget",
+            stats.reassembly_failures(),
+        )
+        .gauge(
+            "naia_client_reassembly_bytes",
+            "Fragment bytes held for partly received messages",
+            stats.reassembly_bytes() as f64,
+        )
+        .counter(
+            "naia_client_queue_drops_total",
+            "Messages turned away because the send queue was full",
+            stats.queue_drops(),
+        )
+        .gauge(
+            "naia_client_messages_sent_per_second",
+            "Messages sent over the last sampled second",
+            messages_sent,
+        )
+        .gauge(
+            "naia_client_messages_received_per_second",
+            "Messages received over the last sampled second",
+            messages_received,
+        )
+        .gauge(
+            "naia_client_bytes_sent_per_second",
+            "Bytes sent over the last sampled second",
+            bytes_sent,
+        )
+        .gauge(
+            "naia_client_bytes_received_per_second",
+            "Bytes received over the last sampled second",
+            bytes_received,
+        );
+        if let Some(rtt) = stats.average_rtt() {
+            page.gauge(
+                "naia_client_average_rtt_seconds",
+                "Average PING round trip",
+                rtt.as_secs_f64(),
+            );
+        }
+        page.finish()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Exported> {
+        self.exported.lock().expect("metrics lock poisoned")
+    }
+}
+
+async fn sample_rates(endpoint: MetricsEndpoint) {
+    let mut interval = tokio::time::interval(RATE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut exported = endpoint.lock();
+        let totals = match &exported.connection {
+            Some((stats, _)) => [
+                stats.messages_sent(),
+                stats.messages_received(),
+                stats.bytes_sent(),
+                stats.bytes_received(),
+            ],
+            None => continue,
+        };
+        let now = Instant::now();
+        let seconds = exported.sampled_at.map_or(0.0, |sampled_at| {
+            now.duration_since(sampled_at).as_secs_f64()
+        });
+        if seconds > 0.0 {
+            let sampled = exported.sampled;
+            for (rate, (total, sampled)) in
+                exported.rates.iter_mut().zip(totals.iter().zip(sampled))
+            {
+                *rate = total.saturating_sub(sampled) as f64 / seconds;
+            }
+        }
+        exported.sampled = totals;
+        exported.sampled_at = Some(now);
+    }
+}
+
+async fn accept_loop(listener: TcpListener, endpoint: MetricsEndpoint) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                warn!("Failed to accept a metrics connection: {}", error);
+                continue;
+            }
+        };
+        let endpoint = endpoint.clone();
+        tokio::spawn(
+            async move {
+                if let Err(error) = respond(stream, &endpoint).await {
+                    debug!("Metrics request from {} failed: {:#}", peer, error);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+async fn respond(mut stream: TcpStream, endpoint: &MetricsEndpoint) -> Result<()> {
+    let request = tokio::time::timeout(REQUEST_TIMEOUT, read_request(&mut stream))
+        .await
+        .context("timed out reading the request")??;
+    let response = if request.starts_with(b"GET /metrics ") {
+        let body = endpoint.render();
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            CONTENT_TYPE,
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// reads up to the end of the headers; a scrape has no body
+async fn read_request(stream: &mut TcpStream) -> Result<Vec<u8>> {
+    let mut request = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        anyhow::ensure!(
+            request.len() < MAX_REQUEST_SIZE,
+            "request headers too large"
+        );
+        let read = stream.read(&mut chunk).await?;
+        anyhow::ensure!(read > 0, "connection closed mid-request");
+        request.extend_from_slice(&chunk[..read]);
+    }
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn renders_the_watched_connection() {
+        let endpoint = MetricsEndpoint::serve("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let page = endpoint.render();
+        assert!(page.contains("naia_client_connections_total 0\n"));
+        assert!(!page.contains("naia_client_messages_sent_total"));
+
+        let stats = Arc::new(ClientStats::default());
+        stats.record_sent(4);
+        stats.record_sent(4);
+        endpoint.watch(stats, Arc::new(ConnectionStatus::default()));
+        let page = endpoint.render();
+        assert!(page.contains("naia_client_connections_total 1\n"));
+        assert!(page.contains("naia_client_connected 0\n"));
+        assert!(page.contains("naia_client_messages_sent_total 2\n"));
+        assert!(page.contains("naia_client_bytes_sent_total 8\n"));
+        assert!(page.contains("naia_client_reassembly_bytes 0\n"));
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::{bail, Result};
+use tokio::time::Duration;
+
+use crate::client_stats::ClientStats;
+
+/// Link quality limits a run must stay within, for using the client as a
+/// network SLO check
+#[derive(Clone, Copy, Default)]
+pub struct QualityThresholds {
+    /// Highest share of PINGs allowed to go unanswered, from 0 to 1
+    pub max_loss: Option<f64>,
+    /// Highest mean round trip time allowed
+    pub max_rtt: Option<Duration>,
+}
+
+impl QualityThresholds {
+    /// Flag & description of every threshold
+    pub const FLAGS: &'static [(&'static str, &'static str)] = &[
+        (
+            "--max-loss",
+            "highest share of PINGs allowed to go unanswered",
+        ),
+        ("--max-rtt-ms", "highest mean round trip time allowed"),
+    ];
+
+    /// Fails naming the first threshold `stats` violates, and the observed
+    /// value
+    pub fn check(&self, stats: &ClientStats) -> Result<()> {
+        if let Some(max_loss) = self.max_loss {
+            match stats.loss() {
+                Some(loss) if loss > max_loss => bail!(
+                    "loss of {:.2}% exceeds --max-loss of {:.2}%",
+                    loss * 100.0,
+                    max_loss * 100.0
+                ),
+                Some(_) => {}
+                None => bail!("nothing was sent, so --max-loss can't be checked"),
+            }
+        }
+
+        if let Some(max_rtt) = self.max_rtt {
+            match stats.average_rtt() {
+                Some(rtt) if rtt > max_rtt => bail!(
+                    "mean round trip time of {}ms exceeds --max-rtt-ms of {}ms",
+                    rtt.as_millis(),
+                    max_rtt.as_millis()
+                ),
+                Some(_) => {}
+                None => bail!("no round trips were measured, so --max-rtt-ms can't be checked"),
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,164 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use signaling_shared::packet::Packet;
+use tokio::time::{self, Duration};
+
+use crate::{loops::SEQUENCE_SIZE, transport::Transport};
+
+/// What the readiness check stamps its PING with. The server answers
+/// `PING <stamp>` with `<reply> <stamp>` whatever its reply, and an echoing
+/// server sends it straight back, so either way the answer ends with it.
+pub const READY_STAMP: &[u8] = b"ready";
+
+/// Confirms a freshly opened transport can actually carry traffic, by sending
+/// a PING stamped with `READY_STAMP` and waiting for an answer carrying the
+/// stamp. Both the write and the wait are retried up to `attempts` times,
+/// since the underlying association may not be fully established the moment
+/// the channel reports open. With `binary` the PING & PONG are binary
+/// `Packet`s, and with `server_sequences` the answer is expected behind the
+/// server's sequence number. Messages up to `max_message_size` bytes can
+/// arrive ahead of it. No `attempts` skips the check, for a server that
+/// answers nothing.
+pub async fn confirm_ready<T: Transport + ?Sized>(
+    transport: &T,
+    attempts: u32,
+    timeout: Duration,
+    binary: bool,
+    server_sequences: bool,
+    max_message_size: usize,
+) -> Result<()> {
+    if attempts == 0 {
+        info!("Skipping the readiness check");
+        return Ok(());
+    }
+    let mut buffer = vec![0u8; max_message_size];
+    let ping = if binary {
+        // sequence 0, so a PONG arriving too late for the check isn't timed
+        let ping = Packet::Ping {
+            sequence: 0,
+            sent_at_us: 0,
+        };
+        Bytes::from(ping.encode())
+    } else {
+        Bytes::from([&b"PING "[..], READY_STAMP].concat())
+    };
+    let is_answer = |message: &[u8]| {
+        let message = match server_sequences {
+            true if message.len() < SEQUENCE_SIZE => return false,
+            true => &message[SEQUENCE_SIZE..],
+            false => message,
+        };
+        if binary {
+            matches!(Packet::decode(message), Ok(Packet::Pong { .. }))
+        } else {
+            message
+                .strip_suffix(READY_STAMP)
+                .is_some_and(|reply| reply.ends_with(b" "))
+        }
+    };
+    for attempt in 1..=attempts {
+        if let Err(error) = transport.send(&ping).await {
+            warn!(
+                "Readiness write {}/{} failed: {:#}",
+                attempt, attempts, error
+            );
+            time::sleep(timeout).await;
+            continue;
+        }
+
+        match time::timeout(timeout, transport.recv(&mut buffer)).await {
+            Ok(Ok(length)) if is_answer(&buffer[..length]) => {
+                info!("Data channel ready after {} attempt(s)", attempt);
+                return Ok(());
+            }
+            Ok(Ok(_)) => debug!("Readiness check ignoring unexpected message"),
+            Ok(Err(error)) => bail!("channel closed during readiness check: {:#}", error),
+            Err(_) => debug!("Readiness attempt {}/{} timed out", attempt, attempts),
+        }
+    }
+
+    bail!(
+        "data channel opened but never became writable after {} attempts",
+        attempts
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MemoryTransport;
+
+    #[tokio::test]
+    async fn messages_larger_than_a_packet_can_arrive_first() {
+        let (near, far) = MemoryTransport::pair();
+        far.send(&Bytes::from(vec![b'x'; 4000])).await.unwrap();
+        far.send(&Bytes::from_static(b"PONG ready")).await.unwrap();
+
+        // each attempt reads one message, so the PONG is the second's
+        confirm_ready(&near, 2, Duration::from_secs(1), false, false, 4096)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_message_over_the_limit_ends_the_check() {
+        let (near, far) = MemoryTransport::pair();
+        far.send(&Bytes::from(vec![b'x'; 4000])).await.unwrap();
+
+        let error = confirm_ready(&near, 1, Duration::from_secs(1), false, false, 1500)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("channel closed"), "{:#}", error);
+    }
+
+    // answers the first message it reads with `answer`, if it's the probe
+    async fn answering(far: MemoryTransport, answer: &'static [u8]) {
+        let mut buffer = [0u8; 64];
+        let length = far.recv(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..length], b"PING ready");
+        far.send(&Bytes::from_static(answer)).await.unwrap();
+        // held open until the check is over
+        let _ = far.recv(&mut buffer).await;
+    }
+
+    #[tokio::test]
+    async fn any_reply_to_the_probe_confirms_it() {
+        // the default reply, another one, and an echo
+        for answer in [&b"PONG ready"[..], b"HELLO ready", b"PING ready"] {
+            let (near, far) = MemoryTransport::pair();
+            tokio::spawn(answering(far, answer));
+            confirm_ready(&near, 1, Duration::from_secs(1), false, false, 1500)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_that_arent_answers_are_skipped() {
+        let (near, far) = MemoryTransport::pair();
+        for message in [&b"PONG"[..], b"already", b"ready"] {
+            far.send(&Bytes::from_static(message)).await.unwrap();
+        }
+        let error = confirm_ready(&near, 3, Duration::from_millis(50), false, false, 1500)
+            .await
+            .unwrap_err();
+        assert!(
+            error.to_string().contains("never became writable"),
+            "{:#}",
+            error
+        );
+        drop(far);
+    }
+
+    #[tokio::test]
+    async fn no_attempts_skips_the_check() {
+        let (near, far) = MemoryTransport::pair();
+        confirm_ready(&near, 0, Duration::from_secs(1), false, false, 1500)
+            .await
+            .unwrap();
+        // nothing was sent
+        drop(near);
+        let mut buffer = [0u8; 64];
+        assert!(far.recv(&mut buffer).await.is_err());
+    }
+}
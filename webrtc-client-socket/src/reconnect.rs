@@ -0,0 +1,477 @@
+use anyhow::Result;
+use rand::Rng;
+use serde::Serialize;
+use std::{collections::BTreeMap, fmt, future::Future};
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    client::{Client, ClientConfig},
+    event_log::ClientEvent,
+    signaling::SignalingError,
+};
+
+/// Why a connection ended or failed, so reconnects can be broken down by
+/// cause. Serialized in snake case, e.g. `keepalive_timeout`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReconnectReason {
+    /// The data channel or the peer connection closed
+    ChannelClosed,
+    /// ICE found no network path to the server
+    IceFailed,
+    /// Nothing was received for `--keepalive-timeout`
+    KeepaliveTimeout,
+    /// The connection reached `--max-session-lifetime`
+    MaxSessionLifetime,
+    /// The signaling server answered the offer with an error status
+    ServerRejected,
+    /// Connecting failed some other way, e.g. the server was unreachable
+    ConnectFailed,
+}
+
+impl ReconnectReason {
+    /// Every reason, in the order they sort in
+    pub const ALL: [ReconnectReason; 6] = [
+        ReconnectReason::ChannelClosed,
+        ReconnectReason::IceFailed,
+        ReconnectReason::KeepaliveTimeout,
+        ReconnectReason::MaxSessionLifetime,
+        ReconnectReason::ServerRejected,
+        ReconnectReason::ConnectFailed,
+    ];
+
+    /// The reason as it's serialized, e.g. `keepalive_timeout`
+    pub fn name(self) -> &'static str {
+        match self {
+            ReconnectReason::ChannelClosed => "channel_closed",
+            ReconnectReason::IceFailed => "ice_failed",
+            ReconnectReason::KeepaliveTimeout => "keepalive_timeout",
+            ReconnectReason::MaxSessionLifetime => "max_session_lifetime",
+            ReconnectReason::ServerRejected => "server_rejected",
+            ReconnectReason::ConnectFailed => "connect_failed",
+        }
+    }
+
+    /// What the reason means, in a line
+    pub fn description(self) -> &'static str {
+        match self {
+            ReconnectReason::ChannelClosed => "the data channel or the peer connection closed",
+            ReconnectReason::IceFailed => "ICE found no network path to the server",
+            ReconnectReason::KeepaliveTimeout => "nothing was received for --keepalive-timeout",
+            ReconnectReason::MaxSessionLifetime => "the connection reached --max-session-lifetime",
+            ReconnectReason::ServerRejected => {
+                "the signaling server answered the offer with an error status"
+            }
+            ReconnectReason::ConnectFailed => "connecting failed some other way",
+        }
+    }
+
+    /// Tags `error` as ending the connection for this reason
+    pub fn error(self, error: anyhow::Error) -> anyhow::Error {
+        anyhow::Error::new(ConnectionEnded {
+            reason: self,
+            error,
+        })
+    }
+
+    /// The reason `error` was tagged with anywhere in its chain, telling a
+    /// rejected offer apart from other untagged failures
+    pub fn of(error: &anyhow::Error) -> ReconnectReason {
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(ended) = cause.downcast_ref::<ConnectionEnded>() {
+                    return Some(ended.reason);
+                }
+                match cause.downcast_ref::<SignalingError>() {
+                    Some(SignalingError::Rejected(..)) => Some(ReconnectReason::ServerRejected),
+                    _ => None,
+                }
+            })
+            .unwrap_or(ReconnectReason::ConnectFailed)
+    }
+}
+
+impl fmt::Display for ReconnectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReconnectReason::ChannelClosed => "channel closed",
+            ReconnectReason::IceFailed => "ICE failed",
+            ReconnectReason::KeepaliveTimeout => "keepalive timeout",
+            ReconnectReason::MaxSessionLifetime => "max session lifetime",
+            ReconnectReason::ServerRejected => "server rejected",
+            ReconnectReason::ConnectFailed => "connect failed",
+        })
+    }
+}
+
+// an error tagged with its reconnect reason, displayed as just the error
+#[derive(Debug)]
+struct ConnectionEnded {
+    reason: ReconnectReason,
+    error: anyhow::Error,
+}
+
+impl fmt::Display for ConnectionEnded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.error)
+    }
+}
+
+impl std::error::Error for ConnectionEnded {}
+
+/// Spacing of reconnect attempts: `base`, doubled for every earlier reconnect
+/// up to `max`, then scaled by a random factor within `jitter` of 1 so a
+/// fleet of clients doesn't reconnect in lockstep
+#[derive(Clone, Copy, Debug)]
+pub struct Backoff {
+    pub base: Duration,
+    pub max: Duration,
+    /// Share of the delay it can be moved by either way, from 0 to 1
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// How long to wait before reconnect number `reconnect`, counting from 1
+    pub fn delay(&self, reconnect: u32) -> Duration {
+        let doublings = reconnect.saturating_sub(1).min(16);
+        let delay = self.base.saturating_mul(1 << doublings).min(self.max);
+        if self.jitter <= 0.0 {
+            return delay;
+        }
+        let jitter = self.jitter.min(1.0);
+        delay.mul_f64(rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter))
+    }
+}
+
+/// Limits how many times the client reconnects after failed connections.
+/// Staying connected for `reset_after` restores the full budget, so
+/// occasional blips in a long run don't use it up
+pub struct ReconnectBudget {
+    max_reconnects: u32,
+    reset_after: Duration,
+    reconnects: u32,
+    failures: Vec<(ReconnectReason, String)>,
+    by_reason: BTreeMap<ReconnectReason, u32>,
+}
+
+impl ReconnectBudget {
+    pub fn new(max_reconnects: u32, reset_after: Duration) -> Self {
+        ReconnectBudget {
+            max_reconnects,
+            reset_after,
+            reconnects: 0,
+            failures: Vec::new(),
+            by_reason: BTreeMap::new(),
+        }
+    }
+
+    /// Records a connection that failed for `reason` with `error` after
+    /// being connected for `connected_for`, returning whether the client
+    /// should reconnect
+    pub fn record_failure(
+        &mut self,
+        reason: ReconnectReason,
+        error: String,
+        connected_for: Duration,
+    ) -> bool {
+        if connected_for >= self.reset_after {
+            self.reconnects = 0;
+        }
+        self.failures.push((reason, error));
+        *self.by_reason.entry(reason).or_default() += 1;
+
+        if self.reconnects >= self.max_reconnects {
+            return false;
+        }
+        self.reconnects += 1;
+        true
+    }
+
+    /// Reconnects used since the budget was last reset
+    pub fn reconnects(&self) -> u32 {
+        self.reconnects
+    }
+
+    /// How long to wait before the current reconnect, counting the
+    /// reconnects since the budget was last reset
+    pub fn delay(&self, backoff: &Backoff) -> Duration {
+        backoff.delay(self.reconnects)
+    }
+
+    pub fn max_reconnects(&self) -> u32 {
+        self.max_reconnects
+    }
+
+    /// Every failure recorded so far with its reason, oldest first
+    pub fn failures(&self) -> &[(ReconnectReason, String)] {
+        &self.failures
+    }
+
+    /// How many failures there have been for each reason, however many
+    /// times the budget was reset
+    pub fn failures_by_reason(&self) -> &BTreeMap<ReconnectReason, u32> {
+        &self.by_reason
+    }
+}
+
+/// Connects a `Client`, and connects it again whenever connecting fails or
+/// the connection ends, waiting out the backoff in between until the budget
+/// is spent. Each attempt tears the old peer connection down and signals
+/// the server afresh; every reconnect is recorded as a
+/// `ClientEvent::Reconnecting` with its `ReconnectReason` on the config's
+/// event log, next to the connection's state changes, so
+/// `EventLog::subscribe` follows them all.
+pub struct Reconnector {
+    config: ClientConfig,
+    backoff: Backoff,
+    budget: ReconnectBudget,
+    // when the current client connected
+    connected_at: Option<Instant>,
+}
+
+impl Reconnector {
+    pub fn new(config: ClientConfig, backoff: Backoff, budget: ReconnectBudget) -> Self {
+        Reconnector {
+            config,
+            backoff,
+            budget,
+            connected_at: None,
+        }
+    }
+
+    /// Connects, retrying failed attempts, until one succeeds, the budget is
+    /// spent (returning the last failure) or `cancel` resolves (returning
+    /// `None`)
+    pub async fn connect_until(
+        &mut self,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<Client>> {
+        tokio::pin!(cancel);
+        loop {
+            let error = match Client::connect_until(self.config.clone(), &mut cancel).await {
+                Ok(Some(client)) => {
+                    self.connected_at = Some(Instant::now());
+                    return Ok(Some(client));
+                }
+                Ok(None) => return Ok(None),
+                Err(error) => error,
+            };
+            let delay = match self.retry_delay(&error, Duration::ZERO) {
+                Some(delay) => delay,
+                None => return Err(error),
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = &mut cancel => return Ok(None),
+            }
+        }
+    }
+
+    /// Closes `client`, whose connection ended with `error`, and connects
+    /// again as `connect_until` does, failing straight away with `error` if
+    /// the budget is already spent
+    pub async fn reconnect_until(
+        &mut self,
+        client: Client,
+        error: anyhow::Error,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<Client>> {
+        client.close().await;
+        let connected_for = self
+            .connected_at
+            .take()
+            .map_or(Duration::ZERO, |connected_at| connected_at.elapsed());
+        let delay = match self.retry_delay(&error, connected_for) {
+            Some(delay) => delay,
+            None => return Err(error),
+        };
+        tokio::pin!(cancel);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = &mut cancel => return Ok(None),
+        }
+        self.connect_until(cancel).await
+    }
+
+    /// Records a connection that failed with `error` after being connected
+    /// for `connected_for`, returning how long to wait before reconnecting,
+    /// or `None` once the budget is spent
+    pub fn retry_delay(
+        &mut self,
+        error: &anyhow::Error,
+        connected_for: Duration,
+    ) -> Option<Duration> {
+        let reason = ReconnectReason::of(error);
+        let message = format!("{:#}", error);
+        if !self
+            .budget
+            .record_failure(reason, message.clone(), connected_for)
+        {
+            error!(
+                "Giving up after {} reconnects, failures were:",
+                self.budget.max_reconnects()
+            );
+            for (attempt, (reason, error)) in self.budget.failures().iter().enumerate() {
+                error!("  {}: {} ({})", attempt + 1, error, reason);
+            }
+            return None;
+        }
+
+        let delay = self.budget.delay(&self.backoff);
+        let count = self.budget.failures_by_reason()[&reason];
+        warn!(
+            "Connection failed: {} ({}, {} so far); reconnecting ({}/{}) in {:?}",
+            message,
+            reason,
+            count,
+            self.budget.reconnects(),
+            self.budget.max_reconnects(),
+            delay
+        );
+        self.config.events.record(ClientEvent::Reconnecting {
+            attempt: self.budget.reconnects(),
+            reason,
+            error: message,
+        });
+        Some(delay)
+    }
+
+    /// Failures so far for each reason, see `ReconnectBudget::failures_by_reason`
+    pub fn failures_by_reason(&self) -> &BTreeMap<ReconnectReason, u32> {
+        self.budget.failures_by_reason()
+    }
+
+    /// The config every attempt connects with
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{anyhow, Context};
+    use reqwest::StatusCode;
+
+    #[test]
+    fn reasons_are_found_anywhere_in_the_chain() {
+        let tagged = ReconnectReason::KeepaliveTimeout.error(anyhow!("nothing received"));
+        assert_eq!(
+            ReconnectReason::of(&tagged),
+            ReconnectReason::KeepaliveTimeout
+        );
+        // tagging doesn't change the message
+        assert_eq!(format!("{:#}", tagged), "nothing received");
+
+        let wrapped = Err::<(), _>(tagged).context("run failed").unwrap_err();
+        assert_eq!(
+            ReconnectReason::of(&wrapped),
+            ReconnectReason::KeepaliveTimeout
+        );
+
+        let rejected = anyhow::Error::new(SignalingError::Rejected(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "full".to_string(),
+        ));
+        assert_eq!(
+            ReconnectReason::of(&rejected),
+            ReconnectReason::ServerRejected
+        );
+
+        assert_eq!(
+            ReconnectReason::of(&anyhow!("connection refused")),
+            ReconnectReason::ConnectFailed
+        );
+    }
+
+    #[test]
+    fn reasons_serialize_in_snake_case() {
+        let event = ClientEvent::Reconnecting {
+            attempt: 2,
+            reason: ReconnectReason::MaxSessionLifetime,
+            error: "connection open for 1s".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "event": "reconnecting",
+                "attempt": 2,
+                "reason": "max_session_lifetime",
+                "error": "connection open for 1s",
+            })
+        );
+    }
+
+    #[test]
+    fn the_budget_counts_failures_by_reason() {
+        let mut budget = ReconnectBudget::new(2, Duration::from_secs(60));
+        assert!(budget.record_failure(
+            ReconnectReason::IceFailed,
+            "no path".to_string(),
+            Duration::ZERO
+        ));
+        assert!(budget.record_failure(
+            ReconnectReason::ChannelClosed,
+            "closed".to_string(),
+            Duration::from_secs(1)
+        ));
+        // staying connected resets the budget but not the counts
+        assert!(budget.record_failure(
+            ReconnectReason::ChannelClosed,
+            "closed".to_string(),
+            Duration::from_secs(60)
+        ));
+        assert_eq!(budget.reconnects(), 1);
+        assert_eq!(
+            budget.failures_by_reason().iter().collect::<Vec<_>>(),
+            [
+                (&ReconnectReason::ChannelClosed, &2),
+                (&ReconnectReason::IceFailed, &1)
+            ]
+        );
+        assert_eq!(
+            budget.failures()[0],
+            (ReconnectReason::IceFailed, "no path".to_string())
+        );
+    }
+
+    #[test]
+    fn each_reconnect_is_recorded_with_its_reason() {
+        let config = ClientConfig::new("http://127.0.0.1:9/rtc_session".parse().unwrap());
+        let mut events = config.events.subscribe();
+        let backoff = Backoff {
+            base: Duration::from_millis(10),
+            max: Duration::from_millis(10),
+            jitter: 0.0,
+        };
+        let mut reconnector =
+            Reconnector::new(config, backoff, ReconnectBudget::new(1, Duration::MAX));
+
+        let error = ReconnectReason::ChannelClosed.error(anyhow!("data channel closed"));
+        assert_eq!(
+            reconnector.retry_delay(&error, Duration::ZERO),
+            Some(Duration::from_millis(10))
+        );
+        match events.try_recv().unwrap() {
+            ClientEvent::Reconnecting {
+                attempt,
+                reason,
+                error,
+            } => {
+                assert_eq!(attempt, 1);
+                assert_eq!(reason, ReconnectReason::ChannelClosed);
+                assert_eq!(error, "data channel closed");
+            }
+            other => panic!("expected a reconnect, got {:?}", other),
+        }
+
+        // the budget is spent, so nothing more is recorded
+        assert_eq!(reconnector.retry_delay(&error, Duration::ZERO), None);
+        assert!(events.try_recv().is_err());
+        assert_eq!(
+            reconnector.failures_by_reason()[&ReconnectReason::ChannelClosed],
+            2
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use crate::{burst::send_burst, client_stats::ClientStats, transport::Transport};
+
+/// Reads commands from stdin until it closes. Supports `burst N`, sending
+/// binary `Ping`s with `binary`.
+pub async fn run_repl<T: Transport + ?Sized>(
+    transport: Arc<T>,
+    stats: Arc<ClientStats>,
+    binary: bool,
+) -> Result<()> {
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await.context("failed to read stdin")? {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next().map(str::parse::<u32>)) {
+            (None, _) => {}
+            (Some("burst"), Some(Ok(count))) => {
+                send_burst(&*transport, &stats, count, binary).await?;
+            }
+            (Some("burst"), _) => warn!("usage: burst <count>"),
+            (Some(command), _) => warn!("unknown command '{}', try: burst <count>", command),
+        }
+    }
+
+    Ok(())
+}
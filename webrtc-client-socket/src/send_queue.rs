@@ -0,0 +1,152 @@
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::fmt;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::Instrument;
+
+use crate::{client_stats::ClientStats, transport::Transport};
+
+/// Messages a data channel's send queue holds by default
+pub const DEFAULT_SEND_QUEUE_CAPACITY: usize = 1024;
+
+/// Returned by `Client::send` when the send queue is full; the message was
+/// dropped rather than queued
+#[derive(Debug)]
+pub struct SendQueueFull;
+
+impl fmt::Display for SendQueueFull {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "send queue full, message dropped")
+    }
+}
+
+impl std::error::Error for SendQueueFull {}
+
+/// A bounded queue in front of a transport, drained by a single writer task
+/// so every producer's messages go out one at a time, in the order they were
+/// queued. The writer waits for the inner transport to buffer no more than
+/// `max_buffered_amount` before each write.
+pub struct SendQueue {
+    inner: Arc<dyn Transport>,
+    sender: mpsc::Sender<Bytes>,
+    stats: Arc<ClientStats>,
+    // bytes queued but not yet handed to the inner transport
+    queued_bytes: Arc<AtomicUsize>,
+    // why the writer stopped, once it has
+    closed: Arc<Mutex<Option<String>>>,
+}
+
+impl SendQueue {
+    /// Starts the writer task, in the current span, draining into `inner`
+    pub fn new(
+        inner: Arc<dyn Transport>,
+        stats: Arc<ClientStats>,
+        capacity: usize,
+        max_buffered_amount: usize,
+    ) -> SendQueue {
+        let (sender, receiver) = mpsc::channel(capacity.max(1));
+        let queued_bytes = Arc::new(AtomicUsize::new(0));
+        let closed = Arc::new(Mutex::new(None));
+        tokio::spawn(
+            write_queued(
+                Arc::clone(&inner),
+                receiver,
+                Arc::clone(&queued_bytes),
+                Arc::clone(&closed),
+                max_buffered_amount,
+            )
+            .in_current_span(),
+        );
+        SendQueue {
+            inner,
+            sender,
+            stats,
+            queued_bytes,
+            closed,
+        }
+    }
+
+    /// Queues `payload` without waiting, failing with `SendQueueFull` (and
+    /// counting the drop) if there's no room for it
+    pub fn try_send(&self, payload: &Bytes) -> Result<usize> {
+        // counted before the writer can take it off the queue again
+        self.queued_bytes
+            .fetch_add(payload.len(), Ordering::Relaxed);
+        match self.sender.try_send(payload.clone()) {
+            Ok(()) => Ok(payload.len()),
+            Err(TrySendError::Full(_)) => {
+                self.queued_bytes
+                    .fetch_sub(payload.len(), Ordering::Relaxed);
+                self.stats.record_queue_drop();
+                Err(SendQueueFull.into())
+            }
+            Err(TrySendError::Closed(_)) => {
+                self.queued_bytes
+                    .fetch_sub(payload.len(), Ordering::Relaxed);
+                Err(self.closed_error())
+            }
+        }
+    }
+
+    fn closed_error(&self) -> anyhow::Error {
+        match &*self.closed.lock().expect("send queue lock poisoned") {
+            Some(reason) => anyhow!("send queue closed: {}", reason),
+            None => anyhow!("send queue closed"),
+        }
+    }
+}
+
+// the loops send through the queue too, waiting for room instead of dropping
+#[async_trait]
+impl Transport for SendQueue {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        self.queued_bytes
+            .fetch_add(payload.len(), Ordering::Relaxed);
+        if self.sender.send(payload.clone()).await.is_err() {
+            self.queued_bytes
+                .fetch_sub(payload.len(), Ordering::Relaxed);
+            return Err(self.closed_error());
+        }
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        self.inner.recv(buffer).await
+    }
+
+    fn buffered_amount(&self) -> usize {
+        self.inner.buffered_amount() + self.queued_bytes.load(Ordering::Relaxed)
+    }
+    async fn close(&self) -> Result<()> {
+        self.inner.close().await
+    }
+}
+
+async fn write_queued(
+    inner: Arc<dyn Transport>,
+    mut receiver: mpsc::Receiver<Bytes>,
+    queued_bytes: Arc<AtomicUsize>,
+    closed: Arc<Mutex<Option<String>>>,
+    max_buffered_amount: usize,
+) {
+    while let Some(message) = receiver.recv().await {
+        if inner.buffered_amount() > max_buffered_amount {
+            inner.buffered_amount_low(max_buffered_amount / 2).await;
+        }
+        let result = inner.send(&message).await;
+        queued_bytes.fetch_sub(message.len(), Ordering::Relaxed);
+        if let Err(error) = result {
+            info!(
+                "Datachannel closed; Stop writing queued messages: {}",
+                error
+            );
+            *closed.lock().expect("send queue lock poisoned") = Some(format!("{:#}", error));
+            return;
+        }
+    }
+}
@@ -0,0 +1,825 @@
+use anyhow::{anyhow, Result};
+use std::{future::Future, sync::Arc};
+use tokio::sync::{mpsc, watch};
+use tokio::time::Duration;
+use tracing::Instrument;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::{
+    ack::{retransmit_loop, AckSettings, AckTracker},
+    addr_cell::AddrCell,
+    burst::send_burst,
+    candidate_pair::monitor_candidate_pair,
+    challenge::verify_server,
+    checksum::ChecksumTransport,
+    client::{ChannelMessage, Client},
+    client_stats::{log_stats, write_stats_file, ClientStats},
+    compression::{negotiate_compression, CompressedTransport},
+    connection_status::ConnectionStatus,
+    event_log::{ClientEvent, EventLog},
+    keepalive::{keepalive_loop, receive_timeout},
+    log_sampler::LogSampler,
+    loops::{read_loop, write_loop, ReadSettings, SendSettings},
+    readiness::confirm_ready,
+    reconnect::ReconnectReason,
+    repl::run_repl,
+    slow_start::SlowStart,
+    transport::Transport,
+};
+
+// how long the read & write loops get to exit once the session ends
+const TASK_JOIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a session does over a connected client: what it checks & negotiates
+/// before trusting the data channel, which loops run over it, and what ends
+/// it. The binary, message size & sequence settings in `read` apply to the
+/// checks & negotiation too.
+#[derive(Clone)]
+pub struct SessionSettings {
+    pub send: SendSettings,
+    pub read: ReadSettings,
+    /// Tries at the PING the data channel has to answer before it's used,
+    /// none skipping the check
+    pub ready_attempts: u32,
+    /// How long each of those waits for the PONG
+    pub ready_timeout: Duration,
+    /// Secret the server has to prove it knows before it's trusted
+    pub shared_secret: Option<String>,
+    pub challenge_timeout: Duration,
+    /// Ask the server to compress payloads, sending them as-is if it
+    /// doesn't agree within `compression_timeout`
+    pub compress: bool,
+    pub compression_timeout: Duration,
+    /// Append a CRC32 to every message sent, for a server validating
+    /// checksums
+    pub checksum: bool,
+    /// Log 1 in every this many received messages
+    pub log_every: u64,
+    /// Log at most this many received messages per second
+    pub log_max_per_sec: Option<u32>,
+    /// Have the server acknowledge every message, retransmitting the ones
+    /// it doesn't
+    pub ack: Option<AckSettings>,
+    pub slow_start: Option<SlowStart>,
+    /// PINGs sent back to back once connected, before the write loop starts
+    pub burst: Option<u32>,
+    /// Read commands such as `burst N` from stdin
+    pub interactive: bool,
+    /// Send the single byte `keepalive_marker` this often
+    pub keepalive_interval: Option<Duration>,
+    pub keepalive_marker: u8,
+    /// End the session once nothing has been received for this long
+    pub keepalive_timeout: Option<Duration>,
+    /// End the session once it has been open this long
+    pub max_session_lifetime: Option<Duration>,
+    /// End the session, successfully, once it has run this long
+    pub duration: Option<Duration>,
+    /// How long the last echoes get to arrive once `send.count` is sent,
+    /// before the session ends successfully
+    pub drain: Duration,
+    /// Append a stats snapshot to this file every interval
+    pub stats_file: Option<(String, Duration)>,
+    /// Log the stats this often
+    pub stats_log_interval: Option<Duration>,
+    /// Log the selected ICE candidate pair this often
+    pub candidate_pair_interval: Option<Duration>,
+    /// Extra channels whose messages are logged
+    pub log_channels: Vec<String>,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        SessionSettings {
+            send: SendSettings::default(),
+            read: ReadSettings::default(),
+            ready_attempts: 5,
+            ready_timeout: Duration::from_secs(1),
+            shared_secret: None,
+            challenge_timeout: Duration::from_secs(5),
+            compress: false,
+            compression_timeout: Duration::from_secs(5),
+            checksum: false,
+            log_every: 1,
+            log_max_per_sec: None,
+            ack: None,
+            slow_start: None,
+            burst: None,
+            interactive: false,
+            keepalive_interval: None,
+            keepalive_marker: 0,
+            keepalive_timeout: None,
+            max_session_lifetime: None,
+            duration: None,
+            drain: Duration::from_secs(1),
+            stats_file: None,
+            stats_log_interval: None,
+            candidate_pair_interval: None,
+            log_channels: Vec::new(),
+        }
+    }
+}
+
+impl Client {
+    /// Runs a session over the open data channel until a loop ends it, the
+    /// connection fails or `stop` resolves. It ends with an error, tagged
+    /// with its `ReconnectReason` where there is one, if it failed: a check,
+    /// a negotiation or a loop failing, the channel closing, the keepalive
+    /// timing out, the lifetime running out or the peer connection ending.
+    /// The loops are stopped & given a moment to exit before it returns; the
+    /// client stays open.
+    pub async fn run_session(
+        &self,
+        settings: &SessionSettings,
+        stop: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let labels: Vec<&str> = settings.log_channels.iter().map(String::as_str).collect();
+        let connection = Connection {
+            transport: self.transport(),
+            stats: Arc::clone(self.stats()),
+            status: Arc::clone(self.status()),
+            events: self.events().clone(),
+            addr_cell: self.addr_cell().clone(),
+            peer_connection: Some(Arc::clone(self.peer_connection())),
+            extra_incoming: (!labels.is_empty()).then(|| self.route_incoming(&labels)),
+        };
+        run_connection(connection, settings, self.closed(), stop)
+            .instrument(self.span().clone())
+            .await
+    }
+}
+
+// what a session runs over, apart from the peer connection ending
+struct Connection {
+    transport: Arc<dyn Transport>,
+    stats: Arc<ClientStats>,
+    status: Arc<ConnectionStatus>,
+    events: EventLog,
+    addr_cell: AddrCell,
+    peer_connection: Option<Arc<RTCPeerConnection>>,
+    extra_incoming: Option<mpsc::Receiver<ChannelMessage>>,
+}
+
+async fn run_connection(
+    connection: Connection,
+    settings: &SessionSettings,
+    closed: impl Future<Output = anyhow::Error>,
+    stop: impl Future<Output = ()>,
+) -> Result<()> {
+    let Connection {
+        transport,
+        stats,
+        status,
+        events,
+        addr_cell,
+        peer_connection,
+        extra_incoming,
+    } = connection;
+    let read = &settings.read;
+    // what the checks & negotiation go over; compression, once agreed, goes
+    // under the checksums
+    let checked = checksummed(Arc::clone(&transport), settings.checksum);
+
+    // tasks send here to end the session, with an error if it failed
+    let (shutdown_sender, mut shutdown_receiver) = mpsc::unbounded_channel::<Result<()>>();
+    // flipped once the session ends, so the loops stop instead of being dropped
+    let (stop_sender, stop_receiver) = watch::channel(false);
+
+    if let Some((path, interval)) = settings.stats_file.clone() {
+        let stats = Arc::clone(&stats);
+        let shutdown_sender = shutdown_sender.clone();
+        tokio::spawn(
+            async move {
+                if let Err(error) = write_stats_file(&path, interval, &stats).await {
+                    let _ = shutdown_sender.send(Err(error));
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    if let Some(interval) = settings.stats_log_interval {
+        let stats = Arc::clone(&stats);
+        let stop = stop_receiver.clone();
+        tokio::spawn(async move { log_stats(&stats, interval, stop).await }.in_current_span());
+    }
+
+    if let (Some(interval), Some(peer_connection)) =
+        (settings.candidate_pair_interval, peer_connection)
+    {
+        tokio::spawn(
+            monitor_candidate_pair(peer_connection, interval, events.clone()).in_current_span(),
+        );
+    }
+
+    // the association may not be ready to carry data the moment the channel opens
+    if let Err(error) = confirm_ready(
+        &*checked,
+        settings.ready_attempts,
+        settings.ready_timeout,
+        read.binary,
+        read.server_sequences,
+        read.max_message_size,
+    )
+    .await
+    {
+        error!("Data channel never became usable: {:#}", error);
+        return Err(error);
+    }
+
+    // don't trust the connection until the server proves it knows the secret
+    if let Some(secret) = &settings.shared_secret {
+        if let Err(error) = verify_server(
+            &*checked,
+            secret.as_bytes(),
+            settings.challenge_timeout,
+            read.max_message_size,
+        )
+        .await
+        {
+            error!("Server identity challenge failed: {:#}", error);
+            return Err(error);
+        }
+        info!("Server passed the identity challenge");
+    }
+
+    // everything from here on goes through the compression layer, if agreed
+    let transport: Arc<dyn Transport> = if settings.compress {
+        let timeout = settings.compression_timeout;
+        match negotiate_compression(&*checked, timeout, read.max_message_size).await {
+            Ok(true) => {
+                info!("Server agreed to compress payloads");
+                checksummed(
+                    Arc::new(CompressedTransport::new(transport)),
+                    settings.checksum,
+                )
+            }
+            Ok(false) => {
+                warn!(
+                    "Server didn't agree to compress payloads within {:?}, sending them as-is",
+                    timeout
+                );
+                checked
+            }
+            Err(error) => {
+                error!("Compression negotiation failed: {:#}", error);
+                return Err(error);
+            }
+        }
+    } else {
+        checked
+    };
+
+    status.set_channel_open(true);
+    events.record(ClientEvent::DataChannelOpen);
+
+    let acks = settings.ack.map(|_| Arc::new(AckTracker::default()));
+
+    // Handle reading from the data channel
+    let read_transport = Arc::clone(&transport);
+    let read_stats = Arc::clone(&stats);
+    let read_addr_cell = addr_cell.clone();
+    let read_shutdown_sender = shutdown_sender.clone();
+    let read_status = Arc::clone(&status);
+    let read_events = events.clone();
+    let read_stop = stop_receiver.clone();
+    let log_sampler = LogSampler::new(settings.log_every, settings.log_max_per_sec);
+    let read_settings = ReadSettings {
+        acks: acks.clone(),
+        ..read.clone()
+    };
+    let read_task = tokio::spawn(
+        async move {
+            let result = read_loop(
+                read_transport,
+                read_stats,
+                log_sampler,
+                read_addr_cell,
+                read_settings,
+                read_stop,
+            )
+            .await;
+            read_status.set_channel_open(false);
+            read_events.record(ClientEvent::DataChannelClosed);
+            let error = match result {
+                Ok(()) => ReconnectReason::ChannelClosed.error(anyhow!("data channel closed")),
+                Err(error) => error.context("read loop failed"),
+            };
+            let _ = read_shutdown_sender.send(Err(error));
+        }
+        .in_current_span(),
+    );
+
+    // the extra channels only log what arrives on them
+    if let Some(mut incoming) = extra_incoming {
+        tokio::spawn(
+            async move {
+                while let Some(message) = incoming.recv().await {
+                    info!(
+                        "Received on '{}': {}",
+                        message.label,
+                        String::from_utf8_lossy(&message.payload)
+                    );
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    if let Some(count) = settings.burst {
+        if let Err(error) = send_burst(&*transport, &stats, count, read.binary).await {
+            warn!("{:#}", error);
+        }
+    }
+    if settings.interactive {
+        let repl_channel = Arc::clone(&transport);
+        let repl_stats = Arc::clone(&stats);
+        let binary = read.binary;
+        tokio::spawn(
+            async move {
+                if let Err(error) = run_repl(repl_channel, repl_stats, binary).await {
+                    warn!("{:#}", error);
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    if let Some(interval) = settings.keepalive_interval {
+        let keepalive_channel = Arc::clone(&transport);
+        let marker = settings.keepalive_marker;
+        let keepalive_stop = stop_receiver.clone();
+        let keepalive_shutdown_sender = shutdown_sender.clone();
+        tokio::spawn(
+            async move {
+                if let Err(error) =
+                    keepalive_loop(keepalive_channel, marker, interval, keepalive_stop).await
+                {
+                    let _ =
+                        keepalive_shutdown_sender.send(Err(error.context("keepalive loop failed")));
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    // an unreliable channel never errors when the server goes quiet
+    if let Some(timeout) = settings.keepalive_timeout {
+        let timeout_stats = Arc::clone(&stats);
+        let timeout_stop = stop_receiver.clone();
+        let timeout_shutdown_sender = shutdown_sender.clone();
+        tokio::spawn(
+            async move {
+                if let Err(error) = receive_timeout(&timeout_stats, timeout, timeout_stop).await {
+                    warn!("{:#}; treating the connection as dead", error);
+                    let _ = timeout_shutdown_sender.send(Err(error));
+                }
+            }
+            .in_current_span(),
+        );
+    }
+
+    if let Some(lifetime) = settings.max_session_lifetime {
+        let lifetime_shutdown_sender = shutdown_sender.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(lifetime).await;
+                let error = anyhow!("connection open for {:?}", lifetime);
+                let _ = lifetime_shutdown_sender
+                    .send(Err(ReconnectReason::MaxSessionLifetime.error(error)));
+            }
+            .in_current_span(),
+        );
+    }
+
+    // resend whatever the server doesn't acknowledge in time
+    if let (Some(acks), Some(ack_settings)) = (&acks, settings.ack) {
+        tokio::spawn(
+            retransmit_loop(
+                Arc::clone(&transport),
+                Arc::clone(acks),
+                Arc::clone(&stats),
+                ack_settings,
+                stop_receiver.clone(),
+            )
+            .in_current_span(),
+        );
+    }
+
+    // Handle writing to the data channel
+    let send_settings = SendSettings {
+        acks,
+        binary: read.binary,
+        ..settings.send.clone()
+    };
+    let slow_start = settings.slow_start;
+    let write_addr_cell = addr_cell.clone();
+    let write_shutdown_sender = shutdown_sender.clone();
+    let counted = send_settings.count.is_some();
+    let drain = settings.drain;
+    let write_task = tokio::spawn(
+        async move {
+            let result = write_loop(
+                transport,
+                stats,
+                send_settings,
+                slow_start,
+                write_addr_cell,
+                stop_receiver,
+            )
+            .await;
+            if let Err(error) = result {
+                let _ = write_shutdown_sender.send(Err(error.context("write loop failed")));
+                return;
+            }
+            // once the count is sent, give the last echoes a moment to arrive
+            if counted {
+                tokio::time::sleep(drain).await;
+                let _ = write_shutdown_sender.send(Ok(()));
+            }
+        }
+        .in_current_span(),
+    );
+
+    // end the session once it has been connected for long enough
+    if let Some(run_duration) = settings.duration {
+        tokio::spawn(
+            async move {
+                tokio::time::sleep(run_duration).await;
+                info!("Run duration of {:?} elapsed", run_duration);
+                let _ = shutdown_sender.send(Ok(()));
+            }
+            .in_current_span(),
+        );
+    }
+
+    // wait until a task ends the session, the connection fails, or the
+    // caller stops it
+    let result = tokio::select! {
+        result = shutdown_receiver.recv() => result.unwrap_or(Ok(())),
+        error = closed => Err(error),
+        _ = stop => Ok(()),
+    };
+    let _ = stop_sender.send(true);
+
+    // the loops finish what they're doing before the channel is closed
+    let joined = tokio::time::timeout(TASK_JOIN_TIMEOUT, async {
+        let _ = tokio::join!(read_task, write_task);
+    })
+    .await;
+    if joined.is_err() {
+        warn!(
+            "The read & write loops didn't exit within {:?}",
+            TASK_JOIN_TIMEOUT
+        );
+    }
+    info!(
+        "Connected at shutdown: {} ({})",
+        status.is_connected(),
+        status.connection_state()
+    );
+
+    result
+}
+
+// appends a checksum to everything sent over `transport`, if asked to
+fn checksummed(transport: Arc<dyn Transport>, checksum: bool) -> Arc<dyn Transport> {
+    if checksum {
+        Arc::new(ChecksumTransport::new(transport))
+    } else {
+        transport
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use signaling_shared::checksum::strip_checksum;
+    use tokio::sync::oneshot;
+
+    use crate::transport::MemoryTransport;
+
+    // a connection over `near`, with the stats & events to look at after
+    fn connection(near: MemoryTransport) -> (Connection, Arc<ClientStats>, EventLog) {
+        let stats = Arc::new(ClientStats::default());
+        let events = EventLog::default();
+        let connection = Connection {
+            transport: Arc::new(near),
+            stats: Arc::clone(&stats),
+            status: Arc::new(ConnectionStatus::default()),
+            events: events.clone(),
+            addr_cell: AddrCell::default(),
+            peer_connection: None,
+            extra_incoming: None,
+        };
+        (connection, stats, events)
+    }
+
+    // answers every PING, stamped or not, with the matching PONG, until
+    // `answers` have been sent or the client goes away; then keeps the
+    // transport open, but quiet, if `linger`
+    async fn pong_server(far: MemoryTransport, answers: usize, linger: bool) {
+        let mut buffer = vec![0u8; 1500];
+        for _ in 0..answers {
+            let length = match far.recv(&mut buffer).await {
+                Ok(length) => length,
+                Err(_) => return,
+            };
+            let message = String::from_utf8_lossy(&buffer[..length]).replacen("PING", "PONG", 1);
+            if far.send(&Bytes::from(message)).await.is_err() {
+                return;
+            }
+        }
+        if linger {
+            while far.recv(&mut buffer).await.is_ok() {}
+        }
+    }
+
+    // sends every message straight back, like the server's `Echo`
+    async fn echo_server(far: MemoryTransport) {
+        let mut buffer = vec![0u8; 1500];
+        while let Ok(length) = far.recv(&mut buffer).await {
+            let message = Bytes::copy_from_slice(&buffer[..length]);
+            if far.send(&message).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn settings() -> SessionSettings {
+        SessionSettings {
+            send: SendSettings {
+                interval: Duration::from_millis(1),
+                ..SendSettings::default()
+            },
+            ready_attempts: 1,
+            ready_timeout: Duration::from_millis(200),
+            drain: Duration::from_millis(50),
+            ..SessionSettings::default()
+        }
+    }
+
+    async fn run(
+        connection: Connection,
+        settings: SessionSettings,
+        closed: impl Future<Output = anyhow::Error>,
+        stop: impl Future<Output = ()>,
+    ) -> Result<()> {
+        tokio::time::timeout(
+            Duration::from_secs(5),
+            run_connection(connection, &settings, closed, stop),
+        )
+        .await
+        .expect("the session never ended")
+    }
+
+    #[tokio::test]
+    async fn a_counted_session_ends_once_the_last_echo_drains() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, usize::MAX, false));
+        let (connection, stats, events) = connection(near);
+        let mut recorded = events.subscribe();
+        let settings = SessionSettings {
+            send: SendSettings {
+                count: Some(3),
+                ..settings().send
+            },
+            ..settings()
+        };
+
+        run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stats.messages_sent(), 3);
+        assert!(stats.pongs_received() >= 3);
+        assert!(matches!(
+            recorded.try_recv(),
+            Ok(ClientEvent::DataChannelOpen)
+        ));
+        assert!(matches!(
+            recorded.try_recv(),
+            Ok(ClientEvent::DataChannelClosed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn an_echoing_server_passes_the_readiness_check() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(echo_server(far));
+        let (connection, stats, _) = connection(near);
+        let settings = SessionSettings {
+            send: SendSettings {
+                count: Some(3),
+                ..settings().send
+            },
+            ..settings()
+        };
+
+        run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stats.messages_sent(), 3);
+        assert!(stats.messages_received() >= 3);
+    }
+
+    #[tokio::test]
+    async fn checksums_go_on_everything_sent() {
+        let (near, far) = MemoryTransport::pair();
+        // answers PINGs like `pong_server`, but only once their checksum's off
+        let server = tokio::spawn(async move {
+            let mut buffer = vec![0u8; 1500];
+            let mut checked = 0;
+            while let Ok(length) = far.recv(&mut buffer).await {
+                let message = strip_checksum(&buffer[..length]).expect("no checksum");
+                let pong = String::from_utf8_lossy(message).replacen("PING", "PONG", 1);
+                checked += 1;
+                if far.send(&Bytes::from(pong)).await.is_err() {
+                    break;
+                }
+            }
+            checked
+        });
+        let (connection, stats, _) = connection(near);
+        let settings = SessionSettings {
+            send: SendSettings {
+                count: Some(3),
+                ..settings().send
+            },
+            checksum: true,
+            ..settings()
+        };
+
+        run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap();
+        assert!(stats.pongs_received() >= 3);
+        // the readiness PING & the three sent, at least
+        assert!(server.await.unwrap() >= 4);
+    }
+
+    #[tokio::test]
+    async fn stopping_ends_the_session() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, usize::MAX, false));
+        let (connection, _, _) = connection(near);
+        let (stop_sender, stop) = oneshot::channel::<()>();
+        let stopping = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let _ = stop_sender.send(());
+        });
+
+        let stop = async {
+            let _ = stop.await;
+        };
+        run(connection, settings(), std::future::pending(), stop)
+            .await
+            .unwrap();
+        stopping.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_server_going_away_closes_the_channel() {
+        let (near, far) = MemoryTransport::pair();
+        // answers the readiness PING, then drops its end
+        tokio::spawn(pong_server(far, 1, false));
+        let (connection, _, _) = connection(near);
+
+        let error = run(
+            connection,
+            settings(),
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(ReconnectReason::of(&error), ReconnectReason::ChannelClosed);
+        assert_eq!(format!("{:#}", error), "data channel closed");
+    }
+
+    #[tokio::test]
+    async fn a_quiet_server_times_the_keepalive_out() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, 1, true));
+        let (connection, _, _) = connection(near);
+        let settings = SessionSettings {
+            keepalive_timeout: Some(Duration::from_millis(50)),
+            ..settings()
+        };
+
+        let error = run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            ReconnectReason::of(&error),
+            ReconnectReason::KeepaliveTimeout
+        );
+    }
+
+    #[tokio::test]
+    async fn the_session_ends_at_its_lifetime() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, usize::MAX, false));
+        let (connection, _, _) = connection(near);
+        let settings = SessionSettings {
+            max_session_lifetime: Some(Duration::from_millis(50)),
+            ..settings()
+        };
+
+        let error = run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            ReconnectReason::of(&error),
+            ReconnectReason::MaxSessionLifetime
+        );
+    }
+
+    #[tokio::test]
+    async fn a_failing_task_ends_the_session_with_its_error() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, usize::MAX, false));
+        let (connection, _, _) = connection(near);
+        let settings = SessionSettings {
+            stats_file: Some((
+                "/nonexistent/directory/stats.jsonl".to_string(),
+                Duration::from_secs(1),
+            )),
+            ..settings()
+        };
+
+        let error = run(
+            connection,
+            settings,
+            std::future::pending(),
+            std::future::pending(),
+        )
+        .await
+        .unwrap_err();
+        assert!(
+            format!("{:#}", error).contains("/nonexistent/directory/stats.jsonl"),
+            "{:#}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn the_peer_connection_ending_ends_the_session() {
+        let (near, far) = MemoryTransport::pair();
+        tokio::spawn(pong_server(far, usize::MAX, false));
+        let (connection, _, _) = connection(near);
+        let closed = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            ReconnectReason::IceFailed.error(anyhow!("peer connection failed"))
+        };
+
+        let error = run(connection, settings(), closed, std::future::pending())
+            .await
+            .unwrap_err();
+        assert_eq!(ReconnectReason::of(&error), ReconnectReason::IceFailed);
+    }
+
+    #[tokio::test]
+    async fn an_unusable_channel_never_opens_the_session() {
+        let (near, far) = MemoryTransport::pair();
+        // never answers the readiness PING
+        tokio::spawn(pong_server(far, 0, true));
+        let (connection, stats, events) = connection(near);
+        let mut recorded = events.subscribe();
+
+        assert!(run(
+            connection,
+            settings(),
+            std::future::pending(),
+            std::future::pending()
+        )
+        .await
+        .is_err());
+        assert_eq!(stats.messages_sent(), 0);
+        assert!(recorded.try_recv().is_err());
+    }
+}
@@ -0,0 +1,481 @@
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::StatusCode;
+use serde_json::{error::Category, Value};
+pub use signaling_shared::{SessionAnswer, SessionCandidate, SessionResponse, TrickleResponse};
+use std::{convert::TryFrom, fmt};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+use webrtc::peer_connection::RTCPeerConnection;
+
+/// JSON pointers (RFC 6901) locating each field of the session response,
+/// so the client can adapt to a server's response schema. `candidates`
+/// points at a single candidate object or an array of them, and the
+/// candidate fields are resolved within each of those objects.
+#[derive(Clone, PartialEq)]
+pub struct ResponseFieldPaths {
+    pub sdp: String,
+    pub type_str: String,
+    pub candidates: String,
+    pub candidate: String,
+    pub sdp_m_line_index: String,
+    pub sdp_mid: String,
+}
+
+impl Default for ResponseFieldPaths {
+    fn default() -> Self {
+        ResponseFieldPaths {
+            sdp: "/answer/sdp".to_string(),
+            type_str: "/answer/type".to_string(),
+            candidates: "/candidate".to_string(),
+            candidate: "/candidate".to_string(),
+            sdp_m_line_index: "/sdpMLineIndex".to_string(),
+            sdp_mid: "/sdpMid".to_string(),
+        }
+    }
+}
+
+impl ResponseFieldPaths {
+    /// Whether every path is the naia server's own
+    pub fn is_default(&self) -> bool {
+        *self == ResponseFieldPaths::default()
+    }
+
+    /// Overrides a single field's path from a `name=/json/pointer` mapping
+    pub fn apply_mapping(&mut self, mapping: &str) -> Result<()> {
+        let (name, pointer) = mapping.split_once('=').ok_or_else(|| {
+            anyhow!(
+                "field mapping '{}' is not of the form name=/pointer",
+                mapping
+            )
+        })?;
+        if !pointer.is_empty() && !pointer.starts_with('/') {
+            bail!(
+                "JSON pointer '{}' for field '{}' must start with '/'",
+                pointer,
+                name
+            );
+        }
+
+        let path = match name {
+            "sdp" => &mut self.sdp,
+            "type" => &mut self.type_str,
+            "candidates" => &mut self.candidates,
+            "candidate" => &mut self.candidate,
+            "sdpMLineIndex" => &mut self.sdp_m_line_index,
+            "sdpMid" => &mut self.sdp_mid,
+            _ => bail!(
+                "unknown response field '{}', expected one of sdp, type, candidates, candidate, sdpMLineIndex, sdpMid",
+                name
+            ),
+        };
+        *path = pointer.to_string();
+
+        Ok(())
+    }
+}
+
+/// Why a session response couldn't be read
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalingError {
+    /// The response body isn't JSON at all
+    InvalidJson(String),
+    /// Nothing exists at the field's JSON pointer
+    MissingField(String),
+    /// The field exists but holds the wrong kind of JSON value
+    WrongType(String),
+    /// The field is a number outside the range it must fit in
+    OutOfRange(String),
+    /// The response doesn't match the default schema in a way no single
+    /// field accounts for, as reported by serde
+    InvalidShape(String),
+    /// The server answered with a non-2xx status; the body is kept for
+    /// callers, but left out of the message since it's often a whole HTML page
+    Rejected(StatusCode, String),
+}
+
+impl fmt::Display for SignalingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalingError::InvalidJson(error) => {
+                write!(f, "session response is not valid JSON: {}", error)
+            }
+            SignalingError::MissingField(pointer) => {
+                write!(f, "session response has no field at '{}'", pointer)
+            }
+            SignalingError::WrongType(pointer) => {
+                write!(
+                    f,
+                    "session response field at '{}' has the wrong type",
+                    pointer
+                )
+            }
+            SignalingError::OutOfRange(pointer) => {
+                write!(f, "session response field at '{}' is out of range", pointer)
+            }
+            SignalingError::InvalidShape(error) => {
+                write!(f, "session response has an unexpected shape: {}", error)
+            }
+            SignalingError::Rejected(status, _) => {
+                write!(f, "signaling server rejected the offer with {}", status)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SignalingError {}
+
+/// Parses the server's session response. The default schema deserializes
+/// straight into SessionResponse; remapped fields are looked up by their
+/// JSON pointers instead. Either way, a missing or mistyped field is
+/// reported as that field's error.
+pub fn get_session_response(
+    input: &str,
+    paths: &ResponseFieldPaths,
+) -> Result<SessionResponse, SignalingError> {
+    if paths.is_default() {
+        return serde_json::from_str(input).map_err(|error| match error.classify() {
+            // serde can't say which candidate field failed the untagged
+            // one-or-many, so the fields are checked one by one for that
+            Category::Data => {
+                match serde_json::from_str(input).map(|json| get_fields(&json, paths)) {
+                    Ok(Err(field_error)) => field_error,
+                    _ => SignalingError::InvalidShape(error.to_string()),
+                }
+            }
+            _ => SignalingError::InvalidJson(error.to_string()),
+        });
+    }
+
+    let json: Value = serde_json::from_str(input)
+        .map_err(|error| SignalingError::InvalidJson(error.to_string()))?;
+    get_fields(&json, paths)
+}
+
+// reads the response field by field, following the paths
+fn get_fields(json: &Value, paths: &ResponseFieldPaths) -> Result<SessionResponse, SignalingError> {
+    let sdp = get_string(json, &paths.sdp)?;
+
+    let type_str = get_string(json, &paths.type_str)?;
+
+    // servers may send one candidate object, or an array of them
+    let candidates = match json.pointer(&paths.candidates) {
+        Some(Value::Array(candidates)) => candidates
+            .iter()
+            .map(|candidate| get_candidate(candidate, paths))
+            .collect::<Result<Vec<_>, _>>()?,
+        Some(candidate) => vec![get_candidate(candidate, paths)?],
+        None => return Err(SignalingError::MissingField(paths.candidates.clone())),
+    };
+
+    Ok(SessionResponse {
+        answer: SessionAnswer { sdp, type_str },
+        candidate: candidates,
+    })
+}
+
+// reads one candidate object, resolving its fields relative to it
+fn get_candidate(
+    json: &Value,
+    paths: &ResponseFieldPaths,
+) -> Result<SessionCandidate, SignalingError> {
+    let candidate = get_string(json, &paths.candidate)?;
+
+    let sdp_m_line_index = match get_field(json, &paths.sdp_m_line_index)? {
+        Value::Number(number) => number
+            .as_u64()
+            .and_then(|index| u16::try_from(index).ok())
+            .ok_or_else(|| SignalingError::OutOfRange(paths.sdp_m_line_index.clone()))?,
+        _ => return Err(SignalingError::WrongType(paths.sdp_m_line_index.clone())),
+    };
+
+    let sdp_mid = get_string(json, &paths.sdp_mid)?;
+
+    Ok(SessionCandidate {
+        candidate,
+        sdp_m_line_index,
+        sdp_mid,
+    })
+}
+
+/// Applies the server's answer as the remote description, then adds its ICE
+/// candidates. A malformed answer is returned as an error, never a panic.
+pub async fn apply_session_response(
+    peer_connection: &RTCPeerConnection,
+    session_response: SessionResponse,
+) -> Result<()> {
+    let mut session_description = RTCSessionDescription::default();
+    session_description.sdp_type = RTCSdpType::Answer;
+    session_description.sdp = session_response.answer.sdp;
+    peer_connection
+        .set_remote_description(session_description)
+        .await
+        .context("failed to apply server answer as remote description")?;
+
+    // a bad candidate is skipped, as long as one of them works
+    if add_server_candidates(peer_connection, session_response.candidate).await == 0 {
+        bail!("none of the server's ICE candidates could be added");
+    }
+
+    Ok(())
+}
+
+/// Adds the server's ICE candidates to the connection, returning how many
+/// could be added. A bad candidate is logged & skipped.
+pub async fn add_server_candidates(
+    peer_connection: &RTCPeerConnection,
+    candidates: Vec<SessionCandidate>,
+) -> usize {
+    let mut added = 0;
+    for candidate in candidates {
+        let candidate_str = candidate.candidate.clone();
+        let ice_candidate = RTCIceCandidateInit {
+            candidate: candidate.candidate,
+            sdp_mid: candidate.sdp_mid,
+            sdp_mline_index: candidate.sdp_m_line_index,
+            ..Default::default()
+        };
+        match peer_connection.add_ice_candidate(ice_candidate).await {
+            Ok(()) => added += 1,
+            Err(error) => warn!(
+                "Failed to add server ICE candidate '{}': {}",
+                candidate_str, error
+            ),
+        }
+    }
+    added
+}
+
+/// Reads the signaling response body as text, failing as soon as it grows
+/// past `max_bytes` instead of buffering an unbounded body first
+pub async fn read_capped_body(mut response: reqwest::Response, max_bytes: usize) -> Result<String> {
+    if let Some(length) = response.content_length() {
+        if length > max_bytes as u64 {
+            bail!(
+                "signaling response of {} bytes exceeds the {} byte limit",
+                length,
+                max_bytes
+            );
+        }
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .context("could not read signaling response")?
+    {
+        if body.len() + chunk.len() > max_bytes {
+            bail!("signaling response exceeds the {} byte limit", max_bytes);
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    String::from_utf8(body).context("signaling response was not valid UTF-8")
+}
+
+fn get_field<'a>(json: &'a Value, pointer: &str) -> Result<&'a Value, SignalingError> {
+    json.pointer(pointer)
+        .ok_or_else(|| SignalingError::MissingField(pointer.to_string()))
+}
+
+fn get_string(json: &Value, pointer: &str) -> Result<String, SignalingError> {
+    get_field(json, pointer)?
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| SignalingError::WrongType(pointer.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_http::serve_once;
+
+    fn parse(input: &str) -> Result<SessionResponse, SignalingError> {
+        get_session_response(input, &ResponseFieldPaths::default())
+    }
+
+    const RESPONSE: &str = r#"{
+        "answer": { "sdp": "v=0", "type": "answer" },
+        "candidate": {
+            "candidate": "candidate:1 1 UDP 2122317823 127.0.0.1 14192 typ host",
+            "sdpMLineIndex": 0,
+            "sdpMid": "0"
+        }
+    }"#;
+
+    // the response with its candidate's sdpMLineIndex replaced
+    fn with_index(index: &str) -> String {
+        RESPONSE.replace(
+            r#""sdpMLineIndex": 0"#,
+            &format!(r#""sdpMLineIndex": {}"#, index),
+        )
+    }
+
+    // paths for a server nesting the response under `data`, so fields are
+    // looked up by pointer rather than by serde
+    fn nested_paths() -> ResponseFieldPaths {
+        let mut paths = ResponseFieldPaths::default();
+        for mapping in [
+            "sdp=/data/answer/sdp",
+            "type=/data/answer/type",
+            "candidates=/data/candidate",
+        ] {
+            paths.apply_mapping(mapping).unwrap();
+        }
+        paths
+    }
+
+    fn nested(input: &str) -> String {
+        format!(r#"{{ "data": {} }}"#, input)
+    }
+
+    #[test]
+    fn reads_the_naia_servers_response() {
+        let response = parse(RESPONSE).unwrap();
+        assert_eq!(response.answer.sdp, "v=0");
+        assert_eq!(response.answer.type_str, "answer");
+        assert_eq!(response.candidate.len(), 1);
+        assert_eq!(response.candidate[0].sdp_m_line_index, 0);
+        assert_eq!(response.candidate[0].sdp_mid, "0");
+    }
+
+    #[test]
+    fn a_missing_sdp_is_a_missing_field() {
+        let input = RESPONSE.replace(r#""sdp": "v=0", "#, "");
+        assert_eq!(
+            parse(&input).unwrap_err(),
+            SignalingError::MissingField("/answer/sdp".to_string())
+        );
+    }
+
+    #[test]
+    fn a_non_numeric_index_is_the_wrong_type() {
+        assert_eq!(
+            parse(&with_index(r#""zero""#)).unwrap_err(),
+            SignalingError::WrongType("/sdpMLineIndex".to_string())
+        );
+    }
+
+    #[test]
+    fn an_index_past_u16_is_out_of_range() {
+        for index in ["65536", "-1", "0.5"] {
+            assert_eq!(
+                parse(&with_index(index)).unwrap_err(),
+                SignalingError::OutOfRange("/sdpMLineIndex".to_string()),
+                "sdpMLineIndex {}",
+                index
+            );
+        }
+        assert_eq!(
+            parse(&with_index("65535")).unwrap().candidate[0].sdp_m_line_index,
+            65535
+        );
+    }
+
+    #[test]
+    fn remapped_fields_fail_the_same_way() {
+        let paths = nested_paths();
+        assert_eq!(
+            get_session_response(&nested(RESPONSE), &paths)
+                .unwrap()
+                .answer
+                .sdp,
+            "v=0"
+        );
+        assert_eq!(
+            get_session_response(&nested(&RESPONSE.replace(r#""sdp": "v=0", "#, "")), &paths)
+                .unwrap_err(),
+            SignalingError::MissingField("/data/answer/sdp".to_string())
+        );
+        assert_eq!(
+            get_session_response(&nested(&with_index("true")), &paths).unwrap_err(),
+            SignalingError::WrongType("/sdpMLineIndex".to_string())
+        );
+        assert_eq!(
+            get_session_response(&nested(&with_index("70000")), &paths).unwrap_err(),
+            SignalingError::OutOfRange("/sdpMLineIndex".to_string())
+        );
+        assert!(matches!(
+            get_session_response("not json", &paths),
+            Err(SignalingError::InvalidJson(_))
+        ));
+    }
+
+    #[test]
+    fn a_body_that_isnt_json_is_invalid_json() {
+        for input in ["<html>502 Bad Gateway</html>", "", r#"{"answer": "#] {
+            assert!(
+                matches!(parse(input), Err(SignalingError::InvalidJson(_))),
+                "{:?}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn the_default_schema_names_the_field_at_fault() {
+        let missing_mid = r#"{
+            "answer": { "sdp": "v=0", "type": "answer" },
+            "candidate": { "candidate": "candidate:1", "sdpMLineIndex": 0 }
+        }"#;
+        assert_eq!(
+            parse(missing_mid).unwrap_err(),
+            SignalingError::MissingField("/sdpMid".to_string())
+        );
+
+        let mistyped_candidate = r#"{
+            "answer": { "sdp": "v=0", "type": "answer" },
+            "candidate": [{ "candidate": 1, "sdpMLineIndex": 0, "sdpMid": "0" }]
+        }"#;
+        assert_eq!(
+            parse(mistyped_candidate).unwrap_err(),
+            SignalingError::WrongType("/candidate".to_string())
+        );
+
+        let no_candidates = r#"{ "answer": { "sdp": "v=0", "type": "answer" } }"#;
+        assert_eq!(
+            parse(no_candidates).unwrap_err(),
+            SignalingError::MissingField("/candidate".to_string())
+        );
+    }
+
+    async fn fetch(response: &str) -> reqwest::Response {
+        let (url, _served) = serve_once(response.as_bytes().to_vec()).await;
+        reqwest::get(url).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn bodies_within_the_limit_are_read() {
+        let response = fetch("HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nPONG").await;
+        assert_eq!(read_capped_body(response, 4).await.unwrap(), "PONG");
+    }
+
+    #[tokio::test]
+    async fn a_declared_length_over_the_limit_is_refused_unread() {
+        let response = fetch("HTTP/1.1 200 OK\r\nContent-Length: 1000\r\n\r\n").await;
+        let error = read_capped_body(response, 100).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "signaling response of 1000 bytes exceeds the 100 byte limit"
+        );
+    }
+
+    #[tokio::test]
+    async fn an_undeclared_body_is_cut_off_at_the_limit() {
+        let chunk = "x".repeat(60);
+        let response = fetch(&format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+            chunk.len(),
+            chunk,
+            chunk.len(),
+            chunk
+        ))
+        .await;
+        let error = read_capped_body(response, 100).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "signaling response exceeds the 100 byte limit"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use std::str::FromStr;
+
+use tokio::time::Duration;
+
+// fraction of the target send rate the ramp starts at
+const INITIAL_RATE: f64 = 0.1;
+
+/// How the send rate grows during slow start
+#[derive(Clone, Copy, Debug)]
+pub enum RampShape {
+    /// The rate grows by the same amount every instant
+    Linear,
+    /// The rate grows by the same factor every instant
+    Exponential,
+}
+
+impl RampShape {
+    /// Name & description of every shape, as `--slow-start-shape` takes them
+    pub const SHAPES: &'static [(&'static str, &'static str)] = &[
+        ("linear", "the rate grows by the same amount every instant"),
+        (
+            "exponential",
+            "the rate grows by the same factor every instant",
+        ),
+    ];
+}
+
+impl FromStr for RampShape {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        match input {
+            "linear" => Ok(RampShape::Linear),
+            "exponential" => Ok(RampShape::Exponential),
+            _ => Err(format!(
+                "unknown ramp shape '{}', expected linear or exponential",
+                input
+            )),
+        }
+    }
+}
+
+/// Ramps the write loop's send rate from a fraction of the target up to the
+/// full rate over a window after connecting, to avoid overwhelming a fresh link
+#[derive(Clone, Copy, Debug)]
+pub struct SlowStart {
+    pub window: Duration,
+    pub shape: RampShape,
+}
+
+impl SlowStart {
+    /// The interval to wait before the next send, `elapsed` after the ramp
+    /// began, given the interval at full rate. Returns `None` once the ramp
+    /// is over.
+    pub fn interval_at(&self, target: Duration, elapsed: Duration) -> Option<Duration> {
+        if elapsed >= self.window {
+            return None;
+        }
+
+        let progress = elapsed.as_secs_f64() / self.window.as_secs_f64();
+        let rate = match self.shape {
+            RampShape::Linear => INITIAL_RATE + (1.0 - INITIAL_RATE) * progress,
+            RampShape::Exponential => INITIAL_RATE * (1.0 / INITIAL_RATE).powf(progress),
+        };
+
+        Some(target.div_f64(rate))
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use reqwest::Url;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+
+use crate::{
+    addr_cell::ServerAddr,
+    client::{Client, ClientConfig},
+};
+
+/// The client in the shape of naia's socket API: connecting hands back a
+/// sender & a receiver of packets, for apps that poll for packets once a
+/// tick rather than drive their own loops. `Client` offers the lot.
+pub struct ClientSocket;
+
+impl ClientSocket {
+    /// Connects to the signaling endpoint at `server_url` with the default
+    /// `ClientConfig`
+    pub async fn connect(server_url: Url) -> Result<(PacketSender, PacketReceiver)> {
+        ClientSocket::connect_with(ClientConfig::new(server_url)).await
+    }
+
+    /// Connects with `config`, resolving once the data channel is open
+    pub async fn connect_with(config: ClientConfig) -> Result<(PacketSender, PacketReceiver)> {
+        Ok(split(Client::connect(config).await?))
+    }
+
+    /// Like `connect_with`, but gives up once `cancel` resolves, returning
+    /// `None`, as `Client::connect_until` does
+    pub async fn connect_until(
+        config: ClientConfig,
+        cancel: impl Future<Output = ()>,
+    ) -> Result<Option<(PacketSender, PacketReceiver)>> {
+        Ok(Client::connect_until(config, cancel).await?.map(split))
+    }
+}
+
+fn split(client: Client) -> (PacketSender, PacketReceiver) {
+    let client = Arc::new(client);
+    (
+        PacketSender {
+            client: Arc::clone(&client),
+        },
+        PacketReceiver {
+            client,
+            incoming: None,
+        },
+    )
+}
+
+/// Sends packets to the server. Clones share the connection, which stays
+/// open until `close` is called.
+#[derive(Clone)]
+pub struct PacketSender {
+    client: Arc<Client>,
+}
+
+impl PacketSender {
+    /// Queues `payload` without waiting, failing with `SendQueueFull` if the
+    /// send queue has no room for it
+    pub fn send(&self, payload: &[u8]) -> Result<()> {
+        self.client.queue(payload)?;
+        Ok(())
+    }
+
+    pub fn server_addr(&self) -> ServerAddr {
+        self.client.server_addr()
+    }
+
+    /// Whether the connection is up & the data channel carrying traffic
+    pub fn is_connected(&self) -> bool {
+        self.client.is_connected()
+    }
+
+    /// The last state reported by the peer connection
+    pub fn connection_state(&self) -> RTCPeerConnectionState {
+        self.client.connection_state()
+    }
+
+    /// The client underneath, for its stats & events
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    /// Closes the data channels & peer connection, which ends the receiver
+    pub async fn close(&self) {
+        self.client.close().await
+    }
+}
+
+/// Receives the server's packets. Reading starts with the first `receive`
+/// or `recv`, so until then the client's own loops can read the transport.
+pub struct PacketReceiver {
+    client: Arc<Client>,
+    incoming: Option<mpsc::Receiver<Bytes>>,
+}
+
+impl PacketReceiver {
+    /// Takes the next packet that has arrived, without waiting: `None` when
+    /// there isn't one yet, and an error once the connection has closed
+    pub fn receive(&mut self) -> Result<Option<Bytes>> {
+        match self.incoming().try_recv() {
+            Ok(packet) => Ok(Some(packet)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err(anyhow!("connection to the server closed")),
+        }
+    }
+
+    /// Waits for the next packet, `None` once the connection has closed
+    pub async fn recv(&mut self) -> Option<Bytes> {
+        self.incoming().recv().await
+    }
+
+    pub fn server_addr(&self) -> ServerAddr {
+        self.client.server_addr()
+    }
+
+    fn incoming(&mut self) -> &mut mpsc::Receiver<Bytes> {
+        let client = &self.client;
+        self.incoming.get_or_insert_with(|| client.incoming())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{MemoryTransport, Transport};
+    use tokio::time::{timeout, Duration};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    async fn socket() -> (PacketSender, PacketReceiver, MemoryTransport) {
+        let (near, far) = MemoryTransport::pair();
+        let (sender, receiver) = split(Client::over(Arc::new(near)).await);
+        (sender, receiver, far)
+    }
+
+    async fn recv_far(far: &MemoryTransport) -> Result<Vec<u8>> {
+        let mut buffer = [0u8; 64];
+        let length = timeout(TIMEOUT, far.recv(&mut buffer)).await??;
+        Ok(buffer[..length].to_vec())
+    }
+
+    #[tokio::test]
+    async fn packets_sent_reach_the_server() {
+        let (sender, _receiver, far) = socket().await;
+        sender.send(b"PING 1").unwrap();
+        sender.clone().send(b"PING 2").unwrap();
+
+        assert_eq!(recv_far(&far).await.unwrap(), b"PING 1");
+        assert_eq!(recv_far(&far).await.unwrap(), b"PING 2");
+        assert_eq!(sender.client().stats().messages_sent(), 2);
+    }
+
+    #[tokio::test]
+    async fn packets_from_the_server_are_received() {
+        let (_sender, mut receiver, far) = socket().await;
+        // nothing has arrived yet
+        assert_eq!(receiver.receive().unwrap(), None);
+
+        far.send(&Bytes::from_static(b"PONG 1")).await.unwrap();
+        far.send(&Bytes::from_static(b"PONG 2")).await.unwrap();
+        let first = timeout(TIMEOUT, receiver.recv()).await.unwrap();
+        assert_eq!(first.as_deref(), Some(&b"PONG 1"[..]));
+        // polled until the second has been read in
+        let second = timeout(TIMEOUT, async {
+            loop {
+                if let Some(packet) = receiver.receive().unwrap() {
+                    return packet;
+                }
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(&second[..], b"PONG 2");
+    }
+
+    #[tokio::test]
+    async fn nothing_is_read_until_the_receiver_is() {
+        let (sender, mut receiver, far) = socket().await;
+        far.send(&Bytes::from_static(b"PONG 1")).await.unwrap();
+
+        // the transport's still free for the client's own loops
+        let mut buffer = [0u8; 64];
+        let transport = sender.client().transport();
+        let length = timeout(TIMEOUT, transport.recv(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer[..length], b"PONG 1");
+
+        far.send(&Bytes::from_static(b"PONG 2")).await.unwrap();
+        let packet = timeout(TIMEOUT, receiver.recv()).await.unwrap();
+        assert_eq!(packet.as_deref(), Some(&b"PONG 2"[..]));
+    }
+
+    #[tokio::test]
+    async fn the_connection_state_is_the_clients() {
+        let (sender, _receiver, _far) = socket().await;
+        assert!(!sender.is_connected());
+        assert_eq!(
+            sender.connection_state(),
+            RTCPeerConnectionState::Unspecified
+        );
+
+        let status = sender.client().status();
+        status.set_connection_state(RTCPeerConnectionState::Connected);
+        assert_eq!(sender.connection_state(), RTCPeerConnectionState::Connected);
+        assert_eq!(
+            sender.client().connection_state(),
+            RTCPeerConnectionState::Connected
+        );
+        // connected, but not until the channel carries traffic
+        assert!(!sender.is_connected());
+        status.set_channel_open(true);
+        assert!(sender.is_connected());
+        assert!(sender.client().is_connected());
+    }
+
+    #[tokio::test]
+    async fn closing_ends_both_directions() {
+        let (sender, mut receiver, far) = socket().await;
+        assert_eq!(receiver.receive().unwrap(), None);
+
+        sender.close().await;
+        // the server sees the channel close
+        assert!(recv_far(&far).await.is_err());
+        // and the receiver ends, rather than waiting forever
+        assert_eq!(timeout(TIMEOUT, receiver.recv()).await.unwrap(), None);
+        assert!(receiver.receive().is_err());
+    }
+}
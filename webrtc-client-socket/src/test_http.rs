@@ -0,0 +1,71 @@
+use std::future::Future;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+/// A request as the mock server received it
+pub struct Request {
+    /// The request line & headers, up to the blank line
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of the header called `name`, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.head.lines().skip(1).find_map(|line| {
+            let (key, value) = line.split_once(':')?;
+            key.eq_ignore_ascii_case(name).then(|| value.trim())
+        })
+    }
+}
+
+/// Serves a single request on a loopback port with `response`, written
+/// as-is, returning the URL to request & the request once it's answered
+pub async fn serve_once(response: Vec<u8>) -> (String, JoinHandle<Request>) {
+    serve_once_with(|_| async move { response }).await
+}
+
+/// Like `serve_once`, but answers with what `respond` makes of the request
+pub async fn serve_once_with<F, R>(respond: F) -> (String, JoinHandle<Request>)
+where
+    F: FnOnce(&Request) -> R + Send + 'static,
+    R: Future<Output = Vec<u8>> + Send,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}/rtc_session", listener.local_addr().unwrap());
+    let served = tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut received = Vec::new();
+        let mut chunk = [0u8; 1024];
+        let head_end = loop {
+            if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end;
+            }
+            let read = stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "connection closed mid-request");
+            received.extend_from_slice(&chunk[..read]);
+        };
+        let head = String::from_utf8(received[..head_end].to_vec()).unwrap();
+        let mut request = Request {
+            head,
+            body: received[head_end + 4..].to_vec(),
+        };
+        let length = request
+            .header("content-length")
+            .map_or(0, |length| length.parse().unwrap());
+        while request.body.len() < length {
+            let read = stream.read(&mut chunk).await.unwrap();
+            assert!(read > 0, "connection closed mid-body");
+            request.body.extend_from_slice(&chunk[..read]);
+        }
+        let response = respond(&request).await;
+        // the client may hang up as soon as it's seen enough of the answer
+        let _ = stream.write_all(&response).await;
+        let _ = stream.shutdown().await;
+        request
+    });
+    (url, served)
+}
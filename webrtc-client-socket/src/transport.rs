@@ -0,0 +1,159 @@
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Mutex as SyncMutex;
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use webrtc::{
+    data::{self, data_channel::DataChannel},
+    sctp,
+};
+
+// how often the buffered amount is checked when it can't be signaled
+const BUFFERED_AMOUNT_POLL: Duration = Duration::from_millis(1);
+// the data channel only signals crossing its threshold, so it's rechecked
+// this often in case that was missed
+const BUFFERED_AMOUNT_LOW_FALLBACK: Duration = Duration::from_millis(100);
+
+/// A message-oriented link that the read & write loops run over
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Sends a single message, returning the number of bytes written
+    async fn send(&self, payload: &Bytes) -> Result<usize>;
+    /// Receives a single message into `buffer`, returning its length
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize>;
+    /// Bytes sent but not yet handed to the network, for backpressure
+    fn buffered_amount(&self) -> usize {
+        0
+    }
+    /// Resolves once no more than `threshold` bytes are buffered
+    async fn buffered_amount_low(&self, threshold: usize) {
+        while self.buffered_amount() > threshold {
+            tokio::time::sleep(BUFFERED_AMOUNT_POLL).await;
+        }
+    }
+    /// Closes the link, ending the far end's reads & this end's own
+    async fn close(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Transport for DataChannel {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        Ok(self.write(payload).await?)
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        match self.read(buffer).await {
+            Ok(length) => Ok(length),
+            // SCTP drops a message that doesn't fit rather than truncating it
+            Err(data::Error::Sctp(sctp::Error::ErrShortBuffer)) => bail!(
+                "received a message larger than the {} byte read buffer",
+                buffer.len()
+            ),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn buffered_amount(&self) -> usize {
+        DataChannel::buffered_amount(self)
+    }
+
+    async fn close(&self) -> Result<()> {
+        Ok(DataChannel::close(self).await?)
+    }
+
+    async fn buffered_amount_low(&self, threshold: usize) {
+        self.set_buffered_amount_low_threshold(threshold);
+        loop {
+            let (sender, receiver) = oneshot::channel();
+            let mut sender = Some(sender);
+            self.on_buffered_amount_low(Box::new(move || {
+                if let Some(sender) = sender.take() {
+                    let _ = sender.send(());
+                }
+                Box::pin(async {})
+            }))
+            .await;
+            // checked after registering, so draining in between isn't missed
+            if DataChannel::buffered_amount(self) <= threshold {
+                return;
+            }
+            let _ = tokio::time::timeout(BUFFERED_AMOUNT_LOW_FALLBACK, receiver).await;
+        }
+    }
+}
+
+/// An in-memory Transport, used to exercise the loops without standing up a
+/// WebRTC connection
+pub struct MemoryTransport {
+    // taken on closing, which ends the other end's reads
+    sender: SyncMutex<Option<mpsc::UnboundedSender<Bytes>>>,
+    receiver: Mutex<mpsc::UnboundedReceiver<Bytes>>,
+    closed: CancellationToken,
+}
+
+impl MemoryTransport {
+    /// Creates both ends of an in-memory pipe, anything sent on one end is
+    /// received on the other
+    pub fn pair() -> (MemoryTransport, MemoryTransport) {
+        let (a_sender, b_receiver) = mpsc::unbounded_channel();
+        let (b_sender, a_receiver) = mpsc::unbounded_channel();
+
+        (
+            MemoryTransport {
+                sender: SyncMutex::new(Some(a_sender)),
+                receiver: Mutex::new(a_receiver),
+                closed: CancellationToken::new(),
+            },
+            MemoryTransport {
+                sender: SyncMutex::new(Some(b_sender)),
+                receiver: Mutex::new(b_receiver),
+                closed: CancellationToken::new(),
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn send(&self, payload: &Bytes) -> Result<usize> {
+        self.sender
+            .lock()
+            .unwrap()
+            .as_ref()
+            .ok_or_else(|| anyhow!("memory transport closed"))?
+            .send(payload.clone())
+            .map_err(|_| anyhow!("memory transport closed"))?;
+        Ok(payload.len())
+    }
+
+    async fn recv(&self, buffer: &mut [u8]) -> Result<usize> {
+        let mut receiver = self.receiver.lock().await;
+        let received = tokio::select! {
+            received = receiver.recv() => received,
+            _ = self.closed.cancelled() => None,
+        };
+        let payload = match received {
+            Some(payload) => payload,
+            None => bail!("memory transport closed"),
+        };
+        if payload.len() > buffer.len() {
+            bail!(
+                "message of {} bytes does not fit in a {} byte buffer",
+                payload.len(),
+                buffer.len()
+            );
+        }
+        buffer[..payload.len()].copy_from_slice(&payload);
+        Ok(payload.len())
+    }
+
+    async fn close(&self) -> Result<()> {
+        self.sender.lock().unwrap().take();
+        self.closed.cancel();
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client as HttpClient, Url};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use webrtc::peer_connection::RTCPeerConnection;
+
+use crate::{
+    client::CONNECTION_ID_HEADER,
+    connection_events::{ConnectionEvent, ConnectionEvents},
+    signaling::{add_server_candidates, read_capped_body, SessionCandidate, TrickleResponse},
+};
+
+/// Where & how local candidates are trickled to the server
+#[derive(Clone)]
+pub(crate) struct TrickleEndpoint {
+    pub http_client: HttpClient,
+    pub url: Url,
+    pub connection_id: String,
+    pub timeout: Duration,
+    pub max_response_bytes: usize,
+    pub events: ConnectionEvents,
+}
+
+/// Posts every local candidate to the trickle endpoint as it's gathered,
+/// adding the candidates the server answers with, until gathering finishes.
+/// Candidates gathered before the server answered the offer wait in
+/// `candidates` until then, since the server only knows the connection once
+/// it has. Each is sent with the mid & index of the local description's data
+/// channel m-line.
+pub(crate) async fn trickle_candidates(
+    endpoint: TrickleEndpoint,
+    peer_connection: Arc<RTCPeerConnection>,
+    mut candidates: mpsc::UnboundedReceiver<String>,
+) {
+    let mut media = None;
+    let mut sent = 0;
+    while let Some(candidate) = candidates.recv().await {
+        if media.is_none() {
+            let description = peer_connection.local_description().await;
+            media = description.and_then(|description| data_media(&description.sdp));
+        }
+        let (sdp_mid, sdp_m_line_index) = match &media {
+            Some(media) => media.clone(),
+            None => {
+                warn!("Stopped trickling ICE candidates: the local description has no data channel m-line");
+                return;
+            }
+        };
+        let candidate = SessionCandidate {
+            candidate,
+            sdp_m_line_index,
+            sdp_mid,
+        };
+        let server_candidates = match post_candidate(&endpoint, &candidate).await {
+            Ok(server_candidates) => server_candidates,
+            // nothing more will get through if one didn't
+            Err(error) => {
+                warn!("Stopped trickling ICE candidates: {:#}", error);
+                return;
+            }
+        };
+        sent += 1;
+        debug!("Trickled ICE candidate '{}'", candidate.candidate);
+        if !server_candidates.is_empty() {
+            let announced: Vec<String> = server_candidates
+                .iter()
+                .map(|candidate| candidate.candidate.clone())
+                .collect();
+            let added = add_server_candidates(&peer_connection, server_candidates).await;
+            info!("Added {} trickled server ICE candidate(s)", added);
+            for candidate in announced {
+                endpoint.events.send(ConnectionEvent::CandidateAdded {
+                    candidate,
+                    local: false,
+                });
+            }
+        }
+    }
+    info!("Trickled {} local ICE candidate(s)", sent);
+}
+
+/// The mid & index of the first `m=application` line in `sdp`, the one the
+/// data channels are negotiated on. `None` if there's none, or it has no mid.
+pub(crate) fn data_media(sdp: &str) -> Option<(String, u16)> {
+    let mut m_lines = 0;
+    let mut application = None;
+    for line in sdp.lines().map(str::trim_end) {
+        if let Some(media) = line.strip_prefix("m=") {
+            // the application section ended without a mid
+            if application.is_some() {
+                return None;
+            }
+            if media.starts_with("application ") {
+                application = Some(m_lines);
+            }
+            m_lines += 1;
+        } else if let (Some(index), Some(mid)) = (application, line.strip_prefix("a=mid:")) {
+            return Some((mid.to_string(), index));
+        }
+    }
+    None
+}
+
+async fn post_candidate(
+    endpoint: &TrickleEndpoint,
+    candidate: &SessionCandidate,
+) -> Result<Vec<SessionCandidate>> {
+    let body = serde_json::to_string(candidate).context("failed to serialize ICE candidate")?;
+    let response = endpoint
+        .http_client
+        .post(endpoint.url.clone())
+        .header(CONNECTION_ID_HEADER, &endpoint.connection_id)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .timeout(endpoint.timeout)
+        .body(body)
+        .send()
+        .await
+        .with_context(|| format!("could not post ICE candidate to {}", endpoint.url))?;
+    let status = response.status();
+    let body = read_capped_body(response, endpoint.max_response_bytes).await?;
+    if !status.is_success() {
+        bail!("trickle endpoint answered {}: {}", status, body.trim());
+    }
+    if body.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let response: TrickleResponse =
+        serde_json::from_str(&body).context("failed to parse trickle response")?;
+    Ok(response.candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidates_belong_to_the_application_m_line() {
+        let sdp = "v=0\r\n\
+                   m=audio 9 UDP/TLS/RTP/SAVPF 111\r\n\
+                   a=mid:audio\r\n\
+                   m=application 9 UDP/DTLS/SCTP webrtc-datachannel\r\n\
+                   c=IN IP4 0.0.0.0\r\n\
+                   a=mid:data\r\n";
+        assert_eq!(data_media(sdp), Some(("data".to_string(), 1)));
+        assert_eq!(
+            data_media("m=application 9 DTLS/SCTP 5000\na=mid:0\n"),
+            Some(("0".to_string(), 0))
+        );
+    }
+
+    #[test]
+    fn a_description_without_a_data_mid_has_no_media() {
+        assert_eq!(
+            data_media("v=0\r\nm=audio 9 RTP/AVP 0\r\na=mid:0\r\n"),
+            None
+        );
+        assert_eq!(
+            data_media("m=application 9 DTLS/SCTP 5000\r\nm=audio 9 RTP/AVP 0\r\na=mid:1\r\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,52 @@
+//! Runs the demo server in-process for the end-to-end tests. naia keeps
+//! its session path in a process-wide cell, so each test binary can only
+//! start one server.
+
+use std::{
+    future::Future,
+    net::{SocketAddr, TcpListener, UdpSocket},
+};
+
+use naia_socket_server::{App, AppConfig, PingPong};
+use tokio::time::{self, Duration};
+use tokio_util::sync::CancellationToken;
+
+/// How long a client gets to connect to the server
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(20);
+
+// a loopback port nothing is listening on right now; naia binds its sockets
+// itself, so the OS can't be asked to pick one of its ports
+fn free_tcp_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+fn free_udp_port() -> u16 {
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap().port()
+}
+
+/// A PING/PONG server on free loopback ports, and the URL to signal it on
+pub fn start_server() -> (App, String) {
+    let session_addr = SocketAddr::from(([127, 0, 0, 1], free_tcp_port()));
+    let config = AppConfig {
+        session_addr: Some(session_addr),
+        webrtc_addr: Some(SocketAddr::from(([127, 0, 0, 1], free_udp_port()))),
+        ..AppConfig::default()
+    };
+    let app = App::new(config, PingPong::default()).unwrap();
+    (app, format!("http://{}/rtc_session", session_addr))
+}
+
+/// Runs `app` while `client` does, then shuts it down, failing if either
+/// takes longer than `timeout`
+pub async fn serve_while<T>(mut app: App, timeout: Duration, client: impl Future<Output = T>) -> T {
+    let shutdown = CancellationToken::new();
+    let client = async {
+        let result = time::timeout(timeout, client).await;
+        shutdown.cancel();
+        result
+    };
+    let (result, ()) = tokio::join!(client, app.run(shutdown.clone()));
+    result.expect("the client took too long")
+}
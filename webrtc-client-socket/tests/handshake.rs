@@ -0,0 +1,77 @@
+//! Signals the in-process demo server & connects ICE to it. This is as far
+//! as a connection gets with these dependencies: webrtc 0.4 fails the DTLS
+//! transport unless an SRTP profile is negotiated, and the server's
+//! webrtc-unreliable never offers one, so `ping_pong.rs` is ignored.
+
+mod common;
+
+use common::{serve_while, start_server, CONNECT_TIMEOUT};
+use tokio::sync::mpsc;
+use webrtc_client_socket::{
+    Client, ClientConfig, ClientEvent, ConnectionEvent, ConnectionEvents, ReconnectReason,
+};
+
+// everything that's arrived on `receiver` so far
+fn drain<T>(receiver: &mut mpsc::UnboundedReceiver<T>) -> Vec<T> {
+    let mut received = Vec::new();
+    while let Ok(item) = receiver.try_recv() {
+        received.push(item);
+    }
+    received
+}
+
+#[tokio::test]
+async fn the_client_signals_the_server_and_connects_ice() {
+    let (app, url) = start_server();
+    let (connection_events, mut received_connection_events) = ConnectionEvents::channel();
+    let config = ClientConfig {
+        ice_servers: Vec::new(),
+        connection_events,
+        ..ClientConfig::new(url.parse().unwrap())
+    };
+    let mut events = config.events.subscribe();
+
+    let connected = serve_while(app, CONNECT_TIMEOUT, async {
+        let connected = Client::connect(config).await;
+        if let Ok(client) = &connected {
+            client.close().await;
+        }
+        connected
+    })
+    .await;
+
+    // the answer's candidates only arrive if signaling worked
+    let connection_events = drain(&mut received_connection_events);
+    assert!(matches!(
+        connection_events.first(),
+        Some(ConnectionEvent::Connecting)
+    ));
+    assert!(
+        connection_events
+            .iter()
+            .any(|event| matches!(event, ConnectionEvent::CandidateAdded { local: false, .. })),
+        "no server candidate in {:?}",
+        connection_events
+    );
+    let ice_states: Vec<_> = drain(&mut events)
+        .into_iter()
+        .filter_map(|event| match event {
+            ClientEvent::IceConnectionState { state } => Some(state),
+            _ => None,
+        })
+        .collect();
+    assert!(
+        ice_states.iter().any(|state| state == "connected"),
+        "ICE went {:?}",
+        ice_states
+    );
+    // past ICE, only the DTLS handshake may fail
+    if let Err(error) = connected {
+        assert_eq!(
+            ReconnectReason::of(&error),
+            ReconnectReason::ChannelClosed,
+            "{:#}",
+            error
+        );
+    }
+}
@@ -0,0 +1,33 @@
+//! A PING answered with a PONG by the in-process demo server, over a real
+//! data channel. Its own binary, since each can only start one server.
+
+mod common;
+
+use common::{serve_while, start_server, CONNECT_TIMEOUT};
+use tokio::time::{self, Duration};
+use webrtc_client_socket::{Client, ClientConfig};
+
+const PONG_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[tokio::test]
+#[ignore = "webrtc 0.4 needs an SRTP profile the server's webrtc-unreliable never negotiates, so DTLS fails; see handshake.rs"]
+async fn a_ping_gets_a_pong() {
+    let (app, url) = start_server();
+    let config = ClientConfig {
+        ice_servers: Vec::new(),
+        ..ClientConfig::new(url.parse().unwrap())
+    };
+
+    let pong = serve_while(app, CONNECT_TIMEOUT + PONG_TIMEOUT, async {
+        let client = Client::connect(config).await.unwrap();
+        let mut incoming = client.incoming();
+        client.send(b"PING").await.unwrap();
+        let pong = time::timeout(PONG_TIMEOUT, incoming.recv()).await;
+        client.close().await;
+        pong
+    })
+    .await;
+
+    let pong = pong.expect("no PONG in time").expect("the channel closed");
+    assert_eq!(&pong[..], b"PONG");
+}
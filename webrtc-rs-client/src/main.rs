@@ -1,255 +1,1200 @@
-use anyhow::Result;
+#[macro_use]
+extern crate tracing;
+
+use anyhow::{anyhow, bail, Context, Result};
 use bytes::Bytes;
+use clap::{ArgSettings, ErrorKind, FromArgMatches, IntoApp, Parser, Subcommand};
+use serde_json::Value;
+use std::env;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use tokio::time::Duration;
-use webrtc::api::setting_engine::SettingEngine;
-use webrtc::api::APIBuilder;
-use webrtc::data_channel::data_channel_init::RTCDataChannelInit;
+use webrtc::dtls_transport::dtls_role::DTLSRole;
 use webrtc::ice_transport::ice_server::RTCIceServer;
-use webrtc::peer_connection::configuration::RTCConfiguration;
-use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
 
-use reqwest::Client as HttpClient;
-use tinyjson::JsonValue;
-use webrtc::dtls_transport::dtls_role::DTLSRole;
-use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
-use webrtc::peer_connection::sdp::sdp_type::RTCSdpType;
-
-const MESSAGE_SIZE: usize = 1500;
-
-#[tokio::main]
-async fn main() -> Result<()> {
-    // setup logging
-    env_logger::Builder::new()
-        .filter(None, log::LevelFilter::Trace)
-        .init();
-
-    // create a SettingEngine and enable Detach
-    let mut setting_engine = SettingEngine::default();
-    setting_engine.detach_data_channels();
-    setting_engine
-        .set_answering_dtls_role(DTLSRole::Client)
-        .expect("error in set_answering_dtls_role!");
-
-    // create the API object
-    let api = APIBuilder::new()
-        .with_setting_engine(setting_engine)
-        .build();
-
-    // prepare the connection's configuration
-    let peer_connection_config = RTCConfiguration {
-        ice_servers: vec![RTCIceServer {
-            urls: vec!["stun:stun.l.google.com:19302".to_owned()],
-            ..Default::default()
-        }],
-        ..Default::default()
-    };
-
-    // create a new RTCPeerConnection
-    let peer_connection = Arc::new(api.new_peer_connection(peer_connection_config).await?);
-
-    // create a config for our new datachannel
-    let mut data_channel_config = RTCDataChannelInit::default();
-    data_channel_config.ordered = Some(false);
-    data_channel_config.max_retransmits = Some(0);
-
-    // create a datachannel with label 'data'
-    let data_channel = peer_connection
-        .create_data_channel("data", Some(data_channel_config))
-        .await?;
-
-    // datachannel on_error callback
-    data_channel
-        .on_error(Box::new(move |error| {
-            println!("data channel error: {:?}", error);
-            Box::pin(async {
-                println!("data channel error!");
-            })
-        }))
-        .await;
-
-    // datachannel on_open callback
-    let data_channel_ref = Arc::clone(&data_channel);
-    data_channel
-        .on_open(Box::new(move || {
-            println!(
-                "Data channel '{}'-'{}' open.",
-                data_channel_ref.label(),
-                data_channel_ref.id()
-            );
-
-            let data_channel_ref_2 = Arc::clone(&data_channel_ref);
-            Box::pin(async move {
-                let detached_data_channel = data_channel_ref_2
-                    .detach()
-                    .await
-                    .expect("data channel detach got error");
-
-                // Handle reading from the data channel
-                let detached_data_channel_1 = Arc::clone(&detached_data_channel);
-                let detached_data_channel_2 = Arc::clone(&detached_data_channel);
-                tokio::spawn(async move {
-                    read_loop(detached_data_channel_1)
-                        .await
-                        .expect("error in read_loop!");
-                });
-
-                // Handle writing to the data channel
-                tokio::spawn(async move {
-                    write_loop(detached_data_channel_2)
-                        .await
-                        .expect("error in write_loop!");
-                });
-            })
-        }))
-        .await;
-
-    // peer_connection's on_ice_candidate callback
-    peer_connection
-        .on_ice_candidate(Box::new(move |candidate_opt| {
-            if let Some(candidate) = &candidate_opt {
-                println!("received ice candidate from: {}", candidate.address);
-            } else {
-                println!("all local candidates received");
-            }
+use reqwest::{Certificate, Url};
+use tokio::time::{Duration, Instant};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+use webrtc::peer_connection::math_rand_alpha;
+use webrtc_client_socket::{
+    build_ice_servers, describe_ice_server, run_load_test, write_catalog, AckSettings, Backoff,
+    ChannelSettings, Client, ClientConfig, ClientEvent, ClientSocket, ClientStats,
+    ConnectionEvents, ConnectionStatus, EventLog, LabeledChannel, LoadTestSettings,
+    MetricsEndpoint, QualityThresholds, RampShape, ReadSettings, ReconnectBudget, Reconnector,
+    ResponseFieldPaths, SendSettings, SessionSettings, SlowStart, CONNECTION_ID_LENGTH,
+    DEFAULT_KEEPALIVE_MARKER, DEFAULT_MAX_BUFFERED_AMOUNT, DEFAULT_MAX_MESSAGE_SIZE,
+    DEFAULT_MAX_REASSEMBLY_BYTES, DEFAULT_MAX_RESPONSE_BYTES, DEFAULT_SEND_QUEUE_CAPACITY,
+    DEFAULT_SIGNALING_ATTEMPTS, DEFAULT_STUN_SERVER,
+};
+
+// used when neither --server-url nor NAIA_SIGNALING_URL is given
+const DEFAULT_SERVER_URL: &str = "http://127.0.0.1:14191/rtc_session";
+const SERVER_URL_ENV: &str = "NAIA_SIGNALING_URL";
+
+// set to `json` to log JSON lines instead of human-readable text
+const LOG_FORMAT_ENV: &str = "NAIA_LOG_FORMAT";
+
+// how long the server has to agree to --compress
+const COMPRESSION_TIMEOUT: Duration = Duration::from_secs(5);
+// how long a flood waits for the last echoes once everything is sent
+const FLOOD_DRAIN: Duration = Duration::from_secs(1);
+
+/// Command-line options for the client
+#[derive(Parser)]
+struct Args {
+    /// TOML or JSON file of settings, named like the flags (`server_url` or
+    /// `server-url`). Flags given on the command line take precedence.
+    #[clap(long)]
+    config: Option<String>,
+
+    /// Signaling endpoint of the server, e.g. `http://[::1]:14191/rtc_session`
+    /// when the server could only bind the IPv6 loopback. Falls back to
+    /// `NAIA_SIGNALING_URL`, then to the local server.
+    #[clap(long)]
+    server_url: Option<String>,
+
+    /// STUN or TURN server to gather candidates with, as a `stun:`, `turn:`
+    /// or `turns:` URL. Can be given more than once; defaults to Google's
+    /// public STUN server.
+    #[clap(long = "ice-server")]
+    ice_servers: Vec<String>,
+
+    /// Username for the TURN servers
+    #[clap(long)]
+    turn_username: Option<String>,
+
+    /// Credential for the TURN servers
+    #[clap(long)]
+    turn_credential: Option<String>,
+
+    /// Print the full error chain and backtrace if the client fails
+    #[clap(long)]
+    debug: bool,
+
+    /// Print the SDP offer the client would send, with its local candidates,
+    /// and exit without contacting the server
+    #[clap(long)]
+    print_offer: bool,
+
+    /// Log filter, e.g. `debug` or `info,webrtc=warn`; overrides RUST_LOG,
+    /// and defaults to `info` when neither is set
+    #[clap(long, parse(try_from_str = parse_log_filter))]
+    log_level: Option<String>,
+
+    /// Id sent to the signaling server and attached to every log line, to
+    /// correlate client & server logs (randomly generated if not given)
+    #[clap(long)]
+    connection_id: Option<String>,
+
+    /// Secret shared with the server; when set, the server must answer an
+    /// HMAC challenge after connecting before the client trusts it
+    #[clap(long)]
+    shared_secret: Option<String>,
+
+    /// How long to wait for the server to answer the challenge
+    #[clap(long, default_value = "5000")]
+    challenge_timeout_ms: u64,
+
+    /// Ask the server to deflate every payload that gets smaller for it,
+    /// falling back to sending them as-is if it doesn't agree
+    #[clap(long)]
+    compress: bool,
+
+    /// Append a CRC32 to every message sent, for a server started with
+    /// NAIA_VALIDATE_CHECKSUMS=1
+    #[clap(long)]
+    checksum: bool,
+
+    /// How many times to retry the first PING before treating the data
+    /// channel as unusable; 0 skips the check
+    #[clap(long, default_value = "5")]
+    ready_attempts: u32,
+
+    /// How long to wait for each readiness PONG
+    #[clap(long, default_value = "1000")]
+    ready_timeout_ms: u64,
+
+    /// Exit cleanly once connected for this long, e.g. `30s`, `500ms`, `2m`
+    #[clap(long, parse(try_from_str = parse_duration))]
+    duration: Option<Duration>,
+
+    /// Overrides where a field is read from in the server's session response,
+    /// as `name=/json/pointer` (names: sdp, type, candidates, and candidate,
+    /// sdpMLineIndex, sdpMid which are relative to each candidate)
+    #[clap(long = "response-field")]
+    response_fields: Vec<String>,
+
+    /// Largest signaling response body the client will read, in bytes
+    #[clap(long, default_value_t = DEFAULT_MAX_RESPONSE_BYTES)]
+    max_response_bytes: usize,
+
+    /// How many times to try the signaling request, e.g. while the server is
+    /// still starting up
+    #[clap(long, default_value_t = DEFAULT_SIGNALING_ATTEMPTS)]
+    signaling_attempts: u32,
+
+    /// PEM file of a CA certificate to trust for an https signaling endpoint,
+    /// on top of the system's roots
+    #[clap(long)]
+    ca_cert: Option<String>,
+
+    /// Skip verifying the https signaling endpoint's certificate, for
+    /// self-signed development certificates only
+    #[clap(long)]
+    insecure_skip_verify: bool,
+
+    /// Time limit on each signaling attempt, e.g. `10s`
+    #[clap(long, default_value = "10s", parse(try_from_str = parse_duration))]
+    signaling_timeout: Duration,
+
+    /// Wait for ICE gathering to complete before sending the offer, so it
+    /// carries every local candidate, for servers that don't trickle
+    #[clap(long)]
+    non_trickle: bool,
+
+    /// Longest to wait for ICE gathering with `--non-trickle`, e.g. `5s`,
+    /// before sending the candidates gathered so far
+    #[clap(long, default_value = "5s", parse(try_from_str = parse_duration))]
+    gathering_timeout: Duration,
+
+    /// Endpoint to post each local ICE candidate to as it's gathered, once
+    /// the server has answered the offer
+    #[clap(long, parse(try_from_str = Url::parse))]
+    trickle_url: Option<Url>,
+
+    /// Milliseconds between PINGs once at full rate, 0 sends as fast as the
+    /// data channel's buffer allows
+    #[clap(long, default_value = "5000")]
+    send_interval_ms: u64,
+
+    /// Message the write loop sends; the server only answers `PING`
+    #[clap(long, default_value = "PING")]
+    payload: String,
+
+    /// Repeat the payload until it is this many bytes, for load testing
+    #[clap(long)]
+    payload_size: Option<usize>,
+
+    /// Load test: send stamped PINGs (padded to `--payload-size`) as fast as
+    /// backpressure allows, until `--duration` or `--count` (one of which is
+    /// required), then log the send rate, loss & round trip distribution
+    #[clap(long)]
+    flood: bool,
+
+    /// Load test: open `--connections` peer connections at once, each sending
+    /// stamped PINGs (padded to `--payload-size`) at `--send-rate`, until
+    /// `--duration`, `--count` or Ctrl-C, then log their combined throughput,
+    /// loss & errors
+    #[clap(long, conflicts_with_all = &["flood", "interactive", "burst", "ack"])]
+    load_test: bool,
+
+    /// Peer connections a load test opens
+    #[clap(long, default_value_t = 10)]
+    connections: usize,
+
+    /// Messages per second each load test connection sends, 0 as fast as
+    /// backpressure allows
+    #[clap(long, default_value_t = 10.0)]
+    send_rate: f64,
+
+    /// Stop sending after this many messages, ending the run once the last
+    /// echoes have had a moment to arrive
+    #[clap(long)]
+    count: Option<u64>,
+
+    /// Send every message with an id for the server to acknowledge,
+    /// retransmitting it until it is; needs a server run with `NAIA_ACK=1`
+    #[clap(long)]
+    ack: bool,
+
+    /// How long to wait for an ACK before retransmitting, e.g. `500ms`
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_duration))]
+    ack_timeout: Duration,
+
+    /// Retransmits of a message before it's given up on
+    #[clap(long, default_value_t = 5)]
+    ack_retransmits: u32,
+
+    /// Ramp the send rate up to full over this long after connecting, e.g. `10s`
+    #[clap(long, parse(try_from_str = parse_duration))]
+    slow_start: Option<Duration>,
+
+    /// Shape of the slow start ramp, `linear` or `exponential`
+    #[clap(long, default_value = "linear")]
+    slow_start_shape: RampShape,
+
+    /// Send this many PINGs back to back once connected, ignoring the pacing
+    #[clap(long)]
+    burst: Option<u32>,
+
+    /// Read commands such as `burst N` from stdin once connected
+    #[clap(long)]
+    interactive: bool,
+
+    /// Send a keepalive this often, e.g. `2s`, to keep the session alive
+    /// between PINGs
+    #[clap(long, parse(try_from_str = parse_duration))]
+    keepalive_interval: Option<Duration>,
+
+    /// Byte value sent as the keepalive, which must match the server's
+    /// `NAIA_KEEPALIVE_MARKER`
+    #[clap(long, default_value_t = DEFAULT_KEEPALIVE_MARKER)]
+    keepalive_marker: u8,
+
+    /// Treat the connection as dead, and end the run (or reconnect), once
+    /// nothing has been received for this long, e.g. `15s`
+    #[clap(long, parse(try_from_str = parse_duration))]
+    keepalive_timeout: Option<Duration>,
+
+    /// End the run (or reconnect) once a connection has been open this
+    /// long, e.g. `10m`, to exercise reconnects on a schedule
+    #[clap(long, parse(try_from_str = parse_duration))]
+    max_session_lifetime: Option<Duration>,
+
+    /// Send & receive every message as a frame with a big-endian u16 length
+    /// prefix; the server must set `NAIA_FRAMED=1` too
+    #[clap(long)]
+    framed: bool,
+
+    /// Split messages into fragments that each fit a data channel message,
+    /// so larger ones get through; the server must set `NAIA_FRAGMENTED=1`
+    #[clap(long)]
+    fragmented: bool,
+
+    /// Most fragment bytes each channel holds for partly received messages
+    /// with --fragmented; past it, the oldest are given up on
+    #[clap(long, default_value_t = DEFAULT_MAX_REASSEMBLY_BYTES)]
+    max_reassembly_bytes: usize,
+
+    /// Speak the binary packet protocol instead of text; the server must set
+    /// `NAIA_BINARY=1` too
+    #[clap(long)]
+    binary: bool,
+
+    /// Count & warn about every received message that isn't a PONG
+    #[clap(long)]
+    strict_pong: bool,
 
-            Box::pin(async {})
-        }))
-        .await;
+    /// Expect the server's sequence number in front of every message but
+    /// its ACKs, and count the gaps; the server must set
+    /// `NAIA_SEQUENCE_OUTBOUND=1`
+    #[clap(long)]
+    server_sequence: bool,
 
-    // create an offer to send to the server
-    let offer = peer_connection.create_offer(None).await?;
+    /// Deliver messages on the data channel in the order they were sent
+    #[clap(long)]
+    ordered: bool,
 
-    // sets the LocalDescription, and starts our UDP listeners
-    peer_connection.set_local_description(offer).await?;
+    /// Retransmit a lost message at most this many times (by default lost
+    /// messages are never retransmitted)
+    #[clap(long)]
+    max_retransmits: Option<u16>,
 
-    // send a request to server to initiate connection (signaling, essentially)
-    let http_client = HttpClient::new();
+    /// Stop retransmitting a lost message this long after sending it,
+    /// instead of limiting the number of retransmits
+    #[clap(long)]
+    max_packet_lifetime_ms: Option<u16>,
 
-    let server_url = "http://127.0.0.1:14191/rtc_session";
+    /// Retransmit lost messages until they arrive
+    #[clap(long, conflicts_with_all = &["max-retransmits", "max-packet-lifetime-ms"])]
+    reliable: bool,
 
-    let sdp = peer_connection.local_description().await.unwrap().sdp;
+    /// DTLS role to take when answering, `client`, `server` or `auto`; only
+    /// worth changing when chasing DTLS handshake failures
+    #[clap(long, default_value = "client", parse(try_from_str = parse_dtls_role))]
+    dtls_role: DTLSRole,
 
-    let request = http_client
-        .post(server_url)
-        .header("Content-Length", sdp.len())
-        .body(sdp);
+    /// Open an extra data channel with this label, as `<label>` for an
+    /// unreliable one or `<label>:<profile>` with a profile of `reliable`,
+    /// `unreliable` or `bulk`; can be given more than once. Messages
+    /// received on it are logged with its label.
+    #[clap(long = "channel", parse(try_from_str = parse_channel))]
+    channels: Vec<LabeledChannel>,
 
-    // wait to receive a response from server
-    let response = match request.send().await {
-        Ok(resp) => resp,
-        Err(err) => {
-            panic!("Could not send request, original error: {:?}", err);
+    /// Pause sending once the data channel buffers more than this many
+    /// bytes, resuming when half of it has drained
+    #[clap(long, default_value_t = DEFAULT_MAX_BUFFERED_AMOUNT)]
+    max_buffered_amount: usize,
+
+    /// Messages the send queue in front of each data channel holds; sends
+    /// wait for room once it's full
+    #[clap(long, default_value_t = DEFAULT_SEND_QUEUE_CAPACITY)]
+    send_queue_capacity: usize,
+
+    /// Largest message the client can receive, in bytes; the data channel
+    /// drops anything larger, so raise this if the server sends big messages
+    #[clap(long, default_value_t = DEFAULT_MAX_MESSAGE_SIZE)]
+    max_message_size: usize,
+
+    /// Only log 1 in every N received messages
+    #[clap(long, default_value = "1")]
+    log_every: u64,
+
+    /// Log at most this many received messages per second
+    #[clap(long)]
+    log_max_per_sec: Option<u32>,
+
+    /// File to periodically append JSON snapshots of the client's stats to
+    #[clap(long)]
+    stats_file: Option<String>,
+
+    /// Log a summary of the stats this often, e.g. `10s`
+    #[clap(long, parse(try_from_str = parse_duration))]
+    stats_log_interval: Option<Duration>,
+
+    /// Time between stats file snapshots, e.g. `500ms` or `5s`
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_interval))]
+    stats_interval: Duration,
+
+    /// Address to serve Prometheus metrics on at `/metrics`, e.g.
+    /// `127.0.0.1:9465`
+    #[clap(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Reconnect after a failed connection at most this many times before
+    /// giving up (by default the client exits on the first failure)
+    #[clap(long)]
+    max_reconnects: Option<u32>,
+
+    /// Staying connected this long restores the full reconnect budget
+    #[clap(long, default_value = "60s", parse(try_from_str = parse_duration))]
+    reconnect_reset: Duration,
+
+    /// Pause before the first reconnect, doubled for each further attempt
+    /// (up to --reconnect-max-delay) so a server that is down isn't hammered
+    #[clap(long, default_value = "1s", parse(try_from_str = parse_duration))]
+    reconnect_delay: Duration,
+
+    /// Longest pause between reconnects, however many attempts have failed
+    #[clap(long, default_value = "30s", parse(try_from_str = parse_duration))]
+    reconnect_max_delay: Duration,
+
+    /// Share of each reconnect pause it's randomly moved by either way, from
+    /// 0 to 1, so clients dropped together don't all reconnect together
+    #[clap(long, default_value = "0.5", parse(try_from_str = parse_jitter))]
+    reconnect_jitter: f64,
+
+    /// Exit non-zero if more than this share of PINGs went unanswered, e.g.
+    /// `0.02` for 2%
+    #[clap(long)]
+    max_loss: Option<f64>,
+
+    /// Exit non-zero if the mean PING round trip took longer than this
+    #[clap(long)]
+    max_rtt_ms: Option<u64>,
+
+    /// Log the selected ICE candidate pair this often, e.g. `5s`, flagging
+    /// when it changes
+    #[clap(long, parse(try_from_str = parse_duration))]
+    candidate_pair_interval: Option<Duration>,
+
+    /// File to append every connection lifecycle event to, as JSON lines
+    #[clap(long)]
+    event_log: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// What to do instead of connecting
+#[derive(Subcommand)]
+enum Command {
+    /// Print every supported data channel profile, compression codec,
+    /// signaling protocol, quality threshold, ramp shape & reconnect reason
+    List,
+}
+
+fn main() {
+    let args = parse_args();
+
+    if let Some(Command::List) = args.command {
+        if let Err(error) = write_catalog(&mut std::io::stdout().lock()) {
+            eprintln!("Error: {}", error);
+            std::process::exit(1);
         }
+        return;
+    }
+
+    // anyhow only captures backtraces when this is set at the time the error
+    // is created. Set before the runtime starts its threads, since changing
+    // the environment while other threads may read it isn't safe.
+    if args.debug {
+        env::set_var("RUST_LIB_BACKTRACE", "1");
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("failed to build the tokio runtime")
+        .block_on(start(args))
+}
+
+async fn start(args: Args) {
+    let connection_id = args
+        .connection_id
+        .clone()
+        .unwrap_or_else(|| math_rand_alpha(CONNECTION_ID_LENGTH));
+
+    init_logging(args.log_level.as_deref());
+
+    // every line logged from here on is tagged with the connection id
+    let span = info_span!("client", conn = %connection_id);
+    run_client(args, connection_id).instrument(span).await
+}
+
+// the command line, with any settings from --config filled in beneath it
+fn parse_args() -> Args {
+    let args = parse_args_and_config();
+    // checked once the config file is merged in, since either half can set
+    // the flags involved
+    if args.flood && args.duration.is_none() && args.count.is_none() {
+        Args::into_app()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "--flood needs --duration or --count to end it",
+            )
+            .exit();
+    }
+    args
+}
+
+fn parse_args_and_config() -> Args {
+    let matches = Args::into_app().get_matches();
+    let path = match matches.value_of("config") {
+        Some(path) => path.to_string(),
+        None => return Args::from_arg_matches(&matches).unwrap_or_else(|error| error.exit()),
     };
-    let response_string = response.text().await.unwrap();
 
-    // parse session from server response
-    let session_response: JsSessionResponse = get_session_response(response_string.as_str());
+    let file_args = config_file_args(&path, |name| matches.occurrences_of(name) > 0)
+        .unwrap_or_else(|error| {
+            eprintln!("Error: {:#}", error);
+            std::process::exit(2);
+        });
+    let program = env::args_os().next().unwrap_or_default();
 
-    // apply the server's response as the remote description
-    let mut session_description = RTCSessionDescription::default();
-    session_description.sdp_type = RTCSdpType::Answer;
-    session_description.sdp = session_response.answer.sdp;
-    peer_connection
-        .set_remote_description(session_description)
-        .await?;
+    // parsed alone first, so a bad value is blamed on the file
+    if let Err(error) = Args::try_parse_from(
+        std::iter::once(program.clone()).chain(file_args.iter().map(Into::into)),
+    ) {
+        eprintln!("Error: invalid setting in config file {}:\n{}", path, error);
+        std::process::exit(2);
+    }
+    Args::parse_from(
+        std::iter::once(program)
+            .chain(file_args.into_iter().map(Into::into))
+            .chain(env::args_os().skip(1)),
+    )
+}
 
-    // create ice candidate
-    let ice_candidate = RTCIceCandidateInit {
-        candidate: session_response.candidate.candidate,
-        sdp_mid: session_response.candidate.sdp_mid,
-        sdp_mline_index: session_response.candidate.sdp_m_line_index,
-        ..Default::default()
+// turns the settings in a TOML or JSON file into the flags they stand for,
+// leaving out any `given` on the command line
+fn config_file_args(path: &str, given: impl Fn(&str) -> bool) -> Result<Vec<String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read config file {}", path))?;
+    let settings: Value = if path.ends_with(".json") {
+        serde_json::from_str(&contents)
+            .with_context(|| format!("config file {} is not valid JSON", path))?
+    } else {
+        toml::from_str(&contents)
+            .with_context(|| format!("config file {} is not valid TOML", path))?
+    };
+    let settings = match settings {
+        Value::Object(settings) => settings,
+        _ => bail!("config file {} must hold a table of settings", path),
     };
-    // add ice candidate to connection
-    if let Err(error) = peer_connection.add_ice_candidate(ice_candidate).await {
-        panic!("Error during add_ice_candidate: {:?}", error);
+
+    let app = Args::into_app();
+    let mut args = Vec::new();
+    for (key, value) in settings {
+        let long = key.replace('_', "-");
+        let arg = app
+            .get_arguments()
+            .find(|arg| arg.get_long() == Some(long.as_str()) && long != "config")
+            .with_context(|| format!("unknown setting '{}' in config file {}", key, path))?;
+        if given(arg.get_name()) {
+            continue;
+        }
+
+        let flag = format!("--{}", long);
+        let invalid = || {
+            anyhow!(
+                "setting '{}' in config file {} has the wrong type",
+                key,
+                path
+            )
+        };
+        if !arg.is_set(ArgSettings::TakesValue) {
+            match value {
+                Value::Bool(true) => args.push(flag),
+                Value::Bool(false) => {}
+                _ => return Err(invalid()),
+            }
+            continue;
+        }
+
+        let values = match value {
+            Value::Array(values) if arg.is_set(ArgSettings::MultipleOccurrences) => values,
+            Value::Array(_) => bail!(
+                "setting '{}' in config file {} takes a single value",
+                key,
+                path
+            ),
+            value => vec![value],
+        };
+        for value in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Number(number) => number.to_string(),
+                Value::Bool(value) => value.to_string(),
+                _ => return Err(invalid()),
+            };
+            args.push(flag.clone());
+            args.push(value);
+        }
     }
+    Ok(args)
+}
 
-    // don't block .. I'm sure there's a better way to do this
-    loop {}
+// human-readable logs by default, or JSON lines with NAIA_LOG_FORMAT=json
+// for log ingestion. The filter comes from --log-level, then RUST_LOG, then
+// defaults to info.
+fn init_logging(log_level: Option<&str>) {
+    let filter = match log_level {
+        Some(log_level) => EnvFilter::new(log_level),
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+    };
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if env::var(LOG_FORMAT_ENV).as_deref() == Ok("json") {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
 }
 
-// read_loop shows how to read from the datachannel directly
-async fn read_loop(data_channel: Arc<webrtc::data::data_channel::DataChannel>) -> Result<()> {
-    let mut buffer = vec![0u8; MESSAGE_SIZE];
+// connects, reconnecting on failure if allowed, until the run ends
+async fn run_client(args: Args, connection_id: String) {
+    info!("Connection id: {}", connection_id);
+
+    let server_url =
+        resolve_server_url(&args).unwrap_or_else(|error| exit_with_error(&args, error));
+    info!("Signaling server: {}", server_url);
+
+    let ice_server_urls = if args.ice_servers.is_empty() {
+        vec![DEFAULT_STUN_SERVER.to_string()]
+    } else {
+        args.ice_servers.clone()
+    };
+    let ice_servers = build_ice_servers(
+        &ice_server_urls,
+        args.turn_username.as_deref(),
+        args.turn_credential.as_deref(),
+    )
+    .unwrap_or_else(|error| exit_with_error(&args, error));
+    for ice_server in &ice_servers {
+        info!("ICE server: {}", describe_ice_server(ice_server));
+    }
+
+    let events = match &args.event_log {
+        Some(path) => EventLog::open(path).unwrap_or_else(|error| exit_with_error(&args, error)),
+        None => EventLog::default(),
+    };
+
+    // flags that can't work fail before anything connects
+    let base_config = client_config(&args, &connection_id, server_url, ice_servers, &events)
+        .unwrap_or_else(|error| exit_with_error(&args, error));
+
+    let backoff = Backoff {
+        base: args.reconnect_delay,
+        max: args.reconnect_max_delay,
+        jitter: args.reconnect_jitter,
+    };
+    // each attempt gets its own status & stats, so the reconnector only
+    // decides whether & when to reconnect
+    let mut reconnector = args.max_reconnects.map(|max_reconnects| {
+        let budget = ReconnectBudget::new(max_reconnects, args.reconnect_reset);
+        Reconnector::new(base_config.clone(), backoff, budget)
+    });
+
+    if args.print_offer {
+        let offer = Client::offer(base_config)
+            .await
+            .unwrap_or_else(|error| exit_with_error(&args, error));
+        print!("{}", offer);
+        return;
+    }
+
+    if args.load_test {
+        load_test(&args, base_config).await;
+        return;
+    }
+
+    let metrics = match args.metrics_addr {
+        Some(address) => {
+            let metrics = MetricsEndpoint::serve(address)
+                .await
+                .unwrap_or_else(|error| exit_with_error(&args, error));
+            info!("Serving metrics on http://{}/metrics", address);
+            Some(metrics)
+        }
+        None => None,
+    };
+
+    let quality_thresholds = QualityThresholds {
+        max_loss: args.max_loss,
+        max_rtt: args.max_rtt_ms.map(Duration::from_millis),
+    };
+
     loop {
-        let message_length = match data_channel.read(&mut buffer).await {
-            Ok(length) => length,
-            Err(err) => {
-                println!("Datachannel closed; Exit the read_loop: {}", err);
-                return Ok(());
-            }
+        let connection_status = Arc::new(ConnectionStatus::default());
+        let stats = Arc::new(ClientStats::default());
+        let config = ClientConfig {
+            status: Arc::clone(&connection_status),
+            stats: Arc::clone(&stats),
+            ..base_config.clone()
+        };
+        if let Some(metrics) = &metrics {
+            metrics.watch(Arc::clone(&stats), Arc::clone(&connection_status));
+        }
+        let result = run(&args, config).await;
+        let error = match result {
+            Ok(()) => match quality_thresholds.check(&stats) {
+                Ok(()) => {
+                    events.record(ClientEvent::Finished);
+                    return;
+                }
+                Err(error) => {
+                    events.record(ClientEvent::Error {
+                        reason: format!("{:#}", error),
+                    });
+                    exit_with_error(&args, error);
+                }
+            },
+            Err(error) => error,
         };
+        events.record(ClientEvent::Error {
+            reason: format!("{:#}", error),
+        });
+
+        let delay = reconnector.as_mut().and_then(|reconnector| {
+            reconnector.retry_delay(&error, connection_status.connected_for())
+        });
+        let delay = match delay {
+            Some(delay) => delay,
+            None => exit_with_error(&args, error),
+        };
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            signal = shutdown_signal() => {
+                info!("{} received, not reconnecting", signal);
+                return;
+            }
+        }
+    }
+}
+
+// unreliable & unordered unless the flags ask for more
+fn channel_settings(args: &Args) -> ChannelSettings {
+    let limited = args.max_retransmits.is_some() || args.max_packet_lifetime_ms.is_some();
+    ChannelSettings {
+        ordered: args.ordered,
+        max_retransmits: match args.max_retransmits {
+            None if !args.reliable && !limited => Some(0),
+            max_retransmits => max_retransmits,
+        },
+        max_packet_lifetime: args.max_packet_lifetime_ms,
+    }
+}
 
-        println!(
-            "Message from DataChannel: {}",
-            String::from_utf8(buffer[..message_length].to_vec())?
+fn exit_with_error(args: &Args, error: anyhow::Error) -> ! {
+    if args.debug {
+        eprintln!("Error: {:?}", error);
+    } else {
+        eprintln!("Error: {:#}", error);
+    }
+    std::process::exit(1);
+}
+
+// picks the signaling URL from the flag, then the environment, then the
+// default, failing early if it can't be used
+fn resolve_server_url(args: &Args) -> Result<Url> {
+    let server_url = match &args.server_url {
+        Some(server_url) => server_url.clone(),
+        None => env::var(SERVER_URL_ENV).unwrap_or_else(|_| DEFAULT_SERVER_URL.to_string()),
+    };
+
+    let parsed = Url::parse(&server_url)
+        .with_context(|| format!("invalid signaling server URL '{}'", server_url))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        bail!(
+            "signaling server URL '{}' must use http or https",
+            server_url
         );
     }
+    Ok(parsed)
 }
 
-// write_loop shows how to write to the datachannel directly
-async fn write_loop(data_channel: Arc<webrtc::data::data_channel::DataChannel>) -> Result<()> {
-    let mut result = Result::<usize>::Ok(0);
-    while result.is_ok() {
-        let timeout = tokio::time::sleep(Duration::from_secs(5));
-        tokio::pin!(timeout);
+// builds the client's config from the flags, failing on any that can't work
+fn client_config(
+    args: &Args,
+    connection_id: &str,
+    server_url: Url,
+    ice_servers: Vec<RTCIceServer>,
+    events: &EventLog,
+) -> Result<ClientConfig> {
+    let mut response_field_paths = ResponseFieldPaths::default();
+    for mapping in &args.response_fields {
+        response_field_paths.apply_mapping(mapping)?;
+    }
 
-        tokio::select! {
-            _ = timeout.as_mut() =>{
-                let message = "PING".to_string();
-                println!("Sending '{}'", message);
-                result = data_channel.write(&Bytes::from(message)).await.map_err(Into::into);
+    let ca_certificate = match &args.ca_cert {
+        Some(path) => {
+            let pem = std::fs::read(path)
+                .with_context(|| format!("failed to read CA certificate {}", path))?;
+            let certificate = Certificate::from_pem(&pem)
+                .with_context(|| format!("invalid CA certificate {}", path))?;
+            Some(certificate)
+        }
+        None => None,
+    };
+
+    let channel = channel_settings(args);
+    channel.validate()?;
+
+    Ok(ClientConfig {
+        server_url,
+        connection_id: connection_id.to_string(),
+        ice_servers,
+        answering_dtls_role: args.dtls_role,
+        response_field_paths,
+        max_response_bytes: args.max_response_bytes,
+        signaling_attempts: args.signaling_attempts,
+        signaling_timeout: args.signaling_timeout,
+        gathering_timeout: args.non_trickle.then_some(args.gathering_timeout),
+        trickle_url: args.trickle_url.clone(),
+        ca_certificate,
+        insecure_skip_verify: args.insecure_skip_verify,
+        framed: args.framed,
+        fragmented: args.fragmented,
+        max_reassembly_bytes: args.max_reassembly_bytes,
+        channel,
+        extra_channels: args.channels.clone(),
+        max_message_size: args.max_message_size,
+        send_queue_capacity: args.send_queue_capacity,
+        max_buffered_amount: args.max_buffered_amount,
+        status: Arc::new(ConnectionStatus::default()),
+        stats: Arc::new(ClientStats::default()),
+        events: events.clone(),
+        connection_events: ConnectionEvents::default(),
+    })
+}
+
+async fn run(args: &Args, config: ClientConfig) -> Result<()> {
+    let stats = Arc::clone(&config.stats);
+    // Ctrl-C or SIGTERM while signaling still tears the peer connection down
+    let signal = async {
+        info!("{} received, shutting down", shutdown_signal().await);
+    };
+    let connected = ClientSocket::connect_until(config, signal).await;
+
+    // the peer connection is torn down however the connection attempt ends
+    let result = match connected {
+        // the session reads the transport itself, so the receiver is unused
+        Ok(Some((sender, _receiver))) => {
+            let started = Instant::now();
+            let stop = async {
+                info!("{} received, shutting down", shutdown_signal().await);
+            };
+            let result = sender
+                .client()
+                .run_session(&session_settings(args), stop)
+                .await;
+            if args.flood {
+                log_flood_summary(&stats, started.elapsed());
             }
-        };
+            sender.close().await;
+            result
+        }
+        Ok(None) => Ok(()),
+        Err(error) => Err(error),
+    };
+
+    info!(
+        "Summary: sent {} messages ({} bytes), received {} messages ({} bytes)",
+        stats.messages_sent(),
+        stats.bytes_sent(),
+        stats.messages_received(),
+        stats.bytes_received()
+    );
+    if args.strict_pong {
+        info!("Unexpected responses: {}", stats.unexpected_responses());
+    }
+    if let Some(loss) = stats.loss() {
+        info!("Loss: {:.2}%", loss * 100.0);
+    }
+    if let Some(loss) = stats.sequence_loss() {
+        info!(
+            "Sequence gaps: {} missing ({:.2}%), {} out of order",
+            stats.missing_sequences(),
+            loss * 100.0,
+            stats.out_of_order()
+        );
+    }
+    if let Some(loss) = stats.server_sequence_loss() {
+        info!(
+            "Server sequence gaps: {} missing ({:.2}%), {} out of order",
+            stats.server_missing_sequences(),
+            loss * 100.0,
+            stats.server_out_of_order()
+        );
+    }
+    if stats.acks_received() + stats.retransmits() + stats.acks_dropped() > 0 {
+        info!(
+            "Acknowledgements: {} received, {} retransmits, {} given up on",
+            stats.acks_received(),
+            stats.retransmits(),
+            stats.acks_dropped()
+        );
+    }
+    if stats.queue_drops() > 0 {
+        info!("Send queue full: {} messages dropped", stats.queue_drops());
+    }
+    if stats.reassembly_failures() > 0 {
+        info!(
+            "Reassembly: {} messages missing fragments given up on",
+            stats.reassembly_failures()
+        );
+    }
+    if let Some(rtt) = stats.average_rtt() {
+        info!("Mean round trip time: {:?}", rtt);
+    }
+    if let Some(jitter) = stats.jitter() {
+        info!("Round trip jitter: {:?}", jitter);
     }
 
-    Ok(())
+    result
 }
 
-#[derive(Clone)]
-pub struct SessionAnswer {
-    pub sdp: String,
-    pub type_str: String,
+// what the session does once connected, from the flags
+fn session_settings(args: &Args) -> SessionSettings {
+    let send = if args.flood {
+        SendSettings {
+            interval: Duration::ZERO,
+            pad_to: args.payload_size,
+            ..SendSettings::default()
+        }
+    } else {
+        SendSettings {
+            interval: Duration::from_millis(args.send_interval_ms),
+            payload: build_payload(&args.payload, args.payload_size),
+            ..SendSettings::default()
+        }
+    };
+    SessionSettings {
+        send: SendSettings {
+            max_buffered_amount: args.max_buffered_amount,
+            count: args.count,
+            ..send
+        },
+        read: ReadSettings {
+            strict_pong: args.strict_pong,
+            max_message_size: args.max_message_size,
+            acks: None,
+            binary: args.binary,
+            server_sequences: args.server_sequence,
+        },
+        ready_attempts: args.ready_attempts,
+        ready_timeout: Duration::from_millis(args.ready_timeout_ms),
+        shared_secret: args.shared_secret.clone(),
+        challenge_timeout: Duration::from_millis(args.challenge_timeout_ms),
+        compress: args.compress,
+        compression_timeout: COMPRESSION_TIMEOUT,
+        checksum: args.checksum,
+        log_every: args.log_every,
+        // a flood would otherwise log every PONG
+        log_max_per_sec: match args.log_max_per_sec {
+            None if args.flood => Some(1),
+            log_max_per_sec => log_max_per_sec,
+        },
+        ack: args.ack.then_some(AckSettings {
+            timeout: args.ack_timeout,
+            max_retransmits: args.ack_retransmits,
+        }),
+        slow_start: args.slow_start.map(|window| SlowStart {
+            window,
+            shape: args.slow_start_shape,
+        }),
+        burst: args.burst,
+        interactive: args.interactive,
+        keepalive_interval: args.keepalive_interval,
+        keepalive_marker: args.keepalive_marker,
+        keepalive_timeout: args.keepalive_timeout,
+        max_session_lifetime: args.max_session_lifetime,
+        duration: args.duration,
+        drain: FLOOD_DRAIN,
+        stats_file: args
+            .stats_file
+            .clone()
+            .map(|path| (path, args.stats_interval)),
+        stats_log_interval: args.stats_log_interval,
+        candidate_pair_interval: args.candidate_pair_interval,
+        log_channels: args
+            .channels
+            .iter()
+            .map(|channel| channel.label.clone())
+            .collect(),
+    }
 }
 
-pub struct SessionCandidate {
-    pub candidate: String,
-    pub sdp_m_line_index: u16,
-    pub sdp_mid: String,
+#[cfg(unix)]
+async fn shutdown_signal() -> &'static str {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut terminate = signal(SignalKind::terminate()).expect("failed to listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => "Ctrl-C",
+        _ = terminate.recv() => "SIGTERM",
+    }
 }
 
-pub struct JsSessionResponse {
-    pub answer: SessionAnswer,
-    pub candidate: SessionCandidate,
+#[cfg(not(unix))]
+async fn shutdown_signal() -> &'static str {
+    let _ = tokio::signal::ctrl_c().await;
+    "Ctrl-C"
 }
 
-fn get_session_response(input: &str) -> JsSessionResponse {
-    let json_obj: JsonValue = input.parse().unwrap();
+// runs the load test and logs its summary, failing if no connection opened
+async fn load_test(args: &Args, config: ClientConfig) {
+    let interval = if args.send_rate > 0.0 {
+        Duration::from_secs_f64(1.0 / args.send_rate)
+    } else {
+        Duration::ZERO
+    };
+    let settings = LoadTestSettings {
+        connections: args.connections,
+        send: SendSettings {
+            interval,
+            pad_to: args.payload_size,
+            max_buffered_amount: args.max_buffered_amount,
+            count: args.count,
+            binary: args.binary,
+            ..SendSettings::default()
+        },
+        read: ReadSettings {
+            strict_pong: args.strict_pong,
+            max_message_size: args.max_message_size,
+            acks: None,
+            binary: args.binary,
+            server_sequences: args.server_sequence,
+        },
+        duration: args.duration,
+        log_every: args.log_every,
+        // every connection would otherwise log every PONG
+        log_max_per_sec: args.log_max_per_sec.or(Some(1)),
+    };
+    info!(
+        "Load test: {} connections at {} messages/s each",
+        args.connections, args.send_rate
+    );
+    let stop = async {
+        info!("{} received, ending the load test", shutdown_signal().await);
+    };
+    let summary = run_load_test(config, settings, stop).await;
+
+    let seconds = summary.elapsed.as_secs_f64().max(f64::EPSILON);
+    info!(
+        "Load test: {} of {} connections opened in {:?}",
+        summary.connected, summary.connections, summary.elapsed
+    );
+    info!(
+        "Load test: sent {} messages ({} bytes), {:.1} messages/s ({:.0} B/s)",
+        summary.messages_sent,
+        summary.bytes_sent,
+        summary.messages_sent as f64 / seconds,
+        summary.bytes_sent as f64 / seconds
+    );
+    info!(
+        "Load test: received {} messages ({} bytes), {:.1} messages/s ({:.0} B/s)",
+        summary.messages_received,
+        summary.bytes_received,
+        summary.messages_received as f64 / seconds,
+        summary.bytes_received as f64 / seconds
+    );
+    if let Some(loss) = summary.loss() {
+        info!(
+            "Load test: {} unanswered ({:.2}%)",
+            summary.lost,
+            loss * 100.0
+        );
+    }
+    if summary.read_errors + summary.queue_drops > 0 {
+        info!(
+            "Load test: {} read errors, {} messages dropped with the send queue full",
+            summary.read_errors, summary.queue_drops
+        );
+    }
+    if let (Some(average), Some(max)) = (summary.average_rtt, summary.max_rtt) {
+        info!("Load test: mean round trip {:?}, max {:?}", average, max);
+    }
+    for (error, count) in &summary.errors {
+        warn!("Load test: {} connection(s) ended with: {}", count, error);
+    }
+
+    if summary.connected == 0 && summary.connections > 0 {
+        exit_with_error(args, anyhow!("no load test connection opened"));
+    }
+}
 
-    let sdp_opt: Option<&String> = json_obj["answer"]["sdp"].get();
-    let sdp: String = sdp_opt.unwrap().clone();
+// the send rate over the run and how round trips were distributed
+fn log_flood_summary(stats: &ClientStats, elapsed: Duration) {
+    let seconds = elapsed.as_secs_f64().max(f64::EPSILON);
+    info!(
+        "Flood: sent {} messages ({} bytes) in {:?}, {:.1} messages/s ({:.0} B/s)",
+        stats.messages_sent(),
+        stats.bytes_sent(),
+        elapsed,
+        stats.messages_sent() as f64 / seconds,
+        stats.bytes_sent() as f64 / seconds
+    );
+    if let (Some(p50), Some(p90), Some(p99), Some(max)) = (
+        stats.rtt_percentile(50.0),
+        stats.rtt_percentile(90.0),
+        stats.rtt_percentile(99.0),
+        stats.max_rtt(),
+    ) {
+        info!(
+            "Round trips: p50 under {:?}, p90 under {:?}, p99 under {:?}, max {:?}",
+            p50, p90, p99, max
+        );
+    }
+}
 
-    let type_str_opt: Option<&String> = json_obj["answer"]["type"].get();
-    let type_str: String = type_str_opt.unwrap().clone();
+// repeats `payload` until it is `size` bytes long, when a size is given
+fn build_payload(payload: &str, size: Option<usize>) -> Bytes {
+    match size {
+        Some(size) if !payload.is_empty() => {
+            Bytes::from(payload.bytes().cycle().take(size).collect::<Vec<u8>>())
+        }
+        Some(size) => Bytes::from(vec![0u8; size]),
+        None => Bytes::from(payload.to_string()),
+    }
+}
 
-    let candidate_opt: Option<&String> = json_obj["candidate"]["candidate"].get();
-    let candidate: String = candidate_opt.unwrap().clone();
+// checked up front, since a bad filter would otherwise fall back silently
+fn parse_log_filter(input: &str) -> Result<String, String> {
+    EnvFilter::try_new(input)
+        .map(|_| input.to_string())
+        .map_err(|error| format!("invalid log filter '{}': {}", input, error))
+}
 
-    let sdp_m_line_index_opt: Option<&f64> = json_obj["candidate"]["sdpMLineIndex"].get();
-    let sdp_m_line_index: u16 = *(sdp_m_line_index_opt.unwrap()) as u16;
+fn parse_jitter(input: &str) -> Result<f64, String> {
+    let jitter: f64 = input
+        .parse()
+        .map_err(|_| format!("'{}' is not a number", input))?;
+    if !(0.0..=1.0).contains(&jitter) {
+        return Err(format!("jitter {} is outside 0 to 1", jitter));
+    }
+    Ok(jitter)
+}
 
-    let sdp_mid_opt: Option<&String> = json_obj["candidate"]["sdpMid"].get();
-    let sdp_mid: String = sdp_mid_opt.unwrap().clone();
+fn parse_dtls_role(input: &str) -> Result<DTLSRole, String> {
+    match input {
+        "client" => Ok(DTLSRole::Client),
+        "server" => Ok(DTLSRole::Server),
+        "auto" => Ok(DTLSRole::Auto),
+        _ => Err(format!(
+            "unknown DTLS role '{}', expected client, server or auto",
+            input
+        )),
+    }
+}
 
-    JsSessionResponse {
-        answer: SessionAnswer { sdp, type_str },
-        candidate: SessionCandidate {
-            candidate,
-            sdp_m_line_index,
-            sdp_mid,
+// parses `<label>` or `<label>:<profile>` into an extra data channel
+fn parse_channel(input: &str) -> Result<LabeledChannel, String> {
+    let (label, settings) = match input.split_once(':') {
+        Some((label, profile)) => match ChannelSettings::profile(profile) {
+            Some(settings) => (label, settings),
+            None => {
+                return Err(format!(
+                "unknown data channel profile '{}' in '{}', expected reliable, unreliable or bulk",
+                profile, input
+            ))
+            }
         },
+        None => (input, ChannelSettings::unreliable()),
+    };
+    if label.is_empty() {
+        return Err(format!("data channel '{}' has no label", input));
+    }
+    Ok(LabeledChannel {
+        label: label.to_string(),
+        settings,
+    })
+}
+
+// parses durations like `30s`, `500ms` or `2m`, a bare number means seconds
+fn parse_duration(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let (number, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", input))?;
+
+    let seconds_per_unit = match unit {
+        "ms" => return Ok(Duration::from_millis(number)),
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        _ => return Err(format!("unknown duration unit '{}' in '{}'", unit, input)),
+    };
+    number
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration '{}' is too long", input))
+}
+
+// like `parse_duration`, for intervals that have to be longer than zero
+fn parse_interval(input: &str) -> Result<Duration, String> {
+    let interval = parse_duration(input)?;
+    if interval.is_zero() {
+        return Err(format!(
+            "interval '{}' must be longer than zero",
+            input.trim()
+        ));
+    }
+    Ok(interval)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_each_unit() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 30s "), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse_duration("1h"), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn intervals_must_be_longer_than_zero() {
+        assert_eq!(parse_interval("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_interval("5"), Ok(Duration::from_secs(5)));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("0ms").is_err());
+        assert!(parse_interval("1d").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1d").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX / 60)).is_err());
+        assert!(parse_duration(&format!("{}m", u64::MAX)).is_err());
     }
 }